@@ -1,9 +1,41 @@
 fn main() {
+    println!("cargo:rustc-check-cfg=cfg(linker)");
+    println!("cargo:rustc-check-cfg=cfg(renameat2_dynamic)");
+    println!("cargo:rustc-check-cfg=cfg(renameat2_syscall)");
+    println!("cargo:rustc-check-cfg=cfg(syscall_table)");
+    // Set by cargo fuzz.
+    println!("cargo:rustc-check-cfg=cfg(fuzzing)");
+    // z/OS isn't a known target in all versions of rustc.
+    println!("cargo:rustc-check-cfg=cfg(target_os, values(\"zos\"))");
+
+    if syscall_table() {
+        println!("cargo:rustc-cfg=syscall_table");
+    }
+
     if supported() {
         println!("cargo:rustc-cfg=linker");
     }
 }
 
+/// Whether the syscall number for renameat2 is known for the target. This must
+/// be kept in sync with the table in src/linux.rs.
+fn syscall_table() -> bool {
+    let os = std::env::var("CARGO_CFG_TARGET_OS").unwrap();
+    let arch = std::env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+
+    os == "linux" && [
+        "x86",
+        "x86_64",
+        "arm",
+        "aarch64",
+        "riscv64",
+        "loongarch64",
+        "s390x",
+        "powerpc",
+        "powerpc64",
+    ].contains(&arch.as_str())
+}
+
 #[cfg(feature = "always-supported")]
 fn supported() -> bool {
     true
@@ -21,6 +53,37 @@ fn supported() -> bool {
 fn supported() -> bool {
     use std::process::Command;
 
+    // Only the Linux backend depends on what's available at link-time. Other
+    // targets declare what they need directly so a C compiler isn't required
+    // to cross-compile to them.
+    if std::env::var("CARGO_CFG_TARGET_OS").unwrap() != "linux" {
+        return false;
+    }
+
+    // rustix makes the syscalls itself so nothing needs to be linked. The libc
+    // crate knows the syscall number for every architecture.
+    if std::env::var_os("CARGO_FEATURE_RUSTIX").is_some()
+        || std::env::var_os("CARGO_FEATURE_LIBC").is_some() {
+        return true;
+    }
+
+    // glibc supports dlsym so the wrapper can be looked up at runtime instead.
+    // That way, the binary works with whichever version of glibc it ends up
+    // running against rather than the one it was built against.
+    if std::env::var("CARGO_CFG_TARGET_ENV").unwrap() == "gnu" {
+        println!("cargo:rustc-cfg=renameat2_dynamic");
+        return true;
+    }
+
+    // Some versions of musl don't expose a wrapper around the renameat2
+    // syscall. If we know the syscall number for the target architecture, we
+    // can make the syscall ourselves. That doesn't need a C compiler so it's
+    // checked before probing for a wrapper.
+    if syscall_table() {
+        println!("cargo:rustc-cfg=renameat2_syscall");
+        return true;
+    }
+
     let dir = tempfile::tempdir().unwrap();
     let test_c = dir.path().join("test.c");
 
@@ -33,37 +96,34 @@ fn supported() -> bool {
     // It might be better to #include the relevant headers and check that the
     // argument types are as expected.
 
-    if target.contains("linux") {
-        std::fs::write(&test_c, b"
-            void renameat2();
-            void statfs();
-
-            int main() {
-                renameat2();
-                statfs();
-            }"
-        ).unwrap();
-
-        let status = Command::new(compiler_path)
-            .current_dir(dir.path())
-            .arg("test.c")
-            .status()
-            .unwrap();
-
-        if status.success() {
-            return true;
-        }
-
-        // musl doesn't expose a wrapper around the renameat2 syscall but it
-        // does have the syscall number definition. So we're providing our own
-        // wrapper. Although, the syscall might not exist and we'd get an error
-        // instead of using the fallback in that case.
-        if target.contains("musl") {
-            cc::Build::new()
-                .file("src/linux-musl.c")
-                .compile("linux-musl");
-            return true;
-        }
+    std::fs::write(&test_c, b"
+        void renameat2();
+        void statfs();
+
+        int main() {
+            renameat2();
+            statfs();
+        }"
+    ).unwrap();
+
+    let status = Command::new(compiler_path)
+        .current_dir(dir.path())
+        .arg("test.c")
+        .status()
+        .unwrap();
+
+    if status.success() {
+        return true;
+    }
+
+    // For other architectures, musl does have the syscall number definition.
+    // So we're providing our own wrapper in C. Although, the syscall might not
+    // exist and we'd get an error instead of using the fallback in that case.
+    if target.contains("musl") {
+        cc::Build::new()
+            .file("src/linux-musl.c")
+            .compile("linux-musl");
+        return true;
     }
 
     false
@@ -0,0 +1,139 @@
+#ifndef RENAMORE_H
+#define RENAMORE_H
+
+/* This file is generated by cbindgen. Don't edit it by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The kind of the last error, like Rust's `std::io::ErrorKind`.
+ *
+ * In C, the variants are prefixed with `RENAMORE_ERROR_KIND_`.
+ */
+typedef enum RenamoreErrorKind {
+  /**
+   * There hasn't been an error on this thread.
+   */
+  RENAMORE_ERROR_KIND_OK = 0,
+  /**
+   * An error that doesn't fit any of the other kinds.
+   */
+  RENAMORE_ERROR_KIND_OTHER,
+  /**
+   * The source path doesn't exist.
+   */
+  RENAMORE_ERROR_KIND_NOT_FOUND,
+  /**
+   * The destination path already exists.
+   */
+  RENAMORE_ERROR_KIND_ALREADY_EXISTS,
+  /**
+   * The process doesn't have permission.
+   */
+  RENAMORE_ERROR_KIND_PERMISSION_DENIED,
+  /**
+   * The operation isn't supported by the platform or the file system.
+   */
+  RENAMORE_ERROR_KIND_UNSUPPORTED,
+  /**
+   * The paths are on different file systems.
+   */
+  RENAMORE_ERROR_KIND_CROSSES_DEVICES,
+  /**
+   * A path is null, empty, contains a NUL byte or isn't UTF-8 (on platforms
+   * that require it).
+   */
+  RENAMORE_ERROR_KIND_INVALID_INPUT,
+  /**
+   * The destination path is a directory that isn't empty.
+   */
+  RENAMORE_ERROR_KIND_DIRECTORY_NOT_EMPTY,
+  /**
+   * One of the paths is on a read-only file system.
+   */
+  RENAMORE_ERROR_KIND_READ_ONLY_FILESYSTEM,
+} RenamoreErrorKind;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Rename a file without overwriting the destination path if it exists.
+ *
+ * Returns 0 on success and -1 on failure. See `renamore::rename_exclusive`.
+ *
+ * # Safety
+ *
+ * `from` and `to` must be null or point to NUL-terminated strings.
+ */
+int renamore_rename_exclusive(const char *from, const char *to);
+
+/**
+ * Rename a file without overwriting the destination path if it exists, using
+ * a non-atomic fallback if necessary.
+ *
+ * Returns 1 if the rename was atomic, 0 if the fallback was used and -1 on
+ * failure. See `renamore::rename_exclusive_fallback`.
+ *
+ * # Safety
+ *
+ * `from` and `to` must be null or point to NUL-terminated strings.
+ */
+int renamore_rename_exclusive_fallback(const char *from, const char *to);
+
+/**
+ * Atomically swap two files.
+ *
+ * Returns 0 on success and -1 on failure. See `renamore::rename_exchange`.
+ *
+ * # Safety
+ *
+ * `a` and `b` must be null or point to NUL-terminated strings.
+ */
+int renamore_rename_exchange(const char *a, const char *b);
+
+/**
+ * Determine whether `renamore_rename_exclusive` is atomic on the volume that
+ * a path is on.
+ *
+ * Returns 1 if it is, 0 if it isn't and -1 on failure. See
+ * `renamore::rename_exclusive_is_atomic`.
+ *
+ * # Safety
+ *
+ * `path` must be null or point to a NUL-terminated string.
+ */
+int renamore_is_atomic(const char *path);
+
+/**
+ * The OS error code of the last failure on this thread.
+ *
+ * This is an errno value on Unix and a `GetLastError` value on Windows. It's
+ * 0 if the error didn't come from the OS (like an invalid path) or if there
+ * hasn't been a failure.
+ */
+int renamore_last_error(void);
+
+/**
+ * The kind of the last failure on this thread.
+ */
+RenamoreErrorKind renamore_last_error_kind(void);
+
+/**
+ * A description of the last failure on this thread, or null if there hasn't
+ * been one.
+ *
+ * The string is owned by the library and is valid until the next failure on
+ * this thread.
+ */
+const char *renamore_last_error_message(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RENAMORE_H */
@@ -0,0 +1,229 @@
+//! C bindings for renamore.
+//!
+//! This builds a shared library (`librenamore_capi.so`, `librenamore_capi.dylib`
+//! or `renamore_capi.dll`) and a static library that can be used from C, C++
+//! or anything else with a C FFI (like Python's ctypes). The declarations are
+//! in `include/renamore.h`, which is generated with cbindgen:
+//!
+//! ```sh
+//! cbindgen --config capi/cbindgen.toml --output capi/include/renamore.h capi
+//! ```
+//!
+//! # Errors
+//!
+//! Errors are reported in the style of errno. Functions return -1 on failure
+//! and the details can be retrieved on the same thread with
+//! [`renamore_last_error`], [`renamore_last_error_kind`] and
+//! [`renamore_last_error_message`]. These are left alone on success.
+//!
+//! # Paths
+//!
+//! Paths are NUL-terminated strings. On Unix, they're passed to the OS as they
+//! are so they don't need to be UTF-8. On other platforms, they must be UTF-8.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+#[cfg(all(test, unix))]
+mod tests;
+
+/// The kind of the last error, like Rust's `std::io::ErrorKind`.
+///
+/// In C, the variants are prefixed with `RENAMORE_ERROR_KIND_`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenamoreErrorKind {
+    /// There hasn't been an error on this thread.
+    Ok = 0,
+    /// An error that doesn't fit any of the other kinds.
+    Other,
+    /// The source path doesn't exist.
+    NotFound,
+    /// The destination path already exists.
+    AlreadyExists,
+    /// The process doesn't have permission.
+    PermissionDenied,
+    /// The operation isn't supported by the platform or the file system.
+    Unsupported,
+    /// The paths are on different file systems.
+    CrossesDevices,
+    /// A path is null, empty, contains a NUL byte or isn't UTF-8 (on platforms
+    /// that require it).
+    InvalidInput,
+    /// The destination path is a directory that isn't empty.
+    DirectoryNotEmpty,
+    /// One of the paths is on a read-only file system.
+    ReadOnlyFilesystem,
+}
+
+impl From<ErrorKind> for RenamoreErrorKind {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::NotFound => Self::NotFound,
+            ErrorKind::AlreadyExists => Self::AlreadyExists,
+            ErrorKind::PermissionDenied => Self::PermissionDenied,
+            ErrorKind::Unsupported => Self::Unsupported,
+            ErrorKind::CrossesDevices => Self::CrossesDevices,
+            ErrorKind::InvalidInput => Self::InvalidInput,
+            ErrorKind::DirectoryNotEmpty => Self::DirectoryNotEmpty,
+            ErrorKind::ReadOnlyFilesystem => Self::ReadOnlyFilesystem,
+            _ => Self::Other,
+        }
+    }
+}
+
+struct LastError {
+    code: c_int,
+    kind: RenamoreErrorKind,
+    message: CString,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<LastError>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: &Error) {
+    // The message comes from Display so it can't contain a NUL byte unless a
+    // path in it does.
+    let message = CString::new(error.to_string().replace('\0', "\\0")).unwrap();
+
+    LAST_ERROR.with(|last| {
+        *last.borrow_mut() = Some(LastError {
+            code: renamore::error::raw_os_error(error).unwrap_or(0),
+            kind: error.kind().into(),
+            message,
+        });
+    });
+}
+
+// Converts the result into the value returned to C.
+fn finish(result: Result<c_int>) -> c_int {
+    result.unwrap_or_else(|e| {
+        set_last_error(&e);
+        -1
+    })
+}
+
+unsafe fn path<'a>(path: *const c_char, name: &str) -> Result<&'a Path> {
+    if path.is_null() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("the `{name}` path is null")));
+    }
+
+    let path = CStr::from_ptr(path);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        Ok(Path::new(std::ffi::OsStr::from_bytes(path.to_bytes())))
+    }
+
+    #[cfg(not(unix))]
+    {
+        path.to_str()
+            .map(Path::new)
+            .map_err(|_| {
+                Error::new(ErrorKind::InvalidInput, format!("the `{name}` path isn't UTF-8"))
+            })
+    }
+}
+
+/// Rename a file without overwriting the destination path if it exists.
+///
+/// Returns 0 on success and -1 on failure. See `renamore::rename_exclusive`.
+///
+/// # Safety
+///
+/// `from` and `to` must be null or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn renamore_rename_exclusive(
+    from: *const c_char,
+    to: *const c_char,
+) -> c_int {
+    finish((|| {
+        renamore::rename_exclusive(path(from, "from")?, path(to, "to")?)?;
+        Ok(0)
+    })())
+}
+
+/// Rename a file without overwriting the destination path if it exists, using
+/// a non-atomic fallback if necessary.
+///
+/// Returns 1 if the rename was atomic, 0 if the fallback was used and -1 on
+/// failure. See `renamore::rename_exclusive_fallback`.
+///
+/// # Safety
+///
+/// `from` and `to` must be null or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn renamore_rename_exclusive_fallback(
+    from: *const c_char,
+    to: *const c_char,
+) -> c_int {
+    finish((|| {
+        let atomic = renamore::rename_exclusive_fallback(path(from, "from")?, path(to, "to")?)?;
+        Ok(atomic as c_int)
+    })())
+}
+
+/// Atomically swap two files.
+///
+/// Returns 0 on success and -1 on failure. See `renamore::rename_exchange`.
+///
+/// # Safety
+///
+/// `a` and `b` must be null or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn renamore_rename_exchange(a: *const c_char, b: *const c_char) -> c_int {
+    finish((|| {
+        renamore::rename_exchange(path(a, "a")?, path(b, "b")?)?;
+        Ok(0)
+    })())
+}
+
+/// Determine whether `renamore_rename_exclusive` is atomic on the volume that
+/// a path is on.
+///
+/// Returns 1 if it is, 0 if it isn't and -1 on failure. See
+/// `renamore::rename_exclusive_is_atomic`.
+///
+/// # Safety
+///
+/// `path` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn renamore_is_atomic(path: *const c_char) -> c_int {
+    finish((|| {
+        let atomic = renamore::rename_exclusive_is_atomic(self::path(path, "path")?)?;
+        Ok(atomic as c_int)
+    })())
+}
+
+/// The OS error code of the last failure on this thread.
+///
+/// This is an errno value on Unix and a `GetLastError` value on Windows. It's
+/// 0 if the error didn't come from the OS (like an invalid path) or if there
+/// hasn't been a failure.
+#[no_mangle]
+pub extern "C" fn renamore_last_error() -> c_int {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(0, |last| last.code))
+}
+
+/// The kind of the last failure on this thread.
+#[no_mangle]
+pub extern "C" fn renamore_last_error_kind() -> RenamoreErrorKind {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(RenamoreErrorKind::Ok, |last| last.kind))
+}
+
+/// A description of the last failure on this thread, or null if there hasn't
+/// been one.
+///
+/// The string is owned by the library and is valid until the next failure on
+/// this thread.
+#[no_mangle]
+pub extern "C" fn renamore_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow().as_ref().map_or(std::ptr::null(), |last| last.message.as_ptr())
+    })
+}
@@ -0,0 +1,43 @@
+use std::ffi::{CStr, CString};
+use std::io::Result;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use super::*;
+
+fn c_path(path: &Path) -> CString {
+    CString::new(path.as_os_str().as_bytes()).unwrap()
+}
+
+#[test]
+fn rename_exclusive() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let from = c_path(&dir.path().join("from"));
+    let to = c_path(&dir.path().join("to"));
+
+    std::fs::write(dir.path().join("from"), "from")?;
+    assert_eq!(unsafe { renamore_rename_exclusive(from.as_ptr(), to.as_ptr()) }, 0);
+    assert_eq!(std::fs::read_to_string(dir.path().join("to"))?, "from");
+
+    std::fs::write(dir.path().join("from"), "from")?;
+    assert_eq!(unsafe { renamore_rename_exclusive(from.as_ptr(), to.as_ptr()) }, -1);
+    assert_eq!(renamore_last_error_kind(), RenamoreErrorKind::AlreadyExists);
+    assert_eq!(renamore_last_error(), 17); // EEXIST
+    assert!(!renamore_last_error_message().is_null());
+
+    // The kind is changed by wrapping the error but the code is still there.
+    let populated = dir.path().join("populated");
+    std::fs::create_dir(&populated)?;
+    std::fs::write(populated.join("file"), "file")?;
+    let populated = c_path(&populated);
+    assert_eq!(unsafe { renamore_rename_exclusive(from.as_ptr(), populated.as_ptr()) }, -1);
+    assert_eq!(renamore_last_error_kind(), RenamoreErrorKind::DirectoryNotEmpty);
+    assert_ne!(renamore_last_error(), 0); // EEXIST or ENOTEMPTY
+
+    assert_eq!(unsafe { renamore_rename_exclusive(std::ptr::null(), to.as_ptr()) }, -1);
+    assert_eq!(renamore_last_error_kind(), RenamoreErrorKind::InvalidInput);
+    assert_eq!(renamore_last_error(), 0);
+    let message = unsafe { CStr::from_ptr(renamore_last_error_message()) };
+    assert_eq!(message.to_str().unwrap(), "the `from` path is null");
+
+    Ok(())
+}
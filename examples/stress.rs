@@ -0,0 +1,269 @@
+//! Race `rename_exclusive` against other threads and processes to check that
+//! it never replaces the destination.
+//!
+//! ```text
+//! cargo run --release --example stress -- DIR [--threads N] [--processes N] [--rounds N]
+//! ```
+//!
+//! Each round, every thread tries to claim the same path in DIR. Half of them
+//! rename a file onto it with `rename_exclusive` and the other half create it
+//! with `O_EXCL` (`create_new`). At most one of them may succeed and the path
+//! must end up with the contents written by that one. Anything else means that
+//! something was overwritten, which is reported as a violation.
+//!
+//! DIR must be empty. If anything goes wrong (like `rename_exclusive` not being
+//! supported), then the error is reported and the run stops.
+//!
+//! The threads in a process start each round together. Separate processes
+//! aren't synchronized so they only collide some of the time, but they're
+//! worth using on network file systems where each process might be treated
+//! differently (like running one instance on each NFS client).
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Result, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+
+struct Args {
+    dir: PathBuf,
+    threads: u32,
+    processes: u32,
+    rounds: u32,
+    child: Option<u32>,
+}
+
+fn parse() -> std::result::Result<Args, String> {
+    let mut args = std::env::args_os().skip(1);
+    let mut parsed =
+        Args { dir: PathBuf::new(), threads: 8, processes: 1, rounds: 1000, child: None };
+    let mut dir = None;
+
+    while let Some(arg) = args.next() {
+        let mut number = |name: &str| -> std::result::Result<u32, String> {
+            args.next()
+                .and_then(|value| value.to_str()?.parse().ok())
+                .filter(|&value| value > 0)
+                .ok_or(format!("{} needs a positive number", name))
+        };
+
+        match arg.to_str() {
+            Some("--threads") => parsed.threads = number("--threads")?,
+            Some("--processes") => parsed.processes = number("--processes")?,
+            Some("--rounds") => parsed.rounds = number("--rounds")?,
+            Some("--child") => parsed.child = Some(number("--child")?),
+            _ if dir.is_none() => dir = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument {:?}", arg)),
+        }
+    }
+
+    parsed.dir = dir.ok_or("no directory given")?;
+    Ok(parsed)
+}
+
+fn target(dir: &Path, round: u32) -> PathBuf {
+    dir.join(format!("round-{}", round))
+}
+
+// Returns the rounds won by this process and the token that each winner wrote.
+// If any thread gets an unexpected error, then the rest of the rounds are
+// skipped and the first error is returned. The threads still wait at the
+// barrier for every round so that none of them are left waiting for the others.
+fn contend(dir: &Path, process: u32, threads: u32, rounds: u32) -> Result<Vec<(u32, String)>> {
+    let barrier = Arc::new(Barrier::new(threads as usize));
+    let error = Arc::new(Mutex::new(None));
+    let aborted = Arc::new(AtomicBool::new(false));
+
+    let handles = (0..threads).map(|thread| {
+        let dir = dir.to_path_buf();
+        let (barrier, error, aborted) = (barrier.clone(), error.clone(), aborted.clone());
+
+        std::thread::spawn(move || {
+            let token = format!("{}-{}", process, thread);
+            let source = dir.join(format!("source-{}", token));
+            let renamer = thread % 2 == 0;
+            let mut won = Vec::new();
+
+            let abort = |e: std::io::Error| {
+                error.lock().unwrap().get_or_insert(e);
+                aborted.store(true, Ordering::SeqCst);
+            };
+
+            for round in 0..rounds {
+                let to = target(&dir, round);
+
+                if renamer && !aborted.load(Ordering::SeqCst) {
+                    if let Err(e) = std::fs::write(&source, &token) {
+                        abort(e);
+                    }
+                }
+
+                barrier.wait();
+                if aborted.load(Ordering::SeqCst) {
+                    continue;
+                }
+
+                let result = if renamer {
+                    renamore::rename_exclusive(&source, &to)
+                } else {
+                    std::fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&to)
+                        .and_then(|mut file| file.write_all(token.as_bytes()))
+                };
+
+                match result {
+                    Ok(()) => won.push((round, token.clone())),
+                    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                    Err(e) => abort(e),
+                }
+            }
+
+            let _ = std::fs::remove_file(&source);
+            won
+        })
+    }).collect::<Vec<_>>();
+
+    let mut won = Vec::new();
+
+    for handle in handles {
+        won.extend(handle.join().unwrap());
+    }
+
+    let error = error.lock().unwrap().take();
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(won),
+    }
+}
+
+fn run(args: &Args) -> Result<u32> {
+    let mut won = Vec::new();
+    let exe = std::env::current_exe()?;
+    let children = (1..args.processes).map(|process| {
+        Command::new(&exe)
+            .arg(&args.dir)
+            .args(["--threads", &args.threads.to_string(), "--rounds", &args.rounds.to_string()])
+            .args(["--child", &process.to_string()])
+            .stdout(Stdio::piped())
+            .spawn()
+    }).collect::<Result<Vec<_>>>()?;
+
+    // The children are waited for even if this process failed so that none of
+    // them are still running when the files are removed.
+    let mut result = contend(&args.dir, 0, args.threads, args.rounds).map(|mine| won.extend(mine));
+
+    for child in children {
+        let output = child.wait_with_output()?;
+
+        if !output.status.success() {
+            result = result.and(Err(std::io::Error::other("a child process failed")));
+        }
+        for line in output.stdout.lines() {
+            let line = line?;
+            let (round, token) = line.split_once(' ').unwrap();
+            won.push((round.parse().unwrap(), token.to_string()));
+        }
+    }
+
+    if let Err(e) = result {
+        for round in 0..args.rounds {
+            let _ = std::fs::remove_file(target(&args.dir, round));
+        }
+        return Err(e);
+    }
+
+    let mut winners = BTreeMap::<u32, Vec<String>>::new();
+
+    for (round, token) in won {
+        winners.entry(round).or_default().push(token);
+    }
+
+    let mut violations = 0;
+
+    for round in 0..args.rounds {
+        let contents = std::fs::read_to_string(target(&args.dir, round)).ok();
+        let winners = winners.remove(&round).unwrap_or_default();
+
+        let ok = match winners.as_slice() {
+            [] => contents.is_none(),
+            [winner] => contents.as_ref() == Some(winner),
+            _ => false,
+        };
+
+        if !ok {
+            violations += 1;
+            println!("round {}: winners {:?} but the file contains {:?}", round, winners, contents);
+        }
+        let _ = std::fs::remove_file(target(&args.dir, round));
+    }
+
+    Ok(violations)
+}
+
+fn main() -> ExitCode {
+    let args = match parse() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("stress: {}", message);
+            return ExitCode::from(2);
+        }
+    };
+
+    if let Some(process) = args.child {
+        return match contend(&args.dir, process, args.threads, args.rounds) {
+            Ok(won) => {
+                for (round, token) in won {
+                    println!("{} {}", round, token);
+                }
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("stress: {}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    // Files left behind by an earlier run would look like overwrites.
+    match std::fs::read_dir(&args.dir).map(|mut entries| entries.next().is_none()) {
+        Ok(true) => {}
+        Ok(false) => {
+            eprintln!("stress: {} isn't empty", args.dir.display());
+            return ExitCode::from(2);
+        }
+        Err(e) => {
+            eprintln!("stress: {}: {}", args.dir.display(), e);
+            return ExitCode::from(2);
+        }
+    }
+
+    let file_system = renamore::fs_info(&args.dir).ok().and_then(|info| info.name);
+    let atomic = renamore::rename_exclusive_is_atomic(&args.dir);
+
+    println!("directory: {}", args.dir.display());
+    println!("file system: {}", file_system.as_deref().unwrap_or("unknown"));
+    println!("rename_exclusive_is_atomic: {:?}", atomic);
+    println!(
+        "contenders: {} threads in {} processes for {} rounds",
+        args.threads, args.processes, args.rounds,
+    );
+
+    match run(&args) {
+        Ok(0) => {
+            println!("violations: 0");
+            ExitCode::SUCCESS
+        }
+        Ok(violations) => {
+            println!("violations: {}", violations);
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("stress: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|release: &[u8]| {
+    #[cfg(target_os = "linux")]
+    renamore::fuzzing::parse_kernel_release(release);
+    #[cfg(not(target_os = "linux"))]
+    let _ = release;
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|mountinfo: &str| {
+    #[cfg(target_os = "linux")]
+    renamore::fuzzing::parse_mountinfo(mountinfo);
+    #[cfg(not(target_os = "linux"))]
+    let _ = mountinfo;
+});
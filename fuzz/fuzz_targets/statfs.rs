@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u16, u16, u16, u32)| {
+    #[cfg(target_os = "linux")]
+    renamore::fuzzing::filesystem_support(input.0, input.1, input.2, input.3);
+    #[cfg(not(target_os = "linux"))]
+    let _ = input;
+});
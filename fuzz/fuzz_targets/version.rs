@@ -0,0 +1,7 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|version: &str| {
+    renamore::fuzzing::parse_version(version);
+});
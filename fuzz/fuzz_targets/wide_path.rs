@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Unpaired surrogates are allowed in Windows paths so the input is arbitrary
+// UTF-16 rather than a string.
+fuzz_target!(|input: (Vec<u16>, bool)| {
+    #[cfg(target_os = "windows")]
+    renamore::fuzzing::to_wide_path(&input.0, input.1);
+    #[cfg(not(target_os = "windows"))]
+    let _ = input;
+});
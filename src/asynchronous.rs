@@ -0,0 +1,582 @@
+//! Async versions of the rename functions that work with any runtime.
+//!
+//! Renaming is a blocking system call so it can't be done on an async task
+//! without stalling the runtime. Every runtime has a way of running blocking
+//! work on another thread (like `tokio::task::spawn_blocking` or
+//! `blocking::unblock`) and [`BlockingExecutor`] lets the functions in this
+//! module use whichever one is available. They return a [`Blocking`] future
+//! that resolves once the operation has finished. Nothing here depends on a
+//! particular runtime. The exception is `rename_exclusive_ring`, which is woken
+//! by io_uring and doesn't need an executor.
+//!
+//! This module is only available with the `async` feature.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use renamore::asynchronous::{self, ThreadExecutor};
+//!
+//! async fn publish() -> std::io::Result<()> {
+//!     asynchronous::rename_exclusive(&ThreadExecutor, "build/app.tmp", "dist/app").await
+//! }
+//! ```
+//!
+//! Using Tokio's blocking thread pool:
+//!
+//! ```ignore
+//! let executor = |task| {
+//!     tokio::task::spawn_blocking(task);
+//! };
+//!
+//! renamore::asynchronous::rename_exclusive(&executor, "a", "b").await?;
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::future::Future;
+use std::io::{Error, Result};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use crate::RenameOptions;
+
+/// A blocking task given to a [`BlockingExecutor`].
+pub type Task = Box<dyn FnOnce() + Send + 'static>;
+
+/// Something that can run blocking tasks away from the async runtime.
+///
+/// This is implemented for closures that take a [`Task`] so most runtimes can
+/// be used without writing a type. The task should be run on a thread where
+/// blocking is allowed. If the task is dropped without being run (like when the
+/// runtime is shutting down), then the future resolves to an error rather than
+/// waiting forever.
+pub trait BlockingExecutor {
+    /// Run the task, or arrange for it to be run.
+    fn execute(&self, task: Task);
+}
+
+impl<F: Fn(Task)> BlockingExecutor for F {
+    fn execute(&self, task: Task) {
+        self(task)
+    }
+}
+
+/// An executor that starts a new thread for each task.
+///
+/// This works everywhere but starting a thread is relatively slow. Prefer the
+/// blocking thread pool of the runtime if it has one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadExecutor;
+
+impl BlockingExecutor for ThreadExecutor {
+    fn execute(&self, task: Task) {
+        std::thread::spawn(task);
+    }
+}
+
+struct Shared<T> {
+    result: Option<Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// A future that resolves to the result of a blocking task.
+///
+/// This is returned by the functions in this module. The task is given to the
+/// executor straight away so it runs even if the future is never polled.
+#[must_use = "futures do nothing unless polled"]
+pub struct Blocking<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap();
+
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for Blocking<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Blocking").finish_non_exhaustive()
+    }
+}
+
+// Completes the future when it's dropped, which happens if the task is
+// dropped without running or panics.
+struct Completion<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+    result: Option<Result<T>>,
+}
+
+impl<T> Drop for Completion<T> {
+    fn drop(&mut self) {
+        let result = self.result.take()
+            .unwrap_or_else(|| Err(Error::other("the blocking task didn't finish")));
+        let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Run any blocking operation on the executor.
+///
+/// This is what the other functions in this module are built on.
+pub fn unblock<E, T, F>(executor: &E, f: F) -> Blocking<T>
+where
+    E: BlockingExecutor + ?Sized,
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(Shared { result: None, waker: None }));
+    let completion = Completion { shared: shared.clone(), result: None };
+
+    executor.execute(Box::new(move || {
+        // Moved in as a whole so that it's dropped when the task is.
+        let mut completion = completion;
+        completion.result = Some(f());
+    }));
+
+    Blocking { shared }
+}
+
+/// Rename a file without overwriting the destination path if it exists.
+///
+/// See [`rename_exclusive`](crate::rename_exclusive).
+pub fn rename_exclusive<E, F, T>(executor: &E, from: F, to: T) -> Blocking<()>
+where
+    E: BlockingExecutor + ?Sized,
+    F: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    let (from, to) = (from.as_ref().to_path_buf(), to.as_ref().to_path_buf());
+
+    unblock(executor, move || crate::rename_exclusive(from, to))
+}
+
+/// Rename a file without overwriting the destination path if it exists, using
+/// a non-atomic fallback if necessary.
+///
+/// See [`rename_exclusive_fallback`](crate::rename_exclusive_fallback).
+pub fn rename_exclusive_fallback<E, F, T>(executor: &E, from: F, to: T) -> Blocking<bool>
+where
+    E: BlockingExecutor + ?Sized,
+    F: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    let (from, to) = (from.as_ref().to_path_buf(), to.as_ref().to_path_buf());
+
+    unblock(executor, move || crate::rename_exclusive_fallback(from, to))
+}
+
+/// Atomically swap two files.
+///
+/// See [`rename_exchange`](crate::rename_exchange).
+pub fn rename_exchange<E, A, B>(executor: &E, a: A, b: B) -> Blocking<()>
+where
+    E: BlockingExecutor + ?Sized,
+    A: AsRef<Path>,
+    B: AsRef<Path>,
+{
+    let (a, b) = (a.as_ref().to_path_buf(), b.as_ref().to_path_buf());
+
+    unblock(executor, move || crate::rename_exchange(a, b))
+}
+
+/// Determine support for atomic renames on the volumes that the paths are on,
+/// so that later queries are answered from the cache.
+///
+/// Finding out whether a volume supports atomic renames can mean calling
+/// `statfs`, parsing `/proc/self/mountinfo` or checking the kernel version.
+/// The answer is cached (see [`invalidate_caches`](crate::invalidate_caches))
+/// so calling this at startup with the directories that will be renamed into
+/// keeps that work out of latency-sensitive code later on. This affects
+/// [`rename_exclusive_fallback`](crate::rename_exclusive_fallback),
+/// [`rename_exclusive_support`](crate::rename_exclusive_support),
+/// [`rename_exclusive_atomic_support`](crate::rename_exclusive_atomic_support)
+/// and [`rename_exclusive_is_atomic`](crate::rename_exclusive_is_atomic).
+///
+/// Every path is probed even if some fail. The first error is returned.
+///
+/// # Platform-specific behaviour
+///
+/// Answers are only cached on Linux and Apple platforms. Elsewhere, they're
+/// cheap enough (or depend on more than the volume) that this does nothing
+/// useful.
+///
+/// # Examples
+///
+/// ```no_run
+/// use renamore::asynchronous::{self, ThreadExecutor};
+///
+/// async fn start() -> std::io::Result<()> {
+///     asynchronous::warm_capabilities(&ThreadExecutor, ["/srv/uploads", "/srv/cache"]).await
+/// }
+/// ```
+pub fn warm_capabilities<E, I, P>(executor: &E, paths: I) -> Blocking<()>
+where
+    E: BlockingExecutor + ?Sized,
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path>,
+{
+    let paths = paths.into_iter().map(|path| path.as_ref().to_path_buf()).collect::<Vec<_>>();
+
+    unblock(executor, move || {
+        let mut first_error = None;
+
+        for path in paths {
+            if let Err(e) = crate::rename_exclusive_atomic_support(path) {
+                first_error.get_or_insert(e);
+            }
+        }
+
+        first_error.map_or(Ok(()), Err)
+    })
+}
+
+/// Rename many files without overwriting the destination paths if they exist,
+/// with at most `limit` renames in flight at once.
+///
+/// See [`RenameOptions::rename_exclusive_all_async`].
+pub fn rename_exclusive_all<E, I, F, T>(
+    executor: &E,
+    limit: usize,
+    renames: I,
+) -> Batch<'_, E, I::IntoIter>
+where
+    E: BlockingExecutor + ?Sized,
+    I: IntoIterator<Item = (F, T)>,
+    F: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    RenameOptions::new().rename_exclusive_all_async(executor, limit, renames)
+}
+
+/// Rename many files within a [`Transaction`] so that either all of them
+/// happen or none of them do.
+///
+/// The whole transaction runs as one task on the executor. If any rename
+/// fails, then the transaction is rolled back and that error is returned.
+/// Otherwise, the transaction is committed. See [`Transaction`] for the
+/// platforms that support this.
+///
+/// This is only available with the `txf` feature.
+///
+/// [`Transaction`]: crate::Transaction
+#[cfg(feature = "txf")]
+pub fn rename_exclusive_transaction<E, I, F, T>(executor: &E, renames: I) -> Blocking<()>
+where
+    E: BlockingExecutor + ?Sized,
+    I: IntoIterator<Item = (F, T)>,
+    F: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    let renames = owned(renames);
+
+    unblock(executor, move || {
+        let transaction = crate::Transaction::new()?;
+
+        for (from, to) in renames {
+            if let Err(e) = transaction.rename_exclusive(from, to) {
+                let _ = transaction.rollback();
+                return Err(e);
+            }
+        }
+
+        transaction.commit()
+    })
+}
+
+/// Rename many files on a [`Ring`] without going through an executor.
+///
+/// The renames are submitted straight away and the future is woken by the
+/// kernel as they complete. The ring has an eventfd that the kernel signals and
+/// a thread that waits on it, so no blocking thread pool is involved. The ring
+/// is given back along with the results so that it can be used again. The
+/// results are in the same order as the renames. See
+/// [`Ring::rename_exclusive_all`]. This is only available on Linux with the
+/// `io-uring` feature.
+///
+/// [`Ring`]: crate::Ring
+/// [`Ring::rename_exclusive_all`]: crate::Ring::rename_exclusive_all
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub fn rename_exclusive_ring<I, F, T>(mut ring: crate::Ring, renames: I) -> RingRenames
+where
+    I: IntoIterator<Item = (F, T)>,
+    F: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    let mut batch = crate::uring::Batch::new(&owned(renames));
+
+    match ring.notifier() {
+        Ok(notifier) => {
+            if ring.fill(&mut batch) != 0 {
+                if let Err(e) = ring.submit() {
+                    ring.fail(&mut batch, e);
+                }
+            }
+
+            RingRenames { ring: Some((ring, notifier)), error: None, batch }
+        }
+        Err(e) => RingRenames { ring: None, error: Some(e), batch },
+    }
+}
+
+/// A future that resolves once every rename on a [`Ring`] has completed.
+///
+/// This is returned by [`rename_exclusive_ring`]. This is only available on
+/// Linux with the `io-uring` feature.
+///
+/// [`Ring`]: crate::Ring
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+#[must_use = "futures do nothing unless polled"]
+pub struct RingRenames {
+    ring: Option<(crate::Ring, Arc<crate::uring::Notifier>)>,
+    error: Option<Error>,
+    batch: crate::uring::Batch,
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+impl Future for RingRenames {
+    type Output = Result<(crate::Ring, Vec<Result<()>>)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let (ring, notifier) = match &mut this.ring {
+            Some(ring) => ring,
+            None => return Poll::Ready(Err(this.error.take().expect("polled after completion"))),
+        };
+
+        loop {
+            // The waker is set before looking for completions so that one
+            // arriving in between isn't missed.
+            *notifier.waker.lock().unwrap() = Some(cx.waker().clone());
+            ring.reap(&mut this.batch);
+
+            if ring.fill(&mut this.batch) != 0 {
+                if let Err(e) = ring.submit() {
+                    ring.fail(&mut this.batch, e);
+                    continue;
+                }
+            }
+
+            if this.batch.is_done() {
+                notifier.waker.lock().unwrap().take();
+                let (ring, _) = this.ring.take().unwrap();
+                return Poll::Ready(Ok((ring, std::mem::take(&mut this.batch.results))));
+            }
+
+            if !ring.has_completions() {
+                return Poll::Pending;
+            }
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+impl std::fmt::Debug for RingRenames {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingRenames").finish_non_exhaustive()
+    }
+}
+
+fn owned<I, F, T>(renames: I) -> Vec<(PathBuf, PathBuf)>
+where
+    I: IntoIterator<Item = (F, T)>,
+    F: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    renames.into_iter().map(|(from, to)| (from.as_ref().into(), to.as_ref().into())).collect()
+}
+
+/// Renames that are running on an executor, yielding results as they complete.
+///
+/// This is returned by [`RenameOptions::rename_exclusive_all_async`]. The
+/// renames are taken from the iterator as earlier ones complete so the
+/// iterator can be arbitrarily long (or generate the paths lazily). Nothing is
+/// started until the batch is first polled.
+///
+/// Each result is paired with the index of the rename in the iterator. Results
+/// are yielded in the order that the renames complete, not the order that they
+/// were started.
+///
+/// Each rename is a separate task on the executor. To have the renames happen
+/// all or nothing, use [`rename_exclusive_transaction`] instead. To submit a
+/// whole batch at once with io_uring, use `rename_exclusive_ring`.
+///
+/// There's no `Stream` trait in the standard library so this doesn't implement
+/// one but [`Batch::poll_next`] has the same signature as `Stream::poll_next`
+/// so it's easy to adapt. For example, with the futures crate:
+///
+/// ```ignore
+/// let stream = futures::stream::poll_fn(move |cx| batch.poll_next(cx));
+/// ```
+#[must_use = "batches do nothing unless polled"]
+pub struct Batch<'a, E: ?Sized, I> {
+    executor: &'a E,
+    options: RenameOptions,
+    limit: usize,
+    renames: std::iter::Enumerate<I>,
+    in_flight: Vec<(usize, Blocking<()>)>,
+}
+
+impl<E, I, F, T> Batch<'_, E, I>
+where
+    E: BlockingExecutor + ?Sized,
+    I: Iterator<Item = (F, T)>,
+    F: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    /// Wait for the next rename to complete.
+    ///
+    /// This returns the index of the rename and its result, or `None` once
+    /// every rename has completed.
+    pub async fn next(&mut self) -> Option<(usize, Result<()>)> {
+        std::future::poll_fn(|cx| self.poll_next(cx)).await
+    }
+
+    /// Poll for the next rename to complete.
+    ///
+    /// This is the same as [`Batch::next`] but can be used to implement a
+    /// `Stream`.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<(usize, Result<()>)>> {
+        self.start();
+
+        for i in 0..self.in_flight.len() {
+            if let Poll::Ready(result) = Pin::new(&mut self.in_flight[i].1).poll(cx) {
+                let (index, _) = self.in_flight.swap_remove(i);
+                // Keep the executor busy while the caller deals with the result.
+                self.start();
+                return Poll::Ready(Some((index, result)));
+            }
+        }
+
+        if self.in_flight.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+
+    fn start(&mut self) {
+        while self.in_flight.len() < self.limit {
+            let Some((index, (from, to))) = self.renames.next() else {
+                break;
+            };
+            let options = self.options.clone();
+            let (from, to): (PathBuf, PathBuf) = (from.as_ref().into(), to.as_ref().into());
+
+            let rename = unblock(self.executor, move || options.rename_exclusive(from, to));
+
+            self.in_flight.push((index, rename));
+        }
+    }
+}
+
+impl<E: ?Sized, I> std::fmt::Debug for Batch<'_, E, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Batch")
+            .field("options", &self.options)
+            .field("limit", &self.limit)
+            .field("in_flight", &self.in_flight.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl RenameOptions {
+    /// Rename a file without overwriting the destination path if it exists,
+    /// on the given executor.
+    ///
+    /// See [`rename_exclusive`](crate::rename_exclusive). This is only
+    /// available with the `async` feature.
+    pub fn rename_exclusive_async<E, F, T>(&self, executor: &E, from: F, to: T) -> Blocking<()>
+    where
+        E: BlockingExecutor + ?Sized,
+        F: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        let options = self.clone();
+        let (from, to) = (from.as_ref().to_path_buf(), to.as_ref().to_path_buf());
+
+        unblock(executor, move || options.rename_exclusive(from, to))
+    }
+
+    /// Rename a file without overwriting the destination path if it exists,
+    /// using a non-atomic fallback if necessary, on the given executor.
+    ///
+    /// See [`rename_exclusive_fallback`](crate::rename_exclusive_fallback).
+    /// This is only available with the `async` feature.
+    pub fn rename_exclusive_fallback_async<E, F, T>(
+        &self,
+        executor: &E,
+        from: F,
+        to: T,
+    ) -> Blocking<bool>
+    where
+        E: BlockingExecutor + ?Sized,
+        F: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        let options = self.clone();
+        let (from, to) = (from.as_ref().to_path_buf(), to.as_ref().to_path_buf());
+
+        unblock(executor, move || options.rename_exclusive_fallback(from, to))
+    }
+
+    /// Rename many files without overwriting the destination paths if they
+    /// exist, with at most `limit` renames in flight at once.
+    ///
+    /// Results are yielded by the returned [`Batch`] as the renames complete.
+    /// Each rename is independent so one failing doesn't stop the others. A
+    /// `limit` of zero is treated as one. This is only available with the
+    /// `async` feature.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use renamore::RenameOptions;
+    /// use renamore::asynchronous::ThreadExecutor;
+    ///
+    /// async fn publish(files: &[(String, String)]) {
+    ///     let renames = files.iter().map(|(from, to)| (from, to));
+    ///     let options = RenameOptions::new();
+    ///     let mut batch = options.rename_exclusive_all_async(&ThreadExecutor, 8, renames);
+    ///
+    ///     while let Some((index, result)) = batch.next().await {
+    ///         if let Err(e) = result {
+    ///             eprintln!("{} wasn't published: {e}", files[index].0);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn rename_exclusive_all_async<'a, E, I, F, T>(
+        &self,
+        executor: &'a E,
+        limit: usize,
+        renames: I,
+    ) -> Batch<'a, E, I::IntoIter>
+    where
+        E: BlockingExecutor + ?Sized,
+        I: IntoIterator<Item = (F, T)>,
+        F: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        Batch {
+            executor,
+            options: self.clone(),
+            limit: limit.max(1),
+            renames: renames.into_iter().enumerate(),
+            in_flight: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+use std::io::Result;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use crate::{RenameOperation, RenameReport, Strategy};
+
+type Sink = dyn Fn(&AuditRecord) + Send + Sync;
+
+static SINK: RwLock<Option<Arc<Sink>>> = RwLock::new(None);
+
+pub fn set(sink: Option<Arc<Sink>>) {
+    *SINK.write().unwrap() = sink;
+}
+
+// The lock isn't held while calling the sink so that it can do whatever it
+// likes (including renaming files).
+pub fn record(
+    operation: RenameOperation,
+    from: &Path,
+    to: &Path,
+    result: &Result<bool>,
+    report: &RenameReport,
+) {
+    let Some(sink) = SINK.read().unwrap().clone() else {
+        return;
+    };
+
+    sink(&AuditRecord {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64),
+        operation,
+        from: from.to_path_buf(),
+        to: to.to_path_buf(),
+        outcome: match result {
+            Ok(_) => AuditOutcome::Renamed,
+            Err(e) => AuditOutcome::Failed {
+                kind: format!("{:?}", e.kind()),
+                error: e.to_string(),
+            },
+        },
+        strategy: report.strategy,
+        uid: uid(),
+    });
+}
+
+#[cfg(unix)]
+fn uid() -> Option<u32> {
+    // SAFETY: geteuid always succeeds.
+    Some(unsafe { libc::geteuid() })
+}
+
+#[cfg(not(unix))]
+fn uid() -> Option<u32> {
+    None
+}
+
+/// Whether an audited rename succeeded.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "result")]
+#[non_exhaustive]
+pub enum AuditOutcome {
+    /// The path was renamed.
+    Renamed,
+
+    /// The rename failed and nothing was changed (unless the failure happened
+    /// while cleaning up after a copy across file systems).
+    Failed {
+        /// The [`ErrorKind`](std::io::ErrorKind) of the error, such as
+        /// `AlreadyExists`.
+        kind: String,
+
+        /// The message of the error.
+        error: String,
+    },
+}
+
+/// A record of a single rename.
+///
+/// This is given to the sink set with
+/// [`set_audit_sink`](crate::set_audit_sink) after each rename made through
+/// [`rename_exclusive`](crate::rename_exclusive),
+/// [`rename_exclusive_fallback`](crate::rename_exclusive_fallback) or
+/// [`RenameOptions`](crate::RenameOptions). It can be serialized with serde,
+/// for example as one line of JSON per record in an append-only file.
+/// Serializing fails if either path isn't valid Unicode.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct AuditRecord {
+    /// When the rename finished, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+
+    /// Which function was called.
+    pub operation: RenameOperation,
+
+    /// The path that was renamed.
+    pub from: PathBuf,
+
+    /// The path that it was renamed to.
+    pub to: PathBuf,
+
+    /// Whether the rename succeeded.
+    #[serde(flatten)]
+    pub outcome: AuditOutcome,
+
+    /// How the rename was performed (or attempted).
+    pub strategy: Strategy,
+
+    /// The effective user ID of the process. This is `None` on platforms other
+    /// than Unix.
+    pub uid: Option<u32>,
+}
@@ -0,0 +1,90 @@
+#[cfg(any(all(target_os = "linux", linker), target_vendor = "apple"))]
+mod imp {
+    use std::collections::HashMap;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+    use std::fs::{File, Metadata};
+    use std::io::Result;
+    use std::sync::{Mutex, OnceLock};
+    use crate::AtomicSupport;
+
+    // The answer only depends on the OS and the file system so it's the same for
+    // every path with the same st_dev. A device number could be reused by a
+    // different file system after an unmount but that's rare enough that
+    // invalidate_caches is left to deal with it.
+    fn cache() -> &'static Mutex<HashMap<u64, AtomicSupport>> {
+        static CACHE: OnceLock<Mutex<HashMap<u64, AtomicSupport>>> = OnceLock::new();
+        CACHE.get_or_init(Default::default)
+    }
+
+    fn cached(
+        metadata: Result<Metadata>,
+        query: impl Fn() -> Result<AtomicSupport>,
+    ) -> Result<AtomicSupport> {
+        // If the file can't be stat'ed, let the backend report the error.
+        let Ok(metadata) = metadata else {
+            return query();
+        };
+        let device = metadata.dev();
+
+        if let Some(support) = cache().lock().unwrap().get(&device) {
+            return Ok(support.clone());
+        }
+
+        let support = query()?;
+        cache().lock().unwrap().insert(device, support.clone());
+        Ok(support)
+    }
+
+    pub fn atomic_support(path: &Path) -> Result<AtomicSupport> {
+        cached(std::fs::metadata(path), || crate::registry::atomic_support(path))
+    }
+
+    pub fn atomic_support_fd(dir: &File) -> Result<AtomicSupport> {
+        cached(dir.metadata(), || crate::registry::atomic_support_fd(dir))
+    }
+
+    pub fn invalidate(path: &Path) -> Result<()> {
+        let device = std::fs::metadata(path)?.dev();
+        cache().lock().unwrap().remove(&device);
+        Ok(())
+    }
+
+    pub fn clear() {
+        cache().lock().unwrap().clear();
+    }
+}
+
+// Other platforms either don't look at the file system or don't have a stable
+// way of identifying a volume from a path.
+#[cfg(not(any(all(target_os = "linux", linker), target_vendor = "apple")))]
+mod imp {
+    use std::path::Path;
+    use std::fs::File;
+    use std::io::Result;
+    use crate::AtomicSupport;
+
+    pub fn atomic_support(path: &Path) -> Result<AtomicSupport> {
+        crate::registry::atomic_support(path)
+    }
+
+    pub fn atomic_support_fd(dir: &File) -> Result<AtomicSupport> {
+        crate::registry::atomic_support_fd(dir)
+    }
+
+    pub fn invalidate(_path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn clear() {}
+}
+
+use std::path::Path;
+use std::io::Result;
+use crate::Support;
+
+pub use imp::{atomic_support, atomic_support_fd, clear, invalidate};
+
+pub fn rename_exclusive_support(path: &Path) -> Result<Support> {
+    Ok(atomic_support(path)?.support())
+}
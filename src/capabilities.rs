@@ -0,0 +1,322 @@
+use std::path::{Path, PathBuf};
+use std::io::Result;
+use crate::ParseVersionError;
+
+/// What the file system at a path is capable of.
+///
+/// This is returned by [`capabilities`](crate::capabilities). Fields that
+/// can't be determined on the current platform are left as `false` (or
+/// `None`).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// Whether [`rename_exclusive`](crate::rename_exclusive) is atomic. This
+    /// is the same as what
+    /// [`rename_exclusive_is_atomic`](crate::rename_exclusive_is_atomic)
+    /// returns.
+    pub atomic_exclusive: bool,
+
+    /// The name of the file system, such as `NTFS`, `ReFS` or `fuse.sshfs`.
+    pub file_system: Option<String>,
+
+    /// The directory that the file system is mounted at.
+    pub mount_point: Option<PathBuf>,
+
+    /// Whether the file system supports POSIX rename semantics. That is,
+    /// whether a file that's open can be renamed over the top of another.
+    pub posix_rename: bool,
+
+    /// Whether the file system can clone a range of blocks from one file to
+    /// another without copying them (also known as reflinks).
+    pub block_cloning: bool,
+
+    /// Whether the volume is a Dev Drive.
+    pub dev_drive: bool,
+
+    /// How the file system treats file names that only differ in their
+    /// Unicode normalization.
+    pub normalization: Option<Normalization>,
+
+    /// Whether renames can refuse to follow symlinks. See
+    /// [`RenameOptions::no_symlinks`](crate::RenameOptions::no_symlinks).
+    pub no_symlinks: bool,
+
+    /// Whether the path is on an overlay file system (like the root of a
+    /// Docker container). Renaming a file that comes from a lower layer copies
+    /// it up to the upper layer first, which can take a while for a large
+    /// file. Renaming a directory from a lower layer fails with
+    /// [`ErrorKind::CrossesDevices`](std::io::ErrorKind::CrossesDevices)
+    /// unless the `redirect_dir` feature is enabled.
+    pub copy_up: bool,
+
+    /// Whether [`rename_exchange`](crate::rename_exchange) is supported.
+    pub exchange: bool,
+
+    /// Whether the file system supports renaming with `RENAME_WHITEOUT`, which
+    /// overlay file systems use to hide files in a lower layer. This also
+    /// requires `CAP_MKNOD`.
+    pub whiteout: bool,
+
+    /// Whether file names that only differ in case refer to different files.
+    /// This is `None` if it's up to a server.
+    pub case_sensitive: Option<bool>,
+}
+
+/// How a file system treats file names that only differ in their Unicode
+/// normalization (such as a precomposed `é` and an `e` followed by a combining
+/// acute accent).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Normalization {
+    /// The names refer to different files. This is the case for NTFS and most
+    /// Unix file systems.
+    Sensitive,
+
+    /// The names refer to the same file but the name is stored as it was
+    /// given. This is the case for APFS.
+    Insensitive,
+
+    /// The names refer to the same file and the name is stored normalized.
+    /// This is the case for HFS+ which stores a variant of NFD.
+    Normalizing,
+}
+
+/// Whether an operation is supported by a file system.
+///
+/// This is returned by
+/// [`rename_exclusive_support`](crate::rename_exclusive_support).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Support {
+    /// The operation is supported and is atomic.
+    Supported,
+
+    /// The operation isn't supported or isn't atomic.
+    Unsupported,
+
+    /// The operation will probably be accepted but whether it's atomic
+    /// depends on something that can't be checked, like the server of a
+    /// network file system.
+    Unverifiable,
+}
+
+/// Whether [`rename_exclusive`](crate::rename_exclusive) is atomic, and why
+/// not.
+///
+/// This is returned by
+/// [`rename_exclusive_atomic_support`](crate::rename_exclusive_atomic_support).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum AtomicSupport {
+    /// The operation is supported and is atomic.
+    Yes,
+
+    /// The operation isn't supported or isn't atomic.
+    No(Reason),
+
+    /// The operation will probably be accepted but whether it's atomic
+    /// depends on something that can't be checked, like the server of a
+    /// network file system.
+    Unknown,
+}
+
+impl AtomicSupport {
+    pub(crate) fn support(&self) -> Support {
+        match self {
+            Self::Yes => Support::Supported,
+            Self::No(_) => Support::Unsupported,
+            Self::Unknown => Support::Unverifiable,
+        }
+    }
+}
+
+/// Why [`rename_exclusive`](crate::rename_exclusive) isn't atomic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Reason {
+    /// The OS is too old. On Linux, these are kernel versions. On Windows,
+    /// these are OS versions (like 10.0.17763).
+    KernelTooOld {
+        /// The version that's running.
+        found: Version,
+
+        /// The oldest version that supports the operation on this file
+        /// system.
+        required: Version,
+    },
+
+    /// The file system doesn't support the operation.
+    Filesystem(FsInfo),
+
+    /// The OS or C library doesn't provide the operation at all.
+    Unavailable,
+
+    /// The paths are on different volumes (or different mounts of the same
+    /// volume), so renaming between them fails with
+    /// [`ErrorKind::CrossesDevices`](std::io::ErrorKind::CrossesDevices).
+    CrossesDevices,
+}
+
+/// A version number like 6.1.0.
+///
+/// Versions are ordered by their parts so they can be compared to decide
+/// whether a feature is available. See [`kernel_version`](crate::kernel_version)
+/// for the version of the running OS.
+///
+/// A version can be parsed from a kernel release (like `6.1.0-13-amd64`) or
+/// from text that contains one (like `/proc/version`). Parsing starts at the
+/// first digit and anything after the numbers is ignored. The patch is
+/// optional.
+///
+/// ```
+/// use renamore::Version;
+///
+/// let version: Version = "Linux version 6.1.0-13-amd64 (debian-kernel@lists.debian.org)"
+///     .parse()
+///     .unwrap();
+///
+/// assert_eq!(version, Version::new(6, 1, 0));
+/// assert!(version >= Version::new(3, 15, 0));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Version {
+    /// The major version.
+    pub major: u16,
+
+    /// The minor version.
+    pub minor: u16,
+
+    /// The patch version (or build number on Windows).
+    pub patch: u16,
+}
+
+impl Version {
+    /// Create a version from its parts.
+    pub const fn new(major: u16, minor: u16, patch: u16) -> Self {
+        Self { major, minor, patch }
+    }
+}
+
+impl std::str::FromStr for Version {
+    type Err = ParseVersionError;
+
+    // Some vendor kernels leave out the patch and some have a long suffix.
+    fn from_str(version: &str) -> std::result::Result<Self, Self::Err> {
+        let begin = version.find(|c: char| c.is_ascii_digit()).ok_or(ParseVersionError)?;
+        let mut rest = &version[begin..];
+        let mut parts = [0; 3];
+
+        for (i, part) in parts.iter_mut().enumerate() {
+            let len = rest.bytes().take_while(u8::is_ascii_digit).count();
+
+            if len == 0 {
+                // Only the patch is optional.
+                if i == 2 {
+                    break;
+                }
+                return Err(ParseVersionError);
+            }
+
+            *part = rest[..len].parse().map_err(|_| ParseVersionError)?;
+            rest = &rest[len..];
+
+            match rest.strip_prefix('.') {
+                Some(next) => rest = next,
+                None if i >= 1 => break,
+                None => return Err(ParseVersionError),
+            }
+        }
+
+        Ok(Version::new(parts[0], parts[1], parts[2]))
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// A file system that's known to support
+/// [`rename_exclusive`](crate::rename_exclusive).
+///
+/// This is returned by [`known_filesystems`](crate::known_filesystems).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct KnownFilesystem {
+    /// The name of the file system, such as `ext4` or `NTFS`.
+    pub name: &'static str,
+
+    /// The number that the OS uses to identify the type of file system. See
+    /// [`FsInfo::magic`].
+    pub magic: Option<u32>,
+
+    /// The oldest version of the OS that supports the operation on this file
+    /// system. On Linux, this is a kernel version. On Windows, this is an OS
+    /// version.
+    pub min_version: Version,
+
+    /// Whether the operation is atomic on this file system. This is
+    /// [`Support::Unverifiable`] for network file systems.
+    pub support: Support,
+}
+
+#[cfg(any(
+    all(target_os = "linux", linker),
+    all(target_os = "windows", not(target_vendor = "uwp")),
+))]
+impl KnownFilesystem {
+    pub(crate) const fn new(
+        name: &'static str,
+        magic: Option<u32>,
+        min_version: Version,
+        support: Support,
+    ) -> Self {
+        Self { name, magic, min_version, support }
+    }
+}
+
+/// Information about the file system at a path.
+///
+/// This is returned by [`fs_info`](crate::fs_info) and given by
+/// [`Reason::Filesystem`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct FsInfo {
+    /// The name of the file system, such as `ext4`, `apfs` or `NTFS`.
+    pub name: Option<String>,
+
+    /// The number that the OS uses to identify the type of file system. On
+    /// Linux, this is the magic number from `statfs` (like `0xef53` for
+    /// ext4). On Darwin, this is `f_type` from `statfs`, which is only
+    /// meaningful on the machine it came from.
+    pub magic: Option<u32>,
+
+    /// Whether the file system is known to be on another machine.
+    pub network: bool,
+}
+
+pub fn capabilities(path: &Path) -> Result<Capabilities> {
+    #[allow(unused_mut)]
+    let mut capabilities = Capabilities {
+        atomic_exclusive: crate::cache::rename_exclusive_support(path)? == Support::Supported,
+        ..Capabilities::default()
+    };
+
+    #[cfg(any(
+        all(target_os = "windows", not(target_vendor = "uwp")),
+        target_vendor = "apple",
+        all(target_os = "linux", linker),
+    ))]
+    crate::sys::volume_capabilities(path, &mut capabilities)?;
+
+    Ok(capabilities)
+}
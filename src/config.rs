@@ -0,0 +1,195 @@
+use std::io::{Error, ErrorKind};
+use std::sync::RwLock;
+use crate::{FallbackEvent, RenameOptions};
+
+static CONFIG: RwLock<Option<Config>> = RwLock::new(None);
+
+pub fn set(config: Config) {
+    if config.fallback_hook.is_some() {
+        crate::hook::set(config.fallback_hook);
+    }
+    *CONFIG.write().unwrap() = Some(config);
+}
+
+pub fn defaults() -> Option<RenameOptions> {
+    CONFIG.read().unwrap().as_ref().map(|config| config.defaults.clone())
+}
+
+pub fn fallback() -> bool {
+    CONFIG.read().unwrap().as_ref().is_some_and(|config| config.fallback)
+}
+
+pub fn allow_non_atomic() -> bool {
+    CONFIG.read().unwrap().as_ref().is_none_or(|config| config.allow_non_atomic)
+}
+
+fn env_overrides() -> bool {
+    CONFIG.read().unwrap().as_ref().is_some_and(|config| config.env_overrides)
+}
+
+// The variables are read for every rename so that a test can change them
+// while the program is running.
+pub fn force_non_atomic() -> bool {
+    env_overrides()
+        && std::env::var_os("RENAMORE_FORCE_NON_ATOMIC").is_some_and(|value| value == "1")
+}
+
+pub fn injected_failure() -> Option<Error> {
+    if !env_overrides() {
+        return None;
+    }
+
+    std::env::var("RENAMORE_FAIL_RENAMES").ok().map(|value| failure(&value))
+}
+
+pub fn failure(value: &str) -> Error {
+    if let Ok(code) = value.parse() {
+        return Error::from_raw_os_error(code);
+    }
+
+    // The errno values differ between platforms (and Windows doesn't have
+    // them) so names are mapped to the closest ErrorKind instead.
+    let kind = match value {
+        "EACCES" | "EPERM" => ErrorKind::PermissionDenied,
+        "EBUSY" => ErrorKind::ResourceBusy,
+        "EEXIST" => ErrorKind::AlreadyExists,
+        "EINVAL" => ErrorKind::InvalidInput,
+        "ENOENT" => ErrorKind::NotFound,
+        "ENOSPC" => ErrorKind::StorageFull,
+        "ENOTEMPTY" => ErrorKind::DirectoryNotEmpty,
+        "ENOTSUP" | "EOPNOTSUPP" => ErrorKind::Unsupported,
+        "EROFS" => ErrorKind::ReadOnlyFilesystem,
+        "EXDEV" => ErrorKind::CrossesDevices,
+        _ => ErrorKind::Other,
+    };
+
+    Error::new(kind, format!("{value} (injected by RENAMORE_FAIL_RENAMES)"))
+}
+
+/// Process-wide defaults given to [`configure`](crate::configure).
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use renamore::{Config, RenameOptions};
+///
+/// let mut defaults = RenameOptions::new();
+/// defaults.durable(true).retry(10, Duration::from_millis(50));
+///
+/// renamore::configure(Config::new().defaults(defaults).allow_non_atomic(false));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Config {
+    defaults: RenameOptions,
+    fallback: bool,
+    allow_non_atomic: bool,
+    fallback_hook: Option<fn(&FallbackEvent)>,
+    env_overrides: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            defaults: RenameOptions::builtin(),
+            fallback: false,
+            allow_non_atomic: true,
+            fallback_hook: None,
+            env_overrides: false,
+        }
+    }
+}
+
+impl Config {
+    /// Create a configuration with the built-in defaults.
+    ///
+    /// The defaults are the same as if [`configure`](crate::configure) was
+    /// never called.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the options that [`RenameOptions::new`] starts from.
+    ///
+    /// This also sets the options used by
+    /// [`rename_exclusive`](crate::rename_exclusive) and
+    /// [`rename_exclusive_fallback`](crate::rename_exclusive_fallback), so
+    /// things like durability, retries and the [`Backend`](crate::Backend) can
+    /// be set once for the whole process.
+    pub fn defaults(&mut self, defaults: RenameOptions) -> &mut Self {
+        self.defaults = defaults;
+        self
+    }
+
+    /// Set whether [`rename_exclusive`](crate::rename_exclusive) uses the
+    /// non-atomic fallback.
+    ///
+    /// If this is `true`, then `rename_exclusive` behaves like
+    /// [`rename_exclusive_fallback`](crate::rename_exclusive_fallback) and
+    /// renames non-atomically if it can't be done atomically. This is for
+    /// programs that call `rename_exclusive` from many places and would rather
+    /// rename than fail on file systems that don't support it. It doesn't
+    /// affect [`RenameOptions::rename_exclusive`], and
+    /// [`allow_non_atomic`](Config::allow_non_atomic) still applies. The
+    /// default is `false`.
+    pub fn fallback(&mut self, fallback: bool) -> &mut Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Set whether the non-atomic fallback may be used.
+    ///
+    /// If this is `false`, then
+    /// [`rename_exclusive_fallback`](crate::rename_exclusive_fallback) behaves
+    /// like [`rename_exclusive`](crate::rename_exclusive) and fails with
+    /// [`ErrorKind::Unsupported`] rather than renaming non-atomically. This is
+    /// for programs that would rather fail than risk replacing a file, even
+    /// when called from libraries that ask for the fallback. The default is
+    /// `true`.
+    ///
+    /// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+    pub fn allow_non_atomic(&mut self, allow_non_atomic: bool) -> &mut Self {
+        self.allow_non_atomic = allow_non_atomic;
+        self
+    }
+
+    /// Set the function to call whenever a fallback is about to be used.
+    ///
+    /// See [`set_fallback_hook`](crate::set_fallback_hook). If this isn't set,
+    /// then [`configure`](crate::configure) leaves the current hook alone.
+    pub fn fallback_hook(&mut self, hook: fn(&FallbackEvent)) -> &mut Self {
+        self.fallback_hook = Some(hook);
+        self
+    }
+
+    /// Let environment variables change how files are renamed.
+    ///
+    /// This is meant for end-to-end tests that need to exercise the paths a
+    /// program takes when renaming degrades, without a special build. It's off
+    /// by default so that the environment can't change the behaviour of a
+    /// program that didn't ask for it. The variables are read for each rename
+    /// made through [`RenameOptions`] (including
+    /// [`rename_exclusive`](crate::rename_exclusive) and
+    /// [`rename_exclusive_fallback`](crate::rename_exclusive_fallback)):
+    ///
+    ///  - `RENAMORE_FORCE_NON_ATOMIC=1` acts like
+    ///    [`Backend::NonAtomicOnly`](crate::Backend::NonAtomicOnly).
+    ///  - `RENAMORE_FAIL_RENAMES` makes every rename fail without touching the
+    ///    file system. The value is either an OS error code (like `28`) or the
+    ///    name of an errno value (like `ENOSPC`), which is turned into an error
+    ///    with the closest [`ErrorKind`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use renamore::Config;
+    ///
+    /// // Only in the builds used by the end-to-end tests.
+    /// #[cfg(debug_assertions)]
+    /// renamore::configure(Config::new().env_overrides(true));
+    /// ```
+    pub fn env_overrides(&mut self, env_overrides: bool) -> &mut Self {
+        self.env_overrides = env_overrides;
+        self
+    }
+}
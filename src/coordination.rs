@@ -0,0 +1,56 @@
+use std::path::Path;
+use std::io::{Error, ErrorKind, Result};
+use std::cell::RefCell;
+use std::ptr::NonNull;
+use block2::StackBlock;
+use objc2_foundation::{NSFileCoordinator, NSFileCoordinatorWritingOptions, NSURL};
+
+// File presenters (like document-based apps and the iCloud daemon) are told
+// about the move and get a chance to save or release the files first. The
+// coordinator may hand back different URLs than the ones it was given (if a
+// presenter moved the file in the meantime) so those are the ones renamed.
+
+pub fn coordinate_move(
+    from: &Path,
+    to: &Path,
+    rename: impl Fn(&Path, &Path) -> Result<()>,
+) -> Result<()> {
+    let from_url = NSURL::from_file_path(from).ok_or(ErrorKind::InvalidInput)?;
+    let to_url = NSURL::from_file_path(to).ok_or(ErrorKind::InvalidInput)?;
+    let coordinator = NSFileCoordinator::new();
+    let result = RefCell::new(None);
+
+    let accessor = StackBlock::new(|from_url: NonNull<NSURL>, to_url: NonNull<NSURL>| {
+        let (from_url, to_url) = unsafe { (from_url.as_ref(), to_url.as_ref()) };
+        let paths = from_url.to_file_path().zip(to_url.to_file_path());
+        let rename_result = match paths {
+            Some((from, to)) => rename(&from, &to),
+            None => Err(Error::from(ErrorKind::InvalidInput)),
+        };
+
+        if rename_result.is_ok() {
+            coordinator.itemAtURL_didMoveToURL(from_url, to_url);
+        }
+
+        *result.borrow_mut() = Some(rename_result);
+    });
+
+    let mut error = None;
+
+    coordinator.coordinateWritingItemAtURL_options_writingItemAtURL_options_error_byAccessor(
+        &from_url,
+        NSFileCoordinatorWritingOptions::ForMoving,
+        &to_url,
+        NSFileCoordinatorWritingOptions::ForReplacing,
+        Some(&mut error),
+        &accessor,
+    );
+
+    if let Some(error) = error {
+        return Err(Error::other(error.localizedDescription().to_string()));
+    }
+
+    // The accessor isn't called if coordination fails but that's reported as
+    // an error.
+    result.into_inner().unwrap_or_else(|| Err(Error::other("file coordination failed")))
+}
@@ -0,0 +1,241 @@
+use std::path::{Path, PathBuf};
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::Ordering;
+use crate::RenameOptions;
+use crate::stats::Counter;
+
+// Moving across file systems can't be done with a rename. Instead, `from` is
+// copied to a staging path next to `to`, which is then renamed to `to`. This
+// means that the last step is still exclusive and `to` either doesn't exist or
+// is complete. `from` is only removed after that last step has succeeded.
+
+pub fn move_across(
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+    commit: impl FnOnce(&Path, &Path) -> Result<bool>,
+) -> Result<bool> {
+    let metadata = std::fs::symlink_metadata(from)?;
+    let total = total_size(from, &metadata)?;
+    let staging = reserve_staging(to, metadata.is_dir())?;
+
+    if metadata.is_symlink() {
+        // Symlinks can't be created over the top of the placeholder.
+        std::fs::remove_file(&staging)?;
+    }
+
+    let mut progress = Progress { options, copied: 0, total };
+
+    let result = copy(from, &staging, &metadata, &mut progress)
+        .and_then(|_| commit(&staging, to));
+
+    let atomic = match result {
+        Ok(atomic) => atomic,
+        Err(e) => {
+            // Don't leave a partial copy lying around.
+            let _ = remove(&staging, metadata.is_dir());
+            return Err(e);
+        }
+    };
+
+    remove(from, metadata.is_dir())?;
+    crate::stats::add(Counter::CrossDeviceCopies, 1);
+    Ok(atomic)
+}
+
+pub(crate) struct Progress<'a> {
+    pub options: &'a RenameOptions,
+    pub copied: u64,
+    pub total: u64,
+}
+
+impl Progress<'_> {
+    // `copied` is the progress within the current file.
+    pub fn report(&self, copied: u64) {
+        if let Some(progress) = &self.options.progress {
+            progress(self.copied + copied, self.total);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.options.cancel.as_ref().is_some_and(|c| c.load(Ordering::Relaxed))
+    }
+}
+
+pub fn cancelled() -> Error {
+    Error::other("copy was cancelled")
+}
+
+fn total_size(path: &Path, metadata: &std::fs::Metadata) -> Result<u64> {
+    if !metadata.is_dir() {
+        return Ok(if metadata.is_file() { metadata.len() } else { 0 });
+    }
+
+    let mut total = 0;
+
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        total += total_size(&entry.path(), &entry.metadata()?)?;
+    }
+
+    Ok(total)
+}
+
+// The staging path is in the same directory as `to` so that it's on the same
+// file system. It's created exclusively so that nothing else can be using it.
+fn reserve_staging(to: &Path, is_dir: bool) -> Result<PathBuf> {
+    let name = to.file_name().ok_or(ErrorKind::InvalidInput)?;
+
+    for attempt in 0u32..100 {
+        let mut staging_name = std::ffi::OsString::from(".");
+        staging_name.push(name);
+        staging_name.push(format!(".renamore-{}-{}", std::process::id(), attempt));
+        let staging = to.with_file_name(staging_name);
+
+        let result = if is_dir {
+            std::fs::create_dir(&staging)
+        } else {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&staging)
+                .map(|_| ())
+        };
+
+        match result {
+            Ok(()) => return Ok(staging),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(ErrorKind::AlreadyExists.into())
+}
+
+fn copy(
+    from: &Path,
+    to: &Path,
+    metadata: &std::fs::Metadata,
+    progress: &mut Progress,
+) -> Result<()> {
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            let entry_metadata = entry.metadata()?;
+            let entry_to = to.join(entry.file_name());
+
+            if entry_metadata.is_dir() {
+                std::fs::create_dir(&entry_to)?;
+            }
+
+            copy(&entry.path(), &entry_to, &entry_metadata, progress)?;
+        }
+
+        copy_dir_metadata(from, to, metadata)?;
+        copy_security(from, to, progress.options)?;
+        copy_protection_class(from, to, progress.options)
+    } else if metadata.is_symlink() {
+        copy_symlink(from, to)?;
+        copy_security(from, to, progress.options)
+    } else {
+        if progress.is_cancelled() {
+            return Err(cancelled());
+        }
+        let copied = copy_file(from, to, progress)?;
+        crate::stats::add(Counter::BytesCopied, copied);
+        crate::report::record(|report| report.bytes_copied += copied);
+        #[cfg(feature = "test-util")]
+        if let Some(e) = crate::test_util::copy_fault() {
+            return Err(e);
+        }
+        copy_security(from, to, progress.options)?;
+        copy_protection_class(from, to, progress.options)?;
+        progress.copied += copied;
+        progress.report(0);
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(from)?, to)
+}
+
+#[cfg(target_os = "windows")]
+fn copy_symlink(from: &Path, to: &Path) -> Result<()> {
+    let target = std::fs::read_link(from)?;
+
+    if std::fs::metadata(from).map(|m| m.is_dir()).unwrap_or(false) {
+        std::os::windows::fs::symlink_dir(target, to)
+    } else {
+        std::os::windows::fs::symlink_file(target, to)
+    }
+}
+
+#[cfg(not(any(unix, target_os = "windows")))]
+fn copy_symlink(_from: &Path, _to: &Path) -> Result<()> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+fn remove(path: &Path, is_dir: bool) -> Result<()> {
+    if is_dir {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+// std::fs::copy also copies the permissions.
+#[cfg(not(any(
+    all(target_os = "windows", not(target_vendor = "uwp")),
+    target_vendor = "apple",
+    all(target_os = "linux", linker),
+)))]
+fn copy_file(from: &Path, to: &Path, _progress: &Progress) -> Result<u64> {
+    std::fs::copy(from, to)
+}
+
+#[cfg(any(
+    all(target_os = "windows", not(target_vendor = "uwp")),
+    target_vendor = "apple",
+    all(target_os = "linux", linker),
+))]
+use crate::sys::copy_file;
+
+#[cfg(not(target_vendor = "apple"))]
+fn copy_dir_metadata(_from: &Path, to: &Path, metadata: &std::fs::Metadata) -> Result<()> {
+    std::fs::set_permissions(to, metadata.permissions())
+}
+
+#[cfg(target_vendor = "apple")]
+fn copy_protection_class(from: &Path, to: &Path, options: &RenameOptions) -> Result<()> {
+    if options.preserve_protection {
+        crate::sys::copy_protection_class(from, to)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(target_vendor = "apple"))]
+fn copy_protection_class(_from: &Path, _to: &Path, _options: &RenameOptions) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(all(target_os = "linux", linker))]
+fn copy_security(from: &Path, to: &Path, options: &RenameOptions) -> Result<()> {
+    if options.preserve_security {
+        crate::sys::copy_security_xattrs(from, to)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(all(target_os = "linux", linker)))]
+fn copy_security(_from: &Path, _to: &Path, _options: &RenameOptions) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(target_vendor = "apple")]
+fn copy_dir_metadata(from: &Path, to: &Path, _metadata: &std::fs::Metadata) -> Result<()> {
+    crate::sys::copy_metadata(from, to)
+}
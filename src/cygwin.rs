@@ -0,0 +1,55 @@
+use std::path::Path;
+use std::io::{Error, ErrorKind, Result};
+use std::ffi::{c_char, c_int, c_uint, CString};
+use std::os::unix::prelude::OsStrExt;
+use crate::{AtomicSupport, RenameOptions};
+
+// Linking will fail on Cygwin versions prior to 3.0. This also covers MSYS2
+// when targeting the MSYS runtime (rather than MinGW, which is just Windows).
+
+extern "C" {
+    fn renameat2(
+        olddirfd: c_int,
+        oldpath: *const c_char,
+        newdirfd: c_int,
+        newpath: *const c_char,
+        flags: c_uint,
+    ) -> c_int;
+}
+
+// This is different from Linux.
+const AT_FDCWD: c_int = -2;
+const RENAME_NOREPLACE: c_uint = 1;
+
+pub fn rename_exclusive(from: &Path, to: &Path, _options: &RenameOptions) -> Result<()> {
+    // Paths are passed to Cygwin as-is. Translating POSIX paths (such as
+    // /cygdrive/c or /usr) into Windows paths is Cygwin's job.
+    let from_str = CString::new(from.as_os_str().as_bytes())?;
+    let to_str = CString::new(to.as_os_str().as_bytes())?;
+    crate::instrument::syscall("renameat2", RENAME_NOREPLACE);
+    // This isn't retried on EINTR. See retry_interrupted.
+    let ret = unsafe {
+        renameat2(AT_FDCWD, from_str.as_ptr(), AT_FDCWD, to_str.as_ptr(), RENAME_NOREPLACE)
+    };
+
+    if ret == -1 {
+        let error = Error::last_os_error();
+        // EINVAL is returned if `flags` is invalid. Cygwin only supports
+        // RENAME_NOREPLACE so this is very unlikely. ENOSYS is the same as on
+        // Linux. The original error is kept as the source.
+        if matches!(error.kind(), ErrorKind::InvalidInput | ErrorKind::Unsupported) {
+            Err(Error::new(ErrorKind::Unsupported, error))
+        } else {
+            Err(error)
+        }
+    } else {
+        Ok(())
+    }
+}
+
+pub fn atomic_support(_path: &Path) -> Result<AtomicSupport> {
+    // Cygwin implements RENAME_NOREPLACE by asking Windows not to replace the
+    // destination. Like the Windows backend, it's supported if the linker
+    // doesn't complain.
+    Ok(AtomicSupport::Yes)
+}
@@ -0,0 +1,554 @@
+//! Error types and helpers for classifying errors.
+//!
+//! The error types are also exported from the root of the crate. The helpers
+//! look at the [`ErrorKind`], the raw OS error code and any error wrapped
+//! inside, so they give the same answer for an error returned by this crate,
+//! by [`std::fs`] or built from a raw code with [`Error::from_raw_os_error`].
+
+use std::path::PathBuf;
+use std::io::{Error, ErrorKind};
+
+/// The error returned when renaming a cloud placeholder file with
+/// [`PlaceholderPolicy::Fail`].
+///
+/// This is wrapped in an [`Error`] with [`ErrorKind::Other`] and can be
+/// retrieved using [`Error::get_ref`].
+///
+/// [`PlaceholderPolicy::Fail`]: crate::PlaceholderPolicy::Fail
+/// [`Error`]: std::io::Error
+/// [`ErrorKind::Other`]: std::io::ErrorKind::Other
+/// [`Error::get_ref`]: std::io::Error::get_ref
+#[derive(Debug)]
+pub struct PlaceholderError;
+
+impl std::fmt::Display for PlaceholderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("file is a cloud placeholder")
+    }
+}
+
+impl std::error::Error for PlaceholderError {}
+
+/// The error returned when renaming into or out of the read-only system volume
+/// on macOS.
+///
+/// Since macOS 10.15, the system is on a separate read-only volume that's
+/// joined to the data volume with firmlinks. Renaming to or from a path on the
+/// system volume fails with `EROFS`, or `EXDEV` if the rename crosses a
+/// firmlink. Such errors are replaced with an [`Error`] with
+/// [`ErrorKind::ReadOnlyFilesystem`] wrapping this error. It can be retrieved
+/// using [`Error::get_ref`]. The original error is its [`source`].
+///
+/// [`Error`]: std::io::Error
+/// [`ErrorKind::ReadOnlyFilesystem`]: std::io::ErrorKind::ReadOnlyFilesystem
+/// [`Error::get_ref`]: std::io::Error::get_ref
+/// [`source`]: std::error::Error::source
+#[derive(Debug)]
+pub struct SystemVolumeError {
+    error: Error,
+}
+
+impl SystemVolumeError {
+    #[cfg_attr(not(target_vendor = "apple"), allow(dead_code))]
+    pub(crate) fn new(error: Error) -> Self {
+        Self { error }
+    }
+
+    /// The error that the rename failed with.
+    pub fn io_error(&self) -> &Error {
+        &self.error
+    }
+}
+
+impl std::fmt::Display for SystemVolumeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("location is read-only system content")
+    }
+}
+
+impl std::error::Error for SystemVolumeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The error returned when renaming between mounts or subvolumes of the same
+/// type of file system on Linux.
+///
+/// A rename can't cross a mount point, even a bind mount of the same file
+/// system, or a btrfs subvolume. These fail with `EXDEV` even though both
+/// paths are on the same device, which makes them easy to mistake for a bug.
+/// Such errors are replaced with an [`Error`] with
+/// [`ErrorKind::CrossesDevices`] wrapping this error. It can be retrieved using
+/// [`Error::get_ref`]. The original error is its [`source`].
+///
+/// [`Error`]: std::io::Error
+/// [`ErrorKind::CrossesDevices`]: std::io::ErrorKind::CrossesDevices
+/// [`Error::get_ref`]: std::io::Error::get_ref
+/// [`source`]: std::error::Error::source
+#[derive(Debug)]
+pub struct BoundaryError {
+    error: Error,
+}
+
+impl BoundaryError {
+    #[cfg_attr(not(all(target_os = "linux", linker)), allow(dead_code))]
+    pub(crate) fn new(error: Error) -> Self {
+        Self { error }
+    }
+
+    /// The error that the rename failed with.
+    pub fn io_error(&self) -> &Error {
+        &self.error
+    }
+}
+
+impl std::fmt::Display for BoundaryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("paths are in different mounts or subvolumes")
+    }
+}
+
+impl std::error::Error for BoundaryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The error returned when a rename succeeded but syncing a directory
+/// afterwards failed with [`RenameOptions::durable`].
+///
+/// The rename has happened and shouldn't be retried, but it might not survive
+/// a crash. Such errors are wrapped in an [`Error`] with [`ErrorKind::Other`]
+/// so that they aren't mistaken for the rename failing. This error can be
+/// retrieved using [`Error::get_ref`]. The error from the sync is its
+/// [`source`].
+///
+/// [`RenameOptions::durable`]: crate::RenameOptions::durable
+/// [`Error`]: std::io::Error
+/// [`ErrorKind::Other`]: std::io::ErrorKind::Other
+/// [`Error::get_ref`]: std::io::Error::get_ref
+/// [`source`]: std::error::Error::source
+#[derive(Debug)]
+pub struct DurabilityError {
+    error: Error,
+}
+
+impl DurabilityError {
+    pub(crate) fn new(error: Error) -> Self {
+        Self { error }
+    }
+
+    /// The error that syncing the directory failed with.
+    pub fn io_error(&self) -> &Error {
+        &self.error
+    }
+}
+
+impl std::fmt::Display for DurabilityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("renamed but syncing the directory failed")
+    }
+}
+
+impl std::error::Error for DurabilityError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The error returned when the source of a rename is a symlink and
+/// [`RenameOptions::no_follow_source`] is set.
+///
+/// This is wrapped in an [`Error`] with [`ErrorKind::InvalidInput`] and can be
+/// retrieved using [`Error::get_ref`].
+///
+/// [`RenameOptions::no_follow_source`]: crate::RenameOptions::no_follow_source
+/// [`Error`]: std::io::Error
+/// [`ErrorKind::InvalidInput`]: std::io::ErrorKind::InvalidInput
+/// [`Error::get_ref`]: std::io::Error::get_ref
+#[derive(Debug)]
+pub struct SymlinkError;
+
+impl std::fmt::Display for SymlinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("source is a symlink")
+    }
+}
+
+impl std::error::Error for SymlinkError {}
+
+/// The error returned when a rename fails because something involved can't be
+/// modified.
+///
+/// Renaming on a read-only mount fails with `EROFS` (or
+/// `ERROR_WRITE_PROTECT` on Windows). Renaming a file or directory with the
+/// immutable or append-only attribute (see `chattr(1)` and `chflags(1)`), or
+/// renaming into or out of such a directory, fails with `EPERM`, which is easy
+/// to mistake for an ordinary permissions problem. When a rename fails with
+/// one of these, the paths are checked and the error is replaced with an
+/// [`Error`] wrapping this error. The kind is
+/// [`ErrorKind::ReadOnlyFilesystem`] for [`ReadOnlyCause::Mount`] and
+/// [`ErrorKind::PermissionDenied`] for [`ReadOnlyCause::Immutable`]. It can be
+/// retrieved using [`Error::get_ref`]. The original error is its [`source`].
+///
+/// This is only detected on Linux, Darwin and Windows. Windows doesn't have an
+/// immutable attribute.
+///
+/// [`Error`]: std::io::Error
+/// [`ErrorKind::ReadOnlyFilesystem`]: std::io::ErrorKind::ReadOnlyFilesystem
+/// [`ErrorKind::PermissionDenied`]: std::io::ErrorKind::PermissionDenied
+/// [`Error::get_ref`]: std::io::Error::get_ref
+/// [`source`]: std::error::Error::source
+#[derive(Debug)]
+pub struct ReadOnlyError {
+    cause: ReadOnlyCause,
+    error: Error,
+}
+
+impl ReadOnlyError {
+    /// Which path can't be modified and why.
+    pub fn cause(&self) -> &ReadOnlyCause {
+        &self.cause
+    }
+
+    /// The error that the rename failed with.
+    pub fn io_error(&self) -> &Error {
+        &self.error
+    }
+}
+
+impl std::fmt::Display for ReadOnlyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.cause {
+            ReadOnlyCause::Mount(path) => write!(f, "{:?} is on a read-only file system", path),
+            ReadOnlyCause::Immutable(path) => write!(f, "{:?} is immutable or append-only", path),
+        }
+    }
+}
+
+impl std::error::Error for ReadOnlyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// The reason for a [`ReadOnlyError`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ReadOnlyCause {
+    /// The path is on a file system that's mounted read-only (or a volume
+    /// that's write-protected).
+    Mount(PathBuf),
+
+    /// The path has the immutable or append-only attribute.
+    Immutable(PathBuf),
+}
+
+impl ReadOnlyCause {
+    // Only some backends detect this.
+    #[allow(dead_code)]
+    pub(crate) fn into_error(self, error: Error) -> Error {
+        let kind = match self {
+            Self::Mount(_) => ErrorKind::ReadOnlyFilesystem,
+            Self::Immutable(_) => ErrorKind::PermissionDenied,
+        };
+
+        Error::new(kind, ReadOnlyError { cause: self, error })
+    }
+}
+
+/// The error returned when parsing a [`Version`](crate::Version) fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseVersionError;
+
+impl std::fmt::Display for ParseVersionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid version number")
+    }
+}
+
+impl std::error::Error for ParseVersionError {}
+
+/// The operation that failed with a [`RenameError`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum RenameOperation {
+    /// [`rename_exclusive`](crate::rename_exclusive).
+    Exclusive,
+
+    /// [`rename_exclusive_fallback`](crate::rename_exclusive_fallback).
+    ExclusiveFallback,
+
+    /// [`rename_exchange`](crate::rename_exchange).
+    Exchange,
+}
+
+impl std::fmt::Display for RenameOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Exclusive | Self::ExclusiveFallback => "rename",
+            Self::Exchange => "exchange",
+        })
+    }
+}
+
+/// The error returned by the `_detailed` variants of the rename functions.
+///
+/// An [`Error`] on its own doesn't say which rename failed, which makes it hard
+/// to report when many files are being renamed. This carries both paths and the
+/// operation along with the underlying error. It can be converted into an
+/// [`Error`] with the same [`ErrorKind`] that wraps it, so `?` works in
+/// functions that return [`std::io::Result`].
+///
+/// The paths are [`PathBuf`]s unless the error came from the `utf8` module
+/// (with the `camino` feature), which uses camino's `Utf8PathBuf` instead.
+///
+/// # Examples
+///
+/// ```no_run
+/// let result = renamore::RenameOptions::new().rename_exclusive_detailed("a", "b");
+///
+/// if let Err(e) = result {
+///     eprintln!("{}", e); // failed to rename "a" to "b": ...
+///     eprintln!("{:?} {:?}", e.from(), e.kind());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct RenameError<P = PathBuf> {
+    operation: RenameOperation,
+    from: P,
+    to: P,
+    error: Error,
+}
+
+impl<P> RenameError<P> {
+    pub(crate) fn new(operation: RenameOperation, from: P, to: P, error: Error) -> Self {
+        Self { operation, from, to, error }
+    }
+
+    /// The operation that failed.
+    pub fn operation(&self) -> RenameOperation {
+        self.operation
+    }
+
+    /// The path that was being renamed.
+    pub fn from(&self) -> &P::Target
+    where
+        P: std::ops::Deref,
+    {
+        &self.from
+    }
+
+    /// The path that it was being renamed to.
+    pub fn to(&self) -> &P::Target
+    where
+        P: std::ops::Deref,
+    {
+        &self.to
+    }
+
+    /// The kind of the underlying error.
+    pub fn kind(&self) -> ErrorKind {
+        self.error.kind()
+    }
+
+    /// The underlying error, which has the OS error code (if there is one).
+    pub fn io_error(&self) -> &Error {
+        &self.error
+    }
+
+    /// Take the underlying error, discarding the paths.
+    pub fn into_io_error(self) -> Error {
+        self.error
+    }
+}
+
+impl<P: std::fmt::Debug> std::fmt::Display for RenameError<P> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to {} {:?} {} {:?}: {}",
+            self.operation,
+            self.from,
+            if self.operation == RenameOperation::Exchange { "with" } else { "to" },
+            self.to,
+            self.error,
+        )
+    }
+}
+
+impl<P: std::fmt::Debug> std::error::Error for RenameError<P> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl<P: std::fmt::Debug + Send + Sync + 'static> From<RenameError<P>> for Error {
+    fn from(error: RenameError<P>) -> Self {
+        Error::new(error.kind(), error)
+    }
+}
+
+/// Whether an error means that the paths are on different file systems (or
+/// different mounts).
+///
+/// This is `EXDEV` on Unix and `ERROR_NOT_SAME_DEVICE` on Windows.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// match renamore::rename_exclusive("a", "/mnt/usb/a") {
+///     Err(e) if renamore::error::is_cross_device(&e) => {
+///         renamore::RenameOptions::new().cross_device(true).rename_exclusive("a", "/mnt/usb/a")?;
+///     }
+///     result => result?,
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn is_cross_device(error: &Error) -> bool {
+    classify(error, &|e| {
+        e.kind() == ErrorKind::CrossesDevices || codes::CROSS_DEVICE.contains(&e.raw_os_error())
+    })
+}
+
+/// Whether an error means that the platform or the file system doesn't support
+/// the operation.
+///
+/// This is `ENOTSUP`, `EOPNOTSUPP` or `ENOSYS` on Unix and
+/// `ERROR_NOT_SUPPORTED` or `ERROR_INVALID_FUNCTION` on Windows. `EINVAL`
+/// isn't included because it usually means something else. The functions in
+/// this crate translate it to [`ErrorKind::Unsupported`] when it's known to
+/// mean that a flag isn't supported.
+pub fn is_unsupported(error: &Error) -> bool {
+    classify(error, &|e| {
+        e.kind() == ErrorKind::Unsupported || codes::UNSUPPORTED.contains(&e.raw_os_error())
+    })
+}
+
+/// Whether an error means that something is already at the destination.
+///
+/// This is `EEXIST` or `ENOTEMPTY` on Unix and `ERROR_FILE_EXISTS`,
+/// `ERROR_ALREADY_EXISTS` or `ERROR_DIR_NOT_EMPTY` on Windows. The rename
+/// functions in this crate return [`ErrorKind::AlreadyExists`], or
+/// [`ErrorKind::DirectoryNotEmpty`] if the destination is a directory with
+/// something in it.
+pub fn is_destination_conflict(error: &Error) -> bool {
+    classify(error, &|e| {
+        matches!(e.kind(), ErrorKind::AlreadyExists | ErrorKind::DirectoryNotEmpty)
+            || codes::CONFLICT.contains(&e.raw_os_error())
+    })
+}
+
+/// The raw OS error code of an error or of the error that it wraps.
+///
+/// Some errors from this crate are wrapped to change their kind (like `EEXIST`
+/// for a populated directory becoming [`ErrorKind::DirectoryNotEmpty`]) or to
+/// add detail (like a [`BoundaryError`]). [`Error::raw_os_error`] is `None` for
+/// these but the original error is kept as the source. This finds its code.
+pub fn raw_os_error(error: &Error) -> Option<i32> {
+    find(error, &|e| e.raw_os_error())
+}
+
+// Errors from this crate are often wrapped to change their kind while keeping
+// the original as the source.
+fn classify(error: &Error, matches: &dyn Fn(&Error) -> bool) -> bool {
+    find(error, &|e| matches(e).then_some(())).is_some()
+}
+
+fn find<T>(error: &Error, f: &dyn Fn(&Error) -> Option<T>) -> Option<T> {
+    if let Some(found) = f(error) {
+        return Some(found);
+    }
+
+    let mut source: Option<&(dyn std::error::Error + 'static)> = match error.get_ref() {
+        Some(inner) => Some(inner),
+        None => return None,
+    };
+
+    while let Some(inner) = source {
+        if let Some(inner) = inner.downcast_ref::<Error>() {
+            return find(inner, f);
+        }
+        source = inner.source();
+    }
+
+    None
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) mod codes {
+    use errno::*;
+
+    pub const CROSS_DEVICE: &[Option<i32>] = &[Some(EXDEV)];
+    pub const UNSUPPORTED: &[Option<i32>] = &[Some(EOPNOTSUPP), Some(ENOSYS)];
+    pub const CONFLICT: &[Option<i32>] = &[Some(EEXIST), Some(ENOTEMPTY)];
+
+    // MIPS and SPARC inherited some of their numbers from other systems. Every
+    // other architecture uses the generic ones.
+    #[cfg(not(any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "mips32r6",
+        target_arch = "mips64r6",
+        target_arch = "sparc",
+        target_arch = "sparc64",
+    )))]
+    pub(crate) mod errno {
+        pub const EOPNOTSUPP: i32 = 95;
+        pub const ENOSYS: i32 = 38;
+        pub const ENOTEMPTY: i32 = 39;
+    }
+
+    #[cfg(any(
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "mips32r6",
+        target_arch = "mips64r6",
+    ))]
+    pub(crate) mod errno {
+        pub const EOPNOTSUPP: i32 = 122;
+        pub const ENOSYS: i32 = 89;
+        pub const ENOTEMPTY: i32 = 93;
+    }
+
+    #[cfg(any(target_arch = "sparc", target_arch = "sparc64"))]
+    pub(crate) mod errno {
+        pub const EOPNOTSUPP: i32 = 45;
+        pub const ENOSYS: i32 = 90;
+        pub const ENOTEMPTY: i32 = 66;
+    }
+
+    const EXDEV: i32 = 18;
+    const EEXIST: i32 = 17;
+}
+
+#[cfg(target_vendor = "apple")]
+mod codes {
+    pub const CROSS_DEVICE: &[Option<i32>] = &[Some(18)];
+    pub const UNSUPPORTED: &[Option<i32>] = &[Some(45), Some(102), Some(78)];
+    pub const CONFLICT: &[Option<i32>] = &[Some(17), Some(66)];
+}
+
+#[cfg(target_os = "windows")]
+mod codes {
+    pub const CROSS_DEVICE: &[Option<i32>] = &[Some(17)];
+    pub const UNSUPPORTED: &[Option<i32>] = &[Some(50), Some(1)];
+    pub const CONFLICT: &[Option<i32>] = &[Some(80), Some(183), Some(145)];
+}
+
+// The kind is enough on other platforms.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    target_os = "windows",
+)))]
+mod codes {
+    pub const CROSS_DEVICE: &[Option<i32>] = &[];
+    pub const UNSUPPORTED: &[Option<i32>] = &[];
+    pub const CONFLICT: &[Option<i32>] = &[];
+}
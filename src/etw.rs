@@ -0,0 +1,110 @@
+#![allow(clippy::upper_case_acronyms)]
+
+use std::path::Path;
+use std::io::Result;
+use std::ffi::c_void;
+use std::hash::{BuildHasher, RandomState};
+use std::os::windows::prelude::OsStrExt;
+use std::sync::OnceLock;
+use crate::RenameOptions;
+
+// The events are plain strings written with EventWriteString so that they can
+// be read without a manifest.
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn EventRegister(
+        ProviderId: *const GUID,
+        EnableCallback: *mut c_void,
+        CallbackContext: *mut c_void,
+        RegHandle: *mut u64,
+    ) -> u32;
+
+    fn EventProviderEnabled(RegHandle: u64, Level: u8, Keyword: u64) -> u8;
+
+    fn EventWriteString(RegHandle: u64, Level: u8, Keyword: u64, String: *const u16) -> u32;
+}
+
+#[repr(C)]
+struct GUID {
+    data1: u32,
+    data2: u16,
+    data3: u16,
+    data4: [u8; 8],
+}
+
+// 3c6b1e52-8d0a-4f8e-9a3b-5e2f7c1d9a40
+const PROVIDER_ID: GUID = GUID {
+    data1: 0x3c6b1e52,
+    data2: 0x8d0a,
+    data3: 0x4f8e,
+    data4: [0x9a, 0x3b, 0x5e, 0x2f, 0x7c, 0x1d, 0x9a, 0x40],
+};
+
+const TRACE_LEVEL_ERROR: u8 = 2;
+const TRACE_LEVEL_INFORMATION: u8 = 4;
+
+// The provider is registered the first time it's used and stays registered
+// until the process exits. A handle of zero means that registration failed.
+fn provider() -> u64 {
+    static HANDLE: OnceLock<u64> = OnceLock::new();
+
+    *HANDLE.get_or_init(|| {
+        let mut handle = 0;
+        let ret = unsafe {
+            EventRegister(&PROVIDER_ID, std::ptr::null_mut(), std::ptr::null_mut(), &mut handle)
+        };
+        if ret == 0 { handle } else { 0 }
+    })
+}
+
+// Paths are hashed with a key that's chosen randomly for each process, so
+// the same path only gives the same hash within one process. A hash can't be
+// reversed by hashing guesses without the key. The hash function isn't stable
+// across Rust versions either.
+fn format_path(path: &Path, options: &RenameOptions) -> String {
+    static KEY: OnceLock<RandomState> = OnceLock::new();
+
+    if options.etw_raw_paths {
+        path.display().to_string()
+    } else {
+        format!("{:016x}", KEY.get_or_init(RandomState::new).hash_one(path))
+    }
+}
+
+pub fn trace_rename(
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+    strategy: &str,
+    result: &Result<()>,
+) {
+    let handle = provider();
+    let level = if result.is_ok() { TRACE_LEVEL_INFORMATION } else { TRACE_LEVEL_ERROR };
+
+    // Don't bother formatting anything if nobody is listening.
+    if handle == 0 || unsafe { EventProviderEnabled(handle, level, 0) } == 0 {
+        return;
+    }
+
+    let result = match result {
+        Ok(()) => "ok".to_string(),
+        Err(e) => match e.raw_os_error() {
+            Some(code) => format!("error {code}"),
+            None => format!("{:?}", e.kind()),
+        },
+    };
+    let message = format!(
+        "rename from={} to={} strategy={} result={}",
+        format_path(from, options),
+        format_path(to, options),
+        strategy,
+        result,
+    );
+    let message: Vec<u16> = std::ffi::OsStr::new(&message)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+
+    unsafe { EventWriteString(handle, level, 0, message.as_ptr()) };
+}
@@ -0,0 +1,74 @@
+//! Entry points for the fuzz targets in `fuzz/`.
+//!
+//! This is only compiled with `--cfg fuzzing`, which `cargo fuzz` sets. The
+//! functions panic if an invariant is broken, which the fuzzer reports as a
+//! crash.
+
+/// Parse a version and check that it survives a round trip through `Display`.
+pub fn parse_version(version: &str) {
+    if let Ok(parsed) = version.parse::<crate::Version>() {
+        assert_eq!(parsed.to_string().parse(), Ok(parsed));
+    }
+}
+
+/// Parse the release that `uname` gives, up to the first NUL.
+#[cfg(all(target_os = "linux", linker))]
+pub fn parse_kernel_release(release: &[u8]) {
+    let end = release.iter().position(|&b| b == 0).unwrap_or(release.len());
+    let mut terminated = release[..end].to_vec();
+    terminated.push(0);
+
+    let release = std::ffi::CStr::from_bytes_with_nul(&terminated).unwrap();
+
+    if let Ok(kernel) = crate::linux::parse_kernel_release(release) {
+        let string = release.to_str().unwrap();
+        assert_eq!(string.parse(), Ok(kernel.version));
+        assert_eq!(kernel.wsl1, crate::linux::is_wsl1(string));
+    }
+}
+
+/// Look up a magic number from `statfs` with a kernel version.
+#[cfg(all(target_os = "linux", linker))]
+pub fn filesystem_support(major: u16, minor: u16, patch: u16, magic: u32) {
+    use crate::{AtomicSupport, FsInfo, Reason, Version};
+
+    let version = Version::new(major, minor, patch);
+    let fs_info = || Ok(FsInfo { magic: Some(magic), ..FsInfo::default() });
+
+    match crate::linux::filesystem_support(version, magic, fs_info).unwrap() {
+        AtomicSupport::No(Reason::KernelTooOld { found, required }) => {
+            assert_eq!(found, version);
+            assert!(found < required);
+        }
+        AtomicSupport::No(Reason::Filesystem(info)) => {
+            assert_eq!(info.magic, Some(magic));
+            let mut known = crate::linux::KNOWN_FILESYSTEMS.iter();
+            assert!(!known.any(|known| known.magic == Some(magic)));
+        }
+        _ => {}
+    }
+}
+
+/// Parse the contents of `/proc/self/mountinfo`.
+#[cfg(all(target_os = "linux", linker))]
+pub fn parse_mountinfo(mountinfo: &str) {
+    for mount in crate::mountinfo::parse(mountinfo) {
+        assert!(!mount.options.is_empty());
+    }
+}
+
+/// Convert a path to a wide string for the Windows API.
+#[cfg(target_os = "windows")]
+pub fn to_wide_path(path: &[u16], exact: bool) {
+    use std::os::windows::ffi::OsStringExt;
+
+    let path = std::path::PathBuf::from(std::ffi::OsString::from_wide(path));
+
+    if let Ok(wide) = crate::windows::to_wide_path(&path, exact) {
+        assert_eq!(wide.last(), Some(&0));
+        if exact {
+            assert!(wide.starts_with(&r"\\?\".encode_utf16().collect::<Vec<_>>())
+                || wide.starts_with(&r"\\.\".encode_utf16().collect::<Vec<_>>()));
+        }
+    }
+}
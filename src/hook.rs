@@ -0,0 +1,92 @@
+use std::path::Path;
+use std::io::Error;
+use std::sync::RwLock;
+
+type Hook = fn(&FallbackEvent);
+
+static HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+pub fn set(hook: Option<Hook>) {
+    *HOOK.write().unwrap() = hook;
+}
+
+// The lock isn't held while calling the hook so that it can replace itself.
+pub fn notify(event: &FallbackEvent) {
+    let hook = *HOOK.read().unwrap();
+
+    if let Some(hook) = hook {
+        hook(event);
+    }
+}
+
+/// The fallback described by a [`FallbackEvent`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Fallback {
+    /// The destination path was reserved before renaming onto it, as
+    /// [`rename_exclusive_fallback`](crate::rename_exclusive_fallback) does
+    /// when an atomic rename isn't supported. This isn't atomic.
+    NonAtomic,
+
+    /// The path was copied to another file system. See
+    /// [`RenameOptions::cross_device`](crate::RenameOptions::cross_device).
+    Copy,
+
+    /// On Windows, `MoveFileExW` was used because the file system doesn't
+    /// support `FileRenameInfoEx`. This doesn't replace the destination but it
+    /// isn't documented to be atomic.
+    MoveFile,
+}
+
+impl Fallback {
+    // The name used in tracing and log records.
+    #[cfg(any(feature = "tracing", feature = "log"))]
+    pub(crate) fn strategy(self) -> &'static str {
+        match self {
+            Self::NonAtomic => "rename_exclusive_non_atomic",
+            Self::Copy => "copy",
+            Self::MoveFile => "MoveFileExW",
+        }
+    }
+}
+
+/// A fallback that's about to be used.
+///
+/// This is given to the hook set with
+/// [`set_fallback_hook`](crate::set_fallback_hook).
+#[derive(Debug)]
+pub struct FallbackEvent<'a> {
+    fallback: Fallback,
+    from: &'a Path,
+    to: &'a Path,
+    reason: &'a Error,
+}
+
+impl<'a> FallbackEvent<'a> {
+    pub(crate) fn new(fallback: Fallback, from: &'a Path, to: &'a Path, reason: &'a Error) -> Self {
+        Self { fallback, from, to, reason }
+    }
+
+    /// The fallback that's about to be used.
+    pub fn fallback(&self) -> Fallback {
+        self.fallback
+    }
+
+    /// The path that's being renamed.
+    pub fn from(&self) -> &'a Path {
+        self.from
+    }
+
+    /// The path that it's being renamed to.
+    pub fn to(&self) -> &'a Path {
+        self.to
+    }
+
+    /// The error that made the fallback necessary. This is usually
+    /// [`ErrorKind::Unsupported`](std::io::ErrorKind::Unsupported) or
+    /// [`ErrorKind::CrossesDevices`](std::io::ErrorKind::CrossesDevices).
+    pub fn reason(&self) -> &'a Error {
+        self.reason
+    }
+}
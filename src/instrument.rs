@@ -0,0 +1,103 @@
+// Events are emitted at the points where something interesting is decided:
+// which system call is made, when a non-atomic fallback is used, when a
+// transient error is retried and when something about the file system couldn't
+// be determined. Without any of the features that consume them, these compile
+// to nothing. The tracing and log features can be enabled together.
+
+use std::path::Path;
+use std::io::{Error, Result};
+use std::time::Duration;
+use crate::{FallbackEvent, Strategy};
+
+pub struct Operation {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+    #[cfg(feature = "tracing")]
+    start: std::time::Instant,
+}
+
+pub fn operation(name: &'static str, from: &Path, to: &Path) -> Operation {
+    #[cfg(not(feature = "tracing"))]
+    let _ = (name, from, to);
+
+    Operation {
+        #[cfg(feature = "tracing")]
+        span: tracing::debug_span!("renamore", operation = name, ?from, ?to).entered(),
+        #[cfg(feature = "tracing")]
+        start: std::time::Instant::now(),
+    }
+}
+
+impl Operation {
+    pub fn finish<T>(self, result: &Result<T>) {
+        #[cfg(feature = "tracing")]
+        {
+            let elapsed_us = self.start.elapsed().as_micros() as u64;
+
+            match result {
+                Ok(_) => tracing::debug!(elapsed_us, "finished"),
+                Err(e) => tracing::debug!(elapsed_us, error = %e, kind = ?e.kind(), "failed"),
+            }
+
+            drop(self.span);
+        }
+
+        #[cfg(not(feature = "tracing"))]
+        let _ = result;
+    }
+}
+
+// Some backends never make a system call of their own.
+#[allow(dead_code)]
+pub fn syscall(name: &'static str, flags: u32) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(syscall = name, flags = format_args!("{:#x}", flags), "calling");
+
+    #[cfg(not(feature = "tracing"))]
+    let _ = (name, flags);
+}
+
+// This is also where the hook set with `set_fallback_hook` is called and the
+// report is updated.
+pub fn fallback(event: &FallbackEvent) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        strategy = event.fallback().strategy(),
+        error = %event.reason(),
+        "using a non-atomic fallback",
+    );
+
+    #[cfg(feature = "log")]
+    log::warn!("using a non-atomic fallback ({}): {}", event.fallback().strategy(), event.reason());
+
+    crate::report::record(|report| {
+        if !report.fell_back {
+            report.strategy = Strategy::Fallback(event.fallback());
+            report.fell_back = true;
+        }
+    });
+    crate::hook::notify(event);
+}
+
+pub fn retry(attempt: u32, delay: Duration, error: &Error) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(attempt, delay_ms = delay.as_millis() as u64, error = %error, "retrying");
+
+    #[cfg(feature = "log")]
+    log::debug!("retrying in {:?} (attempt {}): {}", delay, attempt, error);
+
+    #[cfg(not(any(feature = "tracing", feature = "log")))]
+    let _ = (attempt, delay, error);
+}
+
+// Detection failures aren't errors. Something less specific is used instead.
+pub fn detection_failed(what: &'static str, error: &Error) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(what, error = %error, "detection failed");
+
+    #[cfg(feature = "log")]
+    log::debug!("couldn't determine the {}: {}", what, error);
+
+    #[cfg(not(any(feature = "tracing", feature = "log")))]
+    let _ = (what, error);
+}
@@ -50,20 +50,119 @@
 //! ## Platform-specific behaviour
 //!
 //! On Linux, the `renameat2` syscall is used. A wrapper around this syscall is
-//! provided by glibc since version 2.28 but not musl (yet?). The existence of
-//! the wrapper is checked at build time and a wrapper is provided if one isn't
-//! found. In case something goes wrong, there are two features that can be used
-//! to bypass this mechanism.
+//! provided by glibc since version 2.28 but not musl (yet?). With glibc, the
+//! wrapper is looked up at runtime so the same binary works with older and
+//! newer versions of glibc. Otherwise, the existence of the wrapper is checked
+//! at build time and a wrapper is provided if one isn't found. The syscall is
+//! made directly on x86, x86_64, ARM, AArch64, RISC-V (64-bit), LoongArch,
+//! s390x and PowerPC. Other architectures rely on a C wrapper with musl and the
+//! non-atomic fallback otherwise. In case something goes wrong, there are two
+//! features that can be used to bypass this mechanism.
 //!
 //!  - `always-supported`. Assume that `renameat2` exists.
 //!  - `always-fallback`. Assume that `renameat2` doesn't exist.
 //!
 //! Hopefully using these features shouldn't be necessary. If they do become
 //! necessary, then there might be a bug.
+//!
+//! Alternatively, the `rustix` feature makes the syscalls through [rustix]
+//! instead. This doesn't depend on what libc provides and works on every
+//! architecture that rustix supports.
+//!
+//! The `libc` feature is similar but uses the declarations from the [libc]
+//! crate. The syscall is made directly using the syscall number from libc.
+//!
+//! [rustix]: https://crates.io/crates/rustix
+//! [libc]: https://crates.io/crates/libc
+//!
+//! On Cygwin (including the MSYS2 runtime), `renameat2` is also used. Cygwin
+//! has provided it since version 3.0. Paths are given to Cygwin untranslated so
+//! POSIX paths like `/cygdrive/c` work as they would in any other Cygwin
+//! program.
+//!
+//! On ESP-IDF, none of the supported file systems can rename without replacing
+//! so [`rename_exclusive`] always returns [`ErrorKind::Unsupported`].
+//!
+//! The same goes for GNU Hurd and z/OS UNIX System Services. glibc's
+//! `renameat2` on the Hurd does accept `RENAME_NOREPLACE` (it becomes the
+//! `excl` argument of the `dir_rename` RPC) but renamore doesn't call it there
+//! yet. On these platforms, [`rename_exclusive_fallback`] emulates the
+//! operation by reserving the destination path before renaming onto it.
+//!
+//! [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+//!
+//! ## Features
+//!
+//! The optional features are described in the [README] and by the items that
+//! they add.
+//!
+//! [README]: https://github.com/indianakernick/renamore#features
+#![cfg_attr(docsrs, feature(doc_cfg))]
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::ffi::OsStr;
 use std::io::{Error, ErrorKind, Result};
 
+#[cfg(feature = "async")]
+pub mod asynchronous;
+#[cfg(feature = "audit")]
+mod audit;
+mod cache;
+mod capabilities;
+mod config;
+#[cfg(all(target_vendor = "apple", feature = "apple-coordination"))]
+mod coordination;
+mod copy;
+pub mod error;
+#[cfg(fuzzing)]
+#[doc(hidden)]
+pub mod fuzzing;
+mod hook;
+mod instrument;
+mod lockfile;
+mod maildir;
+#[cfg(feature = "normalization")]
+mod normalization;
+mod options;
+mod plan;
+mod probe;
+#[cfg(feature = "notify")]
+mod race;
+mod registry;
+mod renamer;
+mod report;
+mod stats;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+#[cfg(feature = "txf")]
+mod transaction;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+#[cfg(feature = "camino")]
+pub mod utf8;
+
+#[cfg(feature = "audit")]
+pub use audit::{AuditOutcome, AuditRecord};
+pub use capabilities::{AtomicSupport, Capabilities, FsInfo, KnownFilesystem, Normalization};
+pub use capabilities::{Reason, Support, Version};
+pub use config::Config;
+pub use error::{BoundaryError, DurabilityError, ParseVersionError, PlaceholderError};
+pub use error::{ReadOnlyCause, ReadOnlyError};
+pub use error::{RenameError, RenameOperation, SymlinkError, SystemVolumeError};
+pub use hook::{Fallback, FallbackEvent};
+pub use lockfile::Lockfile;
+pub use maildir::Maildir;
+pub use options::{AclInheritance, Backend, PlaceholderPolicy, RenameOptions, RenameOptionsConfig};
+pub use plan::{PlannedRename, RenamePlan};
+pub use renamer::{Renamer, SystemRenamer};
+pub use report::{RenameReport, Strategy};
+#[cfg(feature = "stats")]
+pub use stats::Stats;
+#[cfg(feature = "txf")]
+pub use transaction::Transaction;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use uring::Ring;
+
 /// Rename a file without overwriting the destination path if it exists.
 ///
 /// Unlike a combination of [`try_exists`] and [`rename`], this operation is
@@ -74,12 +173,23 @@ use std::io::{Error, ErrorKind, Result};
 /// [`rename`]: std::fs::rename
 /// [TOCTTOU]: https://en.wikipedia.org/wiki/Time-of-check_to_time-of-use
 ///
+/// If the process has been configured with [`Config::fallback`], then this is
+/// the same as [`rename_exclusive_fallback`] and the rename might not be
+/// atomic.
+///
 /// # Platform-specific behaviour
 ///
 /// On Linux, this calls `renameat2` with `RENAME_NOREPLACE`. On Darwin (macOS,
-/// iOS, watchOS, tvOS), this calls `renamex_np` with `RENAME_EXCL`. On Windows,
-/// this calls `MoveFileExW` with no flags. On all other platforms, this returns
-/// [`ErrorKind::Unsupported`] unconditionally.
+/// iOS, watchOS, tvOS), this calls `renamex_np` with `RENAME_EXCL`. It's looked
+/// up at runtime and [`ErrorKind::Unsupported`] is returned on versions that
+/// predate it (like macOS 10.11). On Windows, this calls
+/// `SetFileInformationByHandle` with `FileRenameInfoEx` and
+/// `FILE_RENAME_FLAG_POSIX_SEMANTICS`. If that's not supported by the OS or the
+/// file system, this falls back to `MoveFileExW` with no flags (or
+/// `MoveFileFromAppW` for UWP targets because neither is available in an app
+/// container). On Cygwin (and MSYS2), this calls `renameat2` with
+/// `RENAME_NOREPLACE` and leaves path translation to the Cygwin runtime. On all
+/// other platforms, this returns [`ErrorKind::Unsupported`] unconditionally.
 ///
 /// # Errors
 ///
@@ -87,12 +197,58 @@ use std::io::{Error, ErrorKind, Result};
 /// it's not supported but the rename request is otherwise valid, then
 /// [`ErrorKind::Unsupported`] will be returned. If the operation is supported
 /// but a file at `to` exists, then [`ErrorKind::AlreadyExists`] will be
-/// returned.
+/// returned. If `to` is a directory that isn't empty, then
+/// [`ErrorKind::DirectoryNotEmpty`] is returned instead on every platform
+/// (rather than `EEXIST`, `ENOTEMPTY` or one of several Windows errors,
+/// depending on the platform). Both are considered conflicts by
+/// [`error::is_destination_conflict`].
+///
+/// If either path is empty or contains a NUL byte, then
+/// [`ErrorKind::InvalidInput`] will be returned with a message that says which
+/// one it is. This is checked before anything else on every platform.
+///
+/// If the rename is interrupted by a signal, then [`ErrorKind::Interrupted`]
+/// is returned. The rename isn't retried because it might have already
+/// happened (NFS can report `EINTR` after the server has done it).
+///
+/// On Windows, if the file name of `to` would be altered by Win32 path
+/// normalization, then [`ErrorKind::InvalidInput`] will be returned. See
+/// [`RenameOptions::exact_names`].
+///
+/// On Linux, if `from` and `to` are in different mounts or btrfs subvolumes of
+/// the same file system, then the error wraps a [`BoundaryError`].
+///
+/// On Linux, Darwin and Windows, if the rename fails because one of the paths
+/// is on a read-only mount or has the immutable or append-only attribute, then
+/// the error wraps a [`ReadOnlyError`] that says which path it is.
 ///
 /// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
 /// [`ErrorKind::AlreadyExists`]: std::io::ErrorKind::AlreadyExists
+/// [`ErrorKind::DirectoryNotEmpty`]: std::io::ErrorKind::DirectoryNotEmpty
+/// [`ErrorKind::InvalidInput`]: std::io::ErrorKind::InvalidInput
+/// [`ErrorKind::Interrupted`]: std::io::ErrorKind::Interrupted
 pub fn rename_exclusive<F: AsRef<Path>, T: AsRef<Path>>(from: F, to: T) -> Result<()> {
-    sys::rename_exclusive(from.as_ref(), to.as_ref())
+    if config::fallback() {
+        return rename_exclusive_fallback(from, to).map(drop);
+    }
+
+    RenameOptions::new().rename_exclusive(from, to)
+}
+
+/// Rename a file without overwriting the destination path if it exists,
+/// returning a [`RenameError`] with both paths if it fails.
+///
+/// See [`rename_exclusive`]. Like it, this uses the non-atomic fallback if the
+/// process has been configured with [`Config::fallback`].
+pub fn rename_exclusive_detailed<F: AsRef<Path>, T: AsRef<Path>>(
+    from: F,
+    to: T,
+) -> std::result::Result<(), RenameError> {
+    if config::fallback() {
+        return RenameOptions::new().rename_exclusive_fallback_detailed(from, to).map(drop);
+    }
+
+    RenameOptions::new().rename_exclusive_detailed(from, to)
 }
 
 /// Determine whether an atomic [`rename_exclusive`] is supported.
@@ -101,18 +257,42 @@ pub fn rename_exclusive<F: AsRef<Path>, T: AsRef<Path>>(from: F, to: T) -> Resul
 /// necessary functions are available at link-time, and the OS implements the
 /// operation for the file system of the given path. If this function returns
 /// `Ok(true)`, then a call to `rename_exclusive` at the same path is unlikely
-/// to return [`ErrorKind::Unsupported`] if it fails.
+/// to return [`ErrorKind::Unsupported`] if it fails. This only considers one
+/// path. A rename between two supported paths may still fail with
+/// [`ErrorKind::CrossesDevices`] if they're in different mounts or subvolumes.
+///
+/// The path doesn't need to exist. If it doesn't (like the destination of a
+/// rename that hasn't happened yet), its nearest ancestor that does exist is
+/// checked instead. The same goes for [`rename_exclusive_support`],
+/// [`rename_exclusive_atomic_support`] and [`rename_exclusive_probe`].
 ///
 /// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+/// [`ErrorKind::CrossesDevices`]: std::io::ErrorKind::CrossesDevices
 ///
 /// # Platform-specific behaviour
 ///
-/// On Linux, this parses `/proc/version` to determine the kernel version and
-/// calls `statfs` to determine the file system type. On Darwin (macOS, iOS,
+/// On Linux, this calls `uname` to determine the kernel version and `statfs`
+/// to determine the file system type. If the kernel version can't be parsed,
+/// then `renameat2` is called with empty paths to check that it exists. CIFS
+/// and FUSE file systems are never considered atomic because that depends on
+/// the server (see [`rename_exclusive_support`]). On Darwin (macOS, iOS,
 /// watchOS, tvOS), this calls `getattrlist` to determine whether the volume at
-/// the path lists `VOL_CAP_INT_RENAME_EXCL` as one of its capabilities. On
-/// Windows, this always returns `Ok(true)` even though that may not be
-/// technically true. On all other platforms, this always returns `Ok(false)`.
+/// the path lists `VOL_CAP_INT_RENAME_EXCL` as one of its capabilities. Volumes
+/// without `MNT_LOCAL` (network volumes) are never considered atomic. On
+/// Windows, this calls `RtlGetVersion` to check that the OS is at least Windows
+/// 10 1809 and `GetVolumeInformationW` to check that the file system is NTFS or
+/// ReFS. Network shares (as reported by `GetDriveTypeW`) are never considered
+/// atomic because that depends on the server. On Cygwin, this always returns
+/// `Ok(true)` even though that may not be technically true. On all other
+/// platforms, this always returns `Ok(false)`.
+///
+/// # Caching
+///
+/// On Linux and Darwin, the result is cached per device (`st_dev`) so checking
+/// many paths on the same volume only queries the OS once. Use
+/// [`rename_exclusive_support_uncached`] to bypass the cache or
+/// [`invalidate_caches`] after remounting. For file systems that aren't
+/// known, [`rename_exclusive_probe`] tries the operation instead.
 ///
 /// # Examples
 ///
@@ -126,86 +306,1305 @@ pub fn rename_exclusive<F: AsRef<Path>, T: AsRef<Path>>(from: F, to: T) -> Resul
 /// # }
 /// ```
 pub fn rename_exclusive_is_atomic<P: AsRef<Path>>(path: P) -> Result<bool> {
-    sys::rename_exclusive_is_atomic(path.as_ref())
+    Ok(cache::rename_exclusive_support(existing_ancestor(path.as_ref()))? == Support::Supported)
 }
 
-/// Rename a file without overwriting the destination path if it exists, using a
-/// non-atomic fallback if necessary.
+/// Determine whether [`rename_exclusive`] is atomic on the volume of an open
+/// directory.
 ///
-/// This is similar to [`rename_exclusive`] except that if performing the
-/// operation atomically is not supported, then a non-atomic fallback
-/// implementation based on [`try_exists`] and [`rename`] will be used.
+/// This is the same as [`rename_exclusive_is_atomic`] except that the volume
+/// is identified by a handle rather than a path. A long-running service can
+/// hold onto a directory and check it without worrying about the path being
+/// replaced or remounted in the meantime.
 ///
-/// [`try_exists`]: std::path::Path::try_exists
-/// [`rename`]: std::fs::rename
+/// # Platform-specific behaviour
+///
+/// On Linux, this uses `fstatfs`. On Darwin, this uses `fstatfs` and
+/// `fgetattrlist`. On Windows, this uses `GetVolumeInformationByHandleW` and
+/// network shares are detected with `GetFileInformationByHandleEx`. On all
+/// other platforms, this is the same as [`rename_exclusive_is_atomic`] because
+/// the answer doesn't depend on the file system.
 ///
 /// # Examples
 ///
 /// ```no_run
 /// # fn main() -> std::io::Result<()> {
-/// if renamore::rename_exclusive_fallback("old.txt", "new.txt")? {
-///     // `new.txt` was definitely not overwritten.
-///     println!("The operation was atomic");
-/// } else {
-///     // `new.txt` was probably not overwritten.
-///     println!("The operation was not atomic");
+/// let dir = std::fs::File::open(".")?;
+///
+/// if !renamore::rename_exclusive_is_atomic_fd(&dir)? {
+///     println!("Warning: atomically renaming without overwriting is not supported!");
 /// }
 /// # Ok(())
 /// # }
 /// ```
-pub fn rename_exclusive_fallback<F: AsRef<Path>, T: AsRef<Path>>(from: F, to: T) -> Result<bool> {
-    fn inner(from: &Path, to: &Path) -> Result<bool> {
-        if let Err(e) = sys::rename_exclusive(from, to) {
-            if e.kind() == ErrorKind::Unsupported {
-                rename_exclusive_non_atomic(from, to)?;
-                return Ok(false);
-            }
-            Err(e)
+pub fn rename_exclusive_is_atomic_fd(dir: &std::fs::File) -> Result<bool> {
+    Ok(cache::atomic_support_fd(dir)?.support() == Support::Supported)
+}
+
+/// Determine whether [`rename_exclusive`] is supported, and whether that can
+/// be trusted.
+///
+/// This is a more detailed version of [`rename_exclusive_is_atomic`], which
+/// only returns `Ok(true)` for [`Support::Supported`]. On network file systems
+/// (and FUSE on Linux), the client may accept the request but whether the
+/// server performs it atomically can't be determined. These return
+/// [`Support::Unverifiable`] so that callers can choose to use a fallback that
+/// guards against conflicts in some other way.
+///
+/// # Platform-specific behaviour
+///
+/// On Linux, CIFS, SMB2 and FUSE file systems are unverifiable. NFS, 9p and
+/// Ceph reject the flag so they're unsupported. Under WSL 2, this means that the
+/// Linux file system is supported but the Windows drives under `/mnt` (shared
+/// over 9p) are not. Everything is unverifiable under WSL 1. On Darwin, volumes
+/// without `MNT_LOCAL` that advertise `VOL_CAP_INT_RENAME_EXCL` are
+/// unverifiable. On Windows, network shares are unverifiable. See
+/// [`rename_exclusive_is_atomic`] for the rest.
+///
+/// The result is cached in the same way as [`rename_exclusive_is_atomic`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use renamore::Support;
+///
+/// match renamore::rename_exclusive_support(".")? {
+///     Support::Supported => println!("Atomic"),
+///     Support::Unverifiable => println!("Probably atomic but it's up to the server"),
+///     _ => println!("Not atomic"),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn rename_exclusive_support<P: AsRef<Path>>(path: P) -> Result<Support> {
+    cache::rename_exclusive_support(existing_ancestor(path.as_ref()))
+}
+
+/// Determine whether [`rename_exclusive`] is atomic, and if not, why.
+///
+/// This is a more detailed version of [`rename_exclusive_support`] for
+/// applications that want to report the reason. [`AtomicSupport::Yes`],
+/// [`AtomicSupport::No`] and [`AtomicSupport::Unknown`] correspond to
+/// [`Support::Supported`], [`Support::Unsupported`] and
+/// [`Support::Unverifiable`] respectively. The result is cached in the same
+/// way as [`rename_exclusive_is_atomic`].
+///
+/// # Platform-specific behaviour
+///
+/// On Linux, [`Reason::KernelTooOld`] gives the running kernel version and the
+/// version that added support for the file system. On Windows, it gives the
+/// OS version and Windows 10 1809 (10.0.17763). [`Reason::Filesystem`] is given
+/// for file systems that don't support the operation on any version. On
+/// platforms without the operation at all (or a C library that doesn't
+/// provide it), [`Reason::Unavailable`] is given.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use renamore::{AtomicSupport, Reason};
+///
+/// match renamore::rename_exclusive_atomic_support(".")? {
+///     AtomicSupport::Yes => {}
+///     AtomicSupport::No(Reason::KernelTooOld { found, required }) => {
+///         println!("Atomic renames need {} but this is {}", required, found);
+///     }
+///     AtomicSupport::No(Reason::Filesystem(fs)) => {
+///         println!("Atomic renames aren't supported by {:?}", fs.name);
+///     }
+///     other => println!("Atomic renames aren't available: {:?}", other),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn rename_exclusive_atomic_support<P: AsRef<Path>>(path: P) -> Result<AtomicSupport> {
+    cache::atomic_support(existing_ancestor(path.as_ref()))
+}
+
+/// Determine whether [`rename_exclusive`] from one path to another is atomic.
+///
+/// This is the same as [`rename_exclusive_is_atomic`] except that it also
+/// checks that both paths are on the same volume. A rename between volumes
+/// fails with [`ErrorKind::CrossesDevices`] regardless of what each volume
+/// supports. See [`rename_exclusive_atomic_support_for`] for the reason.
+///
+/// [`ErrorKind::CrossesDevices`]: std::io::ErrorKind::CrossesDevices
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// if !renamore::rename_exclusive_is_atomic_for("downloads/file.part", "documents/file")? {
+///     println!("Warning: the file will be copied rather than renamed");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn rename_exclusive_is_atomic_for<F: AsRef<Path>, T: AsRef<Path>>(
+    from: F,
+    to: T,
+) -> Result<bool> {
+    Ok(rename_exclusive_atomic_support_for(from, to)?.support() == Support::Supported)
+}
+
+/// Determine whether [`rename_exclusive`] from one path to another is atomic,
+/// and if not, why.
+///
+/// If the paths are on different volumes, this returns
+/// [`Reason::CrossesDevices`]. Otherwise, it's the same as
+/// [`rename_exclusive_atomic_support`] for the volume they share. As with
+/// that, the paths don't need to exist.
+///
+/// # Platform-specific behaviour
+///
+/// On Unix platforms, the paths are on the same volume if they have the same
+/// `st_dev`. On Linux, they also need to be on the same mount because renames
+/// between bind mounts fail too. The mount IDs are read from
+/// `/proc/self/fdinfo` so an error is returned if procfs isn't mounted. On
+/// Windows, the volumes are compared with `GetVolumePathNameW`. On all other
+/// platforms, the paths are assumed to be on the same volume.
+pub fn rename_exclusive_atomic_support_for<F: AsRef<Path>, T: AsRef<Path>>(
+    from: F,
+    to: T,
+) -> Result<AtomicSupport> {
+    let from = existing_ancestor(from.as_ref());
+    let to = existing_ancestor(to.as_ref());
+
+    if !volume::same_volume(from, to)? {
+        return Ok(AtomicSupport::No(Reason::CrossesDevices));
+    }
+
+    cache::atomic_support(to)
+}
+
+#[cfg(any(
+    all(target_os = "linux", linker),
+    target_vendor = "apple",
+    all(target_os = "windows", not(target_vendor = "uwp")),
+))]
+use sys as volume;
+
+#[cfg(not(any(
+    all(target_os = "linux", linker),
+    target_vendor = "apple",
+    all(target_os = "windows", not(target_vendor = "uwp")),
+)))]
+mod volume {
+    use std::path::Path;
+    use std::io::Result;
+
+    #[cfg(unix)]
+    pub fn same_volume(a: &Path, b: &Path) -> Result<bool> {
+        use std::os::unix::fs::MetadataExt;
+
+        Ok(a.metadata()?.dev() == b.metadata()?.dev())
+    }
+
+    #[cfg(not(unix))]
+    pub fn same_volume(_a: &Path, _b: &Path) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Determine whether [`rename_exclusive`] is supported without using or
+/// updating the cache.
+///
+/// This is the same as [`rename_exclusive_support`] except that the OS is
+/// always queried. This is useful when a path might be on a file system that
+/// was mounted since the last check.
+pub fn rename_exclusive_support_uncached<P: AsRef<Path>>(path: P) -> Result<Support> {
+    Ok(registry::atomic_support(existing_ancestor(path.as_ref()))?.support())
+}
+
+/// Determine whether [`rename_exclusive`] is supported by trying it.
+///
+/// Rather than comparing the OS version and file system type against a table
+/// like [`rename_exclusive_support`] does, this creates two empty files in the
+/// directory at `path` (or the directory containing `path` if it's a file) and
+/// tries to rename one onto the other. The result is what was observed:
+///
+///  - [`Support::Supported`] if the rename failed with
+///    [`ErrorKind::AlreadyExists`].
+///  - [`Support::Unsupported`] if the rename failed with
+///    [`ErrorKind::Unsupported`] or replaced the other file.
+///
+/// Both files are removed afterwards. This gives an answer for file systems
+/// that the tables don't know about, at the cost of writing to the directory.
+/// It never returns [`Support::Unverifiable`], although for a network file
+/// system the answer only holds for the server at the time of the probe. The
+/// result isn't cached.
+///
+/// [`ErrorKind::AlreadyExists`]: std::io::ErrorKind::AlreadyExists
+/// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+///
+/// # Errors
+///
+/// Returns an error if the files can't be created, for example because the
+/// directory is read-only.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// if renamore::rename_exclusive_probe(".")? != renamore::Support::Supported {
+///     println!("Warning: atomically renaming without overwriting is not supported!");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn rename_exclusive_probe<P: AsRef<Path>>(path: P) -> Result<Support> {
+    probe::probe(existing_ancestor(path.as_ref()))
+}
+
+// An interior NUL would otherwise be rejected while converting to a C string
+// (or wide string) with a message that doesn't say which path it was in, and
+// an empty path means different things on different platforms. `name` is the
+// name of the argument.
+fn check_path(path: &Path, name: &str) -> Result<()> {
+    let problem = if path.as_os_str().is_empty() {
+        "is empty"
+    } else if path.as_os_str().as_encoded_bytes().contains(&0) {
+        "contains a NUL byte"
+    } else {
+        return Ok(());
+    };
+
+    Err(Error::new(ErrorKind::InvalidInput, format!("the `{}` path {}", name, problem)))
+}
+
+// A stream name may start with a colon and end with the stream type, which has
+// to be $DATA. Anything else with a colon in it would name a different stream,
+// and a slash would be taken as part of the path. Returns the bare name.
+fn check_stream_name<'a>(stream: &'a OsStr, name: &str) -> Result<&'a str> {
+    let Some(bare) = stream.to_str() else {
+        let message = format!("the `{}` stream name isn't valid Unicode", name);
+        return Err(Error::new(ErrorKind::InvalidInput, message));
+    };
+    let bare = bare.strip_prefix(':').unwrap_or(bare);
+    let bare = bare.strip_suffix(":$DATA").unwrap_or(bare);
+
+    let problem = if bare.is_empty() {
+        "is empty"
+    } else if bare.contains('\0') {
+        "contains a NUL byte"
+    } else if bare.contains(':') {
+        "contains a colon"
+    } else if bare.contains(['\\', '/']) {
+        "contains a slash"
+    } else {
+        return Ok(bare);
+    };
+
+    Err(Error::new(ErrorKind::InvalidInput, format!("the `{}` stream name {}", name, problem)))
+}
+
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+const RESERVED_NAMES: [&str; 24] = [
+    "CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// Win32 path normalization trims trailing dots and spaces from the file name,
+// and treats a reserved name as a device regardless of the extension. Either
+// way, the file that ends up being created isn't the one that was asked for.
+// This is only checked on Windows.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn check_file_name(path: &Path) -> Result<()> {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return Ok(()),
+    };
+
+    if name.ends_with(['.', ' ']) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "file name ends with a dot or a space",
+        ));
+    }
+
+    let stem = name.split('.').next().unwrap_or_default().trim_end_matches(' ');
+
+    if RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "file name is reserved for a device",
+        ));
+    }
+
+    Ok(())
+}
+
+// The directory containing a path. A path with only a file name is in the
+// current directory.
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+// A path that doesn't exist yet will be on the same volume as the closest
+// directory above it that does. If nothing exists (or whether something exists
+// can't be determined), the path is given to the backend to report the error.
+fn existing_ancestor(path: &Path) -> &Path {
+    for ancestor in path.ancestors() {
+        let ancestor = if ancestor.as_os_str().is_empty() {
+            Path::new(".")
         } else {
-            Ok(true)
+            ancestor
+        };
+
+        if !matches!(ancestor.try_exists(), Ok(false)) {
+            return ancestor;
         }
     }
-    inner(from.as_ref(), to.as_ref())
+
+    path
 }
 
-fn rename_exclusive_non_atomic(from: &Path, to: &Path) -> Result<()> {
-    if to.try_exists()? {
-        return Err(Error::from(ErrorKind::AlreadyExists));
+/// Determine the version of the running OS kernel.
+///
+/// This is the version that [`Reason::KernelTooOld`] compares against. It's
+/// exposed for applications that make their own decisions about which kernel
+/// features to use.
+///
+/// # Platform-specific behaviour
+///
+/// On Linux, this is the release from `uname` (like 6.1.0). Under WSL 1, this
+/// is the version of Linux that's being imitated. On Darwin, this is the
+/// release of the Darwin kernel from `kern.osrelease` (like 22.1.0 for macOS
+/// 13), not the marketing version. On Windows, this is the version from
+/// `RtlGetVersion` with the build number as the patch (like 10.0.19045). On
+/// all other platforms (and UWP targets), this returns
+/// [`ErrorKind::Unsupported`].
+///
+/// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use renamore::Version;
+///
+/// if renamore::kernel_version()? >= Version::new(5, 6, 0) {
+///     println!("openat2 is available");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn kernel_version() -> Result<Version> {
+    kernel::kernel_version()
+}
+
+#[cfg(any(
+    all(target_os = "linux", linker),
+    target_vendor = "apple",
+    all(target_os = "windows", not(target_vendor = "uwp")),
+))]
+use sys as kernel;
+
+#[cfg(not(any(
+    all(target_os = "linux", linker),
+    target_vendor = "apple",
+    all(target_os = "windows", not(target_vendor = "uwp")),
+)))]
+mod kernel {
+    use std::io::{Error, ErrorKind, Result};
+    use crate::Version;
+
+    pub fn kernel_version() -> Result<Version> {
+        Err(Error::from(ErrorKind::Unsupported))
     }
+}
 
-    std::fs::rename(from, to)
+/// List the file systems that [`rename_exclusive_support`] knows about.
+///
+/// This is the table that's consulted at runtime, so tools and documentation
+/// generated from it stay consistent with what the crate does. It doesn't
+/// include predicates registered with [`register_support_predicate`].
+///
+/// # Platform-specific behaviour
+///
+/// On Linux, file systems are identified by their magic number and the minimum
+/// versions are kernel versions. Some file systems have several magic numbers
+/// so names can appear more than once. ZFS isn't listed because support
+/// depends on the version of the OpenZFS module (2.2) rather than the kernel.
+/// On Windows, file systems are identified by name and the minimum version is
+/// the OS version. On Darwin, support is reported by each volume rather than
+/// being looked up in a table so this is empty. It's also empty on all other
+/// platforms.
+///
+/// # Examples
+///
+/// ```
+/// for fs in renamore::known_filesystems() {
+///     println!("{} ({:?}) since {}: {:?}", fs.name, fs.magic, fs.min_version, fs.support);
+/// }
+/// ```
+pub fn known_filesystems() -> &'static [KnownFilesystem] {
+    #[cfg(any(
+        all(target_os = "linux", linker),
+        all(target_os = "windows", not(target_vendor = "uwp")),
+    ))]
+    return sys::KNOWN_FILESYSTEMS;
+
+    #[cfg(not(any(
+        all(target_os = "linux", linker),
+        all(target_os = "windows", not(target_vendor = "uwp")),
+    )))]
+    return &[];
 }
 
-#[cfg(all(target_os = "linux", linker))]
-mod linux;
-#[cfg(all(target_os = "linux", linker))]
-use linux as sys;
+/// Teach [`rename_exclusive_support`] about a file system.
+///
+/// The built-in tables only know about file systems that were around when this
+/// version of the crate was released. A predicate registered with this
+/// function is given the [`FsInfo`] of a path whenever support is determined
+/// and can return `Some` to override the tables or `None` to leave the
+/// decision to them. Predicates registered later are consulted first. This
+/// affects [`rename_exclusive_is_atomic`], [`rename_exclusive_atomic_support`]
+/// and [`capabilities`] as well. Registering a predicate clears the cache (see
+/// [`invalidate_caches`]).
+///
+/// Predicates are only consulted on platforms where [`fs_info`] is supported.
+///
+/// # Examples
+///
+/// ```no_run
+/// use renamore::Support;
+///
+/// // A FUSE file system that's known to honour RENAME_NOREPLACE.
+/// renamore::register_support_predicate(|info| {
+///     match info.name.as_deref() {
+///         Some("fuse.myfs") => Some(Support::Supported),
+///         _ => None,
+///     }
+/// });
+///
+/// // A file system that isn't in the table yet, identified by its magic
+/// // number.
+/// renamore::register_support_predicate(|info| {
+///     (info.magic == Some(0x12345678)).then_some(Support::Supported)
+/// });
+/// ```
+pub fn register_support_predicate<F>(predicate: F)
+where
+    F: Fn(&FsInfo) -> Option<Support> + Send + Sync + 'static,
+{
+    registry::register(std::sync::Arc::new(predicate));
+}
 
-#[cfg(target_vendor = "apple")]
-mod macos;
-#[cfg(target_vendor = "apple")]
-use macos as sys;
+/// Set process-wide defaults.
+///
+/// This is for programs that rename files from many places and can't pass
+/// [`RenameOptions`] to all of them. The [`Config`] sets the options that
+/// [`RenameOptions::new`], [`rename_exclusive`] and
+/// [`rename_exclusive_fallback`] start from, whether the non-atomic fallback
+/// may be used at all and the fallback hook (replacing any hook set with
+/// [`set_fallback_hook`]). Calling this again replaces the whole
+/// configuration, except for the hook if the new configuration doesn't have
+/// one.
+///
+/// Options that have already been created aren't affected so this should be
+/// called early, before anything is renamed.
+///
+/// # Examples
+///
+/// ```
+/// use renamore::{Config, RenameOptions};
+///
+/// let mut defaults = RenameOptions::new();
+/// defaults.durable(true);
+///
+/// renamore::configure(Config::new().defaults(defaults));
+/// ```
+pub fn configure(config: &Config) {
+    config::set(config.clone());
+}
 
-#[cfg(target_os = "windows")]
-mod windows;
-#[cfg(target_os = "windows")]
-use windows as sys;
+/// Set a function to call whenever a fallback is about to be used.
+///
+/// The hook is called by [`rename_exclusive_fallback`] before it uses the
+/// non-atomic fallback, before a path is copied across file systems (see
+/// [`RenameOptions::cross_device`]) and on Windows before `MoveFileExW` is used
+/// instead of `FileRenameInfoEx`. The [`FallbackEvent`] says which fallback it
+/// is, which paths are involved and the error that made it necessary. This
+/// makes it possible to notice when a program that's expected to rename
+/// atomically starts taking the racy path. There's only one hook for the whole
+/// process so this replaces any hook that was set before.
+///
+/// The hook is called on the thread doing the rename so it should be quick.
+///
+/// # Examples
+///
+/// ```
+/// use renamore::{Fallback, FallbackEvent};
+///
+/// fn alert(event: &FallbackEvent) {
+///     if event.fallback() == Fallback::NonAtomic {
+///         eprintln!("renamed {:?} non-atomically: {}", event.from(), event.reason());
+///     }
+/// }
+///
+/// renamore::set_fallback_hook(alert);
+/// ```
+pub fn set_fallback_hook(hook: fn(&FallbackEvent)) {
+    hook::set(Some(hook));
+}
+
+/// Remove the hook set with [`set_fallback_hook`].
+pub fn remove_fallback_hook() {
+    hook::set(None);
+}
+
+/// Set a function to receive an [`AuditRecord`] for every rename.
+///
+/// The sink is called after each call to [`rename_exclusive`],
+/// [`rename_exclusive_fallback`] or one of the rename methods on
+/// [`RenameOptions`] with the paths, whether it succeeded, how it was performed
+/// and who performed it. Failed renames are recorded too. There's only one
+/// sink for the whole process so this replaces any sink that was set before.
+///
+/// The sink is called on the thread doing the rename. A sink that writes to a
+/// file should handle its own errors since there's nowhere to report them.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::io::Write;
+/// use std::sync::Mutex;
+///
+/// let log = Mutex::new(std::fs::OpenOptions::new()
+///     .append(true)
+///     .create(true)
+///     .open("renames.jsonl")?);
+///
+/// renamore::set_audit_sink(move |record| {
+///     let mut log = log.lock().unwrap();
+///     let _ = serde_json::to_writer(&mut *log, record);
+///     let _ = writeln!(log);
+/// });
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(feature = "audit")]
+pub fn set_audit_sink<F>(sink: F)
+where
+    F: Fn(&AuditRecord) + Send + Sync + 'static,
+{
+    audit::set(Some(std::sync::Arc::new(sink)));
+}
+
+/// Remove the sink set with [`set_audit_sink`].
+#[cfg(feature = "audit")]
+pub fn remove_audit_sink() {
+    audit::set(None);
+}
+
+/// Forget the results cached by [`rename_exclusive_support`],
+/// [`rename_exclusive_atomic_support`], [`rename_exclusive_is_atomic`] and
+/// [`capabilities`].
+///
+/// Device numbers can be reused after a file system is unmounted so the cached
+/// result for one could end up being used for another. Call this after
+/// mounting or unmounting file systems in a long-running process. To only
+/// forget the result for one volume, use [`invalidate_cache`].
+pub fn invalidate_caches() {
+    cache::clear();
+}
+
+/// Forget the cached result for the volume that a path is on.
+///
+/// This is like [`invalidate_caches`] but only affects one volume. A daemon
+/// that watches for mount events can call this with the mount point so that
+/// the next query for a path under it asks the OS again. Like
+/// [`rename_exclusive_is_atomic`], the path doesn't need to exist.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// // Something has just been mounted here.
+/// renamore::invalidate_cache("/media/usb")?;
+///
+/// let atomic = renamore::rename_exclusive_is_atomic("/media/usb")?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn invalidate_cache<P: AsRef<Path>>(path: P) -> Result<()> {
+    cache::invalidate(existing_ancestor(path.as_ref()))
+}
+
+/// Determine what the file system at a path is capable of.
+///
+/// This is a more detailed version of [`rename_exclusive_is_atomic`] that's
+/// intended for tools that pick a strategy per volume.
+///
+/// # Platform-specific behaviour
+///
+/// On Windows, `GetVolumeInformationW` is used to determine the file system
+/// name, whether it supports POSIX rename semantics
+/// (`FILE_SUPPORTS_POSIX_UNLINK_RENAME`) and whether it supports block cloning
+/// (`FILE_SUPPORTS_BLOCK_REFCOUNTING`, which is the case for ReFS). Dev Drives
+/// are detected with `FSCTL_QUERY_PERSISTENT_VOLUME_STATE`. Volumes are
+/// assumed to be case insensitive. On Darwin, the kernel version is checked to
+/// determine whether `RENAME_NOFOLLOW_ANY` is supported and the volume
+/// capabilities from `getattrlist` determine support for exchanging, cloning
+/// and case sensitivity. On Linux, the file system type from a single call to
+/// `statfs` and the kernel version are compared against tables to determine
+/// support for exchanging, whiteouts, reflinks and case sensitivity. Overlay
+/// file systems are detected in the same way. Case insensitive directories on
+/// ext4 and f2fs aren't detected. The file system name and mount point are
+/// read from `/proc/self/mountinfo`. That gives names like `fuse.sshfs` rather
+/// than just `fuse`. On all other platforms, only
+/// [`Capabilities::atomic_exclusive`] is determined.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// let capabilities = renamore::capabilities(".")?;
+///
+/// if capabilities.dev_drive {
+///     println!("Building on a Dev Drive");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn capabilities<P: AsRef<Path>>(path: P) -> Result<Capabilities> {
+    capabilities::capabilities(path.as_ref())
+}
+
+/// Get the counters for the renames made by this process.
+///
+/// Only renames made through [`rename_exclusive`],
+/// [`rename_exclusive_fallback`] and [`RenameOptions`] are counted.
+///
+/// # Examples
+///
+/// ```
+/// let stats = renamore::stats();
+///
+/// if stats.fallbacks > 0 {
+///     println!("{} renames weren't atomic", stats.fallbacks);
+/// }
+/// ```
+#[cfg(feature = "stats")]
+pub fn stats() -> Stats {
+    stats::stats()
+}
+
+/// Determine what file system a path is on.
+///
+/// This is intended for reporting in diagnostics. It's the same information
+/// that's given by [`Reason::Filesystem`] when [`rename_exclusive`] isn't
+/// supported.
+///
+/// # Platform-specific behaviour
+///
+/// On Linux, the magic number is from `statfs` and the name is read from
+/// `/proc/self/mountinfo` (it's `None` if procfs isn't mounted). NFS, SMB, 9p,
+/// Ceph, AFS and Coda are considered network file systems. On Darwin, the name,
+/// `f_type` and `MNT_LOCAL` flag are from `statfs`. On Windows, the name is
+/// from `GetVolumeInformationW` and network shares are detected with
+/// `GetDriveTypeW`. There is no magic number. On all other platforms (and
+/// UWP targets), this returns [`ErrorKind::Unsupported`].
+///
+/// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// let info = renamore::fs_info(".")?;
+///
+/// println!("name: {:?}, magic: {:x?}, network: {}", info.name, info.magic, info.network);
+/// # Ok(())
+/// # }
+/// ```
+pub fn fs_info<P: AsRef<Path>>(path: P) -> Result<FsInfo> {
+    fs_info::fs_info(path.as_ref())
+}
+
+#[cfg(any(
+    all(target_os = "linux", linker),
+    target_vendor = "apple",
+    all(target_os = "windows", not(target_vendor = "uwp")),
+))]
+use sys as fs_info;
+
+#[cfg(not(any(
+    all(target_os = "linux", linker),
+    target_vendor = "apple",
+    all(target_os = "windows", not(target_vendor = "uwp")),
+)))]
+mod fs_info {
+    use std::path::Path;
+    use std::io::{Error, ErrorKind, Result};
+    use crate::FsInfo;
+
+    pub fn fs_info(_path: &Path) -> Result<FsInfo> {
+        Err(Error::from(ErrorKind::Unsupported))
+    }
+}
+
+#[cfg(any(
+    all(target_os = "linux", linker),
+    target_vendor = "apple",
+    all(target_os = "windows", not(target_vendor = "uwp")),
+))]
+use sys as fd;
+
+#[cfg(not(any(
+    all(target_os = "linux", linker),
+    target_vendor = "apple",
+    all(target_os = "windows", not(target_vendor = "uwp")),
+)))]
+mod fd {
+    use std::path::Path;
+    use std::fs::File;
+    use std::io::{Error, ErrorKind, Result};
+    use crate::{AtomicSupport, FsInfo};
+
+    // These backends don't look at the path.
+    pub fn atomic_support_fd(_dir: &File) -> Result<AtomicSupport> {
+        crate::sys::atomic_support(Path::new("."))
+    }
+
+    pub fn fs_info_fd(_dir: &File) -> Result<FsInfo> {
+        Err(Error::from(ErrorKind::Unsupported))
+    }
+}
+
+/// Rename a file without overwriting the destination path if it exists, using a
+/// non-atomic fallback if necessary.
+///
+/// This is similar to [`rename_exclusive`] except that if performing the
+/// operation atomically is not supported, then a non-atomic fallback
+/// implementation based on [`try_exists`] and [`rename`] will be used.
+///
+/// [`try_exists`]: std::path::Path::try_exists
+/// [`rename`]: std::fs::rename
+///
+/// # Platform-specific behaviour
+///
+/// On ESP-IDF, GNU Hurd and z/OS, the fallback reserves `to` by creating an
+/// empty file (or directory) there and then renames onto it. If the file system
+/// refuses to replace the placeholder, it is removed and the rename is
+/// attempted again.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// if renamore::rename_exclusive_fallback("old.txt", "new.txt")? {
+///     // `new.txt` was definitely not overwritten.
+///     println!("The operation was atomic");
+/// } else {
+///     // `new.txt` was probably not overwritten.
+///     println!("The operation was not atomic");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn rename_exclusive_fallback<F: AsRef<Path>, T: AsRef<Path>>(from: F, to: T) -> Result<bool> {
+    RenameOptions::new().rename_exclusive_fallback(from, to)
+}
+
+/// Atomically swap two files.
+///
+/// After this succeeds, the file that was at `a` is at `b` and vice versa.
+/// Both paths must exist. They can be any kind of file including directories.
+///
+/// # Platform-specific behaviour
+///
+/// On Linux, this calls `renameat2` with `RENAME_EXCHANGE`. On Darwin, this
+/// calls `renamex_np` with `RENAME_SWAP`. If the volume doesn't support that
+/// but does support `exchangedata` (like older HFS+ volumes), then that's used
+/// instead. `exchangedata` swaps the contents of the files rather than their
+/// names, and only works on regular files. On all other platforms, this
+/// returns [`ErrorKind::Unsupported`] unconditionally.
+///
+/// # Errors
+///
+/// If the operation isn't supported by the platform or the file system, then
+/// [`ErrorKind::Unsupported`] will be returned.
+///
+/// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+pub fn rename_exchange<A: AsRef<Path>, B: AsRef<Path>>(a: A, b: B) -> Result<()> {
+    let (a, b) = (a.as_ref(), b.as_ref());
+
+    check_path(a, "a")?;
+    check_path(b, "b")?;
+    exchange::rename_exchange(a, b)
+}
+
+/// Swap two files, returning a [`RenameError`] with both paths if it fails.
+///
+/// See [`rename_exchange`].
+pub fn rename_exchange_detailed<A: AsRef<Path>, B: AsRef<Path>>(
+    a: A,
+    b: B,
+) -> std::result::Result<(), RenameError> {
+    let (a, b) = (a.as_ref(), b.as_ref());
+
+    rename_exchange(a, b)
+        .map_err(|e| RenameError::new(RenameOperation::Exchange, a.into(), b.into(), e))
+}
+
+/// Validate a staged file and then atomically swap it with a live file.
+///
+/// `validate` is called with `staged` first. If it returns an error, then
+/// nothing is changed and that error is returned. Otherwise, the files are
+/// swapped with [`rename_exchange`] so that `live` has the new version and
+/// `staged` has the previous version. Swapping them again rolls back to the
+/// previous version.
+///
+/// If swapping isn't supported by the platform or the file system, then the
+/// previous version is kept at `live` with `.previous` appended (replacing an
+/// earlier one) and `staged` replaces `live`. On Windows, this is done with
+/// `ReplaceFileW`. Elsewhere, `live` is hard linked to the backup before the
+/// rename so the previous version is never missing.
+///
+/// If `live` doesn't exist, then `staged` is renamed to it with
+/// [`rename_exclusive`] instead. Returns where the previous version ended up,
+/// or `None` if there wasn't one.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// let check = |path: &std::path::Path| {
+///     let status = std::process::Command::new("nginx").arg("-t").arg("-c").arg(path).status()?;
+///     if status.success() {
+///         Ok(())
+///     } else {
+///         Err(std::io::Error::other("the configuration is invalid"))
+///     }
+/// };
+///
+/// if let Some(previous) = renamore::swap_in_validated("nginx.conf.new", "nginx.conf", check)? {
+///     println!("the previous version is at {}", previous.display());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// # Errors
+///
+/// If `live` exists, swapping isn't supported and the backup can't be made
+/// (like on a file system without hard links), then that error is returned and
+/// nothing is changed.
+pub fn swap_in_validated<S, L, V>(staged: S, live: L, validate: V) -> Result<Option<PathBuf>>
+where
+    S: AsRef<Path>,
+    L: AsRef<Path>,
+    V: FnOnce(&Path) -> Result<()>,
+{
+    let (staged, live) = (staged.as_ref(), live.as_ref());
+
+    check_path(staged, "staged")?;
+    check_path(live, "live")?;
+    validate(staged)?;
+
+    // Checking first means that the first install works even where swapping
+    // isn't supported. A dangling symlink still counts as a previous version.
+    let exists = |path: &Path| match path.symlink_metadata() {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    };
+
+    loop {
+        if exists(live)? {
+            let result = match rename_exchange(staged, live) {
+                Err(e) if e.kind() == ErrorKind::Unsupported => {
+                    let mut backup = live.as_os_str().to_owned();
+                    backup.push(".previous");
+                    let backup = PathBuf::from(backup);
+                    replace::replace_with_backup(staged, live, &backup).map(|()| backup)
+                }
+                result => result.map(|()| staged.to_path_buf()),
+            };
+
+            match result {
+                Ok(previous) => return Ok(Some(previous)),
+                Err(e) if e.kind() == ErrorKind::NotFound && !exists(live)? => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Something might create the live file in between.
+        match rename_exclusive(staged, live) {
+            Ok(()) => return Ok(None),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(all(target_os = "windows", not(target_vendor = "uwp")))]
+use sys as replace;
+
+#[cfg(not(all(target_os = "windows", not(target_vendor = "uwp"))))]
+mod replace {
+    use std::path::Path;
+    use std::io::{ErrorKind, Result};
+
+    // The backup is a second name for the live file so that the previous
+    // version is still there if the rename fails.
+    pub fn replace_with_backup(staged: &Path, live: &Path, backup: &Path) -> Result<()> {
+        match std::fs::remove_file(backup) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        std::fs::hard_link(live, backup)?;
+        std::fs::rename(staged, live)
+    }
+}
+
+#[cfg(any(all(target_os = "linux", linker), target_vendor = "apple"))]
+use sys as exchange;
+
+#[cfg(not(any(all(target_os = "linux", linker), target_vendor = "apple")))]
+mod exchange {
+    use std::path::Path;
+    use std::io::{Error, ErrorKind, Result};
+
+    pub fn rename_exchange(_a: &Path, _b: &Path) -> Result<()> {
+        Err(Error::from(ErrorKind::Unsupported))
+    }
+}
+
+/// Rename a file without overwriting the destination path if it exists and
+/// without leaving a directory.
+///
+/// `from` and `to` are relative to `root`. Neither path may escape `root`
+/// through `..`, an absolute path or a symlink. This makes it safe to rename
+/// files using paths from an untrusted source. The last component of each path
+/// is renamed rather than followed if it's a symlink.
+///
+/// # Platform-specific behaviour
+///
+/// On Linux, the parent directories are opened with `openat2` with
+/// `RESOLVE_BENEATH` and `RESOLVE_NO_SYMLINKS`, and then the rename is
+/// performed with `renameat2` relative to them. This requires Linux 5.6. On all
+/// other platforms, this returns [`ErrorKind::Unsupported`] unconditionally.
+///
+/// # Errors
+///
+/// If the operation isn't supported, then [`ErrorKind::Unsupported`] will be
+/// returned. If a path would escape `root`, then the error is `EXDEV`. If a
+/// path contains a symlink, then the error is `ELOOP`. If either path doesn't
+/// have a file name (like `..`), then [`ErrorKind::InvalidInput`] will be
+/// returned.
+///
+/// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+/// [`ErrorKind::InvalidInput`]: std::io::ErrorKind::InvalidInput
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use std::os::fd::AsFd;
+///
+/// let root = std::fs::File::open("uploads")?;
+/// renamore::rename_exclusive_beneath(root.as_fd(), "incoming/a.tmp", "a")?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(unix)]
+pub fn rename_exclusive_beneath<F: AsRef<Path>, T: AsRef<Path>>(
+    root: std::os::fd::BorrowedFd,
+    from: F,
+    to: T,
+) -> Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+
+    check_path(from, "from")?;
+    check_path(to, "to")?;
+    beneath::rename_exclusive_beneath(root, from, to)
+}
+
+#[cfg(all(target_os = "linux", linker))]
+use sys as beneath;
+
+#[cfg(all(unix, not(all(target_os = "linux", linker))))]
+mod beneath {
+    use std::path::Path;
+    use std::io::{Error, ErrorKind, Result};
+    use std::os::fd::BorrowedFd;
+
+    pub fn rename_exclusive_beneath(_root: BorrowedFd, _from: &Path, _to: &Path) -> Result<()> {
+        Err(Error::from(ErrorKind::Unsupported))
+    }
+}
+
+/// Rename a file without overwriting the destination path if it exists,
+/// without leaving a [`cap_std`] directory.
+///
+/// This is only implemented on Linux 5.6 and later. Everywhere else, it returns
+/// [`ErrorKind::Unsupported`] without renaming anything because cap-std
+/// doesn't offer a rename that leaves an existing destination alone.
+///
+/// `from` and `to` are relative to `dir`. This is [`rename_exclusive_beneath`]
+/// for programs that use cap-std to avoid ambient authority. Like the rest of
+/// cap-std, neither path may escape `dir`. This is only available with the
+/// `cap-std` feature.
+///
+/// # Platform-specific behaviour
+///
+/// This is the same as [`rename_exclusive_beneath`], so it requires Linux 5.6.
+/// On all other platforms, this returns [`ErrorKind::Unsupported`]
+/// unconditionally.
+///
+/// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use cap_std::{ambient_authority, fs::Dir};
+///
+/// let uploads = Dir::open_ambient_dir("uploads", ambient_authority())?;
+/// renamore::rename_exclusive_in(&uploads, "incoming/a.tmp", "a")?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "cap-std")]
+pub fn rename_exclusive_in<F: AsRef<Path>, T: AsRef<Path>>(
+    dir: &cap_std::fs::Dir,
+    from: F,
+    to: T,
+) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsFd;
+
+        rename_exclusive_beneath(dir.as_fd(), from, to)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (dir, from, to);
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Determine whether [`rename_exclusive_in`] is atomic on the volume of a
+/// [`cap_std`] directory.
+///
+/// This is [`rename_exclusive_is_atomic_fd`] for a `Dir`. This is only
+/// available with the `cap-std` feature.
+#[cfg(feature = "cap-std")]
+pub fn rename_exclusive_is_atomic_in(dir: &cap_std::fs::Dir) -> Result<bool> {
+    rename_exclusive_is_atomic_fd(&dir.try_clone()?.into_std_file())
+}
+
+/// Schedule a file to be renamed the next time the system starts.
+///
+/// This is intended for installers and updaters that need to replace files
+/// that are in use. The rename happens before anything has a chance to open
+/// the files. If a file exists at `to` at that point, it's replaced. To
+/// schedule `from` to be deleted instead, see [`delete_at_reboot`].
+///
+/// # Platform-specific behaviour
+///
+/// On Windows, this calls `MoveFileExW` with `MOVEFILE_DELAY_UNTIL_REBOOT` and
+/// `MOVEFILE_REPLACE_EXISTING`. This requires administrator privileges. The
+/// paths are made absolute before they are stored. On all other platforms (and
+/// UWP targets), this returns [`ErrorKind::Unsupported`] unconditionally.
+///
+/// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+pub fn rename_at_reboot<F: AsRef<Path>, T: AsRef<Path>>(from: F, to: T) -> Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+
+    check_path(from, "from")?;
+    check_path(to, "to")?;
+    desktop::rename_at_reboot(from, Some(to))
+}
+
+/// Schedule a file to be deleted the next time the system starts.
+///
+/// This is the same as [`rename_at_reboot`] except that the file is deleted
+/// rather than renamed. Directories are only deleted if they're empty at that
+/// point.
+///
+/// # Platform-specific behaviour
+///
+/// On Windows, this calls `MoveFileExW` with a null destination and
+/// `MOVEFILE_DELAY_UNTIL_REBOOT`. On all other platforms (and UWP targets),
+/// this returns [`ErrorKind::Unsupported`] unconditionally.
+///
+/// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+pub fn delete_at_reboot<P: AsRef<Path>>(path: P) -> Result<()> {
+    check_path(path.as_ref(), "path")?;
+    desktop::rename_at_reboot(path.as_ref(), None)
+}
+
+/// Rename an alternate data stream of a file without overwriting another stream
+/// with the same name.
+///
+/// The stream named `from` of the file at `path` is renamed to `to`. Stream
+/// names may be given with or without the leading colon (`":tag"` or `"tag"`).
+/// If the file already has a stream named `to`, then
+/// [`ErrorKind::AlreadyExists`] will be returned. A stream name that's empty,
+/// isn't valid Unicode, or contains a NUL byte, a slash or another colon is
+/// rejected with [`ErrorKind::InvalidInput`] on every platform.
+///
+/// # Platform-specific behaviour
+///
+/// On Windows, this calls `SetFileInformationByHandle` with `FileRenameInfo`
+/// on a handle to the stream. Only NTFS (and ReFS since Windows Server 2012 R2)
+/// supports alternate data streams. On all other platforms (and UWP targets),
+/// this returns [`ErrorKind::Unsupported`] unconditionally.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// renamore::rename_stream_exclusive("photo.jpg", "tags", "tags.old")?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`ErrorKind::AlreadyExists`]: std::io::ErrorKind::AlreadyExists
+/// [`ErrorKind::InvalidInput`]: std::io::ErrorKind::InvalidInput
+/// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+pub fn rename_stream_exclusive<P, F, T>(path: P, from: F, to: T) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: AsRef<OsStr>,
+    T: AsRef<OsStr>,
+{
+    check_path(path.as_ref(), "path")?;
+    let from = check_stream_name(from.as_ref(), "from")?;
+    let to = check_stream_name(to.as_ref(), "to")?;
+    desktop::rename_stream_exclusive(path.as_ref(), from, to)
+}
+
+// Everything that only the desktop Windows backend implements.
+#[cfg(all(target_os = "windows", not(target_vendor = "uwp")))]
+use windows as desktop;
+
+#[cfg(not(all(target_os = "windows", not(target_vendor = "uwp"))))]
+mod desktop {
+    use std::path::Path;
+    use std::io::{Error, ErrorKind, Result};
+
+    pub fn rename_at_reboot(_from: &Path, _to: Option<&Path>) -> Result<()> {
+        Err(Error::from(ErrorKind::Unsupported))
+    }
+
+    pub fn rename_stream_exclusive(_path: &Path, _from: &str, _to: &str) -> Result<()> {
+        Err(Error::from(ErrorKind::Unsupported))
+    }
+}
+
+fn rename_if_absent(from: &Path, to: &Path) -> Result<()> {
+    if retry_interrupted_io(|| to.try_exists())? {
+        return Err(Error::from(ErrorKind::AlreadyExists));
+    }
+
+    std::fs::rename(from, to)
+}
+
+#[cfg(not(any(target_os = "espidf", target_os = "hurd", target_os = "zos")))]
+use rename_if_absent as rename_exclusive_non_atomic;
+
+// None of the file systems on ESP-IDF (FAT, SPIFFS and LittleFS) can rename
+// without replacing and z/OS doesn't have an exclusive rename. glibc on the
+// Hurd implements RENAME_NOREPLACE by passing excl to dir_rename, but nothing
+// calls it yet. These use the stub sys module and emulate the rename by
+// reserving the destination first. The emulation only goes through std::fs so
+// z/OS paths are given to the C runtime as-is.
+#[cfg(any(target_os = "espidf", target_os = "hurd", target_os = "zos"))]
+use posix::rename_exclusive_non_atomic;
+
+// A signal arriving during a slow system call (like a statfs on NFS) makes it
+// fail with EINTR. That's not something the caller can do anything about so
+// the call is retried. This returns whatever the last call returned so that
+// errno can be checked as usual. rustix has its own retry_on_intr.
+//
+// This is only for calls that can safely be repeated, like statfs, xattr reads
+// and ioctl queries. Renames are never retried. On NFS, EINTR can come back
+// after the server has already done the rename. Repeating it would then fail
+// with EEXIST (RENAME_NOREPLACE) or swap the files back (RENAME_EXCHANGE) so
+// the caller gets ErrorKind::Interrupted instead.
+#[cfg(any(
+    all(target_os = "linux", linker, not(feature = "rustix")),
+    target_vendor = "apple",
+    target_os = "cygwin",
+))]
+fn retry_interrupted<T: Copy + PartialEq + From<i8>>(mut f: impl FnMut() -> T) -> T {
+    loop {
+        let ret = f();
+
+        if ret != T::from(-1) || Error::last_os_error().kind() != ErrorKind::Interrupted {
+            return ret;
+        }
+    }
+}
+
+// The same for std functions, which don't retry on EINTR themselves.
+fn retry_interrupted_io<T>(mut f: impl FnMut() -> Result<T>) -> Result<T> {
+    loop {
+        match f() {
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            result => return result,
+        }
+    }
+}
+
+mod posix;
+
+#[cfg(all(target_os = "linux", linker))]
+mod linux;
+#[cfg(all(target_os = "linux", linker))]
+mod mountinfo;
+#[cfg(all(target_os = "linux", linker))]
+use linux as sys;
+
+#[cfg(target_vendor = "apple")]
+mod macos;
+#[cfg(target_vendor = "apple")]
+use macos as sys;
+
+#[cfg(target_os = "windows")]
+mod windows;
+#[cfg(target_os = "windows")]
+use windows as sys;
+
+#[cfg(all(feature = "txf", target_os = "windows", not(target_vendor = "uwp")))]
+mod txf;
+
+#[cfg(all(target_os = "windows", not(target_vendor = "uwp"), feature = "etw"))]
+mod etw;
+
+#[cfg(target_os = "cygwin")]
+mod cygwin;
+#[cfg(target_os = "cygwin")]
+use cygwin as sys;
 
 #[cfg(not(any(
     all(target_os = "linux", linker),
     target_vendor = "apple",
     target_os = "windows",
+    target_os = "cygwin",
 )))]
 mod sys {
     use std::path::Path;
     use std::io::{Error, ErrorKind, Result};
+    use crate::{AtomicSupport, Reason, RenameOptions};
 
-    pub fn rename_exclusive(_from: &Path, _to: &Path) -> Result<()> {
+    pub fn rename_exclusive(_from: &Path, _to: &Path, _options: &RenameOptions) -> Result<()> {
         Err(Error::from(ErrorKind::Unsupported))
     }
 
-    pub fn rename_exclusive_is_atomic(_path: &Path) -> Result<bool> {
-        Ok(false)
+    pub fn atomic_support(_path: &Path) -> Result<AtomicSupport> {
+        Ok(AtomicSupport::No(Reason::Unavailable))
     }
 }
 
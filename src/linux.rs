@@ -1,10 +1,43 @@
 use std::path::Path;
+use std::fs::File;
 use std::io::{Error, ErrorKind, Result};
-use std::ffi::{c_char, c_int, c_uint, CString};
+use std::ffi::{c_uint, CStr, OsStr};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+#[cfg(not(feature = "rustix"))]
+use std::ffi::{c_char, c_int, CString};
 use std::os::unix::prelude::OsStrExt;
+#[cfg(not(feature = "rustix"))]
+use std::os::fd::FromRawFd;
+#[cfg(renameat2_dynamic)]
+use std::{ffi::c_void, sync::OnceLock};
+#[cfg(all(
+    feature = "libc",
+    not(feature = "rustix"),
+    not(all(target_env = "gnu", target_pointer_width = "32")),
+))]
+use libc::{fstatfs, statfs};
+#[cfg(all(
+    feature = "libc",
+    not(feature = "rustix"),
+    target_env = "gnu",
+    target_pointer_width = "32",
+))]
+use libc::{fstatfs64 as fstatfs, statfs64 as statfs};
+use crate::{AtomicSupport, Capabilities, FsInfo, KnownFilesystem, Reason, RenameOptions};
+use crate::{parent, Support, Version};
 
-// Linking will fail with glibc versions prior to 2.28.
+// Linking will fail with glibc versions prior to 2.28 unless the syscall is
+// made directly. With glibc, the wrapper is looked up at runtime and the
+// syscall is made directly if it's missing. With the rustix feature, rustix
+// makes all of the syscalls instead. With the libc feature, the declarations
+// come from the libc crate.
 
+#[cfg(not(any(
+    renameat2_syscall,
+    renameat2_dynamic,
+    feature = "rustix",
+    feature = "libc",
+)))]
 extern "C" {
     fn renameat2(
         olddirfd: c_int,
@@ -15,163 +48,1146 @@ extern "C" {
     ) -> c_int;
 }
 
-const AT_FDCWD: c_int = -100;
-const RENAME_NOREPLACE: c_uint = 1;
-// const RENAME_EXCHANGE: c_uint = 2;
+#[cfg(all(renameat2_syscall, not(any(feature = "rustix", feature = "libc"))))]
+use syscall::renameat2;
 
-pub fn rename_exclusive(from: &Path, to: &Path) -> Result<()> {
+// The syscall is always made directly because libc::renameat2 is only declared
+// for glibc.
+#[cfg(all(feature = "libc", not(feature = "rustix")))]
+unsafe fn renameat2(
+    olddirfd: c_int,
+    oldpath: *const c_char,
+    newdirfd: c_int,
+    newpath: *const c_char,
+    flags: c_uint,
+) -> c_int {
+    libc::syscall(libc::SYS_renameat2, olddirfd, oldpath, newdirfd, newpath, flags) as c_int
+}
+
+#[cfg(renameat2_dynamic)]
+extern "C" {
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+#[cfg(renameat2_dynamic)]
+const RTLD_DEFAULT: *mut c_void = std::ptr::null_mut();
+
+#[cfg(renameat2_dynamic)]
+type Renameat2 = unsafe extern "C" fn(
+    olddirfd: c_int,
+    oldpath: *const c_char,
+    newdirfd: c_int,
+    newpath: *const c_char,
+    flags: c_uint,
+) -> c_int;
+
+#[cfg(renameat2_dynamic)]
+fn get_renameat2() -> Option<Renameat2> {
+    static RENAMEAT2: OnceLock<Option<Renameat2>> = OnceLock::new();
+
+    *RENAMEAT2.get_or_init(|| {
+        let ptr = unsafe { dlsym(RTLD_DEFAULT, c"renameat2".as_ptr()) };
+        if !ptr.is_null() {
+            return Some(unsafe { std::mem::transmute::<*mut c_void, Renameat2>(ptr) });
+        }
+
+        #[cfg(syscall_table)]
+        return Some(syscall::renameat2);
+        #[cfg(not(syscall_table))]
+        return None;
+    })
+}
+
+#[cfg(all(
+    any(renameat2_syscall, all(renameat2_dynamic, syscall_table), all(test, syscall_table)),
+    not(any(feature = "rustix", feature = "libc")),
+))]
+pub(crate) mod syscall {
+    use std::ffi::{c_char, c_int, c_long, c_uint};
+
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+    }
+
+    // This must be kept in sync with the list of architectures in build.rs.
+
+    #[cfg(all(target_arch = "x86_64", target_pointer_width = "64"))]
+    const SYS_RENAMEAT2: c_long = 316;
+    // x32 uses the x86_64 table with __X32_SYSCALL_BIT set.
+    #[cfg(all(target_arch = "x86_64", target_pointer_width = "32"))]
+    const SYS_RENAMEAT2: c_long = 0x40000000 + 316;
+    #[cfg(target_arch = "x86")]
+    const SYS_RENAMEAT2: c_long = 353;
+    #[cfg(target_arch = "arm")]
+    const SYS_RENAMEAT2: c_long = 382;
+    // These use the generic table from asm-generic/unistd.h.
+    #[cfg(any(
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "loongarch64",
+    ))]
+    const SYS_RENAMEAT2: c_long = 276;
+    #[cfg(target_arch = "s390x")]
+    const SYS_RENAMEAT2: c_long = 347;
+    #[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+    const SYS_RENAMEAT2: c_long = 357;
+
+    pub unsafe extern "C" fn renameat2(
+        olddirfd: c_int,
+        oldpath: *const c_char,
+        newdirfd: c_int,
+        newpath: *const c_char,
+        flags: c_uint,
+    ) -> c_int {
+        // The arguments are widened because syscall reads them as longs.
+        syscall(
+            SYS_RENAMEAT2,
+            olddirfd as c_long,
+            oldpath,
+            newdirfd as c_long,
+            newpath,
+            flags as c_long,
+        ) as c_int
+    }
+}
+
+#[cfg(not(feature = "rustix"))]
+pub(crate) const AT_FDCWD: c_int = -100;
+pub(crate) const RENAME_NOREPLACE: c_uint = 1;
+const RENAME_EXCHANGE: c_uint = 2;
+
+#[cfg(not(feature = "rustix"))]
+const CWD: BorrowedFd<'static> = unsafe { BorrowedFd::borrow_raw(AT_FDCWD) };
+
+#[cfg(feature = "rustix")]
+use rustix::fs::CWD;
+
+#[cfg(not(feature = "rustix"))]
+fn renameat2_at(
+    from_dir: BorrowedFd,
+    from: &Path,
+    to_dir: BorrowedFd,
+    to: &Path,
+    flags: c_uint,
+) -> Result<()> {
+    #[cfg(renameat2_dynamic)]
+    let renameat2 = get_renameat2().ok_or(ErrorKind::Unsupported)?;
     let from_str = CString::new(from.as_os_str().as_bytes())?;
     let to_str = CString::new(to.as_os_str().as_bytes())?;
+    // This isn't retried on EINTR. See retry_interrupted.
     let ret = unsafe {
-        renameat2(AT_FDCWD, from_str.as_ptr(), AT_FDCWD, to_str.as_ptr(), RENAME_NOREPLACE)
+        renameat2(
+            from_dir.as_raw_fd(),
+            from_str.as_ptr(),
+            to_dir.as_raw_fd(),
+            to_str.as_ptr(),
+            flags,
+        )
     };
 
     if ret == -1 {
-        let error = Error::last_os_error();
-        // EINVAL is returned if `flags` is invalid or the file system doesn't
-        // support the operation.
-        if error.kind() == ErrorKind::InvalidInput {
-            Err(Error::from(ErrorKind::Unsupported))
-        } else {
-            Err(error)
-        }
+        Err(Error::last_os_error())
     } else {
         Ok(())
     }
 }
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
-struct Version(u64);
+#[cfg(feature = "rustix")]
+fn renameat2_at(
+    from_dir: BorrowedFd,
+    from: &Path,
+    to_dir: BorrowedFd,
+    to: &Path,
+    flags: c_uint,
+) -> Result<()> {
+    use rustix::fs::{renameat_with, RenameFlags};
+
+    // This isn't retried on EINTR. See retry_interrupted.
+    Ok(renameat_with(from_dir, from, to_dir, to, RenameFlags::from_bits_retain(flags))?)
+}
 
-impl Version {
-    const fn new(major: u16, minor: u16, patch: u16) -> Self {
-        Self(((major as u64) << 32) | ((minor as u64) << 16) | patch as u64)
+fn rename_with_flags(from: &Path, to: &Path, flags: c_uint) -> Result<()> {
+    crate::instrument::syscall("renameat2", flags);
+    renameat2_at(CWD, from, CWD, to, flags)
+        .map_err(|e| normalize_error(e, || flag_supported(parent(to), flags)))
+}
+
+const EINVAL: i32 = 22;
+
+// ENOSYS is returned if the kernel predates renameat2 (Linux 3.15) or a seccomp
+// filter doesn't know about it. EINVAL is returned if the file system doesn't
+// support the flag, but also for mistakes like moving a directory into itself.
+// It's only made into Unsupported if the file system isn't known to support the
+// flag. The original error is kept as the source.
+pub fn normalize_error(error: Error, supported: impl FnOnce() -> bool) -> Error {
+    match error.raw_os_error() {
+        Some(EINVAL) if supported() => error,
+        Some(_) if matches!(error.kind(), ErrorKind::InvalidInput | ErrorKind::Unsupported) => {
+            Error::new(ErrorKind::Unsupported, error)
+        }
+        _ => error,
+    }
+}
+
+fn flag_supported(dir: &Path, flags: c_uint) -> bool {
+    if flags & RENAME_EXCHANGE != 0 {
+        let mut capabilities = Capabilities::default();
+        volume_capabilities(dir, &mut capabilities).is_ok() && capabilities.exchange
+    } else {
+        matches!(atomic_support(dir), Ok(AtomicSupport::Yes))
+    }
+}
+
+const EPERM: i32 = 1;
+
+pub fn rename_exclusive(from: &Path, to: &Path, options: &RenameOptions) -> Result<()> {
+    match rename_with_flags(from, to, RENAME_NOREPLACE) {
+        // A seccomp filter that doesn't know about renameat2 might return
+        // EPERM. This is indistinguishable from a genuine EPERM so it's
+        // checked before looking for an immutable file.
+        Err(e) if options.eperm_unsupported && e.raw_os_error() == Some(EPERM) => {
+            Err(Error::new(ErrorKind::Unsupported, e))
+        }
+        result => result.map_err(|e| read_only_error(boundary_error(e, from, to), from, to)),
+    }
+}
+
+pub fn rename_exchange(a: &Path, b: &Path) -> Result<()> {
+    rename_with_flags(a, b, RENAME_EXCHANGE)
+}
+
+pub fn rename_exclusive_beneath(root: BorrowedFd, from: &Path, to: &Path) -> Result<()> {
+    let (from_dir, from_name) = open_parent_beneath(root, from)?;
+    let (to_dir, to_name) = open_parent_beneath(root, to)?;
+
+    crate::instrument::syscall("renameat2", RENAME_NOREPLACE);
+    renameat2_at(
+        from_dir.as_fd(),
+        Path::new(from_name),
+        to_dir.as_fd(),
+        Path::new(to_name),
+        RENAME_NOREPLACE,
+    ).map_err(|e| {
+        let dir = File::from(to_dir);
+        normalize_error(e, || matches!(atomic_support_fd(&dir), Ok(AtomicSupport::Yes)))
+    })
+}
+
+// Renaming between btrfs subvolumes or across a bind mount fails with EXDEV
+// even though it's the same file system. A bind mount has the same st_dev as
+// where it came from. Each btrfs subvolume has its own st_dev but the mount
+// table has the device of the file system that they're all part of. Anything
+// else really is a different file system, so the original error is returned.
+pub fn boundary_error(error: Error, from: &Path, to: &Path) -> Error {
+    if error.kind() != ErrorKind::CrossesDevices {
+        return error;
+    }
+
+    match is_boundary(parent(from), parent(to)) {
+        Ok(true) => Error::new(ErrorKind::CrossesDevices, crate::BoundaryError::new(error)),
+        _ => error,
     }
 }
 
-fn get_kernel_version() -> Result<Version> {
-    let version = std::fs::read_to_string("/proc/version")?;
-    let version_bytes = version.as_bytes();
+fn is_boundary(from: &Path, to: &Path) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
 
-    let major_begin = version_bytes.iter()
-        .position(|c| c.is_ascii_digit())
-        .ok_or(ErrorKind::InvalidData)?;
-    let major_end = major_begin + version_bytes[major_begin..].iter()
-        .position(|c| *c == b'.')
-        .ok_or(ErrorKind::InvalidData)?;
+    if from.metadata()?.dev() == to.metadata()?.dev() {
+        return Ok(true);
+    }
 
-    if major_end == version_bytes.len() - 1 {
-        return Err(ErrorKind::InvalidData.into());
+    match (crate::mountinfo::find(from)?, crate::mountinfo::find(to)?) {
+        (Some(from), Some(to)) => Ok(from.device == to.device),
+        _ => Ok(false),
     }
+}
 
-    let minor_begin = major_end + 1;
-    let minor_end = minor_begin + version_bytes[minor_begin..].iter()
-        .position(|c| *c == b'.')
-        .ok_or(ErrorKind::InvalidData)?;
+// EROFS and EPERM don't say which path is the problem. A read-only mount or a
+// file or directory with the immutable or append-only attribute are the usual
+// culprits. An EPERM from a seccomp filter is left alone.
+pub fn read_only_error(error: Error, from: &Path, to: &Path) -> Error {
+    if !matches!(error.kind(), ErrorKind::ReadOnlyFilesystem | ErrorKind::PermissionDenied) {
+        return error;
+    }
 
-    if minor_end == version_bytes.len() - 1 {
-        return Err(ErrorKind::InvalidData.into());
+    for path in [parent(from), parent(to)] {
+        if is_read_only_mount(path) {
+            return crate::ReadOnlyCause::Mount(path.to_path_buf()).into_error(error);
+        }
     }
 
-    let patch_begin = minor_end + 1;
-    let patch_end = patch_begin + version_bytes[patch_begin..].iter()
-        .position(|c| !c.is_ascii_digit())
-        .ok_or(ErrorKind::InvalidData)?;
+    for path in [from, parent(from), parent(to)] {
+        if is_immutable(path) {
+            return crate::ReadOnlyCause::Immutable(path.to_path_buf()).into_error(error);
+        }
+    }
 
-    let major = version[major_begin..major_end].parse()
-        .map_err(|_| ErrorKind::InvalidData)?;
-    let minor = version[minor_begin..minor_end].parse()
-        .map_err(|_| ErrorKind::InvalidData)?;
-    let patch = version[patch_begin..patch_end].parse()
-        .map_err(|_| ErrorKind::InvalidData)?;
+    error
+}
 
-    Ok(Version::new(major, minor, patch))
+fn is_read_only_mount(path: &Path) -> bool {
+    match crate::mountinfo::find(path) {
+        Ok(Some(mount)) => mount.options.iter().any(|option| option == "ro"),
+        _ => false,
+    }
 }
 
+const FS_IMMUTABLE_FL: u32 = 0x10;
+const FS_APPEND_FL: u32 = 0x20;
+
+fn is_immutable(path: &Path) -> bool {
+    // Opening anything other than a file or directory might block (or have
+    // side effects) and a symlink would be followed.
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_file() || metadata.is_dir() => {}
+        _ => return false,
+    }
+
+    match File::open(path).and_then(|file| get_inode_flags(file.as_fd())) {
+        Ok(flags) => flags & (FS_IMMUTABLE_FL | FS_APPEND_FL) != 0,
+        Err(_) => false,
+    }
+}
+
+// renameat2 doesn't follow a symlink in the last component so only the parent
+// needs to be resolved. Path::file_name rejects a trailing `..`.
+fn open_parent_beneath<'a>(root: BorrowedFd, path: &'a Path) -> Result<(OwnedFd, &'a OsStr)> {
+    let name = path.file_name().ok_or(ErrorKind::InvalidInput)?;
+
+    Ok((openat2_beneath(root, parent(path))?, name))
+}
+
+const RESOLVE_NO_SYMLINKS: u64 = 0x04;
+const RESOLVE_BENEATH: u64 = 0x08;
+
+// O_PATH and O_CLOEXEC have the same values on every architecture in the
+// syscall table and so does the syscall number.
+#[cfg(all(syscall_table, not(any(feature = "rustix", feature = "libc"))))]
+fn openat2_beneath(root: BorrowedFd, path: &Path) -> Result<OwnedFd> {
+    use std::ffi::c_long;
+
+    extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+    }
+
+    #[repr(C)]
+    struct OpenHow {
+        flags: u64,
+        mode: u64,
+        resolve: u64,
+    }
+
+    const SYS_OPENAT2: c_long = 437;
+    const O_PATH: u64 = 0o10000000;
+    const O_CLOEXEC: u64 = 0o2000000;
+
+    let path_str = CString::new(path.as_os_str().as_bytes())?;
+    let how = OpenHow {
+        flags: O_PATH | O_CLOEXEC,
+        mode: 0,
+        resolve: RESOLVE_BENEATH | RESOLVE_NO_SYMLINKS,
+    };
+    let ret = crate::retry_interrupted(|| unsafe {
+        syscall(
+            SYS_OPENAT2,
+            root.as_raw_fd() as c_long,
+            path_str.as_ptr(),
+            &how as *const OpenHow,
+            std::mem::size_of::<OpenHow>(),
+        )
+    });
+
+    if ret == -1 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(unsafe { OwnedFd::from_raw_fd(ret as c_int) })
+    }
+}
+
+#[cfg(all(not(syscall_table), not(any(feature = "rustix", feature = "libc"))))]
+fn openat2_beneath(_root: BorrowedFd, _path: &Path) -> Result<OwnedFd> {
+    Err(Error::from(ErrorKind::Unsupported))
+}
+
+#[cfg(all(feature = "libc", not(feature = "rustix")))]
+fn openat2_beneath(root: BorrowedFd, path: &Path) -> Result<OwnedFd> {
+    let path_str = CString::new(path.as_os_str().as_bytes())?;
+    // open_how is non-exhaustive so it can't be constructed directly.
+    let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+    how.flags = (libc::O_PATH | libc::O_CLOEXEC) as u64;
+    how.resolve = RESOLVE_BENEATH | RESOLVE_NO_SYMLINKS;
+    let ret = crate::retry_interrupted(|| unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            root.as_raw_fd(),
+            path_str.as_ptr(),
+            &how as *const libc::open_how,
+            std::mem::size_of::<libc::open_how>(),
+        )
+    });
+
+    if ret == -1 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(unsafe { OwnedFd::from_raw_fd(ret as c_int) })
+    }
+}
+
+#[cfg(feature = "rustix")]
+fn openat2_beneath(root: BorrowedFd, path: &Path) -> Result<OwnedFd> {
+    use rustix::fs::{openat2, Mode, OFlags, ResolveFlags};
+    use rustix::io::retry_on_intr;
+
+    Ok(retry_on_intr(|| openat2(
+        root,
+        path,
+        OFlags::PATH | OFlags::CLOEXEC,
+        Mode::empty(),
+        ResolveFlags::from_bits_retain(RESOLVE_BENEATH | RESOLVE_NO_SYMLINKS),
+    ))?)
+}
+
+// Unlike /proc/version, uname doesn't depend on procfs being mounted.
+
+#[cfg(not(any(feature = "rustix", feature = "libc")))]
+fn get_kernel() -> Result<Kernel> {
+    #[repr(C)]
+    struct utsname {
+        sysname: [c_char; 65],
+        nodename: [c_char; 65],
+        release: [c_char; 65],
+        version: [c_char; 65],
+        machine: [c_char; 65],
+        domainname: [c_char; 65],
+    }
+
+    extern "C" {
+        fn uname(buf: *mut utsname) -> c_int;
+    }
+
+    let mut buf = std::mem::MaybeUninit::<utsname>::uninit();
+
+    if unsafe { uname(buf.as_mut_ptr()) } == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    let buf = unsafe { buf.assume_init() };
+    parse_kernel_release(unsafe { CStr::from_ptr(buf.release.as_ptr()) })
+}
+
+#[cfg(all(feature = "libc", not(feature = "rustix")))]
+fn get_kernel() -> Result<Kernel> {
+    let mut buf: libc::utsname = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::uname(&mut buf) } == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    parse_kernel_release(unsafe { CStr::from_ptr(buf.release.as_ptr()) })
+}
+
+#[cfg(feature = "rustix")]
+fn get_kernel() -> Result<Kernel> {
+    parse_kernel_release(rustix::system::uname().release())
+}
+
+// Some sandboxes report a made up release (or none at all). Whether the kernel
+// has renameat2 can still be found out by calling it with paths that can't
+// exist. Without knowing anything more specific, only the file systems that
+// supported it from the start are considered supported.
+fn probe_kernel_version() -> Option<Version> {
+    match renameat2_at(CWD, Path::new(""), CWD, Path::new(""), RENAME_NOREPLACE) {
+        Err(e) if e.kind() == ErrorKind::Unsupported => None,
+        _ => Some(Version::new(3, 15, 0)),
+    }
+}
+
+pub(crate) struct Kernel {
+    pub(crate) version: Version,
+    // WSL 1 translates syscalls for the NT kernel. The version it reports is
+    // the version of Linux it's imitating.
+    pub(crate) wsl1: bool,
+}
+
+pub(crate) fn parse_kernel_release(release: &CStr) -> Result<Kernel> {
+    let release = release.to_str().map_err(|_| ErrorKind::InvalidData)?;
+
+    Ok(Kernel {
+        version: release.parse().map_err(|e| Error::new(ErrorKind::InvalidData, e))?,
+        wsl1: is_wsl1(release),
+    })
+}
+
+// WSL 1 releases look like 4.4.0-19041-Microsoft. WSL 2 runs a real kernel with
+// releases like 5.15.153.1-microsoft-standard-WSL2.
+pub(crate) fn is_wsl1(release: &str) -> bool {
+    release.ends_with("-Microsoft")
+}
+
+// f_type is the first member of struct statfs on every architecture but its
+// width varies. It's an int on 32-bit targets (and in statfs64) and on s390x,
+// and a long everywhere else. Reading it as a c_uint would only see the upper
+// half on big-endian 64-bit targets.
+#[cfg(all(
+    not(any(feature = "rustix", feature = "libc")),
+    any(
+        target_arch = "s390x",
+        all(target_pointer_width = "32", not(target_arch = "x86_64")),
+    ),
+))]
+type FsWord = u32;
+
+#[cfg(all(
+    not(any(feature = "rustix", feature = "libc")),
+    not(any(
+        target_arch = "s390x",
+        all(target_pointer_width = "32", not(target_arch = "x86_64")),
+    )),
+))]
+type FsWord = u64;
+
+#[cfg(not(any(feature = "rustix", feature = "libc")))]
 #[repr(C)]
 struct statfs {
-    f_type: c_uint,
-    // We don't care about the rest.
+    f_type: FsWord,
+    // We don't care about the rest. This is larger than struct statfs64 on
+    // every architecture.
     padding: [u64; 16],
 }
 
+#[cfg(not(any(feature = "rustix", feature = "libc")))]
 extern "C" {
+    // On 32-bit glibc, statfs uses 32-bit block counts and fails with
+    // EOVERFLOW on large file systems. musl only has the LFS layout.
+    #[cfg_attr(
+        all(target_env = "gnu", target_pointer_width = "32"),
+        link_name = "statfs64",
+    )]
     fn statfs(path: *const c_char, buf: *mut statfs) -> c_int;
+    #[cfg_attr(
+        all(target_env = "gnu", target_pointer_width = "32"),
+        link_name = "fstatfs64",
+    )]
+    fn fstatfs(fd: c_int, buf: *mut statfs) -> c_int;
 }
 
-fn get_filesystem_type(path: &Path) -> Result<u32> {
+#[cfg(not(feature = "rustix"))]
+pub(crate) fn get_filesystem_type(path: &Path) -> Result<u32> {
     let path_str = CString::new(path.as_os_str().as_bytes())?;
     let mut buf = std::mem::MaybeUninit::<statfs>::uninit();
-    let ret = unsafe { statfs(path_str.as_ptr(), buf.as_mut_ptr()) };
+    let ret = crate::retry_interrupted(|| unsafe { statfs(path_str.as_ptr(), buf.as_mut_ptr()) });
 
     if ret == -1 {
         return Err(Error::last_os_error());
     }
 
-    Ok(unsafe { buf.assume_init() }.f_type)
+    // The width of f_type depends on the architecture in libc::statfs.
+    #[allow(clippy::unnecessary_cast)]
+    Ok(unsafe { buf.assume_init() }.f_type as u32)
+}
+
+#[cfg(feature = "rustix")]
+pub(crate) fn get_filesystem_type(path: &Path) -> Result<u32> {
+    // The width of f_type depends on the architecture but the magic numbers
+    // all fit in 32 bits.
+    #[allow(clippy::unnecessary_cast)]
+    Ok(rustix::io::retry_on_intr(|| rustix::fs::statfs(path))?.f_type as u32)
 }
 
-const FS_EXT4: c_uint = 0xef53; // EXT4_SUPER_MAGIC
-const FS_BTRFS: [c_uint; 2] = [
+#[cfg(not(feature = "rustix"))]
+fn get_filesystem_type_fd(fd: BorrowedFd) -> Result<u32> {
+    let mut buf = std::mem::MaybeUninit::<statfs>::uninit();
+    let ret = crate::retry_interrupted(|| unsafe { fstatfs(fd.as_raw_fd(), buf.as_mut_ptr()) });
+
+    if ret == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    Ok(unsafe { buf.assume_init() }.f_type as u32)
+}
+
+#[cfg(feature = "rustix")]
+fn get_filesystem_type_fd(fd: BorrowedFd) -> Result<u32> {
+    #[allow(clippy::unnecessary_cast)]
+    Ok(rustix::io::retry_on_intr(|| rustix::fs::fstatfs(fd))?.f_type as u32)
+}
+
+const FS_OVERLAY: c_uint = 0x794c7630; // OVERLAYFS_SUPER_MAGIC
+
+const fn known(
+    name: &'static str,
+    magic: u32,
+    version: Version,
+    support: Support,
+) -> KnownFilesystem {
+    KnownFilesystem::new(name, Some(magic), version, support)
+}
+
+// The names are from linux/magic.h. The versions are from the man page for
+// renameat2 where it mentions the file system:
+//
+//  - ext4 (Linux 3.15);
+//  - btrfs, tmpfs, and cifs (Linux 3.17);
+//  - xfs (Linux 4.0);
+//  - Support for many other filesystems was added in Linux 4.9, including
+//    ext2, minix, reiserfs, jfs, vfat, and bpf.
+//
+// "Including" implies that the list isn't exhaustive. Linux 4.9 merged
+// rename2 into rename and added the flag check to every local file system that
+// could rename at the time. File systems added since then support it from the
+// start. Network file systems (NFS, 9p, Ceph) reject the flag. That includes
+// the Windows drives under /mnt in WSL 2 which are shared over 9p.
+pub const KNOWN_FILESYSTEMS: &[KnownFilesystem] = &[
+    // EXT2_SUPER_MAGIC and EXT3_SUPER_MAGIC are the same as EXT4_SUPER_MAGIC.
+    known("ext4", 0xef53, Version::new(3, 15, 0), Support::Supported), // EXT4_SUPER_MAGIC
+    known("btrfs", 0x9123683e, Version::new(3, 17, 0), Support::Supported), // BTRFS_SUPER_MAGIC
+    known("btrfs", 0x73727279, Version::new(3, 17, 0), Support::Supported), // BTRFS_TEST_MAGIC
+    known("tmpfs", 0x01021994, Version::new(3, 17, 0), Support::Supported), // TMPFS_MAGIC
+    known("xfs", 0x58465342, Version::new(4, 0, 0), Support::Supported), // XFS_SUPER_MAGIC
+    // The upper layer has to support it too. That isn't checked.
+    known("overlay", FS_OVERLAY, Version::new(4, 0, 0), Support::Supported),
+    known("ext2", 0xef51, Version::new(4, 9, 0), Support::Supported), // EXT2_OLD_SUPER_MAGIC
+    known("minix", 0x137f, Version::new(4, 9, 0), Support::Supported), // MINIX_SUPER_MAGIC
+    known("minix", 0x138f, Version::new(4, 9, 0), Support::Supported), // MINIX_SUPER_MAGIC2
+    known("minix", 0x2468, Version::new(4, 9, 0), Support::Supported), // MINIX2_SUPER_MAGIC
+    known("minix", 0x2478, Version::new(4, 9, 0), Support::Supported), // MINIX2_SUPER_MAGIC2
+    known("minix", 0x4d5a, Version::new(4, 9, 0), Support::Supported), // MINIX3_SUPER_MAGIC
+    // REISERFS_SUPER_MAGIC
+    known("reiserfs", 0x52654973, Version::new(4, 9, 0), Support::Supported),
+    known("jfs", 0x3153464a, Version::new(4, 9, 0), Support::Supported), // JFS_SUPER_MAGIC
+    // MSDOS_SUPER_MAGIC (also vfat)
+    known("msdos", 0x4d44, Version::new(4, 9, 0), Support::Supported),
+    // vfat was discovered experimentally. It doesn't appear in the man page or
+    // the magic.h header.
+    known("vfat", 0x7c7c6673, Version::new(4, 9, 0), Support::Supported),
+    known("bpf", 0xcafe4a11, Version::new(4, 9, 0), Support::Supported), // BPF_FS_MAGIC
+    known("f2fs", 0xf2f52010, Version::new(4, 9, 0), Support::Supported), // F2FS_SUPER_MAGIC
+    known("nilfs2", 0x3434, Version::new(4, 9, 0), Support::Supported), // NILFS_SUPER_MAGIC
+    known("ocfs2", 0x7461636f, Version::new(4, 9, 0), Support::Supported), // OCFS2_SUPER_MAGIC
+    known("gfs2", 0x01161970, Version::new(4, 9, 0), Support::Supported), // GFS2_MAGIC
+    known("ubifs", 0x24051905, Version::new(4, 9, 0), Support::Supported), // UBIFS_SUPER_MAGIC
+    known("jffs2", 0x72b6, Version::new(4, 9, 0), Support::Supported), // JFFS2_SUPER_MAGIC
+    known("hfsplus", 0x482b, Version::new(4, 9, 0), Support::Supported), // HFSPLUS_SUPER_MAGIC
+    known("hfs", 0x4244, Version::new(4, 9, 0), Support::Supported), // HFS_SUPER_MAGIC
+    known("udf", 0x15013346, Version::new(4, 9, 0), Support::Supported), // UDF_SUPER_MAGIC
+    known("ramfs", 0x858458f6, Version::new(4, 9, 0), Support::Supported), // RAMFS_MAGIC
+    known("hugetlbfs", 0x958458f6, Version::new(4, 9, 0), Support::Supported), // HUGETLBFS_MAGIC
+    known("affs", 0xadff, Version::new(4, 9, 0), Support::Supported), // AFFS_SUPER_MAGIC
+    known("exfat", 0x2011bab0, Version::new(5, 7, 0), Support::Supported), // EXFAT_SUPER_MAGIC
+    known("ntfs3", 0x5346544e, Version::new(5, 15, 0), Support::Supported), // NTFS_SB_MAGIC (ntfs3)
+    // BCACHEFS_SUPER_MAGIC
+    known("bcachefs", 0xca451a4e, Version::new(6, 7, 0), Support::Supported),
+    // The client supports the flag but whether the server (or FUSE daemon)
+    // honours it atomically can't be determined. FUSE passes the flag on since
+    // Linux 4.0.
+    known("cifs", 0xff534d42, Version::new(3, 17, 0), Support::Unverifiable), // CIFS_SUPER_MAGIC
+    known("smb3", 0xfe534d42, Version::new(3, 17, 0), Support::Unverifiable), // SMB2_SUPER_MAGIC
+    known("fuse", 0x65735546, Version::new(4, 0, 0), Support::Unverifiable), // FUSE_SUPER_MAGIC
+];
+
+// ZFS isn't part of the kernel so the version of the module matters instead.
+const FS_ZFS: c_uint = 0x2fc12fc1;
+
+// OpenZFS 2.2 added support for the flags.
+fn zfs_supported() -> bool {
+    let Ok(version) = std::fs::read_to_string("/sys/module/zfs/version") else {
+        return false;
+    };
+    let mut parts = version.trim().split(['.', '-']).map(|p| p.parse::<u32>());
+
+    match (parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) => (major, minor) >= (2, 2),
+        _ => false,
+    }
+}
+
+// RENAME_EXCHANGE wasn't added to every file system in Linux 4.9 like
+// RENAME_NOREPLACE was. These are from the commits that added it.
+const EXCHANGE: &[(c_uint, Version)] = &[
+    (0xef53, Version::new(3, 15, 0)), // EXT4_SUPER_MAGIC
+    (0x01021994, Version::new(3, 17, 0)), // TMPFS_MAGIC
+    (0x58465342, Version::new(4, 0, 0)), // XFS_SUPER_MAGIC
+    (FS_OVERLAY, Version::new(4, 0, 0)),
+    (0x9123683e, Version::new(4, 7, 0)), // BTRFS_SUPER_MAGIC
+    (0xf2f52010, Version::new(4, 8, 0)), // F2FS_SUPER_MAGIC
+    (0x24051905, Version::new(4, 9, 0)), // UBIFS_SUPER_MAGIC
+    (0x01161970, Version::new(4, 9, 0)), // GFS2_MAGIC
+    (0x4d44, Version::new(6, 0, 0)), // MSDOS_SUPER_MAGIC (also vfat)
+    (0xca451a4e, Version::new(6, 7, 0)), // BCACHEFS_SUPER_MAGIC
+];
+
+// RENAME_WHITEOUT is mostly for the upper layer of overlay file systems.
+const WHITEOUT: &[(c_uint, Version)] = &[
+    (0xef53, Version::new(3, 18, 0)), // EXT4_SUPER_MAGIC
+    (0x01021994, Version::new(3, 18, 0)), // TMPFS_MAGIC
+    (0x58465342, Version::new(4, 1, 0)), // XFS_SUPER_MAGIC
+    (0xf2f52010, Version::new(4, 6, 0)), // F2FS_SUPER_MAGIC
+    (0x9123683e, Version::new(4, 7, 0)), // BTRFS_SUPER_MAGIC
+    (0x24051905, Version::new(4, 9, 0)), // UBIFS_SUPER_MAGIC
+    (0xca451a4e, Version::new(6, 7, 0)), // BCACHEFS_SUPER_MAGIC
+];
+
+// XFS needs to be formatted with reflink=1 but that's been the default since
+// xfsprogs 5.1.
+const REFLINK: &[c_uint] = &[
     0x9123683e, // BTRFS_SUPER_MAGIC
-    0x73727279, // BTRFS_TEST_MAGIC
+    0x58465342, // XFS_SUPER_MAGIC
+    0x7461636f, // OCFS2_SUPER_MAGIC
+    0xca451a4e, // BCACHEFS_SUPER_MAGIC
 ];
-const FS_TMPFS: c_uint = 0x01021994; // TMPFS_MAGIC
-const FS_CIFS: c_uint = 0xff534d42; // CIFS_MAGIC_NUMBER
-const FS_XFS: c_uint = 0x58465342; // XFS_SUPER_MAGIC
-// EXT2_SUPER_MAGIC is the same as EXT4_SUPER_MAGIC.
-const FS_EXT2: c_uint = 0xef51; // EXT2_OLD_SUPER_MAGIC
-const FS_MINIX: [c_uint; 5] = [
-    0x137f, // MINIX_SUPER_MAGIC
-    0x138f, // MINIX_SUPER_MAGIC2
-    0x2468, // MINIX2_SUPER_MAGIC
-    0x2478, // MINIX2_SUPER_MAGIC2
-    0x4d5a, // MINIX3_SUPER_MAGIC
+
+// hfsplus is case insensitive unless it was formatted otherwise but that
+// isn't exposed. ext4 and f2fs can be case insensitive per directory.
+const CASE_INSENSITIVE: &[c_uint] = &[
+    0x4d44, // MSDOS_SUPER_MAGIC (also vfat)
+    0x2011bab0, // EXFAT_SUPER_MAGIC
+    0x482b, // HFSPLUS_SUPER_MAGIC
 ];
-const FS_REISERFS: c_uint = 0x52654973; // REISERFS_SUPER_MAGIC
-const FS_JFS: c_uint = 0x3153464a; // JFS_SUPER_MAGIC
-// vfat was discovered experimentally. It doesn't appear in the man page or the
-// magic.h header.
-const FS_VFAT: c_uint = 0x7c7c6673;
-const FS_BPF: c_uint = 0xcafe4a11; // BPF_FS_MAGIC
-
-pub fn rename_exclusive_is_atomic(path: &Path) -> Result<bool> {
-    let kernel = get_kernel_version()?;
+
+pub fn volume_capabilities(path: &Path, capabilities: &mut Capabilities) -> Result<()> {
     let fs = get_filesystem_type(path)?;
 
-    // The man page for renameat2 says this:
-    //
-    //  - ext4 (Linux 3.15);
-    //  - btrfs, tmpfs, and cifs (Linux 3.17);
-    //  - xfs (Linux 4.0);
-    //  - Support for many other filesystems was added in Linux 4.9, including
-    //    ext2, minix, reiserfs, jfs, vfat, and bpf.
-
-    if kernel >= Version::new(3, 15, 0) {
-        if fs == FS_EXT4 {
-            return Ok(true);
+    // The tables don't apply to WSL 1.
+    let version = match get_kernel() {
+        Ok(kernel) if kernel.wsl1 => None,
+        Ok(kernel) => Some(kernel.version),
+        Err(e) => {
+            crate::instrument::detection_failed("kernel version", &e);
+            probe_kernel_version()
         }
+    };
+    #[cfg(renameat2_dynamic)]
+    let version = version.filter(|_| get_renameat2().is_some());
+    let supported = |table: &[(c_uint, Version)]| {
+        version.is_some_and(|version| {
+            table.iter().any(|(magic, required)| *magic == fs && version >= *required)
+        })
+    };
+
+    capabilities.copy_up = fs == FS_OVERLAY;
+    capabilities.exchange = supported(EXCHANGE);
+    capabilities.whiteout = supported(WHITEOUT);
+    capabilities.block_cloning = REFLINK.contains(&fs);
+    // Whether CIFS and FUSE are case sensitive is up to the server.
+    let remote = NETWORK.contains(&fs) || KNOWN_FILESYSTEMS.iter()
+        .any(|known| known.magic == Some(fs) && known.support == Support::Unverifiable);
+    capabilities.case_sensitive = (!remote).then(|| !CASE_INSENSITIVE.contains(&fs));
+
+    // procfs might not be mounted.
+    if let Ok(Some(mount)) = crate::mountinfo::find(path) {
+        capabilities.file_system = Some(mount.file_system);
+        capabilities.mount_point = Some(mount.mount_point);
     }
 
-    if kernel >= Version::new(3, 17, 0) {
-        if FS_BTRFS.contains(&fs) || [FS_TMPFS, FS_CIFS].contains(&fs) {
-            return Ok(true);
+    Ok(())
+}
+
+// A bind mount has the same device as the file system it came from but renames
+// between the two still fail with EXDEV, so the mount IDs are compared too.
+// Each btrfs subvolume has its own device so those are caught by st_dev. A
+// file can't be renamed out of the mount that its directory is on, and the
+// files in an overlay can have a different st_dev to its directories, so the
+// directories are compared.
+pub fn same_volume(a: &Path, b: &Path) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let directory = |path: &Path| -> Result<_> {
+        let metadata = path.metadata()?;
+        if metadata.is_dir() {
+            Ok((path.to_path_buf(), metadata))
+        } else {
+            let parent = parent(path);
+            Ok((parent.to_path_buf(), parent.metadata()?))
         }
+    };
+
+    let (a, a_metadata) = directory(a)?;
+    let (b, b_metadata) = directory(b)?;
+
+    Ok(a_metadata.dev() == b_metadata.dev() && mount_id(&a)? == mount_id(&b)?)
+}
+
+#[cfg(not(any(target_arch = "sparc", target_arch = "sparc64")))]
+const O_PATH: i32 = 0o10000000;
+#[cfg(any(target_arch = "sparc", target_arch = "sparc64"))]
+const O_PATH: i32 = 0x1000000;
+
+// statx only has the mount ID since Linux 5.8 but fdinfo has had it since
+// 3.15. This needs procfs to be mounted.
+fn mount_id(path: &Path) -> Result<u64> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let file = std::fs::OpenOptions::new().read(true).custom_flags(O_PATH).open(path)?;
+    let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", file.as_raw_fd()))?;
+
+    fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("mnt_id:"))
+        .and_then(|id| id.trim().parse().ok())
+        .ok_or_else(|| Error::new(ErrorKind::Unsupported, "fdinfo doesn't have the mount ID"))
+}
+
+pub fn kernel_version() -> Result<Version> {
+    Ok(get_kernel()?.version)
+}
+
+pub fn atomic_support(path: &Path) -> Result<AtomicSupport> {
+    atomic_support_with(|| get_filesystem_type(path), || fs_info(path))
+}
+
+pub fn atomic_support_fd(dir: &File) -> Result<AtomicSupport> {
+    atomic_support_with(|| get_filesystem_type_fd(dir.as_fd()), || fs_info_fd(dir))
+}
+
+fn atomic_support_with(
+    filesystem_type: impl FnOnce() -> Result<u32>,
+    fs_info: impl FnOnce() -> Result<FsInfo>,
+) -> Result<AtomicSupport> {
+    #[cfg(renameat2_dynamic)]
+    if get_renameat2().is_none() {
+        return Ok(AtomicSupport::No(Reason::Unavailable));
     }
 
-    if kernel >= Version::new(4, 0, 0) {
-        if fs == FS_XFS {
-            return Ok(true);
+    let kernel = match get_kernel() {
+        Ok(kernel) => kernel,
+        Err(e) => {
+            crate::instrument::detection_failed("kernel version", &e);
+            match probe_kernel_version() {
+                Some(version) => Kernel { version, wsl1: false },
+                None => return Ok(AtomicSupport::No(Reason::Unavailable)),
+            }
         }
+    };
+
+    // Neither the version nor the file system type say anything about what WSL 1
+    // actually does. Whether drvfs (the Windows drives under /mnt) and lxfs
+    // (the root) honour the flag is up to the NT kernel.
+    if kernel.wsl1 {
+        return Ok(AtomicSupport::Unknown);
+    }
+
+    let fs = filesystem_type()?;
+
+    if fs == FS_ZFS && zfs_supported() {
+        return Ok(AtomicSupport::Yes);
     }
 
-    if kernel >= Version::new(4, 9, 0) {
-        // The man page says "including" which implies that this is not an
-        // exhaustive list.
-        if [FS_EXT2, FS_REISERFS, FS_JFS, FS_VFAT, FS_BPF].contains(&fs) || FS_MINIX.contains(&fs) {
-            return Ok(true);
+    filesystem_support(kernel.version, fs, fs_info)
+}
+
+// Looks up the magic number from statfs in the table. This doesn't touch the
+// system so that it can be tested with any kernel version.
+pub(crate) fn filesystem_support(
+    version: Version,
+    fs: u32,
+    fs_info: impl FnOnce() -> Result<FsInfo>,
+) -> Result<AtomicSupport> {
+    let Some(known) = KNOWN_FILESYSTEMS.iter().find(|known| known.magic == Some(fs)) else {
+        return Ok(AtomicSupport::No(Reason::Filesystem(fs_info()?)));
+    };
+    let required = known.min_version;
+    let support = match known.support {
+        Support::Unverifiable => AtomicSupport::Unknown,
+        _ => AtomicSupport::Yes,
+    };
+
+    if version >= required {
+        Ok(support)
+    } else {
+        Ok(AtomicSupport::No(Reason::KernelTooOld { found: version, required }))
+    }
+}
+
+// These are the file systems that send requests to a server. FUSE isn't
+// included because most FUSE file systems are local.
+const NETWORK: &[c_uint] = &[
+    0x6969, // NFS_SUPER_MAGIC
+    0x517b, // SMB_SUPER_MAGIC
+    0xff534d42, // CIFS_SUPER_MAGIC
+    0xfe534d42, // SMB2_SUPER_MAGIC
+    0x01021997, // V9FS_MAGIC
+    0x00c36400, // CEPH_SUPER_MAGIC
+    0x5346414f, // AFS_SUPER_MAGIC
+    0x6b414653, // AFS_FS_MAGIC
+    0x73757245, // CODA_SUPER_MAGIC
+];
+
+pub fn fs_info(path: &Path) -> Result<FsInfo> {
+    let magic = get_filesystem_type(path)?;
+
+    Ok(FsInfo {
+        // procfs might not be mounted.
+        name: crate::mountinfo::find(path).ok().flatten().map(|mount| mount.file_system),
+        magic: Some(magic),
+        network: NETWORK.contains(&magic),
+    })
+}
+
+pub fn fs_info_fd(dir: &File) -> Result<FsInfo> {
+    let magic = get_filesystem_type_fd(dir.as_fd())?;
+    // The link in procfs is resolved to wherever the directory is now.
+    let link = format!("/proc/self/fd/{}", dir.as_fd().as_raw_fd());
+
+    Ok(FsInfo {
+        name: crate::mountinfo::find(Path::new(&link))
+            .ok()
+            .flatten()
+            .map(|mount| mount.file_system),
+        magic: Some(magic),
+        network: NETWORK.contains(&magic),
+    })
+}
+
+// Extended attributes that hold security labels. security.selinux is the main
+// one but this also covers security.capability, security.ima, SMACK labels
+// and so on. trusted.* is only visible to processes with CAP_SYS_ADMIN.
+const SECURITY_PREFIXES: &[&[u8]] = &[b"security.", b"trusted."];
+
+use crate::error::codes::errno::EOPNOTSUPP as ENOTSUP;
+const ERANGE: i32 = 34;
+
+pub fn copy_security_xattrs(from: &Path, to: &Path) -> Result<()> {
+    let names = match read_xattr(|buf| llistxattr(from, buf)) {
+        Ok(names) => names,
+        Err(e) if e.raw_os_error() == Some(ENOTSUP) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for name in names.split(|b| *b == 0) {
+        if !SECURITY_PREFIXES.iter().any(|prefix| name.starts_with(prefix)) {
+            continue;
+        }
+
+        let name = OsStr::from_bytes(name);
+        let value = read_xattr(|buf| lgetxattr(from, name, buf))?;
+
+        match lsetxattr(to, name, &value) {
+            // The destination file system doesn't support extended attributes.
+            Err(e) if e.raw_os_error() == Some(ENOTSUP) => return Ok(()),
+            result => result?,
+        }
+    }
+
+    Ok(())
+}
+
+// The size is queried first but the attribute can grow in between.
+pub(crate) fn read_xattr(mut read: impl FnMut(&mut [u8]) -> Result<usize>) -> Result<Vec<u8>> {
+    loop {
+        let mut buf = vec![0; read(&mut [])?];
+
+        match read(&mut buf) {
+            Ok(len) => {
+                buf.truncate(len);
+                return Ok(buf);
+            }
+            Err(e) if e.raw_os_error() == Some(ERANGE) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(not(any(feature = "rustix", feature = "libc")))]
+extern "C" {
+    #[link_name = "llistxattr"]
+    fn sys_llistxattr(path: *const c_char, list: *mut c_char, size: usize) -> isize;
+    #[link_name = "lgetxattr"]
+    fn sys_lgetxattr(
+        path: *const c_char,
+        name: *const c_char,
+        value: *mut std::ffi::c_void,
+        size: usize,
+    ) -> isize;
+    #[link_name = "lsetxattr"]
+    fn sys_lsetxattr(
+        path: *const c_char,
+        name: *const c_char,
+        value: *const std::ffi::c_void,
+        size: usize,
+        flags: c_int,
+    ) -> c_int;
+}
+
+#[cfg(all(feature = "libc", not(feature = "rustix")))]
+use libc::{
+    llistxattr as sys_llistxattr,
+    lgetxattr as sys_lgetxattr,
+    lsetxattr as sys_lsetxattr,
+};
+
+#[cfg(not(feature = "rustix"))]
+fn llistxattr(path: &Path, buf: &mut [u8]) -> Result<usize> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let ret = crate::retry_interrupted(|| unsafe {
+        sys_llistxattr(path.as_ptr(), buf.as_mut_ptr().cast(), buf.len())
+    });
+    if ret == -1 { Err(Error::last_os_error()) } else { Ok(ret as usize) }
+}
+
+#[cfg(not(feature = "rustix"))]
+pub(crate) fn lgetxattr(path: &Path, name: &OsStr, buf: &mut [u8]) -> Result<usize> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let name = CString::new(name.as_bytes())?;
+    let ret = crate::retry_interrupted(|| unsafe {
+        sys_lgetxattr(path.as_ptr(), name.as_ptr(), buf.as_mut_ptr().cast(), buf.len())
+    });
+    if ret == -1 { Err(Error::last_os_error()) } else { Ok(ret as usize) }
+}
+
+#[cfg(not(feature = "rustix"))]
+pub(crate) fn lsetxattr(path: &Path, name: &OsStr, value: &[u8]) -> Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let name = CString::new(name.as_bytes())?;
+    let ret = crate::retry_interrupted(|| unsafe {
+        sys_lsetxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0)
+    });
+    if ret == -1 { Err(Error::last_os_error()) } else { Ok(()) }
+}
+
+#[cfg(feature = "rustix")]
+fn llistxattr(path: &Path, buf: &mut [u8]) -> Result<usize> {
+    Ok(rustix::io::retry_on_intr(|| rustix::fs::llistxattr(path, &mut *buf))?)
+}
+
+#[cfg(feature = "rustix")]
+pub(crate) fn lgetxattr(path: &Path, name: &OsStr, buf: &mut [u8]) -> Result<usize> {
+    Ok(rustix::io::retry_on_intr(|| rustix::fs::lgetxattr(path, name, &mut *buf))?)
+}
+
+#[cfg(feature = "rustix")]
+pub(crate) fn lsetxattr(path: &Path, name: &OsStr, value: &[u8]) -> Result<()> {
+    Ok(rustix::io::retry_on_intr(|| {
+        rustix::fs::lsetxattr(path, name, value, rustix::fs::XattrFlags::empty())
+    })?)
+}
+
+// Cloning shares the extents of `from` with the copy so it's instant and
+// doesn't use any space. This works within btrfs, XFS (with reflink=1), bcachefs
+// and OCFS2 file systems and fails with EXDEV or EOPNOTSUPP otherwise. In that
+// case, std::fs::copy uses copy_file_range, which keeps the copy in the kernel
+// (and can clone or copy on the server with NFS and SMB), and then falls back
+// to reading and writing.
+pub fn copy_file(from: &Path, to: &Path, _progress: &crate::copy::Progress) -> Result<u64> {
+    let source = std::fs::File::open(from)?;
+    let metadata = source.metadata()?;
+
+    if metadata.is_file() {
+        let dest = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(to)?;
+
+        if ficlone(dest.as_fd(), source.as_fd()).is_ok() {
+            dest.set_permissions(metadata.permissions())?;
+            return Ok(metadata.len());
         }
     }
 
-    Ok(false)
+    std::fs::copy(from, to)
+}
+
+#[cfg(not(any(feature = "rustix", feature = "libc")))]
+extern "C" {
+    #[cfg(target_env = "musl")]
+    fn ioctl(fd: c_int, request: c_int, ...) -> c_int;
+    #[cfg(not(target_env = "musl"))]
+    fn ioctl(fd: c_int, request: std::ffi::c_ulong, ...) -> c_int;
+}
+
+// _IOW(0x94, 9, int). The direction bits are different on these architectures.
+#[cfg(all(
+    not(any(feature = "rustix", feature = "libc")),
+    not(any(
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "sparc",
+        target_arch = "sparc64",
+    )),
+))]
+const FICLONE: u32 = 0x40049409;
+#[cfg(all(
+    not(any(feature = "rustix", feature = "libc")),
+    any(
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "sparc",
+        target_arch = "sparc64",
+    ),
+))]
+const FICLONE: u32 = 0x80049409;
+
+#[cfg(not(any(feature = "rustix", feature = "libc")))]
+fn ficlone(dest: BorrowedFd, source: BorrowedFd) -> Result<()> {
+    let ret = crate::retry_interrupted(|| unsafe {
+        ioctl(dest.as_raw_fd(), FICLONE as _, source.as_raw_fd())
+    });
+    if ret == -1 { Err(Error::last_os_error()) } else { Ok(()) }
+}
+
+#[cfg(all(feature = "libc", not(feature = "rustix")))]
+fn ficlone(dest: BorrowedFd, source: BorrowedFd) -> Result<()> {
+    let ret = crate::retry_interrupted(|| unsafe {
+        libc::ioctl(dest.as_raw_fd(), libc::FICLONE, source.as_raw_fd())
+    });
+    if ret == -1 { Err(Error::last_os_error()) } else { Ok(()) }
+}
+
+#[cfg(feature = "rustix")]
+fn ficlone(dest: BorrowedFd, source: BorrowedFd) -> Result<()> {
+    Ok(rustix::io::retry_on_intr(|| rustix::fs::ioctl_ficlone(dest, source))?)
+}
+
+// _IOR('f', 1, long). The kernel only writes an int.
+#[cfg(all(
+    not(any(feature = "rustix", feature = "libc")),
+    not(any(
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "sparc",
+        target_arch = "sparc64",
+    )),
+))]
+const FS_IOC_GETFLAGS: u32 = 0x80006601 | (std::mem::size_of::<std::ffi::c_long>() as u32) << 16;
+#[cfg(all(
+    not(any(feature = "rustix", feature = "libc")),
+    any(
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+        target_arch = "mips",
+        target_arch = "mips64",
+        target_arch = "sparc",
+        target_arch = "sparc64",
+    ),
+))]
+const FS_IOC_GETFLAGS: u32 = 0x40006601 | (std::mem::size_of::<std::ffi::c_long>() as u32) << 16;
+
+#[cfg(not(any(feature = "rustix", feature = "libc")))]
+fn get_inode_flags(fd: BorrowedFd) -> Result<u32> {
+    let mut flags: c_int = 0;
+    let ret = crate::retry_interrupted(|| unsafe {
+        ioctl(fd.as_raw_fd(), FS_IOC_GETFLAGS as _, &mut flags)
+    });
+    if ret == -1 { Err(Error::last_os_error()) } else { Ok(flags as u32) }
+}
+
+#[cfg(all(feature = "libc", not(feature = "rustix")))]
+fn get_inode_flags(fd: BorrowedFd) -> Result<u32> {
+    let mut flags: c_int = 0;
+    let ret = crate::retry_interrupted(|| unsafe {
+        libc::ioctl(fd.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags)
+    });
+    if ret == -1 { Err(Error::last_os_error()) } else { Ok(flags as u32) }
+}
+
+#[cfg(feature = "rustix")]
+fn get_inode_flags(fd: BorrowedFd) -> Result<u32> {
+    Ok(rustix::io::retry_on_intr(|| rustix::fs::ioctl_getflags(fd))?.bits())
 }
@@ -0,0 +1,143 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+
+/// A lock on a file that's replaced with new contents when it's committed.
+///
+/// This is the protocol that Git uses to update files like `.git/config`.
+/// Acquiring the lock on `config` creates `config.lock` exclusively, so only
+/// one process can hold it at a time. The new contents are written to the lock
+/// file and then [`commit`](Lockfile::commit) renames it over `config`.
+/// Readers see either the old contents or the new contents but never a mix.
+///
+/// If the lock is dropped without being committed, then the lock file is
+/// removed and `config` is left alone.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Write;
+/// use renamore::Lockfile;
+///
+/// # fn main() -> std::io::Result<()> {
+/// # let dir = tempfile::tempdir()?;
+/// # let config = dir.path().join("config");
+/// let mut lock = Lockfile::acquire(&config)?;
+/// writeln!(lock, "[core]")?;
+/// writeln!(lock, "\tbare = false")?;
+/// lock.commit()?;
+///
+/// assert!(Lockfile::acquire(&config).is_ok());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Lockfile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    // This is taken when the lock is committed or rolled back.
+    file: Option<File>,
+}
+
+impl Lockfile {
+    /// Acquire the lock on a file by creating the file with `.lock` appended
+    /// to its name.
+    ///
+    /// The file itself doesn't need to exist. If the lock file already exists,
+    /// then [`ErrorKind::AlreadyExists`] is returned. That usually means that
+    /// another process holds the lock, but it might have been left behind by a
+    /// process that crashed.
+    pub fn acquire<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        crate::check_path(path, "path")?;
+
+        let mut lock_path = OsString::from(path);
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .map_err(|e| match e.kind() {
+                ErrorKind::AlreadyExists => Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} is locked by {}", path.display(), lock_path.display()),
+                ),
+                _ => e,
+            })?;
+
+        Ok(Self { path: path.to_path_buf(), lock_path, file: Some(file) })
+    }
+
+    /// The path to the file that's locked.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The path to the lock file.
+    pub fn lock_path(&self) -> &Path {
+        &self.lock_path
+    }
+
+    /// The lock file that the new contents are written to.
+    pub fn file(&mut self) -> &mut File {
+        self.file.as_mut().unwrap()
+    }
+
+    /// Replace the file with the contents of the lock file and release the
+    /// lock.
+    ///
+    /// The lock file is synced to disk before the rename and, on Unix, the
+    /// directory is synced after it. If the rename fails, then the lock file
+    /// is removed and the file is left alone. The rename is retried like the
+    /// others in this crate (see [`RenameOptions::retry`]).
+    ///
+    /// [`RenameOptions::retry`]: crate::RenameOptions::retry
+    pub fn commit(mut self) -> Result<()> {
+        let file = self.file.take().unwrap();
+        let result = file.sync_all();
+
+        // Windows won't rename a file that's open.
+        drop(file);
+
+        let result = result.and_then(|()| {
+            crate::RenameOptions::new().durable(true).rename_replace(&self.lock_path, &self.path)
+        });
+
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(&self.lock_path);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Release the lock without changing the file.
+    ///
+    /// This is the same as dropping the lock except that errors are reported.
+    pub fn rollback(mut self) -> Result<()> {
+        drop(self.file.take());
+        std::fs::remove_file(&self.lock_path)
+    }
+}
+
+impl Write for Lockfile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file().flush()
+    }
+}
+
+impl Drop for Lockfile {
+    fn drop(&mut self) {
+        if let Some(file) = self.file.take() {
+            drop(file);
+            let _ = std::fs::remove_file(&self.lock_path);
+        }
+    }
+}
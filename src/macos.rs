@@ -1,39 +1,215 @@
 #![allow(non_camel_case_types)]
 
 use std::path::Path;
+use std::fs::File;
 use std::io::{Error, ErrorKind, Result};
-use std::ffi::{c_char, c_int, c_uint, CString, c_ulong};
-use std::os::unix::prelude::OsStrExt;
+use std::ffi::{c_char, c_int, c_uint, c_void, CStr, CString, c_ulong};
+use std::os::unix::prelude::{AsRawFd, OsStrExt};
+use std::sync::OnceLock;
+use crate::{parent, AtomicSupport, FsInfo, Reason, RenameOptions, Version};
 
-// Linking will fail on Darwin versions prior to 16 which corresponds to these:
+// renamex_np is only available on Darwin 16 and later which corresponds to
+// these:
 //  - macOS 10.12
 //  - iOS 10.0
 //  - tvOS 10.0
 //  - watchOS 3.0
+//
+// It's looked up at runtime rather than linked so that binaries built with an
+// older deployment target still use it when it's available.
 
 extern "C" {
-    fn renamex_np(from: *const c_char, to: *const c_char, flags: c_uint) -> c_int;
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
 }
 
-// const RENAME_SWAP: c_uint = 2;
+const RTLD_DEFAULT: *mut c_void = -2isize as *mut c_void;
+
+type RenamexNp =
+    unsafe extern "C" fn(from: *const c_char, to: *const c_char, flags: c_uint) -> c_int;
+
+fn get_renamex_np() -> Option<RenamexNp> {
+    static RENAMEX_NP: OnceLock<Option<RenamexNp>> = OnceLock::new();
+
+    *RENAMEX_NP.get_or_init(|| {
+        let ptr = unsafe { dlsym(RTLD_DEFAULT, c"renamex_np".as_ptr()) };
+        if ptr.is_null() {
+            None
+        } else {
+            Some(unsafe { std::mem::transmute::<*mut c_void, RenamexNp>(ptr) })
+        }
+    })
+}
+
+const RENAME_SWAP: c_uint = 2;
 const RENAME_EXCL: c_uint = 4;
+// Darwin 22 (macOS 13, iOS 16, tvOS 16, watchOS 9) and later.
+const RENAME_NOFOLLOW_ANY: c_uint = 0x10;
 
-pub fn rename_exclusive(from: &Path, to: &Path) -> Result<()> {
+const DARWIN_NOFOLLOW_ANY: u16 = 22;
+
+fn rename_with_flags(from: &Path, to: &Path, flags: c_uint) -> Result<()> {
+    let renamex_np = get_renamex_np().ok_or(ErrorKind::Unsupported)?;
+    crate::instrument::syscall("renamex_np", flags);
     let from_str = CString::new(from.as_os_str().as_bytes())?;
     let to_str = CString::new(to.as_os_str().as_bytes())?;
-    let ret = unsafe {
-        renamex_np(from_str.as_ptr(), to_str.as_ptr(), RENAME_EXCL)
-    };
+    // This isn't retried on EINTR. See retry_interrupted.
+    let ret = unsafe { renamex_np(from_str.as_ptr(), to_str.as_ptr(), flags) };
 
     if ret == -1 {
-        let error = Error::last_os_error();
-        // EINVAL is returned if `flags` is invalid.
-        // ENOTSUP is returned if the file system doesn't support the operation.
-        if error.kind() == ErrorKind::InvalidInput {
-            Err(Error::from(ErrorKind::Unsupported))
-        } else {
-            Err(error)
+        Err(normalize_error(Error::last_os_error(), || flag_supported(parent(to), flags)))
+    } else {
+        Ok(())
+    }
+}
+
+const EINVAL: c_int = 22;
+const ENOTSUP: c_int = 45;
+
+// ENOTSUP is returned if the file system doesn't support the operation. EINVAL
+// is returned if `flags` is invalid, but also for mistakes like moving a
+// directory into itself. It's only made into Unsupported if the volume doesn't
+// advertise the flag. The original error is kept as the source.
+pub fn normalize_error(error: Error, supported: impl FnOnce() -> bool) -> Error {
+    match error.raw_os_error() {
+        Some(ENOTSUP) => Error::new(ErrorKind::Unsupported, error),
+        Some(EINVAL) if supported() => error,
+        Some(_) if matches!(error.kind(), ErrorKind::InvalidInput | ErrorKind::Unsupported) => {
+            Error::new(ErrorKind::Unsupported, error)
         }
+        _ => error,
+    }
+}
+
+fn flag_supported(dir: &Path, flags: c_uint) -> bool {
+    if flags & RENAME_SWAP != 0 {
+        get_volume_capabilities(dir).is_ok_and(|caps| caps & VOL_CAP_INT_RENAME_SWAP != 0)
+    } else {
+        matches!(atomic_support(dir), Ok(AtomicSupport::Yes))
+    }
+}
+
+pub fn rename_exclusive(from: &Path, to: &Path, options: &RenameOptions) -> Result<()> {
+    let mut flags = RENAME_EXCL;
+
+    if options.no_symlinks {
+        flags |= RENAME_NOFOLLOW_ANY;
+    }
+
+    #[cfg(feature = "apple-coordination")]
+    if options.coordinate {
+        return crate::coordination::coordinate_move(from, to, |from, to| {
+            rename_with_flags(from, to, flags)
+        }).map_err(|e| read_only_error(system_volume_error(e, from, to), from, to));
+    }
+
+    rename_with_flags(from, to, flags)
+        .map_err(|e| read_only_error(system_volume_error(e, from, to), from, to))
+}
+
+const MNT_RDONLY: u32 = 0x00000001;
+const MNT_ROOTFS: u32 = 0x00004000;
+
+fn is_system_volume(path: &Path) -> bool {
+    match get_statfs(parent(path)) {
+        Ok(buf) => buf.f_flags & (MNT_RDONLY | MNT_ROOTFS) == MNT_RDONLY | MNT_ROOTFS,
+        Err(_) => false,
+    }
+}
+
+// The read-only system volume is mounted at the root. Paths like /usr/local
+// are firmlinked to the data volume so a rename between /usr/local and /usr/bin
+// crosses volumes even though they look like they're on the same one.
+fn system_volume_error(error: Error, from: &Path, to: &Path) -> Error {
+    match error.kind() {
+        ErrorKind::ReadOnlyFilesystem | ErrorKind::CrossesDevices
+            if is_system_volume(to) || is_system_volume(from) =>
+        {
+            Error::new(ErrorKind::ReadOnlyFilesystem, crate::SystemVolumeError::new(error))
+        }
+        _ => error,
+    }
+}
+
+// Other read-only volumes (like a mounted disk image) and files or directories
+// with the immutable or append-only flags (set with chflags) give EROFS and
+// EPERM that don't say which path is the problem.
+fn read_only_error(error: Error, from: &Path, to: &Path) -> Error {
+    if !matches!(error.kind(), ErrorKind::ReadOnlyFilesystem | ErrorKind::PermissionDenied)
+        || error.get_ref().is_some_and(|e| e.is::<crate::SystemVolumeError>())
+    {
+        return error;
+    }
+
+    for path in [parent(from), parent(to)] {
+        if get_statfs(path).is_ok_and(|buf| buf.f_flags & MNT_RDONLY != 0) {
+            return crate::ReadOnlyCause::Mount(path.to_path_buf()).into_error(error);
+        }
+    }
+
+    for path in [from, parent(from), parent(to)] {
+        if is_immutable(path) {
+            return crate::ReadOnlyCause::Immutable(path.to_path_buf()).into_error(error);
+        }
+    }
+
+    error
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn is_immutable(path: &Path) -> bool {
+    #[cfg(target_os = "macos")]
+    use std::os::macos::fs::MetadataExt;
+    #[cfg(target_os = "ios")]
+    use std::os::ios::fs::MetadataExt;
+
+    const UF_IMMUTABLE: u32 = 0x00000002;
+    const UF_APPEND: u32 = 0x00000004;
+    const SF_IMMUTABLE: u32 = 0x00020000;
+    const SF_APPEND: u32 = 0x00040000;
+
+    const FLAGS: u32 = UF_IMMUTABLE | UF_APPEND | SF_IMMUTABLE | SF_APPEND;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata.st_flags() & FLAGS != 0,
+        Err(_) => false,
+    }
+}
+
+// std only exposes st_flags on macOS and iOS.
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+fn is_immutable(_path: &Path) -> bool {
+    false
+}
+
+extern "C" {
+    fn exchangedata(path1: *const c_char, path2: *const c_char, options: c_uint) -> c_int;
+}
+
+pub fn rename_exchange(a: &Path, b: &Path) -> Result<()> {
+    match rename_with_flags(a, b, RENAME_SWAP) {
+        Err(e) if e.kind() == ErrorKind::Unsupported => exchange_data(a, b),
+        result => result,
+    }
+}
+
+// HFS+ volumes that predate RENAME_SWAP support exchangedata instead. It swaps
+// the contents (rather than the names) of two files. The effect is the same
+// except that it only works on regular files and the inode numbers stay put.
+fn exchange_data(a: &Path, b: &Path) -> Result<()> {
+    if get_volume_capabilities(a)? & VOL_CAP_INT_EXCHANGEDATA == 0 {
+        return Err(Error::from(ErrorKind::Unsupported));
+    }
+
+    if !std::fs::symlink_metadata(a)?.is_file() || !std::fs::symlink_metadata(b)?.is_file() {
+        return Err(Error::from(ErrorKind::Unsupported));
+    }
+
+    let a_str = CString::new(a.as_os_str().as_bytes())?;
+    let b_str = CString::new(b.as_os_str().as_bytes())?;
+    let ret = unsafe { exchangedata(a_str.as_ptr(), b_str.as_ptr(), 0) };
+
+    if ret == -1 {
+        Err(Error::last_os_error())
     } else {
         Ok(())
     }
@@ -51,10 +227,12 @@ struct attrlist {
 }
 
 const ATTR_BIT_MAP_COUNT: u16 = 5;
+const ATTR_CMN_FLAGS: u32 = 0x00040000;
 const ATTR_VOL_CAPABILITIES: u32 = 0x00020000;
 
 type vol_capabilities_set_t = [u32; 4];
 
+const VOL_CAPABILITIES_FORMAT: usize = 0;
 const VOL_CAPABILITIES_INTERFACES: usize = 1;
 
 #[repr(C)]
@@ -63,7 +241,10 @@ struct vol_capabilities_attr_t {
     valid: vol_capabilities_set_t,
 }
 
-// const VOL_CAP_INT_RENAME_SWAP: u32 = 0x00040000;
+const VOL_CAP_FMT_CASE_SENSITIVE: u32 = 0x00000100;
+const VOL_CAP_INT_EXCHANGEDATA: u32 = 0x00000010;
+const VOL_CAP_INT_CLONE: u32 = 0x00010000;
+const VOL_CAP_INT_RENAME_SWAP: u32 = 0x00040000;
 const VOL_CAP_INT_RENAME_EXCL: u32 = 0x00080000;
 
 #[repr(C)]
@@ -76,14 +257,100 @@ extern "C" {
     fn getattrlist(
         path: *const c_char,
         attrList: *mut attrlist,
-        attrBuf: *mut AttributeBuf,
+        attrBuf: *mut c_void,
+        attrBufSize: usize,
+        options: c_ulong,
+    ) -> c_int;
+    fn fgetattrlist(
+        fd: c_int,
+        attrList: *mut attrlist,
+        attrBuf: *mut c_void,
         attrBufSize: usize,
         options: c_ulong,
     ) -> c_int;
 }
 
-pub fn rename_exclusive_is_atomic(path: &Path) -> Result<bool> {
+pub fn atomic_support(path: &Path) -> Result<AtomicSupport> {
+    atomic_support_with(
+        || get_volume_capability_sets(path),
+        || get_statfs(path),
+    )
+}
+
+pub fn atomic_support_fd(dir: &File) -> Result<AtomicSupport> {
+    atomic_support_with(
+        || get_volume_capability_sets_fd(dir),
+        || get_statfs_fd(dir),
+    )
+}
+
+fn atomic_support_with(
+    capability_sets: impl FnOnce() -> Result<vol_capabilities_attr_t>,
+    statfs: impl FnOnce() -> Result<statfs>,
+) -> Result<AtomicSupport> {
+    if get_renamex_np().is_none() {
+        return Ok(AtomicSupport::No(Reason::Unavailable));
+    }
+
+    let capabilities = capability_sets()?.capabilities[VOL_CAPABILITIES_INTERFACES];
+    let buf = statfs()?;
+
+    if capabilities & VOL_CAP_INT_RENAME_EXCL == 0 {
+        return Ok(AtomicSupport::No(Reason::Filesystem(fs_info_from(&buf))));
+    }
+
+    // SMB, AFP and NFS volumes may advertise the capability but whether the
+    // server honours it atomically is another matter.
+    if buf.f_flags & MNT_LOCAL == 0 {
+        return Ok(AtomicSupport::Unknown);
+    }
+
+    Ok(AtomicSupport::Yes)
+}
+
+pub fn fs_info(path: &Path) -> Result<FsInfo> {
+    Ok(fs_info_from(&get_statfs(path)?))
+}
+
+pub fn fs_info_fd(dir: &File) -> Result<FsInfo> {
+    Ok(fs_info_from(&get_statfs_fd(dir)?))
+}
+
+fn fs_info_from(buf: &statfs) -> FsInfo {
+    FsInfo {
+        name: Some(get_filesystem_name(buf)),
+        magic: Some(buf.f_type),
+        network: buf.f_flags & MNT_LOCAL == 0,
+    }
+}
+
+pub fn same_volume(a: &Path, b: &Path) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(a.metadata()?.dev() == b.metadata()?.dev())
+}
+
+fn get_volume_capabilities(path: &Path) -> Result<u32> {
+    Ok(get_volume_capability_sets(path)?.capabilities[VOL_CAPABILITIES_INTERFACES])
+}
+
+fn get_volume_capability_sets(path: &Path) -> Result<vol_capabilities_attr_t> {
     let path_str = CString::new(path.as_os_str().as_bytes())?;
+
+    get_volume_attributes(|list, buf, size| unsafe {
+        getattrlist(path_str.as_ptr(), list, buf, size, 0)
+    })
+}
+
+fn get_volume_capability_sets_fd(dir: &File) -> Result<vol_capabilities_attr_t> {
+    get_volume_attributes(|list, buf, size| unsafe {
+        fgetattrlist(dir.as_raw_fd(), list, buf, size, 0)
+    })
+}
+
+fn get_volume_attributes(
+    mut getattrlist: impl FnMut(*mut attrlist, *mut c_void, usize) -> c_int,
+) -> Result<vol_capabilities_attr_t> {
     let mut list = attrlist {
         bitmapcount: ATTR_BIT_MAP_COUNT,
         reserved: 0,
@@ -95,22 +362,249 @@ pub fn rename_exclusive_is_atomic(path: &Path) -> Result<bool> {
     };
     let mut buf = std::mem::MaybeUninit::<AttributeBuf>::uninit();
 
+    let ret = crate::retry_interrupted(|| getattrlist(
+        std::ptr::addr_of_mut!(list),
+        buf.as_mut_ptr() as *mut c_void,
+        std::mem::size_of::<AttributeBuf>(),
+    ));
+
+    if ret == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(unsafe { buf.assume_init() }.volume)
+}
+
+extern "C" {
+    fn sysctlbyname(
+        name: *const c_char,
+        oldp: *mut c_void,
+        oldlenp: *mut usize,
+        newp: *mut c_void,
+        newlen: usize,
+    ) -> c_int;
+}
+
+// The release is something like "22.1.0".
+pub fn kernel_version() -> Result<Version> {
+    let mut release = [0u8; 32];
+    let mut len = release.len();
     let ret = unsafe {
+        sysctlbyname(
+            c"kern.osrelease".as_ptr(),
+            release.as_mut_ptr() as *mut c_void,
+            &mut len,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+
+    if ret == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    CStr::from_bytes_until_nul(&release)
+        .map_err(|_| Error::from(ErrorKind::InvalidData))?
+        .to_string_lossy()
+        .parse()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+#[repr(C)]
+struct statfs {
+    f_bsize: u32,
+    f_iosize: i32,
+    f_blocks: u64,
+    f_bfree: u64,
+    f_bavail: u64,
+    f_files: u64,
+    f_ffree: u64,
+    f_fsid: [i32; 2],
+    f_owner: u32,
+    f_type: u32,
+    f_flags: u32,
+    f_fssubtype: u32,
+    f_fstypename: [c_char; 16],
+    f_mntonname: [c_char; 1024],
+    f_mntfromname: [c_char; 1024],
+    f_flags_ext: u32,
+    f_reserved: [u32; 7],
+}
+
+extern "C" {
+    // The 64-bit inode variant has to be asked for explicitly on x86_64.
+    #[cfg_attr(target_arch = "x86_64", link_name = "statfs$INODE64")]
+    fn statfs(path: *const c_char, buf: *mut statfs) -> c_int;
+    #[cfg_attr(target_arch = "x86_64", link_name = "fstatfs$INODE64")]
+    fn fstatfs(fd: c_int, buf: *mut statfs) -> c_int;
+}
+
+const MNT_LOCAL: u32 = 0x00001000;
+
+fn get_statfs(path: &Path) -> Result<statfs> {
+    let path_str = CString::new(path.as_os_str().as_bytes())?;
+    let mut buf = std::mem::MaybeUninit::<statfs>::uninit();
+
+    if crate::retry_interrupted(|| unsafe { statfs(path_str.as_ptr(), buf.as_mut_ptr()) }) == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(unsafe { buf.assume_init() })
+}
+
+fn get_statfs_fd(dir: &File) -> Result<statfs> {
+    let mut buf = std::mem::MaybeUninit::<statfs>::uninit();
+
+    if crate::retry_interrupted(|| unsafe { fstatfs(dir.as_raw_fd(), buf.as_mut_ptr()) }) == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(unsafe { buf.assume_init() })
+}
+
+fn get_filesystem_name(buf: &statfs) -> String {
+    let name = unsafe { CStr::from_ptr(buf.f_fstypename.as_ptr()) };
+    name.to_string_lossy().into_owned()
+}
+
+pub fn volume_capabilities(path: &Path, capabilities: &mut crate::Capabilities) -> Result<()> {
+    use crate::Normalization;
+
+    let fs = get_filesystem_name(&get_statfs(path)?);
+
+    capabilities.no_symlinks = kernel_version()?.major >= DARWIN_NOFOLLOW_ANY;
+    capabilities.normalization = match fs.as_str() {
+        "apfs" => Some(Normalization::Insensitive),
+        "hfs" => Some(Normalization::Normalizing),
+        _ => None,
+    };
+    capabilities.file_system = Some(fs);
+
+    let sets = get_volume_capability_sets(path)?;
+    let interfaces = sets.capabilities[VOL_CAPABILITIES_INTERFACES];
+    let format = sets.capabilities[VOL_CAPABILITIES_FORMAT];
+
+    capabilities.exchange = interfaces & (VOL_CAP_INT_RENAME_SWAP | VOL_CAP_INT_EXCHANGEDATA) != 0;
+    capabilities.block_cloning = interfaces & VOL_CAP_INT_CLONE != 0;
+    let valid_format = sets.valid[VOL_CAPABILITIES_FORMAT];
+    capabilities.case_sensitive = (valid_format & VOL_CAP_FMT_CASE_SENSITIVE != 0)
+        .then_some(format & VOL_CAP_FMT_CASE_SENSITIVE != 0);
+
+    Ok(())
+}
+
+extern "C" {
+    fn copyfile(from: *const c_char, to: *const c_char, state: *mut c_void, flags: u32) -> c_int;
+}
+
+const COPYFILE_ACL: u32 = 1 << 0;
+const COPYFILE_STAT: u32 = 1 << 1;
+const COPYFILE_XATTR: u32 = 1 << 2;
+const COPYFILE_DATA: u32 = 1 << 3;
+const COPYFILE_METADATA: u32 = COPYFILE_ACL | COPYFILE_STAT | COPYFILE_XATTR;
+const COPYFILE_ALL: u32 = COPYFILE_METADATA | COPYFILE_DATA;
+const COPYFILE_EXCL: u32 = 1 << 17;
+const COPYFILE_CLONE: u32 = 1 << 24;
+
+fn copy_with_flags(from: &Path, to: &Path, flags: u32) -> Result<()> {
+    let from_str = CString::new(from.as_os_str().as_bytes())?;
+    let to_str = CString::new(to.as_os_str().as_bytes())?;
+    let ret = unsafe {
+        copyfile(from_str.as_ptr(), to_str.as_ptr(), std::ptr::null_mut(), flags)
+    };
+
+    if ret < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// copyfile preserves extended attributes, ACLs and resource forks (which are
+// stored as an extended attribute) that std::fs::copy would lose. Cloning is
+// attempted first in case `from` and `to` are on the same volume after all.
+// COPYFILE_CLONE implies COPYFILE_EXCL so the staging placeholder is removed
+// first. Nothing else is using that name so it's safe to recreate it.
+pub fn copy_file(from: &Path, to: &Path, _progress: &crate::copy::Progress) -> Result<u64> {
+    match std::fs::remove_file(to) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+
+    copy_with_flags(from, to, COPYFILE_ALL | COPYFILE_EXCL | COPYFILE_CLONE)?;
+    Ok(std::fs::symlink_metadata(to)?.len())
+}
+
+pub fn copy_metadata(from: &Path, to: &Path) -> Result<()> {
+    copy_with_flags(from, to, COPYFILE_METADATA)
+}
+
+#[repr(C)]
+struct FlagsBuf {
+    length: u32,
+    flags: u32,
+}
+
+const FSOPT_NOFOLLOW: c_ulong = 1;
+// The file's contents are stored remotely and will be downloaded on access.
+const SF_DATALESS: u32 = 0x40000000;
+
+// Getting the attributes of a dataless file doesn't cause it to be downloaded.
+pub fn is_placeholder(path: &Path, _options: &RenameOptions) -> Result<bool> {
+    let path_str = CString::new(path.as_os_str().as_bytes())?;
+    let mut list = attrlist {
+        bitmapcount: ATTR_BIT_MAP_COUNT,
+        reserved: 0,
+        commonattr: ATTR_CMN_FLAGS,
+        volattr: 0,
+        dirattr: 0,
+        fileattr: 0,
+        forkattr: 0,
+    };
+    let mut buf = FlagsBuf { length: 0, flags: 0 };
+
+    let ret = crate::retry_interrupted(|| unsafe {
         getattrlist(
             path_str.as_ptr(),
             std::ptr::addr_of_mut!(list),
-            buf.as_mut_ptr(),
-            std::mem::size_of::<AttributeBuf>(),
-            0
+            std::ptr::addr_of_mut!(buf) as *mut c_void,
+            std::mem::size_of::<FlagsBuf>(),
+            FSOPT_NOFOLLOW,
         )
-    };
+    });
 
     if ret == -1 {
         return Err(Error::last_os_error());
     }
 
-    let attrs = unsafe { buf.assume_init_ref() };
-    let capabilities = attrs.volume.capabilities[VOL_CAPABILITIES_INTERFACES];
+    Ok(buf.flags & SF_DATALESS != 0)
+}
+
+extern "C" {
+    fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+}
+
+const F_GETPROTECTIONCLASS: c_int = 63;
+const F_SETPROTECTIONCLASS: c_int = 64;
+
+// Directories have a class too. It's the default for files created in them.
+pub fn copy_protection_class(from: &Path, to: &Path) -> Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let from_file = std::fs::File::open(from)?;
+    let class = unsafe { fcntl(from_file.as_raw_fd(), F_GETPROTECTIONCLASS) };
+
+    if class == -1 {
+        let error = Error::last_os_error();
+        // ENOTSUP is returned if the volume doesn't support data protection.
+        return if error.kind() == ErrorKind::Unsupported { Ok(()) } else { Err(error) };
+    }
+
+    let to_file = std::fs::File::open(to)?;
+
+    if unsafe { fcntl(to_file.as_raw_fd(), F_SETPROTECTIONCLASS, class) } == -1 {
+        return Err(Error::last_os_error());
+    }
 
-    Ok(capabilities & VOL_CAP_INT_RENAME_EXCL != 0)
+    Ok(())
 }
@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A mail directory that messages can be delivered to.
+///
+/// A maildir has three subdirectories: `tmp`, `new` and `cur`. A message is
+/// written to `tmp` under a unique name and then renamed into `new` once it's
+/// complete, so readers never see a partial message. The rename uses
+/// [`rename_exclusive_fallback`](crate::rename_exclusive_fallback) so that a
+/// message can't replace another one if two writers somehow pick the same
+/// name.
+///
+/// Names look like `1700000000.M123456P4321Q7.hostname`. That's the time in
+/// seconds, the microseconds, the process ID and a counter that's unique
+/// within the process, followed by the host name. `/` and `:` in the host name
+/// are written as `\057` and `\072`.
+///
+/// # Examples
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// # let dir = tempfile::tempdir()?;
+/// let maildir = renamore::Maildir::create(dir.path().join("Mail"))?;
+/// let path = maildir.deliver(b"Subject: Hello\r\n\r\nHi!\r\n")?;
+///
+/// assert!(path.starts_with(maildir.path().join("new")));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Maildir {
+    path: PathBuf,
+}
+
+impl Maildir {
+    /// Use an existing maildir.
+    ///
+    /// The subdirectories aren't checked until a message is delivered.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Create a maildir, along with its subdirectories, if it doesn't exist.
+    pub fn create<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let maildir = Self::new(path);
+
+        for subdirectory in ["tmp", "new", "cur"] {
+            std::fs::create_dir_all(maildir.path.join(subdirectory))?;
+        }
+
+        Ok(maildir)
+    }
+
+    /// The path to the maildir.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Deliver a message and return its path in `new`.
+    pub fn deliver(&self, message: &[u8]) -> Result<PathBuf> {
+        self.deliver_with(|file| file.write_all(message))
+    }
+
+    /// Deliver a message that's written by a function and return its path in
+    /// `new`.
+    ///
+    /// The function is given the file in `tmp`. The message is synced to disk
+    /// before it's renamed into `new`. If the function fails, then the file is
+    /// removed and the message isn't delivered.
+    pub fn deliver_with<F>(&self, write: F) -> Result<PathBuf>
+    where
+        F: FnOnce(&mut File) -> Result<()>,
+    {
+        let (name, mut file) = self.create_tmp()?;
+        let tmp = self.path.join("tmp").join(&name);
+        let new = self.path.join("new").join(&name);
+
+        let result = write(&mut file).and_then(|()| file.sync_all()).and_then(|()| {
+            crate::RenameOptions::new().durable(true).rename_exclusive_fallback(&tmp, &new)
+        });
+
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e);
+        }
+
+        Ok(new)
+    }
+
+    // A name that's already in tmp is skipped. That would only happen if
+    // another process on the same host had the same ID and counter.
+    fn create_tmp(&self) -> Result<(String, File)> {
+        let tmp = self.path.join("tmp");
+
+        loop {
+            let name = unique_name();
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(tmp.join(&name));
+
+            match file {
+                Ok(file) => return Ok((name, file)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+fn unique_name() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    format!(
+        "{}.M{}P{}Q{}.{}",
+        now.as_secs(),
+        now.subsec_micros(),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        hostname().replace('/', "\\057").replace(':', "\\072"),
+    )
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    use std::ffi::{c_char, c_int, CStr};
+
+    extern "C" {
+        fn gethostname(name: *mut c_char, len: usize) -> c_int;
+    }
+
+    let mut buf = [0 as c_char; 256];
+
+    // The name might not be terminated if it was truncated.
+    if unsafe { gethostname(buf.as_mut_ptr(), buf.len() - 1) } == -1 {
+        return "localhost".to_string();
+    }
+
+    unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+}
@@ -0,0 +1,299 @@
+//! A command line interface for renamore.
+//!
+//! ```text
+//! renamore mv [-n | --no-clobber] [--fallback] SOURCE DEST
+//! renamore mv --exchange A B
+//! renamore mv --unique SOURCE DEST
+//! renamore mv --check SOURCE DEST
+//! ```
+//!
+//! Without any options, `mv` behaves like `mv -n` except that the destination
+//! can't be created by something else between the check and the rename. If
+//! `DEST` is a directory, `SOURCE` is moved into it.
+//!
+//! This is only built with the `cli` feature.
+
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+usage: renamore mv [-n | --no-clobber] [--fallback] SOURCE DEST
+       renamore mv --exchange A B
+       renamore mv --unique SOURCE DEST
+       renamore mv --check SOURCE DEST
+
+Renames SOURCE to DEST without replacing DEST if it exists. This is atomic so
+DEST can't be created by something else in between.
+
+options:
+  -n, --no-clobber  Fail if DEST exists (the default)
+      --fallback    Use a non-atomic fallback if an atomic rename isn't supported
+      --exchange    Atomically swap A and B
+      --unique      Add a number to the name of DEST until it doesn't exist and
+                    print the path that was used
+      --check       Exit with 0 if the rename would be atomic and 1 otherwise,
+                    without renaming anything
+  -h, --help        Print this message";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    NoClobber,
+    Exchange,
+    Unique,
+    Check,
+}
+
+#[derive(Debug)]
+struct Args {
+    mode: Mode,
+    fallback: bool,
+    paths: Vec<PathBuf>,
+}
+
+enum Command {
+    Help,
+    Move(Args),
+}
+
+fn parse(args: impl IntoIterator<Item = OsString>) -> std::result::Result<Command, String> {
+    let mut args = args.into_iter();
+    let mut mode = None;
+    let mut fallback = false;
+    let mut paths = Vec::new();
+    let mut options_done = false;
+
+    match args.next() {
+        Some(command) if command == "mv" => {}
+        Some(command) if command == "-h" || command == "--help" => return Ok(Command::Help),
+        Some(command) => return Err(format!("unknown command {:?}", command)),
+        None => return Err("no command given".to_string()),
+    }
+
+    for arg in args {
+        let new_mode = match arg.to_str() {
+            _ if options_done => None,
+            Some("--") => {
+                options_done = true;
+                continue;
+            }
+            Some("-h" | "--help") => return Ok(Command::Help),
+            Some("-n" | "--no-clobber") => Some(Mode::NoClobber),
+            Some("--exchange") => Some(Mode::Exchange),
+            Some("--unique") => Some(Mode::Unique),
+            Some("--check") => Some(Mode::Check),
+            Some("--fallback") => {
+                fallback = true;
+                continue;
+            }
+            Some(option) if option.starts_with('-') && option != "-" => {
+                return Err(format!("unknown option {}", option));
+            }
+            _ => None,
+        };
+
+        match new_mode {
+            Some(new_mode) if mode.is_some_and(|mode| mode != new_mode) => {
+                return Err(
+                    "only one of --no-clobber, --exchange, --unique and --check can be given"
+                        .to_string(),
+                );
+            }
+            Some(new_mode) => mode = Some(new_mode),
+            None => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let mode = mode.unwrap_or(Mode::NoClobber);
+
+    if paths.len() != 2 {
+        return Err(format!("expected 2 paths but got {}", paths.len()));
+    }
+    if fallback && mode == Mode::Exchange {
+        return Err("--fallback can't be used with --exchange".to_string());
+    }
+
+    Ok(Command::Move(Args { mode, fallback, paths }))
+}
+
+// Moving into a directory works like mv.
+fn destination(from: &Path, to: &Path) -> PathBuf {
+    match from.file_name() {
+        Some(name) if to.is_dir() => to.join(name),
+        _ => to.to_path_buf(),
+    }
+}
+
+// The number of numbered names that --unique tries after DEST itself.
+const MAX_UNIQUE: u32 = 10000;
+
+// Numbers are added before the extension so that the file keeps its type:
+// `report.txt` becomes `report-1.txt`, `report-2.txt` and so on.
+fn numbered(path: &Path, number: u32) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+
+    name.push(format!("-{}", number));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+
+    path.with_file_name(name)
+}
+
+fn rename(from: &Path, to: &Path, fallback: bool) -> Result<()> {
+    let options = renamore::RenameOptions::new();
+
+    if !fallback {
+        return Ok(options.rename_exclusive_detailed(from, to)?);
+    }
+
+    if !options.rename_exclusive_fallback_detailed(from, to)? {
+        eprintln!("renamore: warning: {} was renamed non-atomically", from.display());
+    }
+    Ok(())
+}
+
+// Each attempt is exclusive so two processes can't pick the same name. A
+// populated directory is a conflict too.
+fn rename_unique(from: &Path, to: &Path, fallback: bool) -> Result<PathBuf> {
+    let mut candidate = to.to_path_buf();
+
+    for number in 1..=MAX_UNIQUE {
+        match rename(from, &candidate, fallback) {
+            Ok(()) => return Ok(candidate),
+            Err(e) if renamore::error::is_destination_conflict(&e) => {
+                candidate = numbered(to, number);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    match rename(from, &candidate, fallback) {
+        Err(e) if renamore::error::is_destination_conflict(&e) => Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} and {} numbered names after it all exist", to.display(), MAX_UNIQUE),
+        )),
+        result => result.map(|()| candidate),
+    }
+}
+
+fn run(args: Args) -> Result<ExitCode> {
+    let [from, to] = <[PathBuf; 2]>::try_from(args.paths).unwrap();
+
+    match args.mode {
+        Mode::NoClobber => {
+            rename(&from, &destination(&from, &to), args.fallback)?;
+        }
+        Mode::Exchange => {
+            renamore::rename_exchange_detailed(&from, &to)?;
+        }
+        Mode::Unique => {
+            let to = rename_unique(&from, &destination(&from, &to), args.fallback)?;
+            println!("{}", to.display());
+        }
+        Mode::Check => {
+            let to = destination(&from, &to);
+
+            if !renamore::rename_exclusive_is_atomic_for(&from, &to)? {
+                println!("not atomic");
+                return Ok(ExitCode::FAILURE);
+            }
+            println!("atomic");
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    let args = match parse(std::env::args_os().skip(1)) {
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Ok(Command::Move(args)) => args,
+        Err(message) => {
+            eprintln!("renamore: {}\n\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    run(args).unwrap_or_else(|e: Error| {
+        eprintln!("renamore: {}", e);
+        ExitCode::FAILURE
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::*;
+
+    fn parse(args: &[&str]) -> std::result::Result<Args, String> {
+        match super::parse(args.iter().map(OsString::from))? {
+            Command::Move(args) => Ok(args),
+            Command::Help => Err("help".to_string()),
+        }
+    }
+
+    #[test]
+    fn parse_args() {
+        let args = parse(&["mv", "a", "b"]).unwrap();
+        assert_eq!(args.mode, Mode::NoClobber);
+        assert_eq!(args.paths, [Path::new("a"), Path::new("b")]);
+
+        let args = parse(&["mv", "--unique", "--fallback", "a", "b"]).unwrap();
+        assert_eq!(args.mode, Mode::Unique);
+        assert!(args.fallback);
+
+        let args = parse(&["mv", "--", "--check", "b"]).unwrap();
+        assert_eq!(args.mode, Mode::NoClobber);
+        assert_eq!(args.paths[0], Path::new("--check"));
+
+        assert!(parse(&["mv", "a"]).is_err());
+        assert!(parse(&["mv", "--exchange", "--unique", "a", "b"]).is_err());
+        assert!(parse(&["mv", "--exchange", "--fallback", "a", "b"]).is_err());
+        assert!(parse(&["mv", "--force", "a", "b"]).is_err());
+        assert!(parse(&["cp", "a", "b"]).is_err());
+    }
+
+    #[test]
+    fn unique() -> Result<()> {
+        assert_eq!(numbered(Path::new("dir/report.txt"), 2), Path::new("dir/report-2.txt"));
+        assert_eq!(numbered(Path::new("dir/report"), 1), Path::new("dir/report-1"));
+
+        let dir = tempfile::tempdir()?;
+        let from = dir.path().join("from");
+        let to = dir.path().join("to.txt");
+
+        std::fs::write(&to, "to")?;
+        std::fs::write(dir.path().join("to-1.txt"), "to")?;
+        std::fs::write(&from, "from")?;
+
+        let args = Args { mode: Mode::Unique, fallback: true, paths: vec![from, to] };
+        assert_eq!(run(args)?, ExitCode::SUCCESS);
+        assert_eq!(std::fs::read_to_string(dir.path().join("to-2.txt"))?, "from");
+
+        Ok(())
+    }
+
+    #[test]
+    fn unique_directory() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let from = dir.path().join("from");
+        let to = dir.path().join("to");
+
+        std::fs::create_dir(&from)?;
+        std::fs::create_dir(&to)?;
+        std::fs::create_dir(to.join("from"))?;
+        std::fs::write(to.join("from").join("file"), "file")?;
+
+        let args = Args { mode: Mode::Unique, fallback: true, paths: vec![from, to.clone()] };
+        assert_eq!(run(args)?, ExitCode::SUCCESS);
+        assert!(to.join("from-1").is_dir());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+use std::io::Result;
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::MetadataExt;
+
+// statfs only gives a magic number. That's the same for every FUSE file system
+// and doesn't say how something was mounted. The mount table has the name that
+// was given to mount (like fuse.sshfs) along with the options.
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mount {
+    pub device: (u32, u32),
+    pub mount_point: PathBuf,
+    pub file_system: String,
+    pub source: String,
+    // The per-mount options followed by the per-superblock options.
+    pub options: Vec<String>,
+}
+
+pub fn find(path: &Path) -> Result<Option<Mount>> {
+    let path = path.canonicalize()?;
+    let device = split_device(path.metadata()?.dev());
+    let mounts = parse(&std::fs::read_to_string("/proc/self/mountinfo")?);
+
+    Ok(find_in(mounts, &path, device))
+}
+
+// The longest mount point that contains the path is the one that it's on. If
+// something has been mounted over the top of something else, the last one
+// wins. The device is checked first so that a bind mount of a btrfs subvolume
+// isn't confused with the subvolume it's mounted inside of.
+pub fn find_in(mounts: Vec<Mount>, path: &Path, device: (u32, u32)) -> Option<Mount> {
+    let mut best: Option<Mount> = None;
+
+    for mount in mounts {
+        if !path.starts_with(&mount.mount_point) {
+            continue;
+        }
+
+        let better = match &best {
+            None => true,
+            Some(best) => {
+                let key = |m: &Mount| (m.device == device, m.mount_point.as_os_str().len());
+                key(&mount) >= key(best)
+            }
+        };
+
+        if better {
+            best = Some(mount);
+        }
+    }
+
+    best
+}
+
+// See proc_pid_mountinfo(5). Lines look like this:
+//
+// 36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 - ext3 /dev/root rw,errors=continue
+pub fn parse(mountinfo: &str) -> Vec<Mount> {
+    mountinfo.lines().filter_map(parse_line).collect()
+}
+
+fn parse_line(line: &str) -> Option<Mount> {
+    let mut fields = line.split(' ');
+    let _id = fields.next()?;
+    let _parent = fields.next()?;
+    let (major, minor) = fields.next()?.split_once(':')?;
+    let _root = fields.next()?;
+    let mount_point = unescape(fields.next()?);
+    let mut options: Vec<String> = fields.next()?.split(',').map(String::from).collect();
+
+    // There are zero or more optional fields terminated by a hyphen.
+    fields.find(|field| *field == "-")?;
+
+    let file_system = unescape_string(fields.next()?);
+    let source = unescape_string(fields.next()?);
+    options.extend(fields.next()?.split(',').map(String::from));
+
+    Some(Mount {
+        device: (major.parse().ok()?, minor.parse().ok()?),
+        mount_point: PathBuf::from(OsString::from_vec(mount_point)),
+        file_system,
+        source,
+        options,
+    })
+}
+
+// Spaces, tabs, newlines and backslashes are written as octal escapes.
+fn unescape(field: &str) -> Vec<u8> {
+    let bytes = field.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            let digits = std::str::from_utf8(&bytes[i + 1..i + 4]).ok();
+            if let Some(byte) = digits.and_then(|d| u8::from_str_radix(d, 8).ok()) {
+                result.push(byte);
+                i += 4;
+                continue;
+            }
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+
+    result
+}
+
+fn unescape_string(field: &str) -> String {
+    String::from_utf8_lossy(&unescape(field)).into_owned()
+}
+
+// This is the encoding used by glibc and musl for dev_t.
+fn split_device(dev: u64) -> (u32, u32) {
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    (major as u32, minor as u32)
+}
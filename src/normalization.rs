@@ -0,0 +1,37 @@
+use std::path::Path;
+use std::io::{Error, ErrorKind, Result};
+use unicode_normalization::UnicodeNormalization;
+
+// Two names that only differ in how they're normalized look identical to a
+// user. Whether the file system treats them as the same file varies. This
+// checks the directory for a variant of the name so that it's treated as a
+// conflict everywhere. Names that aren't valid UTF-8 are left alone.
+
+pub fn check_collision(to: &Path) -> Result<()> {
+    let name = match to.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+    let normalized: String = name.nfc().collect();
+    let parent = match to.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    for entry in std::fs::read_dir(parent)? {
+        let entry_name = entry?.file_name();
+        let entry_name = match entry_name.to_str() {
+            Some(entry_name) => entry_name,
+            None => continue,
+        };
+
+        if entry_name != name && entry_name.nfc().eq(normalized.chars()) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "a differently normalized variant of the file name exists",
+            ));
+        }
+    }
+
+    Ok(())
+}
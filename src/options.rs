@@ -0,0 +1,1106 @@
+use std::path::Path;
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use crate::{Fallback, FallbackEvent, RenameError, RenameOperation, RenameReport};
+use crate::stats::Counter;
+
+/// Options and flags which can be used to configure how a file is renamed.
+///
+/// The [`rename_exclusive`] and [`rename_exclusive_fallback`] functions are
+/// shorthands for calling the methods of the same name on the default options.
+/// Most options only have an effect on some platforms and are ignored
+/// elsewhere.
+///
+/// [`rename_exclusive`]: crate::rename_exclusive
+/// [`rename_exclusive_fallback`]: crate::rename_exclusive_fallback
+///
+/// With the `serde` feature, the options can be loaded from a configuration
+/// file. They're serialized as a [`RenameOptionsConfig`] so the fields are the
+/// same on every platform and with every set of features. Missing fields take
+/// their default values. The progress callback and the cancellation flag
+/// can't be serialized so they're skipped.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// renamore::RenameOptions::new()
+///     .exact_names(true)
+///     .rename_exclusive("old.txt", "new.txt.")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "RenameOptionsConfig", into = "RenameOptionsConfig"))]
+pub struct RenameOptions {
+    pub(crate) exact_names: bool,
+    pub(crate) ignore_readonly: bool,
+    pub(crate) durable: bool,
+    pub(crate) cross_device: bool,
+    pub(crate) placeholders: PlaceholderPolicy,
+    pub(crate) acl_inheritance: AclInheritance,
+    pub(crate) no_symlinks: bool,
+    pub(crate) no_follow_source: bool,
+    pub(crate) preserve_protection: bool,
+    pub(crate) preserve_security: bool,
+    pub(crate) eperm_unsupported: bool,
+    pub(crate) backend: Backend,
+    #[cfg(feature = "etw")]
+    pub(crate) etw_raw_paths: bool,
+    #[cfg(feature = "normalization")]
+    pub(crate) check_normalization: bool,
+    #[cfg(feature = "apple-coordination")]
+    pub(crate) coordinate: bool,
+    #[cfg(feature = "notify")]
+    pub(crate) detect_races: bool,
+    pub(crate) progress: Option<Arc<ProgressFn>>,
+    pub(crate) cancel: Option<Arc<AtomicBool>>,
+    pub(crate) retries: u32,
+    pub(crate) retry_delay: Duration,
+}
+
+type ProgressFn = dyn Fn(u64, u64) + Send + Sync;
+
+/// What to do when renaming a cloud placeholder file.
+///
+/// Cloud sync providers (like OneDrive and iCloud Drive) represent files that
+/// haven't been downloaded as placeholders. Renaming a placeholder can cause it
+/// to be downloaded or change whether it's kept available offline. See
+/// [`RenameOptions::placeholders`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum PlaceholderPolicy {
+    /// Rename placeholders like any other file and leave the rest to the sync
+    /// provider.
+    #[default]
+    Ignore,
+
+    /// Download the contents of the placeholder before renaming it.
+    Hydrate,
+
+    /// Rename the placeholder without it inheriting the pin state of its new
+    /// parent directory.
+    SuppressPinState,
+
+    /// Fail with a [`PlaceholderError`](crate::PlaceholderError) instead of
+    /// renaming the placeholder.
+    Fail,
+}
+
+/// Which implementation is used to rename a file.
+///
+/// This is for tests (and for working around bugs) rather than normal use. It
+/// makes it possible to exercise a fallback on a machine where the native
+/// implementation always works. See [`RenameOptions::backend`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Backend {
+    /// Use the native implementation and fall back if it isn't supported.
+    #[default]
+    Auto,
+
+    /// Only use the native implementation. The non-atomic fallback is never
+    /// used, even by
+    /// [`RenameOptions::rename_exclusive_fallback`].
+    NativeOnly,
+
+    /// Act as if the native implementation isn't supported and use the
+    /// emulation that reserves the destination path before renaming onto it.
+    /// This is what ESP-IDF, GNU Hurd and z/OS use.
+    EmulateOnly,
+
+    /// Act as if the native implementation isn't supported and use the
+    /// fallback that checks whether the destination path exists before
+    /// renaming onto it.
+    NonAtomicOnly,
+}
+
+/// What happens to the permissions of a file after it's moved to a different
+/// directory.
+///
+/// See [`RenameOptions::acl_inheritance`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum AclInheritance {
+    /// Keep the permissions that the file had before it was moved, including
+    /// those inherited from its old directory.
+    #[default]
+    Keep,
+
+    /// Keep the permissions that were set explicitly on the file but replace
+    /// those inherited from its old directory with those inherited from its new
+    /// directory.
+    Inherit,
+
+    /// Discard all permissions of the file and only inherit from its new
+    /// directory.
+    Reset,
+}
+
+impl std::fmt::Debug for RenameOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut f = f.debug_struct("RenameOptions");
+        f.field("exact_names", &self.exact_names)
+            .field("ignore_readonly", &self.ignore_readonly)
+            .field("durable", &self.durable)
+            .field("cross_device", &self.cross_device)
+            .field("placeholders", &self.placeholders)
+            .field("acl_inheritance", &self.acl_inheritance)
+            .field("no_symlinks", &self.no_symlinks)
+            .field("no_follow_source", &self.no_follow_source)
+            .field("preserve_protection", &self.preserve_protection)
+            .field("preserve_security", &self.preserve_security)
+            .field("eperm_unsupported", &self.eperm_unsupported)
+            .field("backend", &self.backend)
+            .field("progress", &self.progress.is_some())
+            .field("cancel", &self.cancel)
+            .field("retries", &self.retries)
+            .field("retry_delay", &self.retry_delay);
+        #[cfg(feature = "etw")]
+        f.field("etw_raw_paths", &self.etw_raw_paths);
+        #[cfg(feature = "normalization")]
+        f.field("check_normalization", &self.check_normalization);
+        #[cfg(feature = "apple-coordination")]
+        f.field("coordinate", &self.coordinate);
+        #[cfg(feature = "notify")]
+        f.field("detect_races", &self.detect_races);
+        f.finish()
+    }
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        crate::config::defaults().unwrap_or_else(Self::builtin)
+    }
+}
+
+/// The options from [`RenameOptions`] that can be written to a configuration
+/// file.
+///
+/// This has a public field for each option that has a setter method of the
+/// same name. Every field is present on every platform and with every set of
+/// features so that a configuration file means the same thing everywhere.
+/// Options that need a feature that isn't enabled are ignored. The progress
+/// callback and the cancellation flag are left out.
+///
+/// With the `serde` feature, this implements `Serialize` and `Deserialize` and
+/// missing fields take their default values. [`RenameOptions`] is serialized
+/// through this type.
+///
+/// # Examples
+///
+/// ```
+/// use renamore::{RenameOptions, RenameOptionsConfig};
+///
+/// let mut config = RenameOptionsConfig::default();
+/// config.durable = true;
+///
+/// let options = RenameOptions::from(config);
+/// assert!(RenameOptionsConfig::from(&options).durable);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[non_exhaustive]
+pub struct RenameOptionsConfig {
+    /// See [`RenameOptions::exact_names`].
+    pub exact_names: bool,
+    /// See [`RenameOptions::ignore_readonly`].
+    pub ignore_readonly: bool,
+    /// See [`RenameOptions::durable`].
+    pub durable: bool,
+    /// See [`RenameOptions::cross_device`].
+    pub cross_device: bool,
+    /// See [`RenameOptions::placeholders`].
+    pub placeholders: PlaceholderPolicy,
+    /// See [`RenameOptions::acl_inheritance`].
+    pub acl_inheritance: AclInheritance,
+    /// See [`RenameOptions::no_symlinks`].
+    pub no_symlinks: bool,
+    /// See [`RenameOptions::no_follow_source`].
+    pub no_follow_source: bool,
+    /// See [`RenameOptions::preserve_protection`].
+    pub preserve_protection: bool,
+    /// See [`RenameOptions::preserve_security`].
+    pub preserve_security: bool,
+    /// See [`RenameOptions::eperm_unsupported`].
+    pub eperm_unsupported: bool,
+    /// See [`RenameOptions::backend`].
+    pub backend: Backend,
+    /// See `RenameOptions::etw_raw_paths`. This needs the `etw` feature.
+    pub etw_raw_paths: bool,
+    /// See `RenameOptions::check_normalization`. This needs the
+    /// `normalization` feature.
+    pub check_normalization: bool,
+    /// See `RenameOptions::coordinate`. This needs the `apple-coordination`
+    /// feature.
+    pub coordinate: bool,
+    /// See `RenameOptions::detect_races`. This needs the `notify` feature.
+    pub detect_races: bool,
+    /// The number of retries. See [`RenameOptions::retry`].
+    pub retries: u32,
+    /// The delay before the first retry. See [`RenameOptions::retry`].
+    pub retry_delay: Duration,
+}
+
+impl Default for RenameOptionsConfig {
+    fn default() -> Self {
+        Self::from(&RenameOptions::default())
+    }
+}
+
+impl From<&RenameOptions> for RenameOptionsConfig {
+    fn from(options: &RenameOptions) -> Self {
+        Self {
+            exact_names: options.exact_names,
+            ignore_readonly: options.ignore_readonly,
+            durable: options.durable,
+            cross_device: options.cross_device,
+            placeholders: options.placeholders,
+            acl_inheritance: options.acl_inheritance,
+            no_symlinks: options.no_symlinks,
+            no_follow_source: options.no_follow_source,
+            preserve_protection: options.preserve_protection,
+            preserve_security: options.preserve_security,
+            eperm_unsupported: options.eperm_unsupported,
+            backend: options.backend,
+            #[cfg(feature = "etw")]
+            etw_raw_paths: options.etw_raw_paths,
+            #[cfg(not(feature = "etw"))]
+            etw_raw_paths: false,
+            #[cfg(feature = "normalization")]
+            check_normalization: options.check_normalization,
+            #[cfg(not(feature = "normalization"))]
+            check_normalization: false,
+            #[cfg(feature = "apple-coordination")]
+            coordinate: options.coordinate,
+            #[cfg(not(feature = "apple-coordination"))]
+            coordinate: false,
+            #[cfg(feature = "notify")]
+            detect_races: options.detect_races,
+            #[cfg(not(feature = "notify"))]
+            detect_races: false,
+            retries: options.retries,
+            retry_delay: options.retry_delay,
+        }
+    }
+}
+
+impl From<RenameOptions> for RenameOptionsConfig {
+    fn from(options: RenameOptions) -> Self {
+        Self::from(&options)
+    }
+}
+
+impl From<RenameOptionsConfig> for RenameOptions {
+    fn from(config: RenameOptionsConfig) -> Self {
+        Self {
+            exact_names: config.exact_names,
+            ignore_readonly: config.ignore_readonly,
+            durable: config.durable,
+            cross_device: config.cross_device,
+            placeholders: config.placeholders,
+            acl_inheritance: config.acl_inheritance,
+            no_symlinks: config.no_symlinks,
+            no_follow_source: config.no_follow_source,
+            preserve_protection: config.preserve_protection,
+            preserve_security: config.preserve_security,
+            eperm_unsupported: config.eperm_unsupported,
+            backend: config.backend,
+            #[cfg(feature = "etw")]
+            etw_raw_paths: config.etw_raw_paths,
+            #[cfg(feature = "normalization")]
+            check_normalization: config.check_normalization,
+            #[cfg(feature = "apple-coordination")]
+            coordinate: config.coordinate,
+            #[cfg(feature = "notify")]
+            detect_races: config.detect_races,
+            progress: None,
+            cancel: None,
+            retries: config.retries,
+            retry_delay: config.retry_delay,
+        }
+    }
+}
+
+impl RenameOptions {
+    // The defaults when nothing has been configured.
+    pub(crate) fn builtin() -> Self {
+        Self {
+            exact_names: false,
+            ignore_readonly: false,
+            durable: false,
+            cross_device: false,
+            placeholders: PlaceholderPolicy::Ignore,
+            acl_inheritance: AclInheritance::Keep,
+            no_symlinks: false,
+            no_follow_source: false,
+            preserve_protection: false,
+            preserve_security: false,
+            eperm_unsupported: false,
+            backend: Backend::Auto,
+            #[cfg(feature = "etw")]
+            etw_raw_paths: false,
+            #[cfg(feature = "normalization")]
+            check_normalization: false,
+            #[cfg(feature = "apple-coordination")]
+            coordinate: false,
+            #[cfg(feature = "notify")]
+            detect_races: false,
+            progress: None,
+            cancel: None,
+            retries: 5,
+            retry_delay: Duration::from_millis(10),
+        }
+    }
+}
+
+impl RenameOptions {
+    /// Create a blank set of options.
+    ///
+    /// All flags are initially set to `false`. Transient failures are retried
+    /// 5 times starting with a delay of 10 milliseconds. If different defaults
+    /// have been set with [`configure`](crate::configure), then those are used
+    /// instead.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pass file names to the file system exactly as they are given.
+    ///
+    /// # Platform-specific behaviour
+    ///
+    /// On Windows, file names that end with a dot or a space are silently
+    /// trimmed and device names (like `CON` or `aux.txt`) refer to devices
+    /// rather than files. By default, renaming to such a name will fail with
+    /// [`ErrorKind::InvalidInput`]. If this option is set, then the verbatim
+    /// (`\\?\`) form of the paths will be used so that the names are taken
+    /// literally. Note that many programs will have trouble opening these
+    /// files.
+    ///
+    /// This option is ignored on other platforms.
+    ///
+    /// [`ErrorKind::InvalidInput`]: std::io::ErrorKind::InvalidInput
+    pub fn exact_names(&mut self, exact_names: bool) -> &mut Self {
+        self.exact_names = exact_names;
+        self
+    }
+
+    /// Rename files and directories even if they have the read-only
+    /// attribute.
+    ///
+    /// # Platform-specific behaviour
+    ///
+    /// On Windows, this passes `FILE_RENAME_FLAG_IGNORE_READONLY_ATTRIBUTE` to
+    /// `SetFileInformationByHandle`. If the rename falls back to `MoveFileExW`
+    /// and access is denied, then the read-only attribute is cleared for the
+    /// duration of the rename and restored afterwards.
+    ///
+    /// This option is ignored on other platforms. The read-only permission of
+    /// a file doesn't affect whether it can be renamed.
+    pub fn ignore_readonly(&mut self, ignore_readonly: bool) -> &mut Self {
+        self.ignore_readonly = ignore_readonly;
+        self
+    }
+
+    /// Don't return until the rename has been written to disk.
+    ///
+    /// Without this, a rename that has succeeded may be lost if the system
+    /// crashes shortly afterwards.
+    ///
+    /// # Platform-specific behaviour
+    ///
+    /// On Unix, the directories containing `from` and `to` are synced after
+    /// renaming. On Windows, `MoveFileExW` is given `MOVEFILE_WRITE_THROUGH`, or
+    /// the directory containing `to` is flushed when renaming by handle.
+    ///
+    /// # Errors
+    ///
+    /// If the rename succeeds but syncing a directory fails, then the error has
+    /// [`ErrorKind::Other`] and wraps a [`DurabilityError`]. The file has been
+    /// renamed but it isn't known whether the rename is on disk.
+    ///
+    /// [`ErrorKind::Other`]: std::io::ErrorKind::Other
+    /// [`DurabilityError`]: crate::DurabilityError
+    pub fn durable(&mut self, durable: bool) -> &mut Self {
+        self.durable = durable;
+        self
+    }
+
+    /// Move files and directories across file systems by copying them.
+    ///
+    /// A rename can't move anything to a different file system (or volume). If
+    /// this option is set and the rename fails for that reason, then `from`
+    /// is copied to a staging path next to `to` and the staging path is
+    /// renamed to `to`. `from` is removed afterwards. If anything goes wrong
+    /// while copying, the staging path is removed and `from` is left alone.
+    ///
+    /// This is not atomic. Something could observe `from` and `to` existing at
+    /// the same time. Although, `to` will never be overwritten or observed
+    /// partially copied. Symlinks are copied rather than followed.
+    ///
+    /// # Platform-specific behaviour
+    ///
+    /// On Windows, files are copied with `CopyFileExW`, which clones blocks on
+    /// ReFS since Windows 11 24H2. On Darwin, files and the metadata of
+    /// directories are copied with `copyfile` so that extended attributes, ACLs
+    /// and resource forks are preserved. Files are cloned with
+    /// `COPYFILE_CLONE` where the volume supports it (like APFS). On Linux,
+    /// files are cloned with the `FICLONE` ioctl (btrfs, XFS and bcachefs).
+    /// Otherwise, `copy_file_range` is used so that the data stays in the
+    /// kernel, with reading and writing as a last resort. On other platforms,
+    /// only the permissions are preserved.
+    pub fn cross_device(&mut self, cross_device: bool) -> &mut Self {
+        self.cross_device = cross_device;
+        self
+    }
+
+    /// Choose what happens when renaming a cloud placeholder file.
+    ///
+    /// # Platform-specific behaviour
+    ///
+    /// On Windows, placeholders are detected by their reparse tag.
+    /// [`PlaceholderPolicy::SuppressPinState`] passes
+    /// `FILE_RENAME_FLAG_SUPPRESS_PIN_STATE_INHERITANCE` to
+    /// `SetFileInformationByHandle`. This is ignored if the rename falls back
+    /// to `MoveFileExW`.
+    ///
+    /// On Darwin, dataless files (such as evicted iCloud Drive files) are
+    /// detected by the `SF_DATALESS` flag.
+    /// [`PlaceholderPolicy::SuppressPinState`] is the same as
+    /// [`PlaceholderPolicy::Ignore`].
+    ///
+    /// On both, [`PlaceholderPolicy::Hydrate`] reads the file through to make
+    /// the sync provider download it. This option is ignored on other
+    /// platforms (and UWP targets).
+    pub fn placeholders(&mut self, policy: PlaceholderPolicy) -> &mut Self {
+        self.placeholders = policy;
+        self
+    }
+
+    /// Choose what happens to the permissions of a file after it's moved.
+    ///
+    /// A rename doesn't change the permissions of a file. When a file is moved
+    /// into a shared directory, it may not end up with the permissions that
+    /// other files in that directory have.
+    ///
+    /// # Platform-specific behaviour
+    ///
+    /// On Windows, the DACL of the file is set with `SetNamedSecurityInfoW`
+    /// and `UNPROTECTED_DACL_SECURITY_INFORMATION` after renaming. If that
+    /// fails, then an error is returned even though the file has been renamed.
+    ///
+    /// This option is ignored on other platforms (and UWP targets). Unix
+    /// permissions aren't inherited from directories.
+    pub fn acl_inheritance(&mut self, acl_inheritance: AclInheritance) -> &mut Self {
+        self.acl_inheritance = acl_inheritance;
+        self
+    }
+
+    /// Write the paths to ETW events as they are rather than hashing them.
+    ///
+    /// By default, paths are hashed so that traces can be correlated without
+    /// revealing file names. The hash is keyed with a random value chosen by
+    /// each process, so it's only consistent within one process. The hash
+    /// function isn't stable and may change in any version. This is only
+    /// available with the `etw` feature and only has an effect on Windows.
+    #[cfg(feature = "etw")]
+    pub fn etw_raw_paths(&mut self, raw: bool) -> &mut Self {
+        self.etw_raw_paths = raw;
+        self
+    }
+
+    /// Refuse to follow symlinks anywhere in the paths.
+    ///
+    /// This protects against a directory in `from` or `to` being replaced by
+    /// a symlink to somewhere else. If a symlink is encountered, then the
+    /// rename fails. Use [`Capabilities::no_symlinks`] to check whether this is
+    /// supported. The non-atomic fallback is never used with this option.
+    ///
+    /// [`Capabilities::no_symlinks`]: crate::Capabilities::no_symlinks
+    ///
+    /// # Platform-specific behaviour
+    ///
+    /// On Darwin, this passes `RENAME_NOFOLLOW_ANY` to `renamex_np`. This
+    /// requires macOS 13, iOS 16, tvOS 16 or watchOS 9. On all other platforms
+    /// (and older versions), renaming with this option set returns
+    /// [`ErrorKind::Unsupported`].
+    ///
+    /// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+    pub fn no_symlinks(&mut self, no_symlinks: bool) -> &mut Self {
+        self.no_symlinks = no_symlinks;
+        self
+    }
+
+    /// Refuse to rename `from` if it's a symlink.
+    ///
+    /// A rename never follows a symlink at `from`. The link itself is moved and
+    /// its target is left alone. That can still come as a surprise to a tool
+    /// that expects `from` to be the file it created, if something else
+    /// replaced it with a symlink in the meantime. With this option set,
+    /// `from` is checked without following symlinks (like `lstat`) and the
+    /// rename fails with a [`SymlinkError`] if it's a symlink. Otherwise, the
+    /// rename goes ahead as usual.
+    ///
+    /// The check isn't atomic with the rename. It narrows the window rather
+    /// than closing it. Use [`no_symlinks`](Self::no_symlinks) where it's
+    /// supported to have the OS refuse symlinks as part of the rename.
+    ///
+    /// [`SymlinkError`]: crate::SymlinkError
+    pub fn no_follow_source(&mut self, no_follow_source: bool) -> &mut Self {
+        self.no_follow_source = no_follow_source;
+        self
+    }
+
+    /// Treat a permission error from the flagged rename as the operation being
+    /// unsupported.
+    ///
+    /// Container runtimes with older seccomp profiles (such as Docker before
+    /// 20.10) block syscalls they don't know about with `EPERM` instead of
+    /// `ENOSYS`. With this option set, [`rename_exclusive_fallback`] falls back
+    /// in that situation. This is off by default because `EPERM` can also be
+    /// a genuine permission error (like renaming someone else's file in a
+    /// sticky directory) and the fallback would then fail in a less obvious
+    /// way.
+    ///
+    /// [`rename_exclusive_fallback`]: Self::rename_exclusive_fallback
+    ///
+    /// # Platform-specific behaviour
+    ///
+    /// This option is ignored on platforms other than Linux.
+    pub fn eperm_unsupported(&mut self, eperm_unsupported: bool) -> &mut Self {
+        self.eperm_unsupported = eperm_unsupported;
+        self
+    }
+
+    /// Treat a file whose name only differs from `to` in its Unicode
+    /// normalization as a conflict.
+    ///
+    /// HFS+ normalizes file names and APFS ignores normalization when looking
+    /// them up, so renaming to an NFC name fails if an NFD variant exists.
+    /// Most other file systems treat them as different names and allow both
+    /// to exist side by side. With this option set, the directory containing
+    /// `to` is checked for such a variant first and [`ErrorKind::AlreadyExists`]
+    /// is returned if there is one. This check isn't atomic.
+    ///
+    /// This is only available with the `normalization` feature.
+    ///
+    /// [`ErrorKind::AlreadyExists`]: std::io::ErrorKind::AlreadyExists
+    #[cfg(feature = "normalization")]
+    pub fn check_normalization(&mut self, check_normalization: bool) -> &mut Self {
+        self.check_normalization = check_normalization;
+        self
+    }
+
+    /// Coordinate the rename with other processes using the files.
+    ///
+    /// Document-based apps and iCloud containers expect moves to go through
+    /// `NSFileCoordinator` so that anything presenting the files can save
+    /// them first and follow them to their new location. This is slower than
+    /// a plain rename and isn't needed by most command line programs.
+    ///
+    /// This is only available with the `apple-coordination` feature and is
+    /// ignored on platforms other than Darwin.
+    #[cfg(feature = "apple-coordination")]
+    pub fn coordinate(&mut self, coordinate: bool) -> &mut Self {
+        self.coordinate = coordinate;
+        self
+    }
+
+    /// Watch for the destination appearing while the non-atomic fallback is
+    /// running.
+    ///
+    /// The fallback used by
+    /// [`rename_exclusive_fallback`](crate::rename_exclusive_fallback) checks
+    /// that the destination doesn't exist and then renames onto it. Anything
+    /// created at the destination in between is replaced. This can't prevent
+    /// that but with this option set, the directory containing the destination
+    /// is watched during the fallback and
+    /// [`RenameReport::race_detected`](crate::RenameReport::race_detected) is
+    /// set if the destination was created or changed before the rename. Use one
+    /// of the `_with_report` methods to see the report. Something else renaming
+    /// onto the destination isn't detected.
+    ///
+    /// This is only available with the `notify` feature. The watch relies on
+    /// the OS notifying about changes so it doesn't work on network file
+    /// systems.
+    #[cfg(feature = "notify")]
+    pub fn detect_races(&mut self, detect_races: bool) -> &mut Self {
+        self.detect_races = detect_races;
+        self
+    }
+
+    /// Give files copied across file systems the same data protection class as
+    /// the originals.
+    ///
+    /// This only has an effect if [`cross_device`](Self::cross_device) is set.
+    ///
+    /// # Platform-specific behaviour
+    ///
+    /// On Darwin, the class is read with `F_GETPROTECTIONCLASS` and applied to
+    /// the copy with `F_SETPROTECTIONCLASS`. Without this, copies get the
+    /// default class of the directory they're created in. Files on volumes that
+    /// don't support data protection are skipped.
+    ///
+    /// This option is ignored on other platforms.
+    pub fn preserve_protection(&mut self, preserve_protection: bool) -> &mut Self {
+        self.preserve_protection = preserve_protection;
+        self
+    }
+
+    /// Give files copied across file systems the same security labels as the
+    /// originals.
+    ///
+    /// This only has an effect if [`cross_device`](Self::cross_device) is set.
+    ///
+    /// # Platform-specific behaviour
+    ///
+    /// On Linux, the `security.*` and `trusted.*` extended attributes are
+    /// copied. This includes the SELinux context (`security.selinux`), which
+    /// confined services depend on. Without this, copies get the default label
+    /// of the directory they're created in. Setting a label may require
+    /// privileges that the process doesn't have, in which case the error is
+    /// returned. File systems that don't support extended attributes are
+    /// skipped.
+    ///
+    /// This option is ignored on other platforms.
+    pub fn preserve_security(&mut self, preserve_security: bool) -> &mut Self {
+        self.preserve_security = preserve_security;
+        self
+    }
+
+    /// Set a function to be called as files are copied across file systems.
+    ///
+    /// The function is given the number of bytes copied so far and the total
+    /// number of bytes to copy. This only has an effect if
+    /// [`cross_device`](Self::cross_device) is set.
+    ///
+    /// # Platform-specific behaviour
+    ///
+    /// On Windows, files are copied with `CopyFileExW` and this is called
+    /// periodically while copying. On other platforms, this is called after
+    /// each file.
+    pub fn progress<P: Fn(u64, u64) + Send + Sync + 'static>(&mut self, progress: P) -> &mut Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Set a flag that can be used to cancel copying files across file systems.
+    ///
+    /// If the flag is set while copying, then the copy stops as soon as
+    /// possible, the staging path is removed and an error is returned. This
+    /// only has an effect if [`cross_device`](Self::cross_device) is set.
+    ///
+    /// # Platform-specific behaviour
+    ///
+    /// On Windows, files are copied with `CopyFileExW` which checks the flag
+    /// periodically while copying. On other platforms, the flag is only checked
+    /// between files.
+    pub fn cancel(&mut self, cancel: Arc<AtomicBool>) -> &mut Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Choose which implementation is used.
+    ///
+    /// The default is [`Backend::Auto`]. With [`Backend::EmulateOnly`] or
+    /// [`Backend::NonAtomicOnly`], the native implementation is skipped as if
+    /// it weren't supported. [`rename_exclusive`](Self::rename_exclusive)
+    /// never renames non-atomically so it fails with
+    /// [`ErrorKind::Unsupported`] and
+    /// [`rename_exclusive_fallback`](Self::rename_exclusive_fallback) uses the
+    /// chosen fallback. This makes it possible to test how a program behaves
+    /// on a platform or file system without atomic renames.
+    ///
+    /// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # fn main() -> std::io::Result<()> {
+    /// use renamore::{Backend, RenameOptions};
+    ///
+    /// let atomic = RenameOptions::new()
+    ///     .backend(Backend::NonAtomicOnly)
+    ///     .rename_exclusive_fallback("a", "b")?;
+    ///
+    /// assert!(!atomic);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn backend(&mut self, backend: Backend) -> &mut Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Set how transient failures are retried.
+    ///
+    /// A failed operation will be attempted again up to `retries` times. The
+    /// delay between attempts starts at `delay` and doubles each time. Setting
+    /// `retries` to zero disables retrying.
+    ///
+    /// # Platform-specific behaviour
+    ///
+    /// On Windows, `ERROR_SHARING_VIOLATION`, `ERROR_LOCK_VIOLATION` and
+    /// `ERROR_DELETE_PENDING` are considered transient. These are usually
+    /// caused by antivirus software or indexers briefly holding open a handle
+    /// to the file. On other platforms, nothing is considered transient.
+    pub fn retry(&mut self, retries: u32, delay: Duration) -> &mut Self {
+        self.retries = retries;
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Rename a file without overwriting the destination path if it exists.
+    ///
+    /// See [`rename_exclusive`](crate::rename_exclusive).
+    pub fn rename_exclusive<F: AsRef<Path>, T: AsRef<Path>>(&self, from: F, to: T) -> Result<()> {
+        self.rename(from.as_ref(), to.as_ref(), false).0.map(|_| ())
+    }
+
+    /// Rename a file without overwriting the destination path if it exists,
+    /// using a non-atomic fallback if necessary.
+    ///
+    /// See [`rename_exclusive_fallback`](crate::rename_exclusive_fallback).
+    pub fn rename_exclusive_fallback<F: AsRef<Path>, T: AsRef<Path>>(
+        &self,
+        from: F,
+        to: T,
+    ) -> Result<bool> {
+        self.rename(from.as_ref(), to.as_ref(), true).0
+    }
+
+    /// Rename a file without overwriting the destination path if it exists,
+    /// returning a [`RenameError`] with both paths if it fails.
+    ///
+    /// See [`rename_exclusive`](crate::rename_exclusive).
+    pub fn rename_exclusive_detailed<F: AsRef<Path>, T: AsRef<Path>>(
+        &self,
+        from: F,
+        to: T,
+    ) -> std::result::Result<(), RenameError> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+
+        self.rename(from, to, false).0
+            .map(|_| ())
+            .map_err(|e| {
+                RenameError::new(RenameOperation::Exclusive, from.into(), to.into(), e)
+            })
+    }
+
+    /// Rename a file without overwriting the destination path if it exists,
+    /// using a non-atomic fallback if necessary, and returning a
+    /// [`RenameError`] with both paths if it fails.
+    ///
+    /// See [`rename_exclusive_fallback`](crate::rename_exclusive_fallback).
+    pub fn rename_exclusive_fallback_detailed<F: AsRef<Path>, T: AsRef<Path>>(
+        &self,
+        from: F,
+        to: T,
+    ) -> std::result::Result<bool, RenameError> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+
+        self.rename(from, to, true).0
+            .map_err(|e| {
+                RenameError::new(RenameOperation::ExclusiveFallback, from.into(), to.into(), e)
+            })
+    }
+
+    /// Rename a file without overwriting the destination path if it exists,
+    /// returning a [`RenameReport`] that describes how it went.
+    ///
+    /// The report is returned even if the rename fails. See
+    /// [`rename_exclusive`](crate::rename_exclusive).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use renamore::{RenameOptions, Strategy};
+    ///
+    /// let (result, report) = RenameOptions::new().rename_exclusive_with_report("a", "b");
+    ///
+    /// println!("{:?} after {} attempts in {:?}", result, report.attempts, report.elapsed);
+    /// assert_eq!(report.strategy, Strategy::Atomic);
+    /// ```
+    pub fn rename_exclusive_with_report<F: AsRef<Path>, T: AsRef<Path>>(
+        &self,
+        from: F,
+        to: T,
+    ) -> (Result<()>, RenameReport) {
+        let (result, report) = self.rename(from.as_ref(), to.as_ref(), false);
+
+        (result.map(|_| ()), report)
+    }
+
+    /// Rename a file without overwriting the destination path if it exists,
+    /// using a non-atomic fallback if necessary, and returning a
+    /// [`RenameReport`] that describes how it went.
+    ///
+    /// The report is returned even if the rename fails. See
+    /// [`rename_exclusive_fallback`](crate::rename_exclusive_fallback).
+    pub fn rename_exclusive_fallback_with_report<F: AsRef<Path>, T: AsRef<Path>>(
+        &self,
+        from: F,
+        to: T,
+    ) -> (Result<bool>, RenameReport) {
+        self.rename(from.as_ref(), to.as_ref(), true)
+    }
+
+    fn rename(&self, from: &Path, to: &Path, fallback: bool) -> (Result<bool>, RenameReport) {
+        let name = if fallback { "rename_exclusive_fallback" } else { "rename_exclusive" };
+        let operation = crate::instrument::operation(name, from, to);
+        let (result, report) = crate::report::capture(|| self.try_rename(from, to, fallback));
+
+        operation.finish(&result);
+
+        #[cfg(feature = "audit")]
+        crate::audit::record(
+            if fallback { RenameOperation::ExclusiveFallback } else { RenameOperation::Exclusive },
+            from,
+            to,
+            &result,
+            &report,
+        );
+
+        (result, report)
+    }
+
+    fn try_rename(&self, from: &Path, to: &Path, fallback: bool) -> Result<bool> {
+        crate::check_path(from, "from")?;
+        crate::check_path(to, "to")?;
+
+        if self.no_symlinks && !cfg!(target_vendor = "apple") {
+            return Err(ErrorKind::Unsupported.into());
+        }
+
+        #[cfg(feature = "normalization")]
+        if self.check_normalization {
+            crate::normalization::check_collision(to)?;
+        }
+
+        if self.no_follow_source && std::fs::symlink_metadata(from)?.is_symlink() {
+            return Err(Error::new(ErrorKind::InvalidInput, crate::SymlinkError));
+        }
+
+        self.check_placeholder(from)?;
+
+        if let Some(e) = crate::config::injected_failure() {
+            return Err(e);
+        }
+
+        let atomic = match self.with_retry(|| self.rename_once(from, to, fallback)) {
+            Err(e) if self.cross_device && e.kind() == ErrorKind::CrossesDevices => {
+                crate::instrument::fallback(&FallbackEvent::new(Fallback::Copy, from, to, &e));
+                crate::copy::move_across(from, to, self, |staging, to| {
+                    self.with_retry(|| self.rename_once(staging, to, fallback))
+                })
+                    .map_err(|e| destination_error(e, to))?
+            }
+            result => result.map_err(|e| destination_error(e, to))?,
+        };
+        self.sync_parents(from, to)?;
+        Ok(atomic)
+    }
+
+    fn rename_once(&self, from: &Path, to: &Path, fallback: bool) -> Result<bool> {
+        match crate::stats::count_native(|| self.rename_native(from, to)) {
+            Ok(()) => Ok(true),
+            // The fallback would follow symlinks.
+            Err(e) if fallback
+                && self.active_backend() != Backend::NativeOnly
+                && crate::config::allow_non_atomic()
+                && !self.no_symlinks
+                && e.kind() == ErrorKind::Unsupported =>
+            {
+                crate::instrument::fallback(&FallbackEvent::new(Fallback::NonAtomic, from, to, &e));
+                self.rename_non_atomic(from, to)?;
+                crate::stats::add(Counter::Fallbacks, 1);
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn rename_native(&self, from: &Path, to: &Path) -> Result<()> {
+        #[cfg(feature = "test-util")]
+        if let Some(e) = crate::test_util::rename_fault() {
+            return Err(e);
+        }
+
+        match self.active_backend() {
+            Backend::Auto | Backend::NativeOnly => crate::sys::rename_exclusive(from, to, self),
+            Backend::EmulateOnly | Backend::NonAtomicOnly => Err(Error::new(
+                ErrorKind::Unsupported,
+                "the native implementation was disabled with RenameOptions::backend",
+            )),
+        }
+    }
+
+    #[cfg(feature = "notify")]
+    fn rename_non_atomic(&self, from: &Path, to: &Path) -> Result<()> {
+        let rename = self.non_atomic();
+
+        if !self.detect_races {
+            return rename(from, to);
+        }
+
+        let raced = crate::race::watch(to, || rename(from, to))?;
+        crate::report::record(|report| report.race_detected |= raced);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "notify"))]
+    fn rename_non_atomic(&self, from: &Path, to: &Path) -> Result<()> {
+        self.non_atomic()(from, to)
+    }
+
+    fn active_backend(&self) -> Backend {
+        if crate::config::force_non_atomic() {
+            Backend::NonAtomicOnly
+        } else {
+            self.backend
+        }
+    }
+
+    fn non_atomic(&self) -> fn(&Path, &Path) -> Result<()> {
+        match self.active_backend() {
+            Backend::EmulateOnly => crate::posix::rename_exclusive_non_atomic,
+            Backend::NonAtomicOnly => crate::rename_if_absent,
+            Backend::Auto | Backend::NativeOnly => crate::rename_exclusive_non_atomic,
+        }
+    }
+
+    #[cfg(any(
+        all(target_os = "windows", not(target_vendor = "uwp")),
+        target_vendor = "apple",
+    ))]
+    fn check_placeholder(&self, from: &Path) -> Result<()> {
+        match self.placeholders {
+            PlaceholderPolicy::Hydrate if crate::sys::is_placeholder(from, self)? => hydrate(from),
+            PlaceholderPolicy::Fail if crate::sys::is_placeholder(from, self)? => {
+                Err(Error::other(crate::PlaceholderError))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(not(any(
+        all(target_os = "windows", not(target_vendor = "uwp")),
+        target_vendor = "apple",
+    )))]
+    fn check_placeholder(&self, _from: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn sync_parents(&self, from: &Path, to: &Path) -> Result<()> {
+        if !self.durable {
+            return Ok(());
+        }
+
+        let (from, to) = (crate::parent(from), crate::parent(to));
+        let sync = || -> Result<()> {
+            std::fs::File::open(to)?.sync_all()?;
+
+            if from != to {
+                std::fs::File::open(from)?.sync_all()?;
+            }
+
+            Ok(())
+        };
+
+        // The rename has already happened so this isn't reported as it failing.
+        sync().map_err(|e| Error::other(crate::DurabilityError::new(e)))
+    }
+
+    // This is handled by the Windows backend.
+    #[cfg(not(unix))]
+    fn sync_parents(&self, _from: &Path, _to: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    // A rename that replaces the destination, retried and synced in the same
+    // way as the exclusive ones.
+    pub(crate) fn rename_replace(&self, from: &Path, to: &Path) -> Result<()> {
+        self.with_retry(|| std::fs::rename(from, to))?;
+        self.sync_parents(from, to)
+    }
+
+    fn with_retry<R>(&self, mut f: impl FnMut() -> Result<R>) -> Result<R> {
+        let mut delay = self.retry_delay;
+
+        let mut attempt_once = || {
+            crate::report::record(|report| report.attempts += 1);
+            f()
+        };
+
+        for attempt in 0..self.retries {
+            match attempt_once() {
+                Err(e) if is_transient(&e) => {
+                    crate::instrument::retry(attempt + 1, delay, &e);
+                    crate::stats::add(Counter::Retries, 1);
+                    std::thread::sleep(delay);
+                    delay = delay.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+
+        attempt_once()
+    }
+}
+
+// Depending on the platform, a directory at the destination can be reported
+// with EEXIST, ENOTEMPTY, ERROR_ALREADY_EXISTS or ERROR_DIR_NOT_EMPTY (the
+// Windows backend has already made ERROR_ACCESS_DENIED into AlreadyExists).
+// These are all made into AlreadyExists, or DirectoryNotEmpty if there's
+// something in the directory. A symlink to a directory would be replaced
+// rather than followed so it counts as a file. The original error is kept as
+// the source so error::raw_os_error still finds its code.
+fn destination_error(error: Error, to: &Path) -> Error {
+    if !crate::error::is_destination_conflict(&error) {
+        return error;
+    }
+
+    let populated = std::fs::symlink_metadata(to).is_ok_and(|m| m.is_dir())
+        && std::fs::read_dir(to).is_ok_and(|mut entries| entries.next().is_some());
+    let kind = if populated {
+        ErrorKind::DirectoryNotEmpty
+    } else {
+        ErrorKind::AlreadyExists
+    };
+
+    if error.kind() == kind {
+        error
+    } else {
+        Error::new(kind, error)
+    }
+}
+
+// Reading a placeholder all the way through makes the sync provider download
+// it. Directories are populated when they're enumerated.
+#[cfg(any(all(target_os = "windows", not(target_vendor = "uwp")), target_vendor = "apple"))]
+fn hydrate(path: &Path) -> Result<()> {
+    if std::fs::metadata(path)?.is_dir() {
+        std::fs::read_dir(path)?.count();
+    } else {
+        std::io::copy(&mut std::fs::File::open(path)?, &mut std::io::sink())?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn is_transient(error: &Error) -> bool {
+    crate::sys::is_transient(error)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn is_transient(_error: &Error) -> bool {
+    false
+}
@@ -0,0 +1,88 @@
+use std::path::PathBuf;
+use std::io::Result;
+use crate::{RenameOptions, RenameOptionsConfig, RenameReport};
+
+/// A list of renames to perform later, along with the options to use.
+///
+/// A plan can be built by one program, shown to a user or saved to a file and
+/// then performed by another. With the `serde` feature, plans implement
+/// `Serialize` and `Deserialize`, and so do the [`RenameReport`]s that
+/// performing one gives back. Missing fields take their default values.
+///
+/// # Examples
+///
+/// ```no_run
+/// let mut plan = renamore::RenamePlan::new();
+/// plan.push("a.tmp", "a").push("b.tmp", "b");
+/// plan.options.durable = true;
+///
+/// for (rename, (result, report)) in plan.renames.iter().zip(plan.execute()) {
+///     println!("{:?} -> {:?}: {:?} ({:?})", rename.from, rename.to, result, report.strategy);
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[non_exhaustive]
+pub struct RenamePlan {
+    /// The options that each rename is performed with.
+    pub options: RenameOptionsConfig,
+
+    /// Whether to use a non-atomic fallback if necessary, like
+    /// [`rename_exclusive_fallback`](crate::rename_exclusive_fallback).
+    pub fallback: bool,
+
+    /// The renames in the order that they're performed.
+    pub renames: Vec<PlannedRename>,
+}
+
+/// A rename in a [`RenamePlan`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct PlannedRename {
+    /// The path to rename.
+    pub from: PathBuf,
+
+    /// The path to rename it to. This must not exist.
+    pub to: PathBuf,
+}
+
+impl PlannedRename {
+    /// A rename from one path to another.
+    pub fn new<F: Into<PathBuf>, T: Into<PathBuf>>(from: F, to: T) -> Self {
+        Self { from: from.into(), to: to.into() }
+    }
+}
+
+impl RenamePlan {
+    /// Create an empty plan with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a rename to the end of the plan.
+    pub fn push<F: Into<PathBuf>, T: Into<PathBuf>>(&mut self, from: F, to: T) -> &mut Self {
+        self.renames.push(PlannedRename::new(from, to));
+        self
+    }
+
+    /// Perform the renames in order without overwriting any destination paths.
+    ///
+    /// Each rename is independent so one failing doesn't stop the others. The
+    /// results are in the same order as the renames and each one comes with a
+    /// report of how it went.
+    pub fn execute(&self) -> Vec<(Result<()>, RenameReport)> {
+        let options = RenameOptions::from(self.options.clone());
+
+        self.renames.iter().map(|rename| {
+            if self.fallback {
+                let (result, report) =
+                    options.rename_exclusive_fallback_with_report(&rename.from, &rename.to);
+                (result.map(drop), report)
+            } else {
+                options.rename_exclusive_with_report(&rename.from, &rename.to)
+            }
+        }).collect()
+    }
+}
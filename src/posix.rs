@@ -0,0 +1,46 @@
+use std::path::Path;
+use std::io::{ErrorKind, Result};
+
+// This is a non-atomic emulation of an exclusive rename for platforms that
+// only offer plain POSIX file operations. Creating a file (or directory) at the
+// destination is an atomic operation that fails if something already exists
+// there so it's used to reserve the destination path before renaming onto it.
+// Some file systems (FAT and SPIFFS) refuse to rename onto an existing path
+// while others (LittleFS) replace it. Backend::EmulateOnly uses this on every
+// platform.
+
+pub fn rename_exclusive_non_atomic(from: &Path, to: &Path) -> Result<()> {
+    // Renaming a directory onto an empty directory is fine but renaming a
+    // directory onto a file is not (and vice versa).
+    if crate::retry_interrupted_io(|| std::fs::symlink_metadata(from))?.is_dir() {
+        std::fs::create_dir(to)?;
+    } else {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(to)?;
+    }
+
+    match std::fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            // The file system won't replace the placeholder so it must be
+            // removed first. This is where the operation stops being atomic.
+            remove_placeholder(to)?;
+            std::fs::rename(from, to)
+        }
+        Err(e) => {
+            // Don't leave the placeholder lying around if the rename failed.
+            let _ = remove_placeholder(to);
+            Err(e)
+        }
+    }
+}
+
+fn remove_placeholder(path: &Path) -> Result<()> {
+    if crate::retry_interrupted_io(|| std::fs::symlink_metadata(path))?.is_dir() {
+        std::fs::remove_dir(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
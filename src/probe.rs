@@ -0,0 +1,55 @@
+use std::path::{Path, PathBuf};
+use std::io::{ErrorKind, Result};
+use crate::{RenameOptions, Support};
+
+// Two files are created next to each other and one is renamed onto the other.
+// If the rename fails because the destination exists, the file system honours
+// the flag. If it succeeds, the flag was ignored and the destination was
+// replaced. Either way, both files are removed afterwards.
+
+pub fn probe(path: &Path) -> Result<Support> {
+    let dir = if std::fs::metadata(path)?.is_dir() {
+        path
+    } else {
+        crate::parent(path)
+    };
+
+    let from = create(dir, "a")?;
+    let to = match create(dir, "b") {
+        Ok(to) => to,
+        Err(e) => {
+            let _ = std::fs::remove_file(&from);
+            return Err(e);
+        }
+    };
+
+    let result = RenameOptions::builtin().rename_exclusive(&from, &to);
+    let _ = std::fs::remove_file(&from);
+    let _ = std::fs::remove_file(&to);
+
+    match result {
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => Ok(Support::Supported),
+        Err(e) if e.kind() == ErrorKind::Unsupported => Ok(Support::Unsupported),
+        Err(e) => Err(e),
+        Ok(()) => Ok(Support::Unsupported),
+    }
+}
+
+fn create(dir: &Path, suffix: &str) -> Result<PathBuf> {
+    for attempt in 0u32..100 {
+        let path = dir.join(format!(
+            ".renamore-probe-{}-{}-{}",
+            std::process::id(),
+            attempt,
+            suffix,
+        ));
+
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(ErrorKind::AlreadyExists.into())
+}
@@ -0,0 +1,73 @@
+use std::path::Path;
+use std::io::{Error, Result};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use notify::event::{EventKind, ModifyKind, RenameMode};
+use notify::{Event, RecursiveMode, Watcher};
+use crate::parent;
+
+// The non-atomic fallback checks that the destination doesn't exist and then
+// renames onto it. Something created at the destination in between is
+// silently replaced. That can't be prevented but it can be noticed by watching
+// the directory. Events for a path arrive in order so anything that happens to
+// the destination before the event for the rename itself must have happened
+// in between. A rename onto the destination by someone else looks the same as
+// ours so that isn't detected.
+
+// How long to wait for the event for the rename to arrive.
+const TIMEOUT: Duration = Duration::from_millis(250);
+
+// Returns whether something else happened to `to` while `rename` was running.
+pub fn watch(to: &Path, rename: impl FnOnce() -> Result<()>) -> Result<bool> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    // Not being able to watch (like running out of inotify watches) shouldn't
+    // stop the rename.
+    let watcher = notify::recommended_watcher(sender).and_then(|mut watcher| {
+        watcher.watch(parent(to), RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    });
+    let watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            let what = "changes to the destination directory";
+            crate::instrument::detection_failed(what, &Error::other(e));
+            return rename().map(|_| false);
+        }
+    };
+
+    rename()?;
+    let raced = wait(&receiver, to);
+
+    drop(watcher);
+    Ok(raced)
+}
+
+fn wait(receiver: &Receiver<notify::Result<Event>>, to: &Path) -> bool {
+    let deadline = Instant::now() + TIMEOUT;
+    let mut raced = false;
+
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let event = match receiver.recv_timeout(timeout) {
+            Ok(Ok(event)) => event,
+            Ok(Err(_)) => continue,
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return raced,
+        };
+
+        // Only the directory containing `to` is watched so the names are
+        // enough to tell which paths are involved.
+        if !event.paths.iter().any(|path| path.file_name() == to.file_name()) {
+            continue;
+        }
+
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(
+                RenameMode::To | RenameMode::Both | RenameMode::Any,
+            )) => return raced,
+            EventKind::Access(_) => {}
+            _ => raced = true,
+        }
+    }
+}
+
@@ -0,0 +1,53 @@
+use std::path::Path;
+use std::fs::File;
+use std::io::Result;
+use std::sync::{Arc, RwLock};
+use crate::{AtomicSupport, FsInfo, Reason, Support};
+
+type Predicate = dyn Fn(&FsInfo) -> Option<Support> + Send + Sync;
+
+static PREDICATES: RwLock<Vec<Arc<Predicate>>> = RwLock::new(Vec::new());
+
+pub fn register(predicate: Arc<Predicate>) {
+    PREDICATES.write().unwrap().push(predicate);
+    // Anything cached before now might have a different answer.
+    crate::cache::clear();
+}
+
+pub fn atomic_support(path: &Path) -> Result<AtomicSupport> {
+    consult(|| crate::fs_info(path), || crate::sys::atomic_support(path))
+}
+
+pub fn atomic_support_fd(dir: &File) -> Result<AtomicSupport> {
+    consult(|| crate::fd::fs_info_fd(dir), || crate::fd::atomic_support_fd(dir))
+}
+
+// The predicates are consulted before the built-in tables. The lock isn't held
+// while calling them so that they can do whatever they like.
+fn consult(
+    info: impl FnOnce() -> Result<FsInfo>,
+    builtin: impl FnOnce() -> Result<AtomicSupport>,
+) -> Result<AtomicSupport> {
+    let predicates = PREDICATES.read().unwrap().clone();
+
+    if predicates.is_empty() {
+        return builtin();
+    }
+
+    // Without file system information, there's nothing to give the predicates.
+    let info = match info() {
+        Ok(info) => info,
+        Err(e) => {
+            crate::instrument::detection_failed("file system", &e);
+            return builtin();
+        }
+    };
+
+    // Later registrations take precedence.
+    match predicates.iter().rev().find_map(|predicate| predicate(&info)) {
+        Some(Support::Supported) => Ok(AtomicSupport::Yes),
+        Some(Support::Unverifiable) => Ok(AtomicSupport::Unknown),
+        Some(_) => Ok(AtomicSupport::No(Reason::Filesystem(info))),
+        None => builtin(),
+    }
+}
@@ -0,0 +1,120 @@
+use std::path::Path;
+use std::io::Result;
+use crate::{RenameOptions, Support};
+
+/// The operations of this crate as a trait.
+///
+/// Code that takes an `impl Renamer` (or a `&dyn Renamer`) rather than calling
+/// the functions directly can be given a mock in unit tests so that they don't
+/// need to touch the file system. [`SystemRenamer`] uses the real functions
+/// and [`RenameOptions`] implements this with its options.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Result;
+/// use std::path::Path;
+/// use renamore::{Renamer, SystemRenamer};
+///
+/// fn publish(renamer: &impl Renamer, name: &str) -> Result<()> {
+///     renamer.rename_exclusive(Path::new(&format!("{name}.tmp")), Path::new(name))
+/// }
+///
+/// # fn main() -> Result<()> {
+/// # let dir = tempfile::tempdir()?;
+/// # std::env::set_current_dir(dir.path())?;
+/// # std::fs::write("report.tmp", "")?;
+/// publish(&SystemRenamer, "report")?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait Renamer {
+    /// See [`rename_exclusive`](crate::rename_exclusive).
+    fn rename_exclusive(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// See [`rename_exclusive_fallback`](crate::rename_exclusive_fallback).
+    fn rename_exclusive_fallback(&self, from: &Path, to: &Path) -> Result<bool>;
+
+    /// See [`rename_exchange`](crate::rename_exchange).
+    fn rename_exchange(&self, a: &Path, b: &Path) -> Result<()>;
+
+    /// See [`rename_exclusive_is_atomic`](crate::rename_exclusive_is_atomic).
+    fn rename_exclusive_is_atomic(&self, path: &Path) -> Result<bool>;
+
+    /// See [`rename_exclusive_support`](crate::rename_exclusive_support).
+    fn rename_exclusive_support(&self, path: &Path) -> Result<Support>;
+}
+
+/// The [`Renamer`] that uses the file system.
+///
+/// Each method calls the function of the same name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SystemRenamer;
+
+impl Renamer for SystemRenamer {
+    fn rename_exclusive(&self, from: &Path, to: &Path) -> Result<()> {
+        crate::rename_exclusive(from, to)
+    }
+
+    fn rename_exclusive_fallback(&self, from: &Path, to: &Path) -> Result<bool> {
+        crate::rename_exclusive_fallback(from, to)
+    }
+
+    fn rename_exchange(&self, a: &Path, b: &Path) -> Result<()> {
+        crate::rename_exchange(a, b)
+    }
+
+    fn rename_exclusive_is_atomic(&self, path: &Path) -> Result<bool> {
+        crate::rename_exclusive_is_atomic(path)
+    }
+
+    fn rename_exclusive_support(&self, path: &Path) -> Result<Support> {
+        crate::rename_exclusive_support(path)
+    }
+}
+
+// The options only affect renaming. Everything else is the same as
+// SystemRenamer.
+impl Renamer for RenameOptions {
+    fn rename_exclusive(&self, from: &Path, to: &Path) -> Result<()> {
+        RenameOptions::rename_exclusive(self, from, to)
+    }
+
+    fn rename_exclusive_fallback(&self, from: &Path, to: &Path) -> Result<bool> {
+        RenameOptions::rename_exclusive_fallback(self, from, to)
+    }
+
+    fn rename_exchange(&self, a: &Path, b: &Path) -> Result<()> {
+        crate::rename_exchange(a, b)
+    }
+
+    fn rename_exclusive_is_atomic(&self, path: &Path) -> Result<bool> {
+        crate::rename_exclusive_is_atomic(path)
+    }
+
+    fn rename_exclusive_support(&self, path: &Path) -> Result<Support> {
+        crate::rename_exclusive_support(path)
+    }
+}
+
+impl<R: Renamer + ?Sized> Renamer for &R {
+    fn rename_exclusive(&self, from: &Path, to: &Path) -> Result<()> {
+        (**self).rename_exclusive(from, to)
+    }
+
+    fn rename_exclusive_fallback(&self, from: &Path, to: &Path) -> Result<bool> {
+        (**self).rename_exclusive_fallback(from, to)
+    }
+
+    fn rename_exchange(&self, a: &Path, b: &Path) -> Result<()> {
+        (**self).rename_exchange(a, b)
+    }
+
+    fn rename_exclusive_is_atomic(&self, path: &Path) -> Result<bool> {
+        (**self).rename_exclusive_is_atomic(path)
+    }
+
+    fn rename_exclusive_support(&self, path: &Path) -> Result<Support> {
+        (**self).rename_exclusive_support(path)
+    }
+}
@@ -0,0 +1,77 @@
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+use crate::Fallback;
+
+// The report for the operation on the current thread. Some of what goes into
+// it (like a fallback on Windows) is only known deep within a backend so it's
+// filled in from wherever that is rather than being passed around.
+thread_local! {
+    static CURRENT: RefCell<Option<RenameReport>> = const { RefCell::new(None) };
+}
+
+pub fn capture<T>(f: impl FnOnce() -> T) -> (T, RenameReport) {
+    let previous = CURRENT.with(|current| current.replace(Some(RenameReport::default())));
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    let report = CURRENT.with(|current| current.replace(previous));
+
+    (result, RenameReport { elapsed, ..report.unwrap_or_default() })
+}
+
+// Does nothing unless a report is being captured.
+pub fn record(f: impl FnOnce(&mut RenameReport)) {
+    CURRENT.with(|current| {
+        if let Some(report) = current.borrow_mut().as_mut() {
+            f(report);
+        }
+    });
+}
+
+/// How a rename was performed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Strategy {
+    /// An atomic rename that doesn't replace the destination.
+    #[default]
+    Atomic,
+
+    /// A fallback was used. If more than one was used (like copying across
+    /// file systems and then using the non-atomic fallback for the final
+    /// rename), this is the first.
+    Fallback(Fallback),
+}
+
+/// What happened during a rename.
+///
+/// This is returned by the `_with_report` methods on
+/// [`RenameOptions`](crate::RenameOptions) whether or not the rename succeeded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct RenameReport {
+    /// How the rename was performed (or attempted).
+    pub strategy: Strategy,
+
+    /// The number of times that the rename was attempted. This is more than one
+    /// if transient errors were retried. See
+    /// [`RenameOptions::retry`](crate::RenameOptions::retry).
+    pub attempts: u32,
+
+    /// How long the whole operation took.
+    pub elapsed: Duration,
+
+    /// Whether a fallback was used. This is the same as checking whether
+    /// `strategy` is [`Strategy::Fallback`].
+    pub fell_back: bool,
+
+    /// The number of bytes copied while moving across file systems.
+    pub bytes_copied: u64,
+
+    /// Whether the destination was created or changed while the non-atomic
+    /// fallback was running, meaning that it was replaced. This is only
+    /// detected if `RenameOptions::detect_races` is set, which requires the
+    /// `notify` feature.
+    pub race_detected: bool,
+}
@@ -0,0 +1,111 @@
+// The counters are always updated through `add` so that the call sites don't
+// need to know whether the `stats` feature is enabled. Without it, `add` does
+// nothing.
+
+#[cfg(feature = "stats")]
+use std::cell::Cell;
+use std::io::Result;
+#[cfg(feature = "stats")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Clone, Copy)]
+pub enum Counter {
+    AtomicRenames,
+    Fallbacks,
+    Retries,
+    CrossDeviceCopies,
+    BytesCopied,
+}
+
+#[cfg(feature = "stats")]
+static COUNTERS: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+
+pub fn add(counter: Counter, amount: u64) {
+    #[cfg(feature = "stats")]
+    COUNTERS[counter as usize].fetch_add(amount, Ordering::Relaxed);
+
+    #[cfg(not(feature = "stats"))]
+    let _ = (counter, amount);
+}
+
+// Set by a backend that falls back to something else by itself (like
+// MoveFileExW on Windows) so that the rename is counted as a fallback rather
+// than an atomic rename.
+#[cfg(feature = "stats")]
+thread_local! {
+    static NATIVE_FALLBACK: Cell<bool> = const { Cell::new(false) };
+}
+
+#[cfg(feature = "stats")]
+fn replace_native_fallback(fell_back: bool) -> bool {
+    NATIVE_FALLBACK.with(|cell| cell.replace(fell_back))
+}
+
+#[cfg(not(feature = "stats"))]
+fn replace_native_fallback(_fell_back: bool) -> bool {
+    false
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn native_fallback() {
+    replace_native_fallback(true);
+}
+
+// Counts a successful rename by the native implementation.
+pub fn count_native(rename: impl FnOnce() -> Result<()>) -> Result<()> {
+    replace_native_fallback(false);
+    rename()?;
+
+    if replace_native_fallback(false) {
+        add(Counter::Fallbacks, 1);
+    } else {
+        add(Counter::AtomicRenames, 1);
+    }
+
+    Ok(())
+}
+
+/// Counters for the renames made by this process.
+///
+/// This is returned by [`stats`](crate::stats). The counters start at zero
+/// and only ever increase. They're updated independently so a snapshot taken
+/// while renames are in progress might be slightly inconsistent.
+#[cfg(feature = "stats")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Stats {
+    /// The number of atomic renames. This includes the final rename of a copy
+    /// across file systems.
+    pub atomic_renames: u64,
+
+    /// The number of times that the non-atomic fallback was used by
+    /// [`rename_exclusive_fallback`](crate::rename_exclusive_fallback). On
+    /// Windows, this includes renames that fell back to `MoveFileExW`.
+    pub fallbacks: u64,
+
+    /// The number of times that a rename was attempted again after a
+    /// transient error. See [`RenameOptions::retry`](crate::RenameOptions::retry).
+    pub retries: u64,
+
+    /// The number of files or directories that were moved across file systems
+    /// by copying them. See
+    /// [`RenameOptions::cross_device`](crate::RenameOptions::cross_device).
+    pub cross_device_copies: u64,
+
+    /// The number of bytes copied while moving across file systems. This
+    /// includes copies that failed or were cancelled.
+    pub bytes_copied: u64,
+}
+
+#[cfg(feature = "stats")]
+pub fn stats() -> Stats {
+    let get = |counter: Counter| COUNTERS[counter as usize].load(Ordering::Relaxed);
+
+    Stats {
+        atomic_renames: get(Counter::AtomicRenames),
+        fallbacks: get(Counter::Fallbacks),
+        retries: get(Counter::Retries),
+        cross_device_copies: get(Counter::CrossDeviceCopies),
+        bytes_copied: get(Counter::BytesCopied),
+    }
+}
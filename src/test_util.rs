@@ -0,0 +1,418 @@
+//! Injecting failures to test error handling.
+//!
+//! Some failures (like a file system that doesn't support atomic renames or
+//! running out of space halfway through a copy) are hard to arrange for in a
+//! test. [`Faults`] makes the renames on the current thread fail in a chosen
+//! way so that the code handling them can be tested deterministically. The
+//! failures are injected where the OS would have been called, so retries,
+//! fallbacks and cleanup happen just as they would for a real failure.
+//!
+//! [`MemoryFs`] goes further and doesn't touch the file system at all. It's a
+//! [`Renamer`] for unit tests (and Miri) that only need the semantics of the
+//! operations.
+//!
+//! This module is only available with the `test-util` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! use renamore::test_util::{Fault, Faults};
+//!
+//! # let dir = tempfile::tempdir().unwrap();
+//! # let (from, to) = (dir.path().join("a"), dir.path().join("b"));
+//! # std::fs::write(&from, "").unwrap();
+//! let atomic = Faults::new()
+//!     .fail_rename(1, Fault::Unsupported)
+//!     .run(|| renamore::rename_exclusive_fallback(&from, &to))
+//!     .unwrap();
+//!
+//! assert!(!atomic);
+//! ```
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::{Renamer, Support};
+
+thread_local! {
+    static PLAN: RefCell<Option<Plan>> = const { RefCell::new(None) };
+}
+
+struct Plan {
+    faults: Faults,
+    renames: u32,
+}
+
+// Called instead of the native rename.
+pub(crate) fn rename_fault() -> Option<Error> {
+    PLAN.with(|plan| {
+        let mut plan = plan.borrow_mut();
+        let plan = plan.as_mut()?;
+
+        plan.renames += 1;
+        plan.faults.renames.iter()
+            .find(|(nth, _)| nth.is_none_or(|nth| nth == plan.renames))
+            .map(|(_, fault)| fault.error())
+    })
+}
+
+// Called after each file has been copied across file systems. Only the first
+// copy fails.
+pub(crate) fn copy_fault() -> Option<Error> {
+    PLAN.with(|plan| {
+        plan.borrow_mut().as_mut()?.faults.copy.take().map(|fault| fault.error())
+    })
+}
+
+/// A failure to inject.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Fault {
+    /// The operation isn't supported, as if the file system didn't support
+    /// atomic renames.
+    Unsupported,
+
+    /// The destination path exists.
+    AlreadyExists,
+
+    /// The paths are on different file systems (`EXDEV`).
+    CrossesDevices,
+
+    /// Another process has the file open. On Windows, this is
+    /// `ERROR_SHARING_VIOLATION`, which is retried (see
+    /// [`RenameOptions::retry`](crate::RenameOptions::retry)). On other
+    /// platforms, this is [`ErrorKind::ResourceBusy`], which isn't.
+    SharingViolation,
+
+    /// The file system is full (`ENOSPC`).
+    StorageFull,
+
+    /// An error with this kind.
+    Kind(ErrorKind),
+
+    /// An error with this OS error code.
+    Os(i32),
+}
+
+impl Fault {
+    fn error(self) -> Error {
+        match self {
+            Self::Unsupported => ErrorKind::Unsupported.into(),
+            Self::AlreadyExists => ErrorKind::AlreadyExists.into(),
+            Self::CrossesDevices => ErrorKind::CrossesDevices.into(),
+            #[cfg(target_os = "windows")]
+            Self::SharingViolation => Error::from_raw_os_error(32),
+            #[cfg(not(target_os = "windows"))]
+            Self::SharingViolation => ErrorKind::ResourceBusy.into(),
+            Self::StorageFull => ErrorKind::StorageFull.into(),
+            Self::Kind(kind) => kind.into(),
+            Self::Os(code) => Error::from_raw_os_error(code),
+        }
+    }
+}
+
+/// A plan of failures to inject into the renames on the current thread.
+///
+/// Failures are injected in place of the atomic rename. Renames are counted
+/// from 1 and every attempt counts, including retries and the final rename
+/// after copying across file systems. The non-atomic fallback isn't affected
+/// and neither are renames made by other threads (like the executors in
+/// `asynchronous`).
+#[derive(Clone, Debug, Default)]
+pub struct Faults {
+    renames: Vec<(Option<u32>, Fault)>,
+    copy: Option<Fault>,
+}
+
+impl Faults {
+    /// Create a plan that doesn't inject anything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make the `nth` rename fail.
+    pub fn fail_rename(&mut self, nth: u32, fault: Fault) -> &mut Self {
+        self.renames.push((Some(nth), fault));
+        self
+    }
+
+    /// Make every rename fail.
+    ///
+    /// Failures for specific renames added with [`Faults::fail_rename`] before
+    /// this take precedence.
+    pub fn fail_every_rename(&mut self, fault: Fault) -> &mut Self {
+        self.renames.push((None, fault));
+        self
+    }
+
+    /// Make copying across file systems fail after the first file has been
+    /// copied.
+    ///
+    /// This only happens if [`RenameOptions::cross_device`] is set and a
+    /// rename fails with [`Fault::CrossesDevices`] (or really does cross
+    /// devices).
+    ///
+    /// [`RenameOptions::cross_device`]: crate::RenameOptions::cross_device
+    pub fn fail_copy(&mut self, fault: Fault) -> &mut Self {
+        self.copy = Some(fault);
+        self
+    }
+
+    /// Call `f` with the failures injected into the renames that it makes on
+    /// this thread.
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        let plan = Plan { faults: self.clone(), renames: 0 };
+        let previous = PLAN.with(|current| current.replace(Some(plan)));
+
+        // Restore the previous plan even if `f` panics.
+        struct Restore(Option<Plan>);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                PLAN.with(|current| *current.borrow_mut() = self.0.take());
+            }
+        }
+
+        let _restore = Restore(previous);
+        f()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    File(Vec<u8>),
+    Dir,
+}
+
+/// An in-memory file system that implements [`Renamer`].
+///
+/// Files and directories are identified by their paths, which are compared
+/// component by component (so `a/./b` and `a/b/` are the same as `a/b`). The
+/// root (and the empty path) always exists. Renames have the same semantics
+/// as [`rename_exclusive`](crate::rename_exclusive): the parent of the
+/// destination must be a directory and the rename fails with
+/// [`ErrorKind::AlreadyExists`] if the destination exists. Renaming a
+/// directory moves everything in it.
+///
+/// There's no current directory, so relative paths aren't resolved against
+/// anything. A relative path like `a` and an absolute path like `/a` are
+/// different files. `..` isn't resolved either.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+/// use renamore::Renamer;
+/// use renamore::test_util::MemoryFs;
+///
+/// let fs = MemoryFs::new();
+/// fs.create_dir("out").unwrap();
+/// fs.write("report.tmp", "done").unwrap();
+///
+/// fs.rename_exclusive(Path::new("report.tmp"), Path::new("out/report")).unwrap();
+/// assert_eq!(fs.read("out/report").unwrap(), b"done");
+/// ```
+#[derive(Debug)]
+pub struct MemoryFs {
+    nodes: Mutex<BTreeMap<PathBuf, Node>>,
+    atomic: AtomicBool,
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        Self { nodes: Mutex::default(), atomic: AtomicBool::new(true) }
+    }
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    path.components().collect()
+}
+
+fn not_found() -> Error {
+    ErrorKind::NotFound.into()
+}
+
+impl MemoryFs {
+    /// Create an empty file system where atomic renames are supported.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether atomic renames are supported.
+    ///
+    /// If they aren't, then [`Renamer::rename_exclusive`] fails with
+    /// [`ErrorKind::Unsupported`] and [`Renamer::rename_exclusive_fallback`]
+    /// returns `false`.
+    pub fn set_atomic(&self, atomic: bool) {
+        self.atomic.store(atomic, Ordering::Relaxed);
+    }
+
+    /// Create a directory. Its parent must exist.
+    pub fn create_dir<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.create(path.as_ref(), Node::Dir)
+    }
+
+    /// Create or replace a file. Its parent must exist.
+    pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(&self, path: P, contents: C) -> Result<()> {
+        let path = normalize(path.as_ref());
+        let mut nodes = self.nodes.lock().unwrap();
+
+        check_parent(&nodes, &path)?;
+        if nodes.get(&path) == Some(&Node::Dir) {
+            return Err(ErrorKind::IsADirectory.into());
+        }
+        nodes.insert(path, Node::File(contents.as_ref().to_vec()));
+        Ok(())
+    }
+
+    /// Read the contents of a file.
+    pub fn read<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
+        match self.nodes.lock().unwrap().get(&normalize(path.as_ref())) {
+            Some(Node::File(contents)) => Ok(contents.clone()),
+            Some(Node::Dir) => Err(ErrorKind::IsADirectory.into()),
+            None => Err(not_found()),
+        }
+    }
+
+    /// Determine whether a file or directory exists.
+    pub fn exists<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = normalize(path.as_ref());
+        is_root(&path) || self.nodes.lock().unwrap().contains_key(&path)
+    }
+
+    /// The paths of every file and directory, in order.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.nodes.lock().unwrap().keys().cloned().collect()
+    }
+
+    fn create(&self, path: &Path, node: Node) -> Result<()> {
+        let path = normalize(path);
+        let mut nodes = self.nodes.lock().unwrap();
+
+        check_parent(&nodes, &path)?;
+        if is_root(&path) || nodes.contains_key(&path) {
+            return Err(ErrorKind::AlreadyExists.into());
+        }
+        nodes.insert(path, node);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        crate::check_path(from, "from")?;
+        crate::check_path(to, "to")?;
+
+        let (from, to) = (normalize(from), normalize(to));
+        let mut nodes = self.nodes.lock().unwrap();
+
+        if !nodes.contains_key(&from) {
+            return Err(not_found());
+        }
+        check_parent(&nodes, &to)?;
+        if is_root(&to) || nodes.contains_key(&to) {
+            return Err(ErrorKind::AlreadyExists.into());
+        }
+        if to.starts_with(&from) {
+            return Err(Error::new(ErrorKind::InvalidInput, "can't move a directory into itself"));
+        }
+
+        for (path, node) in take_tree(&mut nodes, &from) {
+            nodes.insert(moved(&path, &from, &to), node);
+        }
+        Ok(())
+    }
+}
+
+fn is_root(path: &Path) -> bool {
+    path.parent().is_none()
+}
+
+fn check_parent(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> Result<()> {
+    match path.parent() {
+        Some(parent) if !is_root(parent) && !parent.as_os_str().is_empty() => {
+            match nodes.get(parent) {
+                Some(Node::Dir) => Ok(()),
+                Some(Node::File(_)) => Err(ErrorKind::NotADirectory.into()),
+                None => Err(not_found()),
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+// Where `path` ends up when `from` is renamed to `to`.
+fn moved(path: &Path, from: &Path, to: &Path) -> PathBuf {
+    match path.strip_prefix(from) {
+        Ok(rest) if !rest.as_os_str().is_empty() => to.join(rest),
+        _ => to.to_path_buf(),
+    }
+}
+
+// Removes a path and everything under it.
+fn take_tree(nodes: &mut BTreeMap<PathBuf, Node>, root: &Path) -> Vec<(PathBuf, Node)> {
+    let paths = nodes.keys()
+        .filter(|path| path.starts_with(root))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    paths.into_iter().map(|path| {
+        let node = nodes.remove(&path).unwrap();
+        (path, node)
+    }).collect()
+}
+
+impl Renamer for MemoryFs {
+    fn rename_exclusive(&self, from: &Path, to: &Path) -> Result<()> {
+        if !self.atomic.load(Ordering::Relaxed) {
+            return Err(ErrorKind::Unsupported.into());
+        }
+        self.rename(from, to)
+    }
+
+    fn rename_exclusive_fallback(&self, from: &Path, to: &Path) -> Result<bool> {
+        self.rename(from, to)?;
+        Ok(self.atomic.load(Ordering::Relaxed))
+    }
+
+    fn rename_exchange(&self, a: &Path, b: &Path) -> Result<()> {
+        crate::check_path(a, "a")?;
+        crate::check_path(b, "b")?;
+
+        let (a, b) = (normalize(a), normalize(b));
+        let mut nodes = self.nodes.lock().unwrap();
+
+        if !nodes.contains_key(&a) || !nodes.contains_key(&b) {
+            return Err(not_found());
+        }
+        if a.starts_with(&b) || b.starts_with(&a) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "can't exchange a directory with something inside it",
+            ));
+        }
+
+        let (tree_a, tree_b) = (take_tree(&mut nodes, &a), take_tree(&mut nodes, &b));
+
+        for (path, node) in tree_a {
+            nodes.insert(moved(&path, &a, &b), node);
+        }
+        for (path, node) in tree_b {
+            nodes.insert(moved(&path, &b, &a), node);
+        }
+        Ok(())
+    }
+
+    fn rename_exclusive_is_atomic(&self, _path: &Path) -> Result<bool> {
+        Ok(self.atomic.load(Ordering::Relaxed))
+    }
+
+    fn rename_exclusive_support(&self, _path: &Path) -> Result<Support> {
+        if self.atomic.load(Ordering::Relaxed) {
+            Ok(Support::Supported)
+        } else {
+            Ok(Support::Unsupported)
+        }
+    }
+}
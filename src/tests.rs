@@ -127,3 +127,2337 @@ fn rename_exclusive_is_atomic() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn rename_exclusive_is_atomic_fd() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let file = std::fs::File::open(dir.path())?;
+
+    assert_eq!(
+        super::rename_exclusive_is_atomic_fd(&file)?,
+        super::rename_exclusive_is_atomic(dir.path())?,
+    );
+
+    Ok(())
+}
+
+#[test]
+fn rename_exclusive_is_atomic_missing() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let missing = dir.path().join("missing").join("file");
+
+    assert_eq!(
+        super::rename_exclusive_is_atomic(&missing)?,
+        super::rename_exclusive_is_atomic(dir.path())?,
+    );
+    assert_eq!(
+        super::rename_exclusive_support_uncached(&missing)?,
+        super::rename_exclusive_support_uncached(dir.path())?,
+    );
+
+    Ok(())
+}
+
+#[test]
+fn rename_exclusive_is_atomic_for() -> Result<()> {
+    use super::{AtomicSupport, Reason};
+
+    let dir = tempfile::tempdir()?;
+    let from = dir.path().join("from");
+    std::fs::write(&from, "from")?;
+
+    assert_eq!(
+        super::rename_exclusive_is_atomic_for(&from, dir.path().join("to"))?,
+        super::rename_exclusive_is_atomic(dir.path())?,
+    );
+
+    if cfg!(all(target_os = "linux", linker)) {
+        assert_eq!(
+            super::rename_exclusive_atomic_support_for(&from, "/proc/self")?,
+            AtomicSupport::No(Reason::CrossesDevices),
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn invalid_paths() {
+    let error = super::rename_exclusive("", "a").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert_eq!(error.to_string(), "the `from` path is empty");
+
+    let error = super::rename_exclusive_fallback("a", "b\0c").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert_eq!(error.to_string(), "the `to` path contains a NUL byte");
+
+    let error = super::rename_exchange("a\0", "b").unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert_eq!(error.to_string(), "the `a` path contains a NUL byte");
+}
+
+#[test]
+fn rename_exclusive_detailed() -> Result<()> {
+    use super::{RenameError, RenameOperation, RenameOptions};
+
+    let dir = tempfile::tempdir()?;
+    let from = dir.path().join("from");
+    let to = dir.path().join("to");
+    std::fs::write(&from, "from")?;
+    std::fs::write(&to, "to")?;
+
+    let error = match RenameOptions::new().rename_exclusive_detailed(&from, &to) {
+        Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+        result => result.unwrap_err(),
+    };
+
+    assert_eq!(error.operation(), RenameOperation::Exclusive);
+    assert_eq!(error.from(), from);
+    assert_eq!(error.to(), to);
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert!(error.to_string().starts_with("failed to rename"));
+
+    let error = super::rename_exclusive_detailed(&from, &to).unwrap_err();
+    assert_eq!(error.operation(), RenameOperation::Exclusive);
+    assert_eq!(error.to(), to);
+
+    // Converting keeps the kind and the paths.
+    let error = std::io::Error::from(error);
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(error.get_ref().unwrap().downcast_ref::<RenameError>().unwrap().from(), from);
+
+    Ok(())
+}
+
+#[test]
+fn rename_exclusive_non_empty_directory() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let from = dir.path().join("from");
+    let empty = dir.path().join("empty");
+    let full = dir.path().join("full");
+    std::fs::create_dir(&from)?;
+    std::fs::create_dir(&empty)?;
+    std::fs::create_dir(&full)?;
+    std::fs::write(full.join("file"), "file")?;
+
+    match super::rename_exclusive(&from, &full) {
+        Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+        result => {
+            let error = result.unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::DirectoryNotEmpty);
+            assert!(super::error::raw_os_error(&error).is_some());
+        }
+    }
+
+    let error = super::rename_exclusive(&from, &empty).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+
+    // The fallback is the same.
+    let error = super::rename_exclusive_fallback(&from, &full).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::DirectoryNotEmpty);
+    assert!(super::error::is_destination_conflict(&error));
+
+    Ok(())
+}
+
+#[test]
+fn classify_errors() {
+    use std::io::Error;
+    use super::error::{is_cross_device, is_destination_conflict, is_unsupported};
+
+    assert!(is_cross_device(&Error::from(ErrorKind::CrossesDevices)));
+    assert!(is_unsupported(&Error::new(ErrorKind::Unsupported, "flag")));
+    assert!(is_destination_conflict(&Error::from(ErrorKind::AlreadyExists)));
+    assert!(!is_destination_conflict(&Error::from(ErrorKind::NotFound)));
+
+    // The original error is kept inside when the kind is changed.
+    let wrapped = Error::other(Error::from(ErrorKind::CrossesDevices));
+    assert!(is_cross_device(&wrapped));
+
+    #[cfg(any(target_os = "linux", target_vendor = "apple"))]
+    {
+        assert!(is_cross_device(&Error::from_raw_os_error(18)));
+        assert!(is_destination_conflict(&Error::from_raw_os_error(17)));
+        assert!(!is_unsupported(&Error::from_raw_os_error(22)));
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        assert!(is_cross_device(&Error::from_raw_os_error(17)));
+        assert!(is_destination_conflict(&Error::from_raw_os_error(80)));
+        assert!(is_unsupported(&Error::from_raw_os_error(50)));
+    }
+}
+
+#[test]
+fn kernel_version() -> Result<()> {
+    match super::kernel_version() {
+        Ok(version) => println!("kernel version: {}", version),
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {}
+        Err(e) => return Err(e),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn parse_version() {
+    use super::Version;
+
+    let versions = [
+        ("6.1.0-13-amd64", Version::new(6, 1, 0)),
+        ("6.1", Version::new(6, 1, 0)),
+        ("6.8.0-rc3", Version::new(6, 8, 0)),
+        ("6.9-rc1+", Version::new(6, 9, 0)),
+        ("3.10.0-1160.el7.x86_64", Version::new(3, 10, 0)),
+        ("4.4.302-android-g1234abcd", Version::new(4, 4, 302)),
+        ("5.15.153.1-microsoft-standard-WSL2", Version::new(5, 15, 153)),
+        (
+            "Linux version 6.5.0-35-generic (buildd@lcy02-amd64-079) (x86_64-linux-gnu-gcc-12 (Ubuntu 12.3.0-1ubuntu1~22.04) 12.3.0, GNU ld (GNU Binutils for Ubuntu) 2.38) #35~22.04.1-Ubuntu SMP PREEMPT_DYNAMIC Tue May  7 09:00:52 UTC 2",
+            Version::new(6, 5, 0),
+        ),
+        (
+            "Linux version 4.19.113-perf+ (builder@host) (clang version 10.0.7) #1 SMP PREEMPT Thu Jan 1 00:00:00 CST 2021",
+            Version::new(4, 19, 113),
+        ),
+        (
+            "Linux version 5.15.133.1-microsoft-standard-WSL2 (root@1c602f52c2e4) (gcc (GCC) 11.2.0, GNU ld (GNU Binutils) 2.37) #1 SMP Thu Oct 5 21:02:42 UTC 2023",
+            Version::new(5, 15, 133),
+        ),
+    ];
+
+    for (string, version) in versions {
+        assert_eq!(string.parse::<Version>().unwrap(), version, "{}", string);
+    }
+
+    for string in ["", "Linux", "6", "6-rc1", "6.", "99999.1.0"] {
+        assert!(string.parse::<Version>().is_err(), "{}", string);
+    }
+}
+
+#[test]
+#[cfg(all(target_os = "linux", linker))]
+fn boundary_error() -> Result<()> {
+    use std::io::Error;
+    use super::linux::boundary_error;
+
+    let dir = tempfile::tempdir()?;
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+
+    // EXDEV on the same device.
+    let error = boundary_error(Error::from_raw_os_error(18), &path_a, &path_b);
+    assert_eq!(error.kind(), ErrorKind::CrossesDevices);
+    let boundary = error.get_ref().unwrap().downcast_ref::<super::BoundaryError>().unwrap();
+    assert_eq!(boundary.io_error().raw_os_error(), Some(18));
+
+    // EEXIST is left alone.
+    let error = boundary_error(Error::from_raw_os_error(17), &path_a, &path_b);
+    assert_eq!(error.raw_os_error(), Some(17));
+
+    Ok(())
+}
+
+// A tmpfs at /dev/shm is a different file system so renaming onto it isn't a
+// boundary.
+#[test]
+#[cfg(all(target_os = "linux", linker))]
+fn boundary_error_different_devices() -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = tempfile::tempdir()?;
+    let Ok(other) = tempfile::tempdir_in("/dev/shm") else {
+        return Ok(());
+    };
+    if dir.path().metadata()?.dev() == other.path().metadata()?.dev() {
+        return Ok(());
+    }
+
+    let path_a = dir.path().join("a");
+    let path_b = other.path().join("b");
+    std::fs::write(&path_a, "a")?;
+
+    let error = super::rename_exclusive(&path_a, &path_b).unwrap_err();
+    if error.kind() != ErrorKind::Unsupported {
+        assert_eq!(error.raw_os_error(), Some(18));
+    }
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(target_os = "linux", linker))]
+fn read_only_error() -> Result<()> {
+    use std::io::Error;
+    use super::linux::read_only_error;
+    use super::{ReadOnlyCause, ReadOnlyError};
+
+    let dir = tempfile::tempdir()?;
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+    std::fs::write(&path_a, "a")?;
+
+    // EPERM for some other reason is left alone.
+    let error = read_only_error(Error::from_raw_os_error(1), &path_a, &path_b);
+    assert_eq!(error.raw_os_error(), Some(1));
+
+    // EEXIST isn't checked.
+    let error = read_only_error(Error::from_raw_os_error(17), &path_a, &path_b);
+    assert_eq!(error.raw_os_error(), Some(17));
+
+    let error = ReadOnlyCause::Immutable(path_a.clone()).into_error(Error::from_raw_os_error(1));
+    assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+    assert!(error.to_string().ends_with("is immutable or append-only"));
+
+    let error = ReadOnlyCause::Mount(path_a).into_error(Error::from_raw_os_error(30));
+    assert_eq!(error.kind(), ErrorKind::ReadOnlyFilesystem);
+    let read_only = error.get_ref().unwrap().downcast_ref::<ReadOnlyError>().unwrap();
+    assert_eq!(read_only.io_error().raw_os_error(), Some(30));
+
+    Ok(())
+}
+
+// Setting the immutable attribute needs CAP_LINUX_IMMUTABLE and a file system
+// that supports it, so this is skipped if chattr fails.
+#[test]
+#[cfg(all(target_os = "linux", linker))]
+fn read_only_error_immutable() -> Result<()> {
+    use std::process::{Command, Stdio};
+    use super::{ReadOnlyCause, ReadOnlyError, RenameOptions};
+
+    let dir = tempfile::tempdir()?;
+    let locked = dir.path().join("locked");
+    let path_a = locked.join("a");
+    let path_b = dir.path().join("b");
+
+    std::fs::create_dir(&locked)?;
+    std::fs::write(&path_a, "a")?;
+
+    let chattr = |flag: &str| {
+        Command::new("chattr").arg(flag).arg(&locked).stderr(Stdio::null()).status()
+    };
+    if !chattr("+i").is_ok_and(|status| status.success()) {
+        return Ok(());
+    }
+
+    let result = super::rename_exclusive(&path_a, &path_b);
+    let eperm = RenameOptions::new().eperm_unsupported(true).rename_exclusive(&path_a, &path_b);
+    chattr("-i")?;
+
+    let error = result.unwrap_err();
+    if error.kind() != ErrorKind::Unsupported {
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+        let read_only = error.get_ref().unwrap().downcast_ref::<ReadOnlyError>().unwrap();
+        assert_eq!(read_only.cause(), &ReadOnlyCause::Immutable(locked));
+        assert_eq!(read_only.io_error().raw_os_error(), Some(1));
+
+        assert_eq!(eperm.unwrap_err().kind(), ErrorKind::Unsupported);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(target_os = "linux", linker))]
+fn is_wsl1() {
+    use super::linux::is_wsl1;
+
+    assert!(is_wsl1("4.4.0-19041-Microsoft"));
+    assert!(is_wsl1("4.4.0-22621-Microsoft"));
+    assert!(!is_wsl1("5.15.153.1-microsoft-standard-WSL2"));
+    assert!(!is_wsl1("6.1.0-13-amd64"));
+}
+
+#[test]
+#[cfg(all(target_os = "linux", linker))]
+fn get_filesystem_type() -> Result<()> {
+    use super::linux::get_filesystem_type;
+
+    // If the layout of struct statfs is wrong, this would read garbage or a
+    // half of f_type. That's most likely to happen on 32-bit and big-endian
+    // targets.
+    if Path::new("/proc/self").exists() {
+        assert_eq!(get_filesystem_type(Path::new("/proc"))?, 0x9fa0);
+    }
+
+    let dir = tempfile::tempdir()?;
+    assert_ne!(get_filesystem_type(dir.path())?, 0);
+
+    Ok(())
+}
+
+#[test]
+fn rename_exclusive_support() -> Result<()> {
+    let dir = std::env::current_dir()?;
+    let support = super::rename_exclusive_support(&dir)?;
+
+    println!("rename_exclusive support: {:?}", support);
+    assert_eq!(support == super::Support::Supported, super::rename_exclusive_is_atomic(&dir)?);
+
+    // The cached result is the same as asking the OS again.
+    assert_eq!(super::rename_exclusive_support_uncached(&dir)?, support);
+    super::invalidate_caches();
+    assert_eq!(super::rename_exclusive_support(&dir)?, support);
+    super::invalidate_cache(&dir)?;
+    assert_eq!(super::rename_exclusive_support(&dir)?, support);
+
+    Ok(())
+}
+
+#[test]
+fn rename_exclusive_atomic_support() -> Result<()> {
+    use super::{AtomicSupport, Reason};
+
+    let dir = std::env::current_dir()?;
+    let support = super::rename_exclusive_atomic_support(&dir)?;
+
+    println!("rename_exclusive atomic support: {:?}", support);
+    assert_eq!(support.support(), super::rename_exclusive_support(&dir)?);
+
+    if let AtomicSupport::No(Reason::KernelTooOld { found, required }) = support {
+        assert!(found < required);
+    }
+
+    assert!(super::Version::new(4, 9, 0) < super::Version::new(4, 10, 0));
+    assert_eq!(super::Version::new(6, 1, 0).to_string(), "6.1.0");
+
+    Ok(())
+}
+
+#[test]
+fn known_filesystems() -> Result<()> {
+    use super::{AtomicSupport, Reason};
+
+    let known = super::known_filesystems();
+
+    if cfg!(all(target_os = "linux", linker)) {
+        assert!(known.iter().any(|fs| fs.name == "ext4" && fs.magic == Some(0xef53)));
+    }
+
+    // A file system in the table is never rejected because of the file system.
+    let dir = tempfile::tempdir()?;
+
+    if let Ok(info) = super::fs_info(dir.path()) {
+        let listed = known.iter().any(|fs| match fs.magic {
+            Some(magic) => info.magic == Some(magic),
+            None => info.name.as_deref() == Some(fs.name),
+        });
+        let support = super::rename_exclusive_atomic_support(dir.path())?;
+
+        if listed {
+            assert!(!matches!(support, AtomicSupport::No(Reason::Filesystem(_))));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn register_support_predicate() -> Result<()> {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let dir = tempfile::tempdir()?;
+    let expected = super::rename_exclusive_support_uncached(dir.path())?;
+    let called = Arc::new(AtomicBool::new(false));
+
+    // Other tests are running at the same time so this can't change anything.
+    super::register_support_predicate({
+        let called = called.clone();
+        move |_| {
+            called.store(true, Ordering::Relaxed);
+            None
+        }
+    });
+
+    assert_eq!(super::rename_exclusive_support_uncached(dir.path())?, expected);
+    assert_eq!(called.load(Ordering::Relaxed), super::fs_info(dir.path()).is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn rename_exclusive_probe() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let file = dir.path().join("file");
+    std::fs::write(&file, "file")?;
+
+    let support = super::rename_exclusive_probe(dir.path())?;
+    assert_eq!(super::rename_exclusive_probe(&file)?, support);
+
+    // The probe finds support wherever the tables say that there is, and
+    // cleans up after itself. It can find support where the tables don't (like
+    // on FUSE, or on Windows where MoveFileExW also refuses to replace).
+    if super::rename_exclusive_is_atomic(dir.path())? {
+        assert_eq!(support, super::Support::Supported);
+    }
+    assert_ne!(support, super::Support::Unverifiable);
+    assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn rename_exclusive_posix() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+    let path_c = dir.path().join("c");
+
+    std::fs::write(&path_a, "a")?;
+    std::fs::create_dir(&path_b)?;
+
+    // Rename a file to a non-existent path.
+    super::posix::rename_exclusive_non_atomic(&path_a, &path_c)?;
+    assert!(!path_a.try_exists()?);
+    assert_eq!(std::fs::read_to_string(&path_c)?, "a");
+
+    // Rename a directory to a non-existent path.
+    super::posix::rename_exclusive_non_atomic(&path_b, &path_a)?;
+    assert!(!path_b.try_exists()?);
+    assert!(std::fs::metadata(&path_a)?.is_dir());
+
+    // Rename a file to an existing directory.
+    assert!(is_exists_error(super::posix::rename_exclusive_non_atomic(&path_c, &path_a)));
+    assert_eq!(std::fs::read_to_string(&path_c)?, "a");
+
+    // Rename a directory to an existing file.
+    assert!(is_exists_error(super::posix::rename_exclusive_non_atomic(&path_a, &path_c)));
+    assert!(std::fs::metadata(&path_a)?.is_dir());
+
+    // Rename a non-existent path. The placeholder shouldn't be created.
+    let path_d = path_b.with_extension("d");
+    assert!(super::posix::rename_exclusive_non_atomic(&path_b, &path_d).is_err());
+    assert!(!path_d.try_exists()?);
+
+    Ok(())
+}
+
+// The architectures are the ones in the syscall table in src/linux.rs.
+#[cfg(all(
+    target_os = "linux",
+    any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "riscv64",
+        target_arch = "loongarch64",
+        target_arch = "s390x",
+        target_arch = "powerpc",
+        target_arch = "powerpc64",
+    ),
+    linker,
+    syscall_table,
+    not(any(feature = "rustix", feature = "libc")),
+))]
+#[test]
+fn renameat2_syscall() -> Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::prelude::OsStrExt;
+    use super::linux::{syscall::renameat2, AT_FDCWD, RENAME_NOREPLACE};
+
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+    let path_c = dir.path().join("c");
+
+    std::fs::write(&path_a, "a")?;
+    std::fs::write(&path_b, "b")?;
+
+    let str_a = CString::new(path_a.as_os_str().as_bytes())?;
+    let str_b = CString::new(path_b.as_os_str().as_bytes())?;
+    let str_c = CString::new(path_c.as_os_str().as_bytes())?;
+
+    // Rename a file to an existing file.
+    let ret = unsafe {
+        renameat2(AT_FDCWD, str_a.as_ptr(), AT_FDCWD, str_b.as_ptr(), RENAME_NOREPLACE)
+    };
+    assert_eq!(ret, -1);
+    assert_eq!(std::io::Error::last_os_error().kind(), ErrorKind::AlreadyExists);
+    assert_eq!(std::fs::read_to_string(&path_b)?, "b");
+
+    // Rename a file to a non-existent path.
+    let ret = unsafe {
+        renameat2(AT_FDCWD, str_a.as_ptr(), AT_FDCWD, str_c.as_ptr(), RENAME_NOREPLACE)
+    };
+    assert_eq!(ret, 0);
+    assert!(!path_a.try_exists()?);
+    assert_eq!(std::fs::read_to_string(&path_c)?, "a");
+
+    Ok(())
+}
+
+#[test]
+fn rename_exclusive_long() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    // Longer than MAX_PATH on Windows but well short of PATH_MAX elsewhere.
+    let mut path_dir = dir.path().to_owned();
+    for _ in 0..20 {
+        path_dir.push("directory");
+    }
+    std::fs::create_dir_all(&path_dir)?;
+
+    let path_a = path_dir.join("a");
+    let path_b = path_dir.join("b");
+    std::fs::write(&path_a, "a")?;
+
+    // Rename a file with a long path to a non-existent path.
+    super::rename_exclusive(&path_a, &path_b)?;
+    assert!(!path_a.try_exists()?);
+    assert_eq!(std::fs::read_to_string(&path_b)?, "a");
+
+    // Rename a file with a long path to an existing file.
+    std::fs::write(&path_a, "b")?;
+    assert!(is_exists_error(super::rename_exclusive(&path_a, &path_b)));
+    assert_eq!(std::fs::read_to_string(&path_b)?, "a");
+
+    // A file name longer than any file system allows is an error rather than
+    // being truncated to something that might exist.
+    let path_c = path_dir.join("c".repeat(300));
+    assert!(super::rename_exclusive(&path_a, &path_c).is_err());
+    assert_eq!(std::fs::read_to_string(&path_a)?, "b");
+    assert_eq!(std::fs::read_dir(&path_dir)?.count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn check_file_name() {
+    use super::check_file_name;
+
+    for name in ["b.", "b ", "b. ", "aux", "aux.txt", "CON", "com1.tar.gz", "lpt9 .txt"] {
+        let error = check_file_name(&Path::new("dir").join(name)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput, "{}", name);
+    }
+
+    for name in ["b", ".b", "b.txt", "auxiliary.txt", "com10", "b c"] {
+        assert!(check_file_name(&Path::new("dir").join(name)).is_ok(), "{}", name);
+    }
+
+    assert!(check_file_name(Path::new("/")).is_ok());
+}
+
+#[cfg(target_os = "windows")]
+#[test]
+fn rename_exclusive_exact_names() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b.");
+    let path_aux = dir.path().join("aux.txt");
+
+    std::fs::write(&path_a, "a")?;
+
+    // Rename a file to names that Win32 would alter.
+    for path in [&path_b, &path_aux] {
+        let result = super::rename_exclusive(&path_a, path);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(path_a.try_exists()?);
+    }
+
+    // Rename a file to a name with a trailing dot.
+    super::RenameOptions::new()
+        .exact_names(true)
+        .rename_exclusive(&path_a, &path_b)?;
+    assert!(!path_a.try_exists()?);
+    assert!(!dir.path().join("b").try_exists()?);
+
+    Ok(())
+}
+
+#[test]
+fn rename_exclusive_durable() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+    let path_b_c = path_b.join("c");
+
+    std::fs::write(&path_a, "a")?;
+    std::fs::create_dir(&path_b)?;
+
+    // Rename a file into another directory.
+    super::RenameOptions::new()
+        .durable(true)
+        .rename_exclusive(&path_a, &path_b_c)?;
+    assert!(!path_a.try_exists()?);
+    assert_eq!(std::fs::read_to_string(&path_b_c)?, "a");
+
+    // Renaming only needs write and search permission on the directory but
+    // syncing it needs read permission. Root can open it anyway.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::set_permissions(&path_b, std::fs::Permissions::from_mode(0o300))?;
+
+        if std::fs::File::open(&path_b).is_err() {
+            let result = super::RenameOptions::new()
+                .durable(true)
+                .rename_exclusive(&path_b_c, path_b.join("d"));
+            let error = result.unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::Other);
+            assert!(error.get_ref().unwrap().is::<super::DurabilityError>());
+            assert!(path_b.join("d").try_exists()?);
+        }
+
+        std::fs::set_permissions(&path_b, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "windows"))]
+#[test]
+fn rename_at_reboot() {
+    let result = super::rename_at_reboot("a", "b");
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
+    let result = super::delete_at_reboot("a");
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
+}
+
+// Scheduling needs administrator privileges. If it works, then the source is
+// removed so that nothing happens at the next reboot.
+#[cfg(all(target_os = "windows", not(target_vendor = "uwp")))]
+#[test]
+fn rename_at_reboot() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let from = dir.path().join("from");
+    let to = dir.path().join("to");
+
+    std::fs::write(&from, "from")?;
+    std::fs::write(&to, "to")?;
+
+    match super::rename_at_reboot(&from, &to) {
+        Ok(()) => {}
+        Err(e) => assert_eq!(e.kind(), ErrorKind::PermissionDenied),
+    }
+
+    // The destination is only replaced at reboot.
+    assert_eq!(std::fs::read_to_string(&to)?, "to");
+    std::fs::remove_file(&from)?;
+
+    Ok(())
+}
+
+#[test]
+fn rename_exclusive_with_report() -> Result<()> {
+    use super::{Fallback, RenameOptions, Strategy};
+
+    let dir = tempfile::tempdir()?;
+    let from = dir.path().join("from");
+    let to = dir.path().join("to");
+    std::fs::write(&from, "from")?;
+
+    let (result, report) = RenameOptions::new().rename_exclusive_fallback_with_report(&from, &to);
+    assert_eq!(report.attempts, 1);
+    assert_eq!(report.bytes_copied, 0);
+
+    if result? {
+        assert_eq!(report.strategy, Strategy::Atomic);
+        assert!(!report.fell_back);
+    } else {
+        assert_eq!(report.strategy, Strategy::Fallback(Fallback::NonAtomic));
+        assert!(report.fell_back);
+    }
+
+    // The report is still returned when the rename fails.
+    std::fs::write(&from, "from")?;
+    let (result, report) = RenameOptions::new().rename_exclusive_with_report(&from, &to);
+    assert!(result.is_err());
+    assert_eq!(report.attempts, 1);
+
+    // Copying is recorded too.
+    let (result, report) = super::report::capture(|| {
+        let copy = dir.path().join("copy");
+        super::copy::move_across(&from, &copy, &RenameOptions::new(), |_, _| Ok(true))
+    });
+    assert!(result?);
+    assert_eq!(report.bytes_copied, 4);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "audit")]
+fn audit_sink() -> Result<()> {
+    use std::sync::Mutex;
+    use super::{AuditOutcome, AuditRecord, RenameOperation};
+
+    let dir = tempfile::tempdir()?;
+    let from = dir.path().join("from");
+    let to = dir.path().join("to");
+    std::fs::write(&from, "from")?;
+    std::fs::write(&to, "to")?;
+
+    // Other tests are renaming at the same time.
+    static RECORDS: Mutex<Vec<AuditRecord>> = Mutex::new(Vec::new());
+    let prefix = dir.path().to_path_buf();
+    super::set_audit_sink(move |record| {
+        if record.from.starts_with(&prefix) {
+            RECORDS.lock().unwrap().push(record.clone());
+        }
+    });
+
+    assert!(super::rename_exclusive(&from, &to).is_err());
+    super::remove_audit_sink();
+    assert!(super::rename_exclusive(&from, &to).is_err());
+
+    let records = RECORDS.lock().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].operation, RenameOperation::Exclusive);
+    assert_eq!(records[0].from, from);
+    assert_eq!(records[0].to, to);
+    assert!(matches!(records[0].outcome, AuditOutcome::Failed { .. }));
+
+    let json = serde_json::to_value(&records[0]).unwrap();
+    assert_eq!(json["result"], "Failed");
+    assert_eq!(json["operation"], "Exclusive");
+    assert!(json["timestamp"].as_u64().unwrap() > 0);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "notify")]
+fn detect_races() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let from = dir.path().join("from");
+    let to = dir.path().join("to");
+
+    std::fs::write(&from, "from")?;
+    let raced = super::race::watch(&to, || super::rename_exclusive_non_atomic(&from, &to))?;
+    assert!(!raced);
+
+    // Something else creates the destination between the check and the rename.
+    std::fs::rename(&to, &from)?;
+    let raced = super::race::watch(&to, || {
+        std::fs::write(&to, "to")?;
+        std::fs::rename(&from, &to)
+    })?;
+    assert!(raced);
+    assert_eq!(std::fs::read_to_string(&to)?, "from");
+
+    Ok(())
+}
+
+#[cfg(feature = "async")]
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake};
+    use std::thread::Thread;
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Arc::new(Unpark(std::thread::current())).into();
+    let mut context = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "async")]
+fn asynchronous() -> Result<()> {
+    use super::asynchronous::{self, ThreadExecutor};
+
+    let dir = tempfile::tempdir()?;
+    let from = dir.path().join("from");
+    let to = dir.path().join("to");
+
+    std::fs::write(&from, "from")?;
+    block_on(asynchronous::rename_exclusive_fallback(&ThreadExecutor, &from, &to))?;
+    assert_eq!(std::fs::read_to_string(&to)?, "from");
+
+    std::fs::write(&from, "from")?;
+    let result = block_on(asynchronous::rename_exclusive_fallback(&ThreadExecutor, &from, &to));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::AlreadyExists);
+
+    // An executor that drops the task without running it.
+    let result = block_on(asynchronous::rename_exclusive(&|_task| {}, &from, &to));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Other);
+    assert!(from.exists());
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "async")]
+fn warm_capabilities() -> Result<()> {
+    use super::asynchronous::{self, ThreadExecutor};
+
+    let dir = tempfile::tempdir()?;
+    let paths = [dir.path().to_path_buf(), dir.path().join("missing/file")];
+
+    block_on(asynchronous::warm_capabilities(&ThreadExecutor, &paths))?;
+    assert_eq!(
+        super::rename_exclusive_atomic_support(&paths[1])?,
+        super::rename_exclusive_atomic_support(&paths[0])?,
+    );
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "async")]
+fn asynchronous_batch() -> Result<()> {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::asynchronous::{self, BlockingExecutor, Task, ThreadExecutor};
+
+    let dir = tempfile::tempdir()?;
+    let renames = (0..10)
+        .map(|i| (dir.path().join(format!("{i}.tmp")), dir.path().join(i.to_string())))
+        .collect::<Vec<_>>();
+
+    for (from, _) in &renames {
+        std::fs::write(from, "from")?;
+    }
+    std::fs::write(&renames[3].1, "to")?;
+
+    // Count the tasks that are running at once.
+    let running = Arc::new(AtomicUsize::new(0));
+    let most = Arc::new(AtomicUsize::new(0));
+    let executor = {
+        let (running, most) = (running.clone(), most.clone());
+        move |task: Task| {
+            let (running, most) = (running.clone(), most.clone());
+            ThreadExecutor.execute(Box::new(move || {
+                most.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                std::thread::sleep(std::time::Duration::from_millis(5));
+                running.fetch_sub(1, Ordering::SeqCst);
+                task();
+            }));
+        }
+    };
+
+    let mut batch = asynchronous::rename_exclusive_all(&executor, 3, renames.clone());
+    let mut results = block_on(async {
+        let mut results = Vec::new();
+        while let Some(result) = batch.next().await {
+            results.push(result);
+        }
+        results
+    });
+
+    assert!(most.load(Ordering::SeqCst) <= 3);
+    results.sort_by_key(|(index, _)| *index);
+    assert_eq!(results.len(), 10);
+
+    for (index, result) in results {
+        if index == 3 {
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::AlreadyExists);
+        } else {
+            result?;
+            assert_eq!(std::fs::read_to_string(&renames[index].1)?, "from");
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(feature = "async", feature = "txf"))]
+fn asynchronous_transaction() -> Result<()> {
+    use super::asynchronous::{self, ThreadExecutor};
+
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+    let path_c = dir.path().join("c");
+
+    std::fs::write(&path_a, "a")?;
+    std::fs::write(&path_b, "b")?;
+
+    // The second rename fails so the first one is rolled back.
+    let renames = [(&path_a, &path_c), (&path_b, &path_c)];
+    let error = block_on(asynchronous::rename_exclusive_transaction(&ThreadExecutor, renames))
+        .unwrap_err();
+
+    assert!(matches!(error.kind(), ErrorKind::AlreadyExists | ErrorKind::Unsupported));
+    assert_eq!(std::fs::read_to_string(&path_a)?, "a");
+    assert!(!path_c.try_exists()?);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "io-uring", feature = "async"))]
+fn asynchronous_ring() -> Result<()> {
+    use super::asynchronous;
+
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+
+    std::fs::write(&path_a, "a")?;
+    std::fs::write(&path_b, "b")?;
+
+    let ring = match super::Ring::new(2) {
+        Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+        result => result?,
+    };
+    let renames = [(&path_a, &path_b)];
+    let (mut ring, results) = block_on(asynchronous::rename_exclusive_ring(ring, renames))?;
+
+    match &results[0] {
+        Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+        result => assert_eq!(result.as_ref().unwrap_err().kind(), ErrorKind::AlreadyExists),
+    }
+
+    // The ring comes back usable.
+    std::fs::remove_file(&path_b)?;
+    ring.rename_exclusive_all(&renames).into_iter().collect::<Result<()>>()?;
+    assert_eq!(std::fs::read_to_string(&path_b)?, "a");
+
+    // More renames than the ring can hold are submitted as earlier ones finish.
+    let renames = (0..5)
+        .map(|i| (dir.path().join(format!("{i}.tmp")), dir.path().join(i.to_string())))
+        .collect::<Vec<_>>();
+
+    for (from, _) in &renames {
+        std::fs::write(from, "")?;
+    }
+
+    let (_, results) = block_on(asynchronous::rename_exclusive_ring(ring, renames.clone()))?;
+
+    assert_eq!(results.len(), 5);
+    results.into_iter().collect::<Result<()>>()?;
+    assert!(renames.iter().all(|(from, to)| !from.exists() && to.exists()));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+fn ring_into_itself() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path_a = dir.path().join("a");
+    std::fs::create_dir(&path_a)?;
+
+    let mut ring = match super::Ring::new(2) {
+        Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+        result => result?,
+    };
+    let results = ring.rename_exclusive_all(&[(&path_a, path_a.join("b"))]);
+
+    // A real EINVAL isn't mistaken for a file system without RENAME_NOREPLACE.
+    if super::rename_exclusive_is_atomic(dir.path())? {
+        assert_eq!(results[0].as_ref().unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "camino")]
+fn camino() -> Result<()> {
+    use camino::Utf8PathBuf;
+
+    let dir = tempfile::tempdir()?;
+    let dir = Utf8PathBuf::try_from(dir.path().to_path_buf()).unwrap();
+    let from = dir.join("from");
+    let to = dir.join("to");
+
+    std::fs::write(&from, "from")?;
+    std::fs::write(&to, "to")?;
+
+    let error = super::utf8::rename_exclusive_detailed(&from, &to).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    assert_eq!(error.from(), from);
+    assert_eq!(error.to(), to);
+
+    std::fs::remove_file(&to)?;
+    super::rename_exclusive(&from, &to)?;
+    assert_eq!(std::fs::read_to_string(&to)?, "from");
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(feature = "cap-std", unix))]
+fn rename_exclusive_in() -> Result<()> {
+    use cap_std::{ambient_authority, fs::Dir};
+
+    let temp = tempfile::tempdir()?;
+    std::fs::create_dir(temp.path().join("dir"))?;
+    std::fs::write(temp.path().join("outside"), "outside")?;
+    let dir = Dir::open_ambient_dir(temp.path().join("dir"), ambient_authority())?;
+
+    dir.write("from", "from")?;
+    match super::rename_exclusive_in(&dir, "from", "to") {
+        Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+        result => result?,
+    }
+    assert_eq!(dir.read_to_string("to")?, "from");
+
+    dir.write("from", "from")?;
+    let result = super::rename_exclusive_in(&dir, "from", "to");
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::AlreadyExists);
+
+    assert!(super::rename_exclusive_in(&dir, "../outside", "escaped").is_err());
+    assert!(!dir.exists("escaped"));
+
+    super::rename_exclusive_is_atomic_in(&dir)?;
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn serde() -> Result<()> {
+    use std::time::Duration;
+    use super::{AtomicSupport, PlaceholderPolicy, Reason, RenameOptions, Version};
+
+    let json = r#"{"durable": true, "placeholders": "Hydrate", "retries": 2}"#;
+    let options: RenameOptions = serde_json::from_str(json)?;
+    assert!(options.durable);
+    assert_eq!(options.placeholders, PlaceholderPolicy::Hydrate);
+    assert_eq!(options.retries, 2);
+    assert_eq!(options.retry_delay, Duration::from_millis(10));
+
+    let json = serde_json::to_string(&options)?;
+    let options: RenameOptions = serde_json::from_str(&json)?;
+    assert!(options.durable);
+
+    // The same fields are written whichever features are enabled.
+    let json = serde_json::to_value(&options)?;
+    assert_eq!(json["detect_races"], false);
+    assert_eq!(json["etw_raw_paths"], false);
+    assert!(json.get("progress").is_none());
+
+    let mut plan = super::RenamePlan::new();
+    plan.push("a.tmp", "a");
+    plan.options.durable = true;
+    let json = serde_json::to_string(&plan)?;
+    assert_eq!(serde_json::from_str::<super::RenamePlan>(&json)?, plan);
+
+    let support = AtomicSupport::No(Reason::KernelTooOld {
+        found: Version::new(3, 10, 0),
+        required: Version::new(3, 15, 0),
+    });
+    let json = serde_json::to_string(&support)?;
+    assert_eq!(serde_json::from_str::<AtomicSupport>(&json)?, support);
+
+    let dir = tempfile::tempdir()?;
+    let capabilities = super::capabilities(dir.path())?;
+    let json = serde_json::to_string(&capabilities)?;
+    assert_eq!(serde_json::from_str::<super::Capabilities>(&json)?, capabilities);
+
+    Ok(())
+}
+
+#[test]
+fn rename_plan() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+    let path_c = dir.path().join("c");
+
+    std::fs::write(&path_a, "a")?;
+    std::fs::write(&path_b, "b")?;
+
+    let mut plan = super::RenamePlan::new();
+    plan.push(&path_a, &path_c).push(&path_b, &path_c);
+
+    let mut results = plan.execute().into_iter().map(|(result, _)| result);
+
+    match results.next().unwrap() {
+        Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+        result => result?,
+    }
+    assert!(is_exists_error(results.next().unwrap()));
+    assert!(results.next().is_none());
+    assert_eq!(std::fs::read_to_string(&path_c)?, "a");
+
+    Ok(())
+}
+
+#[test]
+fn backend() -> Result<()> {
+    use super::{Backend, RenameOptions, Strategy};
+
+    let dir = tempfile::tempdir()?;
+    let from = dir.path().join("from");
+    let to = dir.path().join("to");
+
+    for backend in [Backend::EmulateOnly, Backend::NonAtomicOnly] {
+        let mut options = RenameOptions::new();
+        options.backend(backend);
+
+        std::fs::write(&from, "from")?;
+        let result = options.rename_exclusive(&from, &to);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
+
+        let (result, report) = options.rename_exclusive_fallback_with_report(&from, &to);
+        assert!(!result?);
+        assert!(report.fell_back);
+        assert_eq!(std::fs::read_to_string(&to)?, "from");
+
+        std::fs::write(&from, "from")?;
+        let result = options.rename_exclusive_fallback(&from, &to);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::AlreadyExists);
+        std::fs::remove_file(&to)?;
+    }
+
+    let (result, report) = RenameOptions::new()
+        .backend(Backend::NativeOnly)
+        .rename_exclusive_fallback_with_report(&from, &to);
+    match result {
+        Ok(atomic) => assert!(atomic),
+        Err(e) => assert_eq!(e.kind(), ErrorKind::Unsupported),
+    }
+    assert_eq!(report.strategy, Strategy::Atomic);
+
+    Ok(())
+}
+
+#[test]
+fn injected_failure() {
+    use super::config::{failure, force_non_atomic, injected_failure};
+
+    // The environment isn't consulted unless it's been enabled.
+    assert!(injected_failure().is_none());
+    assert!(!force_non_atomic());
+
+    assert_eq!(failure("ENOSPC").kind(), ErrorKind::StorageFull);
+    assert_eq!(failure("EXDEV").kind(), ErrorKind::CrossesDevices);
+    assert_eq!(failure("EWHATEVER").kind(), ErrorKind::Other);
+    assert_eq!(failure("13").raw_os_error(), Some(13));
+}
+
+#[test]
+fn renamer() -> Result<()> {
+    use std::cell::RefCell;
+    use super::{Renamer, RenameOptions, Support, SystemRenamer};
+
+    fn publish(renamer: &impl Renamer, from: &Path, to: &Path) -> Result<bool> {
+        if renamer.rename_exclusive_is_atomic(to)? {
+            renamer.rename_exclusive(from, to).map(|_| true)
+        } else {
+            renamer.rename_exclusive_fallback(from, to)
+        }
+    }
+
+    #[derive(Default)]
+    struct Mock {
+        calls: RefCell<Vec<PathBuf>>,
+    }
+
+    impl Renamer for Mock {
+        fn rename_exclusive(&self, _from: &Path, to: &Path) -> Result<()> {
+            self.calls.borrow_mut().push(to.to_path_buf());
+            Ok(())
+        }
+
+        fn rename_exclusive_fallback(&self, _from: &Path, _to: &Path) -> Result<bool> {
+            unreachable!()
+        }
+
+        fn rename_exchange(&self, _a: &Path, _b: &Path) -> Result<()> {
+            unreachable!()
+        }
+
+        fn rename_exclusive_is_atomic(&self, _path: &Path) -> Result<bool> {
+            Ok(true)
+        }
+
+        fn rename_exclusive_support(&self, _path: &Path) -> Result<Support> {
+            Ok(Support::Supported)
+        }
+    }
+
+    let mock = Mock::default();
+    assert!(publish(&mock, Path::new("a"), Path::new("b"))?);
+    assert_eq!(*mock.calls.borrow(), [Path::new("b")]);
+
+    let dir = tempfile::tempdir()?;
+    let from = dir.path().join("from");
+    let to = dir.path().join("to");
+
+    std::fs::write(&from, "from")?;
+    publish(&SystemRenamer, &from, &to)?;
+    assert_eq!(std::fs::read_to_string(&to)?, "from");
+
+    std::fs::write(&from, "from")?;
+    let renamer: &dyn Renamer = &RenameOptions::new();
+    let result = publish(&renamer, &from, &to);
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::AlreadyExists);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "test-util")]
+fn fault_injection() -> Result<()> {
+    use super::RenameOptions;
+    use super::test_util::{Fault, Faults};
+
+    let dir = tempfile::tempdir()?;
+    let from = dir.path().join("from");
+    let to = dir.path().join("to");
+
+    std::fs::write(&from, "from")?;
+    let result = Faults::new()
+        .fail_every_rename(Fault::StorageFull)
+        .run(|| super::rename_exclusive(&from, &to));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::StorageFull);
+
+    // The injected failure makes the fallback use a plain rename.
+    let atomic = Faults::new()
+        .fail_rename(1, Fault::Unsupported)
+        .run(|| super::rename_exclusive_fallback(&from, &to))?;
+    assert!(!atomic);
+    std::fs::rename(&to, &from)?;
+
+    // Copying across file systems runs out of space and the partial copy is
+    // removed.
+    std::fs::create_dir(dir.path().join("dir"))?;
+    std::fs::write(dir.path().join("dir/a"), "a")?;
+    std::fs::write(dir.path().join("dir/b"), "b")?;
+    let result = Faults::new()
+        .fail_rename(1, Fault::CrossesDevices)
+        .fail_copy(Fault::StorageFull)
+        .run(|| {
+            RenameOptions::new().cross_device(true).rename_exclusive(dir.path().join("dir"), &to)
+        });
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::StorageFull);
+    assert_eq!(std::fs::read_dir(dir.path())?.count(), 2);
+
+    // A conflict after copying is reported the same way as one without.
+    let full = dir.path().join("full");
+    std::fs::create_dir(&full)?;
+    std::fs::write(full.join("c"), "c")?;
+    let result = Faults::new()
+        .fail_rename(1, Fault::CrossesDevices)
+        .run(|| {
+            RenameOptions::new().cross_device(true).rename_exclusive(dir.path().join("dir"), &full)
+        });
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::DirectoryNotEmpty);
+    assert!(dir.path().join("dir/a").try_exists()?);
+
+    // Without a plan, nothing is injected.
+    super::rename_exclusive_fallback(&from, &to)?;
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "test-util")]
+fn memory_fs() -> Result<()> {
+    use super::Renamer;
+    use super::test_util::MemoryFs;
+
+    let fs = MemoryFs::new();
+    fs.create_dir("dir")?;
+    fs.create_dir("dir/sub")?;
+    fs.write("dir/sub/file", "file")?;
+    fs.write("other", "other")?;
+
+    fs.rename_exclusive(Path::new("dir"), Path::new("moved/"))?;
+    let paths = ["moved", "moved/sub", "moved/sub/file", "other"].map(PathBuf::from);
+    assert_eq!(fs.paths(), paths);
+    // Relative paths aren't resolved against anything.
+    assert!(!fs.exists("/other"));
+
+    let result = fs.rename_exclusive(Path::new("other"), Path::new("moved/./sub/file"));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::AlreadyExists);
+    let result = fs.rename_exclusive(Path::new("missing"), Path::new("new"));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::NotFound);
+    let result = fs.rename_exclusive(Path::new("other"), Path::new("missing/new"));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::NotFound);
+    let result = fs.rename_exclusive(Path::new("moved"), Path::new("moved/sub/inside"));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+
+    fs.rename_exchange(Path::new("other"), Path::new("moved/sub"))?;
+    assert_eq!(fs.read("moved/sub")?, b"other");
+    assert_eq!(fs.read("other/file")?, b"file");
+
+    fs.set_atomic(false);
+    let result = fs.rename_exclusive(Path::new("moved/sub"), Path::new("new"));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
+    assert!(!fs.rename_exclusive_fallback(Path::new("moved/sub"), Path::new("new"))?);
+    assert!(fs.exists("new"));
+    assert!(!fs.exists("moved/sub"));
+
+    Ok(())
+}
+
+#[test]
+fn fallback_hook() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::io::Error;
+    use super::{Fallback, FallbackEvent};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    // Other tests might use a fallback while this one is running.
+    fn hook(event: &FallbackEvent) {
+        if event.from() == Path::new("hook-a") {
+            assert_eq!(event.fallback(), Fallback::Copy);
+            assert_eq!(event.to(), Path::new("hook-b"));
+            assert_eq!(event.reason().kind(), ErrorKind::CrossesDevices);
+            CALLS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let error = Error::from(ErrorKind::CrossesDevices);
+    let (from, to) = (Path::new("hook-a"), Path::new("hook-b"));
+    let event = FallbackEvent::new(Fallback::Copy, from, to, &error);
+
+    super::set_fallback_hook(hook);
+    super::instrument::fallback(&event);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+
+    super::remove_fallback_hook();
+    super::instrument::fallback(&event);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn move_across() -> Result<()> {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_a_b = path_a.join("b");
+    let path_a_c = path_a.join("c");
+    let path_a_c_d = path_a_c.join("d");
+    let path_e = dir.path().join("e");
+
+    std::fs::create_dir(&path_a)?;
+    std::fs::write(&path_a_b, "bb")?;
+    std::fs::create_dir(&path_a_c)?;
+    std::fs::write(&path_a_c_d, "ddd")?;
+
+    let copied = Arc::new(AtomicU64::new(0));
+    let mut options = super::RenameOptions::new();
+    options.progress({
+        let copied = copied.clone();
+        move |c, total| {
+            assert!(c <= total);
+            copied.store(c, Ordering::Relaxed);
+        }
+    });
+
+    let commit = |from: &Path, to: &Path| options.rename_exclusive(from, to).map(|_| true);
+
+    // Move a directory to a non-existent path.
+    assert!(super::copy::move_across(&path_a, &path_e, &options, commit)?);
+    assert!(!path_a.try_exists()?);
+    assert_eq!(std::fs::read_to_string(path_e.join("b"))?, "bb");
+    assert_eq!(std::fs::read_to_string(path_e.join("c").join("d"))?, "ddd");
+    assert_eq!(copied.load(Ordering::Relaxed), 5);
+
+    // Move a file to an existing directory.
+    let commit = |from: &Path, to: &Path| options.rename_exclusive(from, to).map(|_| true);
+    std::fs::write(&path_a, "a")?;
+    let error = super::copy::move_across(&path_a, &path_e, &options, commit).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::DirectoryNotEmpty);
+    assert_eq!(std::fs::read_to_string(&path_a)?, "a");
+    assert_eq!(std::fs::read_dir(dir.path())?.count(), 2);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(target_os = "linux", linker))]
+fn move_across_security() -> Result<()> {
+    use std::ffi::OsStr;
+    use super::linux::{lgetxattr, lsetxattr, read_xattr};
+
+    let dir = tempfile::tempdir()?;
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+    let trusted = OsStr::new("trusted.renamore");
+    let user = OsStr::new("user.renamore");
+
+    std::fs::write(&path_a, "a")?;
+
+    // Setting trusted.* requires CAP_SYS_ADMIN and user.* isn't supported by
+    // every file system.
+    if lsetxattr(&path_a, trusted, b"t").is_err() || lsetxattr(&path_a, user, b"u").is_err() {
+        return Ok(());
+    }
+
+    let mut options = super::RenameOptions::new();
+    options.preserve_security(true);
+    let commit = |from: &Path, to: &Path| options.rename_exclusive(from, to).map(|_| true);
+
+    assert!(super::copy::move_across(&path_a, &path_b, &options, commit)?);
+    assert_eq!(read_xattr(|buf| lgetxattr(&path_b, trusted, buf))?, b"t");
+    assert!(read_xattr(|buf| lgetxattr(&path_b, user, buf)).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn move_across_cancel() -> Result<()> {
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+
+    std::fs::write(&path_a, "a")?;
+
+    let mut options = super::RenameOptions::new();
+    options.cancel(Arc::new(AtomicBool::new(true)));
+
+    let commit = |from: &Path, to: &Path| options.rename_exclusive(from, to).map(|_| true);
+    assert!(super::copy::move_across(&path_a, &path_b, &options, commit).is_err());
+    assert_eq!(std::fs::read_to_string(&path_a)?, "a");
+    assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(feature = "txf", not(all(target_os = "windows", not(target_vendor = "uwp")))))]
+fn transaction() {
+    let result = super::Transaction::new();
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+#[cfg(all(feature = "txf", target_os = "windows", not(target_vendor = "uwp")))]
+fn transaction() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+    let path_c = dir.path().join("c");
+
+    std::fs::write(&path_a, "a")?;
+    std::fs::write(&path_b, "b")?;
+
+    let transaction = match super::Transaction::new() {
+        Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+        result => result?,
+    };
+
+    match transaction.rename_exclusive(&path_a, &path_c) {
+        Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+        result => result?,
+    }
+    assert!(is_exists_error(transaction.rename_exclusive(&path_b, &path_c)));
+
+    // Nothing has happened until the transaction is committed.
+    assert!(path_a.try_exists()?);
+    assert!(!path_c.try_exists()?);
+
+    transaction.commit()?;
+
+    assert!(!path_a.try_exists()?);
+    assert_eq!(std::fs::read_to_string(&path_c)?, "a");
+
+    Ok(())
+}
+
+#[test]
+fn swap_in_validated() -> Result<()> {
+    use super::swap_in_validated;
+
+    let dir = tempfile::tempdir()?;
+    let staged = dir.path().join("config.new");
+    let live = dir.path().join("config");
+
+    std::fs::write(&staged, "invalid")?;
+
+    let validate = |path: &Path| match std::fs::read_to_string(path)?.as_str() {
+        "invalid" => Err(ErrorKind::InvalidData.into()),
+        _ => Ok(()),
+    };
+
+    let result = swap_in_validated(&staged, &live, validate);
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+    assert!(!live.try_exists()?);
+
+    // The first install doesn't need rename_exchange.
+    std::fs::write(&staged, "first")?;
+    assert_eq!(swap_in_validated(&staged, &live, validate)?, None);
+    assert_eq!(std::fs::read_to_string(&live)?, "first");
+
+    // Where swapping isn't supported, the previous version is kept as a backup.
+    std::fs::write(&staged, "second")?;
+    let previous = swap_in_validated(&staged, &live, validate)?.unwrap();
+    assert_eq!(std::fs::read_to_string(&live)?, "second");
+    assert_eq!(std::fs::read_to_string(&previous)?, "first");
+    assert!(previous == staged || previous == dir.path().join("config.previous"));
+
+    // An earlier backup is replaced.
+    std::fs::write(&staged, "third")?;
+    let previous = swap_in_validated(&staged, &live, validate)?.unwrap();
+    assert_eq!(std::fs::read_to_string(&live)?, "third");
+    assert_eq!(std::fs::read_to_string(&previous)?, "second");
+
+    Ok(())
+}
+
+#[test]
+fn replace_with_backup() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let staged = dir.path().join("config.new");
+    let live = dir.path().join("config");
+    let backup = dir.path().join("config.previous");
+
+    std::fs::write(&staged, "new")?;
+    std::fs::write(&live, "old")?;
+    std::fs::write(&backup, "older")?;
+
+    super::replace::replace_with_backup(&staged, &live, &backup)?;
+
+    assert!(!staged.try_exists()?);
+    assert_eq!(std::fs::read_to_string(&live)?, "new");
+    assert_eq!(std::fs::read_to_string(&backup)?, "old");
+
+    Ok(())
+}
+
+#[test]
+fn lockfile() -> Result<()> {
+    use std::io::Write;
+    use super::Lockfile;
+
+    let dir = tempfile::tempdir()?;
+    let config = dir.path().join("config");
+    let lock_path = dir.path().join("config.lock");
+
+    std::fs::write(&config, "old")?;
+
+    let mut lock = Lockfile::acquire(&config)?;
+    assert_eq!(lock.lock_path(), lock_path);
+    assert!(is_exists_error(Lockfile::acquire(&config)));
+
+    lock.write_all(b"new")?;
+    assert_eq!(std::fs::read_to_string(&config)?, "old");
+    lock.commit()?;
+    assert_eq!(std::fs::read_to_string(&config)?, "new");
+    assert!(!lock_path.try_exists()?);
+
+    let mut lock = Lockfile::acquire(&config)?;
+    lock.write_all(b"rolled back")?;
+    lock.rollback()?;
+    assert!(!lock_path.try_exists()?);
+
+    let mut lock = Lockfile::acquire(&config)?;
+    lock.write_all(b"dropped")?;
+    drop(lock);
+    assert!(!lock_path.try_exists()?);
+    assert_eq!(std::fs::read_to_string(&config)?, "new");
+
+    Ok(())
+}
+
+#[test]
+fn maildir() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let maildir = super::Maildir::create(dir.path().join("Mail"))?;
+
+    let first = maildir.deliver(b"first")?;
+    let second = maildir.deliver_with(|file| {
+        use std::io::Write;
+        file.write_all(b"second")
+    })?;
+
+    assert_ne!(first, second);
+    assert_eq!(first.parent(), Some(maildir.path().join("new").as_path()));
+    assert_eq!(std::fs::read_to_string(&first)?, "first");
+    assert_eq!(std::fs::read_to_string(&second)?, "second");
+
+    // A failed delivery doesn't leave anything behind.
+    let result = maildir.deliver_with(|_| Err(ErrorKind::Other.into()));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Other);
+    assert_eq!(std::fs::read_dir(maildir.path().join("tmp"))?.count(), 0);
+    assert_eq!(std::fs::read_dir(maildir.path().join("new"))?.count(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn capabilities() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let capabilities = super::capabilities(dir.path())?;
+
+    assert_eq!(capabilities.atomic_exclusive, super::rename_exclusive_is_atomic(dir.path())?);
+
+    if cfg!(all(target_os = "windows", not(target_vendor = "uwp"))) {
+        assert!(capabilities.file_system.is_some());
+    }
+
+    if cfg!(all(target_os = "linux", linker)) && Path::new("/proc/self/mountinfo").exists() {
+        assert!(capabilities.file_system.is_some());
+        assert!(dir.path().canonicalize()?.starts_with(capabilities.mount_point.unwrap()));
+    }
+
+    if capabilities.exchange {
+        let path_a = dir.path().join("a");
+        let path_b = dir.path().join("b");
+        std::fs::write(&path_a, "a")?;
+        std::fs::write(&path_b, "b")?;
+        super::rename_exchange(&path_a, &path_b)?;
+        assert_eq!(std::fs::read_to_string(&path_a)?, "b");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn fs_info() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let info = match super::fs_info(dir.path()) {
+        Ok(info) => info,
+        Err(e) => {
+            assert_eq!(e.kind(), ErrorKind::Unsupported);
+            return Ok(());
+        }
+    };
+
+    println!("fs_info: {:?}", info);
+    assert!(!info.network);
+
+    if cfg!(target_os = "linux") {
+        assert!(info.magic.is_some());
+    }
+
+    if cfg!(all(target_os = "linux", linker)) && Path::new("/proc/self/mountinfo").exists() {
+        assert_eq!(info.name, super::capabilities(dir.path())?.file_system);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[cfg(not(all(target_os = "windows", not(target_vendor = "uwp"))))]
+fn rename_stream_exclusive() {
+    let result = super::rename_stream_exclusive("a", "b", "c");
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Unsupported);
+
+    // The names are checked first.
+    let invalid = [("b\0", "c"), ("b", "x:y"), (":b", ":"), ("b:$DATA", "c::$DATA"), ("b", "c/d")];
+
+    for (from, to) in invalid {
+        let result = super::rename_stream_exclusive("a", from, to);
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput, "{:?}", (from, to));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let from = std::ffi::OsStr::from_bytes(b"\xff");
+        let result = super::rename_stream_exclusive("a", from, "c");
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+#[cfg(all(target_os = "windows", not(target_vendor = "uwp")))]
+fn rename_stream_exclusive() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("a");
+
+    std::fs::write(&path, "a")?;
+    // This fails if the file system doesn't support streams.
+    if std::fs::write(dir.path().join("a:b"), "b").is_err() {
+        return Ok(());
+    }
+    std::fs::write(dir.path().join("a:c"), "c")?;
+
+    // Rename a stream to a non-existent stream.
+    super::rename_stream_exclusive(&path, "b", ":d")?;
+    assert_eq!(std::fs::read_to_string(dir.path().join("a:d"))?, "b");
+    assert!(!dir.path().join("a:b").try_exists()?);
+
+    // Rename a stream to an existing stream.
+    assert!(is_exists_error(super::rename_stream_exclusive(&path, "c", "d")));
+    assert_eq!(std::fs::read_to_string(dir.path().join("a:c"))?, "c");
+
+    assert_eq!(
+        super::rename_stream_exclusive(&path, "c", "x:y").unwrap_err().kind(),
+        ErrorKind::InvalidInput,
+    );
+    assert_eq!(
+        super::rename_stream_exclusive(&path, "c\0", "e").unwrap_err().kind(),
+        ErrorKind::InvalidInput,
+    );
+
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "windows")]
+fn rename_exclusive_acl_inheritance() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+    let path_b_c = path_b.join("c");
+    let path_d = dir.path().join("d");
+
+    std::fs::write(&path_a, "a")?;
+    std::fs::create_dir(&path_b)?;
+
+    super::RenameOptions::new()
+        .acl_inheritance(super::AclInheritance::Inherit)
+        .rename_exclusive(&path_a, &path_b_c)?;
+    assert_eq!(std::fs::read_to_string(&path_b_c)?, "a");
+
+    super::RenameOptions::new()
+        .acl_inheritance(super::AclInheritance::Reset)
+        .rename_exclusive(&path_b_c, &path_d)?;
+    assert_eq!(std::fs::read_to_string(&path_d)?, "a");
+
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "windows")]
+fn normalize_error() -> Result<()> {
+    use std::io::Error;
+    use super::windows::normalize_error;
+
+    let dir = tempfile::tempdir()?;
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+
+    std::fs::create_dir(&path_a)?;
+
+    // ERROR_FILE_EXISTS and ERROR_ALREADY_EXISTS.
+    for code in [80, 183] {
+        let error = normalize_error(Error::from_raw_os_error(code), &path_b);
+        assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    }
+
+    // ERROR_ACCESS_DENIED is only a conflict if the destination is a directory.
+    let error = normalize_error(Error::from_raw_os_error(5), &path_a);
+    assert_eq!(error.kind(), ErrorKind::AlreadyExists);
+    let error = normalize_error(Error::from_raw_os_error(5), &path_b);
+    assert_eq!(error.raw_os_error(), Some(5));
+    std::fs::write(&path_b, "b")?;
+    let error = normalize_error(Error::from_raw_os_error(5), &path_b);
+    assert_eq!(error.raw_os_error(), Some(5));
+
+    // ERROR_SHARING_VIOLATION is left alone.
+    let error = normalize_error(Error::from_raw_os_error(32), &path_a);
+    assert_eq!(error.raw_os_error(), Some(32));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(any(all(target_os = "linux", linker), target_vendor = "apple"))]
+fn normalize_error_unsupported() {
+    use std::io::Error;
+    use super::sys::normalize_error;
+
+    let unsupported = || false;
+
+    // EINVAL is how the file system says it doesn't support the flags.
+    let error = normalize_error(Error::from_raw_os_error(22), unsupported);
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+
+    // Unless it's known to support them. Then it's a mistake by the caller.
+    let error = normalize_error(Error::from_raw_os_error(22), || true);
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+    // ENOSYS on old kernels. The number differs on some architectures.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    assert_eq!(
+        normalize_error(Error::from_raw_os_error(38), unsupported).kind(),
+        ErrorKind::Unsupported,
+    );
+
+    // ENOTSUP on Darwin.
+    #[cfg(target_vendor = "apple")]
+    assert_eq!(
+        normalize_error(Error::from_raw_os_error(45), || true).kind(),
+        ErrorKind::Unsupported,
+    );
+
+    // EEXIST and ENOENT are left alone.
+    for code in [17, 2] {
+        let error = normalize_error(Error::from_raw_os_error(code), unsupported);
+        assert_eq!(error.raw_os_error(), Some(code));
+    }
+}
+
+// EINVAL from moving a directory into itself isn't the file system saying that
+// it doesn't support the flag.
+#[test]
+fn rename_exclusive_into_itself() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_a_b = path_a.join("b");
+    std::fs::create_dir(&path_a)?;
+
+    let error = super::rename_exclusive(&path_a, &path_a_b).unwrap_err();
+    if super::rename_exclusive_is_atomic(dir.path())? {
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    let error = super::rename_exclusive_fallback(&path_a, &path_a_b).unwrap_err();
+    #[cfg(unix)]
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    #[cfg(not(unix))]
+    let _ = error;
+
+    assert!(path_a.is_dir());
+    assert!(!path_a_b.try_exists()?);
+
+    Ok(())
+}
+
+#[test]
+fn rename_exclusive_no_symlinks() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+
+    std::fs::write(&path_a, "a")?;
+
+    let supported = super::capabilities(dir.path())?.no_symlinks;
+    let mut options = super::RenameOptions::new();
+    options.no_symlinks(true);
+
+    match options.rename_exclusive_fallback(&path_a, &path_b) {
+        Ok(atomic) => {
+            assert!(supported && atomic);
+            assert_eq!(std::fs::read_to_string(&path_b)?, "a");
+        }
+        Err(e) => {
+            assert!(!supported);
+            assert_eq!(e.kind(), ErrorKind::Unsupported);
+            return Ok(());
+        }
+    }
+
+    // Rename through a symlink to a directory.
+    #[cfg(unix)]
+    {
+        let path_c = dir.path().join("c");
+        std::os::unix::fs::symlink(dir.path(), &path_c)?;
+        assert!(options.rename_exclusive(&path_b, path_c.join("d")).is_err());
+        assert!(path_b.try_exists()?);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn rename_exclusive_no_follow_source() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+    let path_c = dir.path().join("c");
+
+    std::fs::write(&path_a, "a")?;
+    std::os::unix::fs::symlink(&path_a, &path_b)?;
+
+    let mut options = super::RenameOptions::new();
+    options.no_follow_source(true);
+
+    let error = options.rename_exclusive_fallback(&path_b, &path_c).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    assert!(error.get_ref().unwrap().is::<super::SymlinkError>());
+    assert!(path_b.symlink_metadata()?.is_symlink());
+    assert!(!path_c.try_exists()?);
+
+    // Regular files are renamed as usual.
+    options.rename_exclusive_fallback(&path_a, &path_c)?;
+    assert_eq!(std::fs::read_to_string(&path_c)?, "a");
+
+    Ok(())
+}
+
+#[test]
+fn rename_exchange() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+    let path_c = dir.path().join("c");
+
+    std::fs::write(&path_a, "a")?;
+    std::fs::write(&path_b, "b")?;
+
+    match super::rename_exchange(&path_a, &path_b) {
+        Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+        result => result?,
+    }
+    assert_eq!(std::fs::read_to_string(&path_a)?, "b");
+    assert_eq!(std::fs::read_to_string(&path_b)?, "a");
+
+    // Both paths must exist.
+    assert!(super::rename_exchange(&path_a, &path_c).is_err());
+    assert_eq!(std::fs::read_to_string(&path_a)?, "b");
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "stats")]
+fn stats() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let from = dir.path().join("from");
+    let to = dir.path().join("to");
+    std::fs::write(&from, "from")?;
+
+    let before = super::stats();
+    let atomic = super::rename_exclusive_fallback(&from, &to)?;
+    let after = super::stats();
+
+    // Other tests are renaming at the same time so the counters could have
+    // gone up by more than one.
+    if atomic {
+        assert!(after.atomic_renames > before.atomic_renames);
+    } else {
+        assert!(after.fallbacks > before.fallbacks);
+    }
+
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "normalization")]
+fn rename_exclusive_normalization() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    // An e with a combining acute accent and a precomposed é.
+    let path_nfd = dir.path().join("e\u{301}");
+    let path_nfc = dir.path().join("\u{e9}");
+
+    std::fs::write(&path_a, "a")?;
+    std::fs::write(&path_nfd, "e")?;
+
+    let result = super::RenameOptions::new()
+        .check_normalization(true)
+        .rename_exclusive(&path_a, &path_nfc);
+    assert!(is_exists_error(result));
+    assert_eq!(std::fs::read_to_string(&path_a)?, "a");
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+fn ring_rename_exclusive_all() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+
+    let path_a = dir.path().join("a");
+    let path_b = dir.path().join("b");
+    let path_c = dir.path().join("c");
+    let path_d = dir.path().join("d");
+
+    std::fs::write(&path_a, "a")?;
+    std::fs::write(&path_b, "b")?;
+    std::fs::write(&path_c, "c")?;
+
+    let mut ring = match super::Ring::new(2) {
+        Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+        result => result?,
+    };
+    let results = ring.rename_exclusive_all(&[
+        (&path_a, &path_d),
+        (&path_b, &path_c),
+        (&path_a, &path_b),
+    ]);
+
+    if let Err(e) = &results[0] {
+        if e.kind() == ErrorKind::Unsupported {
+            return Ok(());
+        }
+    }
+
+    assert_eq!(results.len(), 3);
+    assert!(results[0].is_ok());
+    assert!(matches!(&results[1], Err(e) if e.kind() == ErrorKind::AlreadyExists));
+    assert!(matches!(&results[2], Err(e) if e.kind() == ErrorKind::NotFound));
+    assert_eq!(std::fs::read_to_string(&path_d)?, "a");
+    assert_eq!(std::fs::read_to_string(&path_c)?, "c");
+
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn rename_exclusive_beneath() -> Result<()> {
+    use std::os::fd::AsFd;
+
+    let dir = tempfile::tempdir()?;
+    let root_path = dir.path().join("root");
+    let outside = dir.path().join("outside");
+
+    std::fs::create_dir(&root_path)?;
+    std::fs::create_dir(root_path.join("sub"))?;
+    std::fs::write(root_path.join("sub/a"), "a")?;
+    std::fs::write(root_path.join("b"), "b")?;
+    std::fs::write(&outside, "outside")?;
+    std::os::unix::fs::symlink(dir.path(), root_path.join("link"))?;
+
+    let root = std::fs::File::open(&root_path)?;
+    let root = root.as_fd();
+
+    match super::rename_exclusive_beneath(root, "sub/a", "c") {
+        Err(e) if e.kind() == ErrorKind::Unsupported => return Ok(()),
+        result => result?,
+    }
+
+    assert_eq!(std::fs::read_to_string(root_path.join("c"))?, "a");
+    assert!(!root_path.join("sub/a").exists());
+
+    let result = super::rename_exclusive_beneath(root, "c", "b");
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::AlreadyExists);
+
+    // Escaping through `..`, an absolute path or a symlink.
+    assert!(super::rename_exclusive_beneath(root, "../outside", "d").is_err());
+    assert!(super::rename_exclusive_beneath(root, &outside, "d").is_err());
+    assert!(super::rename_exclusive_beneath(root, "link/outside", "d").is_err());
+    assert!(super::rename_exclusive_beneath(root, "c", "sub/../../d").is_err());
+    assert_eq!(std::fs::read_to_string(&outside)?, "outside");
+    assert!(!dir.path().join("d").exists());
+    assert!(!root_path.join("d").exists());
+
+    let result = super::rename_exclusive_beneath(root, "c", "..");
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(target_os = "linux", linker))]
+fn mountinfo() {
+    use std::path::Path;
+    use super::mountinfo::{find_in, parse};
+
+    let mountinfo = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw,errors=remount-ro
+23 22 0:21 / /proc rw,nosuid,nodev,noexec,relatime shared:12 - proc proc rw
+24 22 0:45 / /home/user/remote rw,nosuid,nodev,relatime shared:300 - fuse.sshfs user@host:/srv rw,user_id=1000,group_id=1000
+25 22 0:46 / /mnt/my\\040disk rw,relatime - vfat /dev/sdb1 rw,fmask=0022
+26 22 0:47 /sub /data rw shared:5 - btrfs /dev/sdc rw,subvol=/sub
+27 26 0:48 / /data/nested rw shared:6 - btrfs /dev/sdc rw,subvol=/sub/nested
+";
+    let mounts = parse(mountinfo);
+
+    assert_eq!(mounts.len(), 6);
+    assert_eq!(mounts[2].file_system, "fuse.sshfs");
+    assert_eq!(mounts[2].source, "user@host:/srv");
+    assert!(mounts[2].options.contains(&"nosuid".to_string()));
+    assert!(mounts[2].options.contains(&"user_id=1000".to_string()));
+    assert_eq!(mounts[3].mount_point, Path::new("/mnt/my disk"));
+
+    let find = |path: &str, device| find_in(parse(mountinfo), Path::new(path), device).unwrap();
+
+    assert_eq!(find("/home/user/remote/file", (0, 45)).file_system, "fuse.sshfs");
+    assert_eq!(find("/home/user/file", (8, 1)).mount_point, Path::new("/"));
+    assert_eq!(find("/mnt/my disk/file", (0, 46)).file_system, "vfat");
+    assert_eq!(find("/data/nested/file", (0, 48)).mount_point, Path::new("/data/nested"));
+    // A subvolume that isn't mounted has its own device.
+    assert_eq!(find("/data/other/file", (0, 49)).mount_point, Path::new("/data"));
+}
+
+#[test]
+#[cfg(all(target_os = "linux", linker, not(feature = "rustix")))]
+fn retry_interrupted() {
+    extern "C" {
+        fn __errno_location() -> *mut i32;
+    }
+
+    const EINTR: i32 = 4;
+    const EIO: i32 = 5;
+
+    let fail = |errno| {
+        unsafe { *__errno_location() = errno };
+        -1
+    };
+
+    let mut calls = 0;
+    let ret = super::retry_interrupted(|| {
+        calls += 1;
+        if calls < 3 { fail(EINTR) } else { 0 }
+    });
+    assert_eq!((ret, calls), (0, 3));
+
+    let mut calls = 0;
+    let ret = super::retry_interrupted(|| {
+        calls += 1;
+        fail(EIO)
+    });
+    assert_eq!((ret, calls), (-1, 1));
+}
+
+#[test]
+fn retry_interrupted_io() {
+    let mut calls = 0;
+    let result = super::retry_interrupted_io(|| {
+        calls += 1;
+        match calls {
+            1 => Err(ErrorKind::Interrupted.into()),
+            _ => Ok(calls),
+        }
+    });
+    assert_eq!(result.unwrap(), 2);
+
+    let mut calls = 0;
+    let result = super::retry_interrupted_io(|| -> Result<()> {
+        calls += 1;
+        Err(ErrorKind::NotFound.into())
+    });
+    assert_eq!((result.unwrap_err().kind(), calls), (ErrorKind::NotFound, 1));
+}
+
+mod properties {
+    use proptest::prelude::*;
+    use super::super::Version;
+
+    proptest! {
+        #[test]
+        fn parse_any_version(version in ".*") {
+            let _ = version.parse::<Version>();
+        }
+
+        #[test]
+        fn parse_vendor_version(
+            major in any::<u16>(),
+            minor in any::<u16>(),
+            patch in proptest::option::of(any::<u16>()),
+            prefix in "(Linux version )?",
+            suffix in "([-+~_][a-zA-Z0-9._+~-]*)?",
+        ) {
+            let string = match patch {
+                Some(patch) => format!("{}{}.{}.{}{}", prefix, major, minor, patch, suffix),
+                None => format!("{}{}.{}{}", prefix, major, minor, suffix),
+            };
+            let version = Version::new(major, minor, patch.unwrap_or(0));
+
+            prop_assert_eq!(string.parse(), Ok(version));
+            prop_assert_eq!(version.to_string().parse(), Ok(version));
+        }
+    }
+
+    #[cfg(all(target_os = "linux", linker))]
+    proptest! {
+        // Half of the magic numbers are from the table so that the versions
+        // get checked.
+        #[test]
+        fn filesystem_support(
+            major in 0..8u16,
+            minor in 0..30u16,
+            magic in prop_oneof![
+                any::<u32>(),
+                proptest::sample::select(super::super::linux::KNOWN_FILESYSTEMS)
+                    .prop_map(|known| known.magic.unwrap()),
+            ],
+        ) {
+            use super::super::{AtomicSupport, FsInfo, Reason};
+            use super::super::linux::{filesystem_support, KNOWN_FILESYSTEMS};
+
+            let version = Version::new(major, minor, 0);
+            let known = KNOWN_FILESYSTEMS.iter().find(|known| known.magic == Some(magic));
+            let support = filesystem_support(version, magic, || Ok(FsInfo::default())).unwrap();
+
+            match known {
+                None => prop_assert!(matches!(support, AtomicSupport::No(Reason::Filesystem(_)))),
+                Some(known) if version < known.min_version => {
+                    let required = known.min_version;
+                    prop_assert_eq!(
+                        support,
+                        AtomicSupport::No(Reason::KernelTooOld { found: version, required }),
+                    );
+                }
+                Some(_) => prop_assert!(!matches!(support, AtomicSupport::No(_))),
+            }
+        }
+
+        #[test]
+        fn mountinfo_escapes(
+            mount_point in "(/[^\0/]{1,12}){1,4}",
+            file_system in "[a-z0-9.]{1,12}",
+        ) {
+            use std::path::Path;
+            use super::super::mountinfo::parse;
+
+            let mut escaped = String::new();
+
+            for c in mount_point.chars() {
+                match c {
+                    ' ' | '\t' | '\n' | '\\' => escaped.push_str(&format!("\\{:03o}", c as u32)),
+                    c => escaped.push(c),
+                }
+            }
+
+            let line = format!(
+                "36 35 98:0 / {} rw,noatime master:1 - {} /dev/root rw",
+                escaped, file_system,
+            );
+            let mounts = parse(&line);
+
+            prop_assert_eq!(mounts.len(), 1);
+            prop_assert_eq!(&mounts[0].mount_point, Path::new(&mount_point));
+            prop_assert_eq!(&mounts[0].file_system, &file_system);
+            prop_assert_eq!(mounts[0].device, (98, 0));
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    proptest! {
+        #[test]
+        fn to_wide_path(
+            path in proptest::collection::vec(any::<u16>(), 0..400),
+            exact in any::<bool>(),
+        ) {
+            use std::ffi::OsString;
+            use std::os::windows::ffi::OsStringExt;
+            use std::path::PathBuf;
+
+            let path = PathBuf::from(OsString::from_wide(&path));
+
+            if let Ok(wide) = super::super::windows::to_wide_path(&path, exact) {
+                prop_assert_eq!(wide.last(), Some(&0));
+            }
+        }
+
+        #[test]
+        fn to_wide_path_unc(
+            server in "[a-z]{1,15}",
+            share in "[a-z]{1,15}",
+            name in "[a-z]{200,300}",
+        ) {
+            use std::path::Path;
+
+            let path = format!(r"\\{}\{}\{}", server, share, name);
+            let wide = super::super::windows::to_wide_path(Path::new(&path), false).unwrap();
+            let expected = format!(r"\\?\UNC\{}\{}\{}", server, share, name);
+
+            prop_assert_eq!(String::from_utf16(&wide[..wide.len() - 1]).unwrap(), expected);
+        }
+    }
+}
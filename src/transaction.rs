@@ -0,0 +1,103 @@
+use std::path::Path;
+use std::io::Result;
+
+/// A group of renames that either all happen or none happen.
+///
+/// Renames performed within a transaction aren't visible outside of it until
+/// it's committed. If the transaction is dropped without being committed, then
+/// it's rolled back and none of the renames happen.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// let transaction = renamore::Transaction::new()?;
+/// transaction.rename_exclusive("a.txt", "backup/a.txt")?;
+/// transaction.rename_exclusive("b.txt", "backup/b.txt")?;
+/// transaction.commit()
+/// # }
+/// ```
+///
+/// # Platform-specific behaviour
+///
+/// On Windows, this uses Transactional NTFS (`CreateTransaction` and
+/// `MoveFileTransactedW`). Microsoft has deprecated Transactional NTFS and it
+/// may be unavailable in future versions of Windows. It's only supported on
+/// local NTFS volumes. On other file systems, renaming will fail with
+/// [`ErrorKind::Unsupported`]. On all other platforms (and UWP targets),
+/// [`Transaction::new`] returns [`ErrorKind::Unsupported`] unconditionally.
+///
+/// This is only available with the `txf` feature so that other programs don't
+/// link to `ktmw32`.
+///
+/// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+#[derive(Debug)]
+pub struct Transaction {
+    handle: txf::Transaction,
+}
+
+impl Transaction {
+    /// Begin a new transaction.
+    pub fn new() -> Result<Self> {
+        Ok(Self { handle: txf::Transaction::new()? })
+    }
+
+    /// Rename a file within the transaction without overwriting the
+    /// destination path if it exists.
+    ///
+    /// If a file at `to` exists, then [`ErrorKind::AlreadyExists`] will be
+    /// returned. The transaction can still be committed after an error but the
+    /// failed rename won't be a part of it.
+    ///
+    /// [`ErrorKind::AlreadyExists`]: std::io::ErrorKind::AlreadyExists
+    pub fn rename_exclusive<F: AsRef<Path>, T: AsRef<Path>>(&self, from: F, to: T) -> Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+
+        crate::check_path(from, "from")?;
+        crate::check_path(to, "to")?;
+        self.handle.rename_exclusive(from, to)
+    }
+
+    /// Make all of the renames in the transaction happen.
+    pub fn commit(self) -> Result<()> {
+        self.handle.commit()
+    }
+
+    /// Undo all of the renames in the transaction.
+    ///
+    /// This is the same as dropping the transaction except that errors are
+    /// reported.
+    pub fn rollback(self) -> Result<()> {
+        self.handle.rollback()
+    }
+}
+
+#[cfg(all(target_os = "windows", not(target_vendor = "uwp")))]
+use crate::txf;
+
+#[cfg(not(all(target_os = "windows", not(target_vendor = "uwp"))))]
+mod txf {
+    use std::path::Path;
+    use std::io::{Error, ErrorKind, Result};
+
+    #[derive(Debug)]
+    pub enum Transaction {}
+
+    impl Transaction {
+        pub fn new() -> Result<Self> {
+            Err(Error::from(ErrorKind::Unsupported))
+        }
+
+        pub fn rename_exclusive(&self, _from: &Path, _to: &Path) -> Result<()> {
+            match *self {}
+        }
+
+        pub fn commit(self) -> Result<()> {
+            match self {}
+        }
+
+        pub fn rollback(self) -> Result<()> {
+            match self {}
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::path::Path;
+use std::io::{Error, ErrorKind, Result};
+use std::ffi::{c_int, c_void};
+use crate::check_file_name;
+use crate::windows::to_wide_path;
+
+// Linking will fail on Windows versions prior to Vista.
+
+#[link(name = "ktmw32")]
+extern "system" {
+    fn CreateTransaction(
+        lpTransactionAttributes: *mut c_void,
+        UOW: *mut c_void,
+        CreateOptions: u32,
+        IsolationLevel: u32,
+        IsolationFlags: u32,
+        Timeout: u32,
+        Description: *mut u16,
+    ) -> *mut c_void;
+
+    fn CommitTransaction(TransactionHandle: *mut c_void) -> c_int;
+
+    fn RollbackTransaction(TransactionHandle: *mut c_void) -> c_int;
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn MoveFileTransactedW(
+        lpExistingFileName: *const u16,
+        lpNewFileName: *const u16,
+        lpProgressRoutine: *mut c_void,
+        lpData: *mut c_void,
+        dwFlags: u32,
+        hTransaction: *mut c_void,
+    ) -> c_int;
+
+    fn CloseHandle(hObject: *mut c_void) -> c_int;
+}
+
+const INVALID_HANDLE_VALUE: *mut c_void = -1isize as *mut c_void;
+
+const ERROR_NOT_SUPPORTED: i32 = 50;
+const ERROR_RM_NOT_ACTIVE: i32 = 6801;
+const ERROR_TRANSACTIONS_UNSUPPORTED_REMOTE: i32 = 6805;
+
+#[derive(Debug)]
+pub struct Transaction {
+    handle: *mut c_void,
+}
+
+// Transaction handles can be used from any thread.
+unsafe impl Send for Transaction {}
+unsafe impl Sync for Transaction {}
+
+impl Transaction {
+    pub fn new() -> Result<Self> {
+        let handle = unsafe {
+            CreateTransaction(
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                0,
+                0,
+                0,
+                0,
+                std::ptr::null_mut(),
+            )
+        };
+
+        if handle == INVALID_HANDLE_VALUE {
+            Err(Error::last_os_error())
+        } else {
+            Ok(Self { handle })
+        }
+    }
+
+    pub fn rename_exclusive(&self, from: &Path, to: &Path) -> Result<()> {
+        check_file_name(to)?;
+
+        let from_str = to_wide_path(from, false)?;
+        let to_str = to_wide_path(to, false)?;
+        // Without MOVEFILE_REPLACE_EXISTING, the destination isn't replaced.
+        // Unlike MoveFileExW, the check and the rename are isolated from
+        // everything outside of the transaction.
+        let ret = unsafe {
+            MoveFileTransactedW(
+                from_str.as_ptr(),
+                to_str.as_ptr(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                0,
+                self.handle,
+            )
+        };
+
+        if ret != 0 {
+            return Ok(());
+        }
+
+        let error = Error::last_os_error();
+
+        match error.raw_os_error() {
+            // The file system (or the redirector) doesn't support transactions.
+            Some(
+                ERROR_NOT_SUPPORTED
+                | ERROR_RM_NOT_ACTIVE
+                | ERROR_TRANSACTIONS_UNSUPPORTED_REMOTE
+            ) => Err(Error::new(ErrorKind::Unsupported, error)),
+            _ => Err(error),
+        }
+    }
+
+    pub fn commit(self) -> Result<()> {
+        if unsafe { CommitTransaction(self.handle) } == 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn rollback(self) -> Result<()> {
+        if unsafe { RollbackTransaction(self.handle) } == 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Closing the last handle to a transaction that hasn't been committed rolls it
+// back.
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.handle) };
+    }
+}
@@ -0,0 +1,391 @@
+use std::path::Path;
+use std::io::{Error, ErrorKind, Result};
+use std::ffi::CString;
+use std::os::unix::prelude::OsStrExt;
+#[cfg(feature = "async")]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+#[cfg(feature = "async")]
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex};
+#[cfg(feature = "async")]
+use std::task::Waker;
+use io_uring::{opcode, types, IoUring, Probe};
+use crate::parent;
+
+const AT_FDCWD: i32 = -100;
+const RENAME_NOREPLACE: u32 = 1;
+
+/// An io_uring instance for performing many exclusive renames at once.
+///
+/// Each call to [`Ring::rename_exclusive_all`] submits the renames as
+/// `IORING_OP_RENAMEAT` operations with `RENAME_NOREPLACE` and waits for them
+/// all to complete. This avoids a syscall per rename which makes a difference
+/// when renaming tens of thousands of files.
+///
+/// [`Ring::rename_exclusive_all`] blocks the calling thread until every rename
+/// in the batch has completed. From async code, use
+/// `asynchronous::rename_exclusive_ring` instead. Its future is woken by the
+/// kernel when renames complete so no blocking thread pool is needed.
+///
+/// This is only available on Linux with the `io-uring` feature. It requires
+/// Linux 5.11.
+///
+/// # Examples
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// let mut ring = renamore::Ring::new(64)?;
+/// let results = ring.rename_exclusive_all(&[("a.tmp", "a"), ("b.tmp", "b")]);
+///
+/// for result in results {
+///     result?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Ring {
+    ring: IoUring,
+    // Set if waiting for completions failed. Some operations might still be in
+    // flight and their completions would be mistaken for the results of the
+    // next batch.
+    poisoned: bool,
+    // Created the first time the ring is used from async code.
+    #[cfg(feature = "async")]
+    notifier: Option<Arc<Notifier>>,
+}
+
+impl std::fmt::Debug for Ring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ring")
+            .field("entries", &self.ring.params().sq_entries())
+            .field("poisoned", &self.poisoned)
+            .finish()
+    }
+}
+
+impl Ring {
+    /// Create a ring that can have up to `entries` renames in flight at once.
+    ///
+    /// # Errors
+    ///
+    /// If io_uring isn't supported by the kernel (or is disabled), or the
+    /// kernel doesn't have `IORING_OP_RENAMEAT`, then
+    /// [`ErrorKind::Unsupported`] will be returned.
+    ///
+    /// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+    pub fn new(entries: u32) -> Result<Self> {
+        let ring = match IoUring::new(entries) {
+            Ok(ring) => ring,
+            // ENOSYS if the kernel is too old and EPERM if it's been disabled
+            // with the io_uring_disabled sysctl or seccomp.
+            Err(e) if matches!(e.kind(), ErrorKind::Unsupported | ErrorKind::PermissionDenied) => {
+                return Err(Error::new(ErrorKind::Unsupported, e));
+            }
+            Err(e) => return Err(e),
+        };
+
+        // Otherwise every rename would fail with EINVAL, which can't be told
+        // apart from a file system that doesn't support RENAME_NOREPLACE.
+        let mut probe = Probe::new();
+        ring.submitter().register_probe(&mut probe)?;
+
+        if !probe.is_supported(opcode::RenameAt::CODE) {
+            return Err(Error::new(ErrorKind::Unsupported, "IORING_OP_RENAMEAT isn't supported"));
+        }
+
+        Ok(Self {
+            ring,
+            poisoned: false,
+            #[cfg(feature = "async")]
+            notifier: None,
+        })
+    }
+
+    /// Rename each pair of paths without overwriting the destination paths if
+    /// they exist.
+    ///
+    /// The results are in the same order as the renames. Renames are performed
+    /// concurrently so they shouldn't depend on each other. See
+    /// [`rename_exclusive`](crate::rename_exclusive) for the meaning of the
+    /// errors.
+    ///
+    /// If waiting for the renames fails, then each rename that was submitted
+    /// gets that error and the ring can't be used again. Any later call
+    /// returns an error for every rename.
+    pub fn rename_exclusive_all<F, T>(&mut self, renames: &[(F, T)]) -> Vec<Result<()>>
+    where
+        F: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        let mut batch = Batch::new(renames);
+
+        loop {
+            self.fill(&mut batch);
+
+            if batch.in_flight == 0 {
+                break;
+            }
+
+            // This waits for everything that's in flight before submitting
+            // more, which is one syscall per submission queue worth of renames.
+            match self.ring.submit_and_wait(batch.in_flight) {
+                Ok(_) => self.reap(&mut batch),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => self.fail(&mut batch, e),
+            }
+        }
+
+        std::mem::take(&mut batch.results)
+    }
+
+    // Push as many renames as there's room for onto the submission queue.
+    // Returns how many were pushed.
+    pub(crate) fn fill(&mut self, batch: &mut Batch) -> usize {
+        if self.poisoned {
+            while batch.next < batch.paths.len() {
+                if batch.paths[batch.next].is_some() {
+                    batch.results[batch.next] =
+                        Err(Error::other("the ring failed while waiting for an earlier batch"));
+                }
+                batch.next += 1;
+            }
+            return 0;
+        }
+
+        let capacity = self.ring.params().sq_entries() as usize;
+        let mut pushed = 0;
+
+        while batch.next < batch.paths.len() && batch.in_flight < capacity {
+            let i = batch.next;
+            batch.next += 1;
+
+            let (from, to) = match &batch.paths[i] {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let entry = opcode::RenameAt::new(
+                types::Fd(AT_FDCWD),
+                from.as_ptr(),
+                types::Fd(AT_FDCWD),
+                to.as_ptr(),
+            )
+                .flags(RENAME_NOREPLACE)
+                .build()
+                .user_data(i as u64);
+
+            // There are never more renames in flight than the submission queue
+            // can hold so this can't fail. The paths outlive the operations.
+            unsafe { self.ring.submission().push(&entry).unwrap() };
+            batch.pending[i] = true;
+            batch.in_flight += 1;
+            pushed += 1;
+        }
+
+        pushed
+    }
+
+    // Take the results of any renames that have completed.
+    pub(crate) fn reap(&mut self, batch: &mut Batch) {
+        if self.poisoned {
+            return;
+        }
+
+        for entry in self.ring.completion() {
+            let i = entry.user_data() as usize;
+            let ret = entry.result();
+            batch.results[i] = if ret >= 0 {
+                Ok(())
+            } else {
+                let (_, to) = batch.paths[i].as_ref().unwrap();
+                Err(to_error(-ret, Path::new(std::ffi::OsStr::from_bytes(to.as_bytes()))))
+            };
+            batch.pending[i] = false;
+            batch.in_flight -= 1;
+        }
+    }
+
+    // The renames that are in flight may still complete but there's no way to
+    // find out. The kernel might still read the paths so they're leaked rather
+    // than freed.
+    pub(crate) fn fail(&mut self, batch: &mut Batch, error: Error) {
+        for (i, pending) in batch.pending.iter_mut().enumerate() {
+            if std::mem::take(pending) {
+                batch.results[i] = Err(match error.raw_os_error() {
+                    Some(code) => Error::from_raw_os_error(code),
+                    None => Error::new(error.kind(), error.to_string()),
+                });
+            }
+        }
+
+        self.poisoned = true;
+        batch.in_flight = 0;
+        batch.leaked = true;
+    }
+
+}
+
+#[cfg(feature = "async")]
+impl Ring {
+    pub(crate) fn submit(&mut self) -> Result<()> {
+        loop {
+            match self.ring.submit() {
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                result => return result.map(|_| ()),
+            }
+        }
+    }
+
+    pub(crate) fn has_completions(&mut self) -> bool {
+        !self.poisoned && !self.ring.completion().is_empty()
+    }
+
+    // The kernel writes to an eventfd whenever a rename completes. A thread
+    // waits on it and wakes whichever task is waiting on the ring.
+    pub(crate) fn notifier(&mut self) -> Result<Arc<Notifier>> {
+        if let Some(notifier) = &self.notifier {
+            return Ok(notifier.clone());
+        }
+
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+
+        if fd == -1 {
+            return Err(Error::last_os_error());
+        }
+
+        let notifier = Arc::new(Notifier {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            waker: Mutex::new(None),
+            closed: AtomicBool::new(false),
+        });
+
+        self.ring.submitter().register_eventfd(fd)?;
+
+        let thread_notifier = notifier.clone();
+        std::thread::Builder::new()
+            .name("renamore-ring".into())
+            .spawn(move || thread_notifier.run())?;
+
+        self.notifier = Some(notifier.clone());
+        Ok(notifier)
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for Ring {
+    fn drop(&mut self) {
+        if let Some(notifier) = &self.notifier {
+            notifier.closed.store(true, Ordering::Release);
+            notifier.signal();
+        }
+    }
+}
+
+/// The renames given to a [`Ring`] along with their results so far.
+pub(crate) struct Batch {
+    paths: Vec<Option<(CString, CString)>>,
+    pub(crate) results: Vec<Result<()>>,
+    // Set for the renames that have been pushed but haven't completed.
+    pending: Vec<bool>,
+    next: usize,
+    in_flight: usize,
+    leaked: bool,
+}
+
+impl Batch {
+    pub(crate) fn new<F, T>(renames: &[(F, T)]) -> Self
+    where
+        F: AsRef<Path>,
+        T: AsRef<Path>,
+    {
+        let mut paths = Vec::with_capacity(renames.len());
+        let mut results = Vec::with_capacity(renames.len());
+
+        for (from, to) in renames {
+            let (from, to) = (from.as_ref(), to.as_ref());
+            let checked = crate::check_path(from, "from").and_then(|_| crate::check_path(to, "to"));
+
+            match checked {
+                Ok(()) => {
+                    results.push(Ok(()));
+                    // The paths were checked for NUL bytes.
+                    paths.push(Some((
+                        CString::new(from.as_os_str().as_bytes()).unwrap(),
+                        CString::new(to.as_os_str().as_bytes()).unwrap(),
+                    )));
+                }
+                Err(e) => {
+                    results.push(Err(e));
+                    paths.push(None);
+                }
+            }
+        }
+
+        let pending = vec![false; paths.len()];
+
+        Self { paths, results, pending, next: 0, in_flight: 0, leaked: false }
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn is_done(&self) -> bool {
+        self.in_flight == 0 && self.next >= self.paths.len()
+    }
+}
+
+impl Drop for Batch {
+    // If the future is dropped while renames are in flight, the kernel might
+    // still read the paths.
+    fn drop(&mut self) {
+        if self.in_flight != 0 || self.leaked {
+            std::mem::forget(std::mem::take(&mut self.paths));
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+pub(crate) struct Notifier {
+    fd: OwnedFd,
+    pub(crate) waker: Mutex<Option<Waker>>,
+    closed: AtomicBool,
+}
+
+#[cfg(feature = "async")]
+impl Notifier {
+    fn run(&self) {
+        loop {
+            let mut count = 0u64;
+            let ret = unsafe {
+                libc::read(self.fd.as_raw_fd(), (&mut count as *mut u64).cast(), 8)
+            };
+
+            if ret == -1 && Error::last_os_error().kind() != ErrorKind::Interrupted {
+                return;
+            }
+            if self.closed.load(Ordering::Acquire) {
+                return;
+            }
+            if let Some(waker) = self.waker.lock().unwrap().take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn signal(&self) {
+        let count = 1u64;
+        unsafe { libc::write(self.fd.as_raw_fd(), (&count as *const u64).cast(), 8) };
+    }
+}
+
+// EINVAL is returned if the file system doesn't support RENAME_NOREPLACE, but
+// also for mistakes like moving a directory into itself. It's only made into
+// Unsupported if the file system isn't known to support the flag. The original
+// error is kept as the source.
+fn to_error(errno: i32, to: &Path) -> Error {
+    let error = Error::from_raw_os_error(errno);
+
+    if error.kind() == ErrorKind::InvalidInput
+        && !matches!(crate::rename_exclusive_is_atomic(parent(to)), Ok(true))
+    {
+        Error::new(ErrorKind::Unsupported, error)
+    } else {
+        error
+    }
+}
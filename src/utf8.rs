@@ -0,0 +1,77 @@
+//! Versions of the `_detailed` functions that take and return [camino] paths.
+//!
+//! Every function in this crate accepts `Utf8Path` because it implements
+//! `AsRef<Path>`. The functions here also give the paths back as
+//! `Utf8PathBuf` in the [`RenameError`] so that projects using camino don't
+//! need to convert them again.
+//!
+//! This module is only available with the `camino` feature.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use camino::Utf8Path;
+//!
+//! let from = Utf8Path::new("build/app.tmp");
+//!
+//! if let Err(e) = renamore::utf8::rename_exclusive_detailed(from, "dist/app") {
+//!     let to: &Utf8Path = e.to();
+//!     eprintln!("{} wasn't published: {}", to, e.io_error());
+//! }
+//! ```
+//!
+//! [camino]: https://crates.io/crates/camino
+
+use std::io::Error;
+use camino::{Utf8Path, Utf8PathBuf};
+use crate::RenameOperation;
+
+/// A [`RenameError`](crate::RenameError) with UTF-8 paths.
+pub type RenameError = crate::RenameError<Utf8PathBuf>;
+
+/// Rename a file without overwriting the destination path if it exists,
+/// returning a [`RenameError`] with both paths if it fails.
+///
+/// See [`rename_exclusive`](crate::rename_exclusive).
+pub fn rename_exclusive_detailed<F, T>(from: F, to: T) -> Result<(), RenameError>
+where
+    F: AsRef<Utf8Path>,
+    T: AsRef<Utf8Path>,
+{
+    let (from, to) = (from.as_ref(), to.as_ref());
+
+    crate::rename_exclusive(from, to).map_err(|e| error(RenameOperation::Exclusive, from, to, e))
+}
+
+/// Rename a file without overwriting the destination path if it exists, using
+/// a non-atomic fallback if necessary, and returning a [`RenameError`] with
+/// both paths if it fails.
+///
+/// See [`rename_exclusive_fallback`](crate::rename_exclusive_fallback).
+pub fn rename_exclusive_fallback_detailed<F, T>(from: F, to: T) -> Result<bool, RenameError>
+where
+    F: AsRef<Utf8Path>,
+    T: AsRef<Utf8Path>,
+{
+    let (from, to) = (from.as_ref(), to.as_ref());
+
+    crate::rename_exclusive_fallback(from, to)
+        .map_err(|e| error(RenameOperation::ExclusiveFallback, from, to, e))
+}
+
+/// Swap two files, returning a [`RenameError`] with both paths if it fails.
+///
+/// See [`rename_exchange`](crate::rename_exchange).
+pub fn rename_exchange_detailed<A, B>(a: A, b: B) -> Result<(), RenameError>
+where
+    A: AsRef<Utf8Path>,
+    B: AsRef<Utf8Path>,
+{
+    let (a, b) = (a.as_ref(), b.as_ref());
+
+    crate::rename_exchange(a, b).map_err(|e| error(RenameOperation::Exchange, a, b, e))
+}
+
+fn error(operation: RenameOperation, from: &Utf8Path, to: &Utf8Path, e: Error) -> RenameError {
+    RenameError::new(operation, from.to_path_buf(), to.to_path_buf(), e)
+}
@@ -1,18 +1,92 @@
+#![allow(non_camel_case_types, clippy::upper_case_acronyms)]
+// The handle based rename isn't available in an app container.
+#![cfg_attr(target_vendor = "uwp", allow(dead_code))]
+
 use std::path::Path;
-use std::io::Result;
-use std::ffi::{c_int, c_ulong, OsStr};
-use std::os::windows::prelude::OsStrExt;
+#[cfg(not(target_vendor = "uwp"))]
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::ffi::{c_int, c_void, OsStr, OsString};
+use std::os::windows::prelude::{OsStrExt, OsStringExt};
+use crate::{check_file_name, AtomicSupport, RenameOptions};
+#[cfg(not(target_vendor = "uwp"))]
+use crate::{FsInfo, KnownFilesystem, Reason, Support, Version};
 
-// Linking will fail on Windows versions prior to XP.
+// These are declared directly rather than checked by the build script so that
+// cross-compiling to Windows doesn't need a C toolchain. Linking will fail on
+// Windows versions prior to XP.
 
-extern "C" {
+#[cfg(not(target_vendor = "uwp"))]
+#[link(name = "kernel32")]
+extern "system" {
     fn MoveFileExW(
         lpExistingFileName: *const u16,
         lpNewFileName: *const u16,
-        dwFlags: c_ulong,
+        dwFlags: u32,
+    ) -> c_int;
+}
+
+// MoveFileExW is not available to apps running in an app container. This is
+// the equivalent of MoveFileW (which doesn't replace the destination either)
+// and has been available since Windows 10.
+
+#[cfg(target_vendor = "uwp")]
+#[link(name = "windowsapp")]
+extern "system" {
+    fn MoveFileFromAppW(
+        lpExistingFileName: *const u16,
+        lpNewFileName: *const u16,
+    ) -> c_int;
+}
+
+// Linking will fail on Windows versions prior to Vista. FileRenameInfoEx is
+// only understood by Windows 10 1809 and later.
+
+#[cfg(not(target_vendor = "uwp"))]
+#[link(name = "kernel32")]
+extern "system" {
+    fn SetFileInformationByHandle(
+        hFile: *mut c_void,
+        FileInformationClass: c_int,
+        lpFileInformation: *const c_void,
+        dwBufferSize: u32,
     ) -> c_int;
 }
 
+#[repr(C)]
+struct FILE_RENAME_INFO {
+    // This is a union with a BOOLEAN ReplaceIfExists for FileRenameInfo.
+    flags: u32,
+    root_directory: *mut c_void,
+    file_name_length: u32,
+    file_name: [u16; 1],
+}
+
+const FILE_RENAME_INFO_CLASS: c_int = 3;
+const FILE_RENAME_INFO_EX: c_int = 22;
+// const FILE_RENAME_FLAG_REPLACE_IF_EXISTS: u32 = 1;
+const FILE_RENAME_FLAG_POSIX_SEMANTICS: u32 = 2;
+const FILE_RENAME_FLAG_SUPPRESS_PIN_STATE_INHERITANCE: u32 = 8;
+const FILE_RENAME_FLAG_IGNORE_READONLY_ATTRIBUTE: u32 = 0x40;
+
+const MOVEFILE_REPLACE_EXISTING: u32 = 1;
+const MOVEFILE_DELAY_UNTIL_REBOOT: u32 = 4;
+const MOVEFILE_WRITE_THROUGH: u32 = 8;
+
+const DELETE: u32 = 0x00010000;
+const FILE_FLAG_OPEN_REPARSE_POINT: u32 = 0x00200000;
+const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x02000000;
+
+const ERROR_INVALID_FUNCTION: i32 = 1;
+const ERROR_ACCESS_DENIED: i32 = 5;
+const ERROR_SHARING_VIOLATION: i32 = 32;
+const ERROR_LOCK_VIOLATION: i32 = 33;
+const ERROR_DELETE_PENDING: i32 = 303;
+const ERROR_NOT_SUPPORTED: i32 = 50;
+const ERROR_FILE_EXISTS: i32 = 80;
+const ERROR_INVALID_PARAMETER: i32 = 87;
+const ERROR_ALREADY_EXISTS: i32 = 183;
+
 fn to_wide(s: &OsStr) -> Vec<u16> {
     let mut wide = Vec::with_capacity(s.len() + 1);
     wide.extend(s.encode_wide());
@@ -20,11 +94,131 @@ fn to_wide(s: &OsStr) -> Vec<u16> {
     wide
 }
 
-pub fn rename_exclusive(from: &Path, to: &Path) -> Result<()> {
-    let from_str = to_wide(from.as_os_str());
-    let to_str = to_wide(to.as_os_str());
+// Paths longer than this fail unless they have the verbatim prefix. It's less
+// than MAX_PATH because directories need room for an 8.3 file name.
+const LEGACY_MAX_PATH: usize = 248;
+
+const VERBATIM_PREFIX: &str = r"\\?\";
+const UNC_PREFIX: &str = r"\\";
+const DEVICE_PREFIX: &str = r"\\.\";
+
+fn starts_with(wide: &[u16], prefix: &str) -> bool {
+    wide.len() >= prefix.len() && prefix.encode_utf16().zip(wide).all(|(a, b)| a == *b)
+}
+
+// Converts a path to a null terminated wide string. Long paths are made
+// absolute and given the verbatim prefix so that they aren't rejected. This
+// skips Win32 path normalization (such as stripping trailing dots) but that has
+// already been done by the conversion to an absolute path. If `exact` is set,
+// then the verbatim prefix is always added and the file name is left alone.
+pub fn to_wide_path(path: &Path, exact: bool) -> Result<Vec<u16>> {
+    let wide = to_wide(path.as_os_str());
+
+    if starts_with(&wide, VERBATIM_PREFIX) || starts_with(&wide, DEVICE_PREFIX) {
+        return Ok(wide);
+    }
+
+    let absolute = if !exact {
+        if wide.len() - 1 < LEGACY_MAX_PATH {
+            return Ok(wide);
+        }
+        std::path::absolute(path)?
+    } else if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+        // Only the parent is normalized so that the file name is preserved.
+        if parent.as_os_str().is_empty() {
+            std::env::current_dir()?.join(name)
+        } else {
+            std::path::absolute(parent)?.join(name)
+        }
+    } else {
+        std::path::absolute(path)?
+    };
+
+    let wide = to_wide(absolute.as_os_str());
+    let mut verbatim = Vec::with_capacity(wide.len() + 8);
+
+    if starts_with(&wide, VERBATIM_PREFIX) {
+        return Ok(wide);
+    } else if starts_with(&wide, UNC_PREFIX) {
+        // \\server\share becomes \\?\UNC\server\share.
+        verbatim.extend(r"\\?\UNC".encode_utf16());
+        verbatim.extend_from_slice(&wide[1..]);
+    } else {
+        verbatim.extend(VERBATIM_PREFIX.encode_utf16());
+        verbatim.extend_from_slice(&wide);
+    }
+
+    Ok(verbatim)
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+fn rename_by_handle(from: &Path, to: &Path, options: &RenameOptions) -> Result<()> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    // Opening the path itself (rather than whatever a symlink there points
+    // to) for DELETE access is all that's required to rename it.
+    let mut open_options = std::fs::OpenOptions::new();
+    open_options
+        .access_mode(DELETE)
+        .custom_flags(FILE_FLAG_OPEN_REPARSE_POINT | FILE_FLAG_BACKUP_SEMANTICS);
+
+    let file = if options.exact_names {
+        let from_str = to_wide_path(from, true)?;
+        open_options.open(OsString::from_wide(&from_str[..from_str.len() - 1]))?
+    } else {
+        open_options.open(from)?
+    };
+
+    let name = to_wide_path(to, options.exact_names)?;
+    let mut flags = FILE_RENAME_FLAG_POSIX_SEMANTICS;
+
+    if options.ignore_readonly {
+        flags |= FILE_RENAME_FLAG_IGNORE_READONLY_ATTRIBUTE;
+    }
+    if options.placeholders == crate::PlaceholderPolicy::SuppressPinState {
+        flags |= FILE_RENAME_FLAG_SUPPRESS_PIN_STATE_INHERITANCE;
+    }
+
+    // Leaving out FILE_RENAME_FLAG_REPLACE_IF_EXISTS is what makes this
+    // exclusive. The file system checks for the destination and performs the
+    // rename while holding the necessary locks.
+    crate::instrument::syscall("SetFileInformationByHandle", flags);
+    set_rename_info(&file, FILE_RENAME_INFO_EX, flags, &name)
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+fn set_rename_info(file: &std::fs::File, class: c_int, flags: u32, name: &[u16]) -> Result<()> {
+    use std::os::windows::io::AsRawHandle;
+
+    let name_offset = std::mem::offset_of!(FILE_RENAME_INFO, file_name);
+    let name_size = std::mem::size_of_val(name);
+    let size = std::mem::size_of::<FILE_RENAME_INFO>().max(name_offset + name_size);
+
+    // FILE_RENAME_INFO is variable length so it's allocated as a buffer of
+    // suitably aligned words.
+    let words = size.div_ceil(std::mem::size_of::<usize>());
+    let mut buf = vec![0usize; words];
+    let info = buf.as_mut_ptr() as *mut FILE_RENAME_INFO;
+
+    unsafe {
+        std::ptr::addr_of_mut!((*info).flags).write(flags);
+        std::ptr::addr_of_mut!((*info).root_directory).write(std::ptr::null_mut());
+        // The length doesn't include the null terminator.
+        std::ptr::addr_of_mut!((*info).file_name_length).write((name_size - 2) as u32);
+        std::ptr::copy_nonoverlapping(
+            name.as_ptr(),
+            std::ptr::addr_of_mut!((*info).file_name) as *mut u16,
+            name.len(),
+        );
+    }
+
     let ret = unsafe {
-        MoveFileExW(from_str.as_ptr(), to_str.as_ptr(), 0)
+        SetFileInformationByHandle(
+            file.as_raw_handle(),
+            class,
+            info as *const c_void,
+            (words * std::mem::size_of::<usize>()) as u32,
+        )
     };
 
     if ret == 0 {
@@ -34,8 +228,948 @@ pub fn rename_exclusive(from: &Path, to: &Path) -> Result<()> {
     }
 }
 
-pub fn rename_exclusive_is_atomic(_path: &Path) -> Result<bool> {
+#[cfg(not(target_vendor = "uwp"))]
+fn rename_by_path(from: &Path, to: &Path, options: &RenameOptions) -> Result<()> {
+    let from_str = to_wide_path(from, options.exact_names)?;
+    let to_str = to_wide_path(to, options.exact_names)?;
+    let flags = if options.durable { MOVEFILE_WRITE_THROUGH } else { 0 };
+    crate::instrument::syscall("MoveFileExW", flags);
+    let ret = unsafe { MoveFileExW(from_str.as_ptr(), to_str.as_ptr(), flags) };
+
+    if ret == 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(target_vendor = "uwp")]
+fn rename_by_path(from: &Path, to: &Path, options: &RenameOptions) -> Result<()> {
+    let from_str = to_wide_path(from, options.exact_names)?;
+    let to_str = to_wide_path(to, options.exact_names)?;
+    crate::instrument::syscall("MoveFileFromAppW", 0);
+    let ret = unsafe { MoveFileFromAppW(from_str.as_ptr(), to_str.as_ptr()) };
+
+    if ret == 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// There's no write-through flag for renaming by handle. The equivalent is to
+// flush the directory that now contains the file. This is the same as what's
+// done on Unix.
+#[cfg(not(target_vendor = "uwp"))]
+fn sync_parent(path: &Path) -> Result<()> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(crate::parent(path))?
+        .sync_all()
+}
+
+// MoveFileExW doesn't have an equivalent to the flag for ignoring the read-only
+// attribute so it's cleared temporarily. On Windows, set_readonly(false) only
+// clears the attribute rather than making anything world writable.
+#[allow(clippy::permissions_set_readonly_false)]
+fn rename_by_path_ignore_readonly(from: &Path, to: &Path, options: &RenameOptions) -> Result<()> {
+    let error = match rename_by_path(from, to, options) {
+        Err(e) if e.raw_os_error() == Some(ERROR_ACCESS_DENIED) => e,
+        result => return result,
+    };
+
+    let mut permissions = std::fs::symlink_metadata(from)?.permissions();
+
+    if !permissions.readonly() {
+        return Err(error);
+    }
+
+    permissions.set_readonly(false);
+    std::fs::set_permissions(from, permissions.clone())?;
+    permissions.set_readonly(true);
+
+    match rename_by_path(from, to, options) {
+        Ok(()) => std::fs::set_permissions(to, permissions),
+        Err(e) => {
+            let _ = std::fs::set_permissions(from, permissions);
+            Err(e)
+        }
+    }
+}
+
+fn rename_by_path_with_options(from: &Path, to: &Path, options: &RenameOptions) -> Result<()> {
+    if options.ignore_readonly {
+        rename_by_path_ignore_readonly(from, to, options)
+    } else {
+        rename_by_path(from, to, options)
+    }
+}
+
+// The paths are stored in the registry and the operation is performed by the
+// session manager early in the boot process. Relative paths would be resolved
+// against whatever the current directory happens to be at that point. The
+// point is to replace files that are in use, so an existing file at the
+// destination is replaced.
+#[cfg(not(target_vendor = "uwp"))]
+pub fn rename_at_reboot(from: &Path, to: Option<&Path>) -> Result<()> {
+    let from_str = to_wide_path(&std::path::absolute(from)?, false)?;
+    let (to_str, flags) = match to {
+        Some(to) => (
+            Some(to_wide_path(&std::path::absolute(to)?, false)?),
+            MOVEFILE_DELAY_UNTIL_REBOOT | MOVEFILE_REPLACE_EXISTING,
+        ),
+        None => (None, MOVEFILE_DELAY_UNTIL_REBOOT),
+    };
+    let to_ptr = to_str.as_ref().map_or(std::ptr::null(), |s| s.as_ptr());
+    let ret = unsafe { MoveFileExW(from_str.as_ptr(), to_ptr, flags) };
+
+    if ret == 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+type LPPROGRESS_ROUTINE = extern "system" fn(
+    i64, i64, i64, i64, u32, u32, *mut c_void, *mut c_void, *mut c_void,
+) -> u32;
+
+#[cfg(not(target_vendor = "uwp"))]
+#[link(name = "kernel32")]
+extern "system" {
+    fn CopyFileExW(
+        lpExistingFileName: *const u16,
+        lpNewFileName: *const u16,
+        lpProgressRoutine: Option<LPPROGRESS_ROUTINE>,
+        lpData: *mut c_void,
+        pbCancel: *mut c_int,
+        dwCopyFlags: u32,
+    ) -> c_int;
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+const PROGRESS_CONTINUE: u32 = 0;
+#[cfg(not(target_vendor = "uwp"))]
+const PROGRESS_CANCEL: u32 = 1;
+
+#[cfg(not(target_vendor = "uwp"))]
+extern "system" fn copy_progress(
+    _total_file_size: i64,
+    total_bytes_transferred: i64,
+    _stream_size: i64,
+    _stream_bytes_transferred: i64,
+    _stream_number: u32,
+    _callback_reason: u32,
+    _source_file: *mut c_void,
+    _destination_file: *mut c_void,
+    data: *mut c_void,
+) -> u32 {
+    let progress = unsafe { &*(data as *const crate::copy::Progress) };
+
+    if progress.is_cancelled() {
+        return PROGRESS_CANCEL;
+    }
+
+    progress.report(total_bytes_transferred as u64);
+    PROGRESS_CONTINUE
+}
+
+// CopyFileExW copies attributes, alternate data streams and other metadata that
+// std::fs::copy would otherwise have to deal with. It also reports progress
+// within a file.
+#[cfg(not(target_vendor = "uwp"))]
+pub fn copy_file(from: &Path, to: &Path, progress: &crate::copy::Progress) -> Result<u64> {
+    let from_str = to_wide_path(from, false)?;
+    let to_str = to_wide_path(to, false)?;
+    let ret = unsafe {
+        CopyFileExW(
+            from_str.as_ptr(),
+            to_str.as_ptr(),
+            Some(copy_progress),
+            progress as *const crate::copy::Progress as *mut c_void,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+
+    if ret == 0 {
+        if progress.is_cancelled() {
+            return Err(crate::copy::cancelled());
+        }
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(std::fs::symlink_metadata(to)?.len())
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+#[link(name = "kernel32")]
+extern "system" {
+    fn ReplaceFileW(
+        lpReplacedFileName: *const u16,
+        lpReplacementFileName: *const u16,
+        lpBackupFileName: *const u16,
+        dwReplaceFlags: u32,
+        lpExclude: *mut c_void,
+        lpReserved: *mut c_void,
+    ) -> c_int;
+}
+
+// ReplaceFileW moves the live file to the backup name (replacing an earlier
+// backup) and keeps its attributes and ACL on the replacement.
+#[cfg(not(target_vendor = "uwp"))]
+pub fn replace_with_backup(staged: &Path, live: &Path, backup: &Path) -> Result<()> {
+    let staged_str = to_wide_path(staged, false)?;
+    let live_str = to_wide_path(live, false)?;
+    let backup_str = to_wide_path(backup, false)?;
+    let ret = unsafe {
+        ReplaceFileW(
+            live_str.as_ptr(),
+            staged_str.as_ptr(),
+            backup_str.as_ptr(),
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+
+    if ret == 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+// Streams are renamed by opening the stream itself and giving it a new name that
+// starts with a colon. FileRenameInfoEx doesn't support streams so the original
+// FileRenameInfo is used. With ReplaceIfExists set to false, this fails if the
+// file already has a stream with the new name. The names have been checked and
+// have no colons.
+#[cfg(not(target_vendor = "uwp"))]
+pub fn rename_stream_exclusive(path: &Path, from: &str, to: &str) -> Result<()> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    let to = OsString::from(format!(":{to}"));
+
+    let mut stream_path = path.as_os_str().to_owned();
+    stream_path.push(format!(":{from}"));
+
+    let file = std::fs::OpenOptions::new()
+        .access_mode(DELETE)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(stream_path)?;
+
+    set_rename_info(&file, FILE_RENAME_INFO_CLASS, 0, &to_wide(&to))
+}
+
+// Another process has the file open in a way that prevents renaming it. This is
+// usually antivirus software or an indexer that will close it very soon.
+pub fn is_transient(error: &Error) -> bool {
+    matches!(
+        error.raw_os_error(),
+        Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION | ERROR_DELETE_PENDING),
+    )
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetFileInformationByHandleEx(
+        hFile: *mut c_void,
+        FileInformationClass: c_int,
+        lpFileInformation: *mut c_void,
+        dwBufferSize: u32,
+    ) -> c_int;
+}
+
+#[repr(C)]
+struct FILE_ATTRIBUTE_TAG_INFO {
+    file_attributes: u32,
+    reparse_tag: u32,
+}
+
+const FILE_ATTRIBUTE_TAG_INFO_CLASS: c_int = 9;
+const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+// The tag of a cloud placeholder has a provider specific value in these bits.
+const IO_REPARSE_TAG_CLOUD: u32 = 0x9000001A;
+const IO_REPARSE_TAG_CLOUD_MASK: u32 = 0x0000F000;
+
+// Opening the file without following the reparse point doesn't cause it to be
+// hydrated.
+#[cfg(not(target_vendor = "uwp"))]
+pub fn is_placeholder(path: &Path, options: &RenameOptions) -> Result<bool> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+
+    let mut open_options = std::fs::OpenOptions::new();
+    open_options
+        .access_mode(0)
+        .custom_flags(FILE_FLAG_OPEN_REPARSE_POINT | FILE_FLAG_BACKUP_SEMANTICS);
+
+    let file = if options.exact_names {
+        let path_str = to_wide_path(path, true)?;
+        open_options.open(OsString::from_wide(&path_str[..path_str.len() - 1]))?
+    } else {
+        open_options.open(path)?
+    };
+
+    let mut info = FILE_ATTRIBUTE_TAG_INFO { file_attributes: 0, reparse_tag: 0 };
+    let ret = unsafe {
+        GetFileInformationByHandleEx(
+            file.as_raw_handle(),
+            FILE_ATTRIBUTE_TAG_INFO_CLASS,
+            std::ptr::addr_of_mut!(info) as *mut c_void,
+            std::mem::size_of::<FILE_ATTRIBUTE_TAG_INFO>() as u32,
+        )
+    };
+
+    if ret == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(info.file_attributes & FILE_ATTRIBUTE_REPARSE_POINT != 0
+        && info.reparse_tag & !IO_REPARSE_TAG_CLOUD_MASK == IO_REPARSE_TAG_CLOUD)
+}
+
+// The same conflict is reported in different ways depending on which function
+// was used and what's at the destination. MoveFileExW fails with access denied
+// if the destination is a directory. Access denied for a file at the
+// destination could mean that it's locked or read-only so it's left alone. The
+// original error is kept as the source.
+pub fn normalize_error(error: Error, to: &Path) -> Error {
+    match error.raw_os_error() {
+        Some(ERROR_FILE_EXISTS | ERROR_ALREADY_EXISTS) => {
+            Error::new(ErrorKind::AlreadyExists, error)
+        }
+        Some(ERROR_ACCESS_DENIED) if std::fs::symlink_metadata(to).is_ok_and(|m| m.is_dir()) => {
+            Error::new(ErrorKind::AlreadyExists, error)
+        }
+        _ => error,
+    }
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+pub fn rename_exclusive(from: &Path, to: &Path, options: &RenameOptions) -> Result<()> {
+    if !options.exact_names {
+        check_file_name(to)?;
+    }
+
+    let mut strategy = "SetFileInformationByHandle";
+    let result = rename_exclusive_unnormalized(from, to, options, &mut strategy)
+        .map_err(|e| read_only_error(normalize_error(e, to), from, to));
+
+    #[cfg(feature = "etw")]
+    crate::etw::trace_rename(from, to, options, strategy, &result);
+
+    result?;
+    apply_acl_inheritance(to, options)
+}
+
+// `strategy` is set to the name of the function that performed the rename.
+#[cfg(not(target_vendor = "uwp"))]
+fn rename_exclusive_unnormalized(
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+    strategy: &mut &'static str,
+) -> Result<()> {
+    match rename_by_handle(from, to, options) {
+        Ok(()) if options.durable => {
+            sync_parent(to).map_err(|e| Error::other(crate::DurabilityError::new(e)))?
+        }
+        Ok(()) => {}
+        Err(e) => match e.raw_os_error() {
+            // Windows versions prior to 10 1809 don't know about
+            // FileRenameInfoEx and some file systems (like FAT) don't support
+            // it. MoveFileExW without MOVEFILE_REPLACE_EXISTING still doesn't
+            // replace the destination but it isn't documented to be atomic.
+            Some(ERROR_INVALID_FUNCTION | ERROR_NOT_SUPPORTED) => {
+                fallback(from, to, options, strategy, &e)?
+            }
+            Some(ERROR_INVALID_PARAMETER) if rename_info_ex_rejected(from) => {
+                fallback(from, to, options, strategy, &e)?
+            }
+            _ => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+// ERROR_INVALID_PARAMETER is also what a bad name gives. It only means that
+// FileRenameInfoEx was rejected if the OS or the file system doesn't support
+// it. Otherwise, falling back would hide the real problem.
+#[cfg(not(target_vendor = "uwp"))]
+fn rename_info_ex_rejected(from: &Path) -> bool {
+    if get_version() < VERSION_FILE_RENAME_INFO_EX {
+        return true;
+    }
+
+    match get_volume_path(from).and_then(|volume| get_volume_information(&volume)) {
+        Ok((fs, _)) => !KNOWN_FILESYSTEMS.iter().any(|known| known.name == fs),
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+fn fallback(
+    from: &Path,
+    to: &Path,
+    options: &RenameOptions,
+    strategy: &mut &'static str,
+    error: &Error,
+) -> Result<()> {
+    *strategy = "MoveFileExW";
+    let event = crate::FallbackEvent::new(crate::Fallback::MoveFile, from, to, error);
+    crate::instrument::fallback(&event);
+    rename_by_path_with_options(from, to, options)?;
+    crate::stats::native_fallback();
+    Ok(())
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+#[link(name = "advapi32")]
+extern "system" {
+    fn GetNamedSecurityInfoW(
+        pObjectName: *const u16,
+        ObjectType: c_int,
+        SecurityInfo: u32,
+        ppsidOwner: *mut *mut c_void,
+        ppsidGroup: *mut *mut c_void,
+        ppDacl: *mut *mut c_void,
+        ppSacl: *mut *mut c_void,
+        ppSecurityDescriptor: *mut *mut c_void,
+    ) -> u32;
+
+    fn SetNamedSecurityInfoW(
+        pObjectName: *const u16,
+        ObjectType: c_int,
+        SecurityInfo: u32,
+        psidOwner: *mut c_void,
+        psidGroup: *mut c_void,
+        pDacl: *mut c_void,
+        pSacl: *mut c_void,
+    ) -> u32;
+
+    fn InitializeAcl(pAcl: *mut c_void, nAclLength: u32, dwAclRevision: u32) -> c_int;
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+#[link(name = "kernel32")]
+extern "system" {
+    fn LocalFree(hMem: *mut c_void) -> *mut c_void;
+}
+
+const SE_FILE_OBJECT: c_int = 1;
+const DACL_SECURITY_INFORMATION: u32 = 4;
+const UNPROTECTED_DACL_SECURITY_INFORMATION: u32 = 0x20000000;
+const ACL_REVISION: u32 = 2;
+// The size of the ACL header.
+const EMPTY_ACL_SIZE: usize = 8;
+
+// A rename keeps the DACL of the file, including the entries that were
+// inherited from the old parent. Setting the DACL with the unprotected flag
+// causes Windows to discard the inherited entries and inherit from the new
+// parent instead.
+#[cfg(not(target_vendor = "uwp"))]
+fn apply_acl_inheritance(path: &Path, options: &RenameOptions) -> Result<()> {
+    use crate::AclInheritance;
+
+    if options.acl_inheritance == AclInheritance::Keep {
+        return Ok(());
+    }
+
+    let path_str = to_wide_path(path, options.exact_names)?;
+    let info = DACL_SECURITY_INFORMATION | UNPROTECTED_DACL_SECURITY_INFORMATION;
+
+    let ret = if options.acl_inheritance == AclInheritance::Inherit {
+        let mut dacl = std::ptr::null_mut();
+        let mut descriptor = std::ptr::null_mut();
+        let ret = unsafe {
+            GetNamedSecurityInfoW(
+                path_str.as_ptr(),
+                SE_FILE_OBJECT,
+                DACL_SECURITY_INFORMATION,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                &mut dacl,
+                std::ptr::null_mut(),
+                &mut descriptor,
+            )
+        };
+
+        if ret != 0 {
+            return Err(Error::from_raw_os_error(ret as i32));
+        }
+
+        // The DACL points into the security descriptor.
+        let ret = unsafe {
+            SetNamedSecurityInfoW(
+                path_str.as_ptr(),
+                SE_FILE_OBJECT,
+                info,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                dacl,
+                std::ptr::null_mut(),
+            )
+        };
+        unsafe { LocalFree(descriptor) };
+        ret
+    } else {
+        // An empty DACL that isn't protected ends up with only the inherited
+        // entries.
+        let mut acl = [0u32; EMPTY_ACL_SIZE / 4];
+        let acl_ptr = acl.as_mut_ptr() as *mut c_void;
+
+        if unsafe { InitializeAcl(acl_ptr, EMPTY_ACL_SIZE as u32, ACL_REVISION) } == 0 {
+            return Err(Error::last_os_error());
+        }
+
+        unsafe {
+            SetNamedSecurityInfoW(
+                path_str.as_ptr(),
+                SE_FILE_OBJECT,
+                info,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                acl_ptr,
+                std::ptr::null_mut(),
+            )
+        }
+    };
+
+    if ret != 0 {
+        Err(Error::from_raw_os_error(ret as i32))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(target_vendor = "uwp")]
+pub fn rename_exclusive(from: &Path, to: &Path, options: &RenameOptions) -> Result<()> {
+    if !options.exact_names {
+        check_file_name(to)?;
+    }
+
+    rename_by_path_with_options(from, to, options).map_err(|e| normalize_error(e, to))
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetVolumePathNameW(
+        lpszFileName: *const u16,
+        lpszVolumePathName: *mut u16,
+        cchBufferLength: u32,
+    ) -> c_int;
+
+    fn GetVolumeInformationW(
+        lpRootPathName: *const u16,
+        lpVolumeNameBuffer: *mut u16,
+        nVolumeNameSize: u32,
+        lpVolumeSerialNumber: *mut u32,
+        lpMaximumComponentLength: *mut u32,
+        lpFileSystemFlags: *mut u32,
+        lpFileSystemNameBuffer: *mut u16,
+        nFileSystemNameSize: u32,
+    ) -> c_int;
+
+    fn GetDriveTypeW(lpRootPathName: *const u16) -> u32;
+
+    fn GetVolumeInformationByHandleW(
+        hFile: *mut c_void,
+        lpVolumeNameBuffer: *mut u16,
+        nVolumeNameSize: u32,
+        lpVolumeSerialNumber: *mut u32,
+        lpMaximumComponentLength: *mut u32,
+        lpFileSystemFlags: *mut u32,
+        lpFileSystemNameBuffer: *mut u16,
+        nFileSystemNameSize: u32,
+    ) -> c_int;
+}
+
+const DRIVE_REMOTE: u32 = 4;
+
+const FILE_SUPPORTS_POSIX_UNLINK_RENAME: u32 = 0x00000400;
+const FILE_SUPPORTS_BLOCK_REFCOUNTING: u32 = 0x08000000;
+const FILE_READ_ONLY_VOLUME: u32 = 0x00080000;
+
+#[repr(C)]
+struct OSVERSIONINFOW {
+    os_version_info_size: u32,
+    major_version: u32,
+    minor_version: u32,
+    build_number: u32,
+    platform_id: u32,
+    csd_version: [u16; 128],
+}
+
+// GetVersionExW lies about the version unless the application has a manifest.
+
+#[cfg(not(target_vendor = "uwp"))]
+#[link(name = "ntdll")]
+extern "system" {
+    fn RtlGetVersion(lpVersionInformation: *mut OSVERSIONINFOW) -> i32;
+}
+
+// Windows 10 1809.
+#[cfg(not(target_vendor = "uwp"))]
+const VERSION_FILE_RENAME_INFO_EX: Version = Version::new(10, 0, 17763);
+
+// These are the file systems that support POSIX semantics for renames. Others
+// (like FAT and exFAT) will cause a fall back to MoveFileExW.
+#[cfg(not(target_vendor = "uwp"))]
+pub const KNOWN_FILESYSTEMS: &[KnownFilesystem] = &[
+    KnownFilesystem::new("NTFS", None, VERSION_FILE_RENAME_INFO_EX, Support::Supported),
+    KnownFilesystem::new("ReFS", None, VERSION_FILE_RENAME_INFO_EX, Support::Supported),
+];
+
+const MAX_PATH: usize = 260;
+
+#[cfg(not(target_vendor = "uwp"))]
+pub fn kernel_version() -> Result<Version> {
+    Ok(get_version())
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+fn get_version() -> Version {
+    let mut info = OSVERSIONINFOW {
+        os_version_info_size: std::mem::size_of::<OSVERSIONINFOW>() as u32,
+        major_version: 0,
+        minor_version: 0,
+        build_number: 0,
+        platform_id: 0,
+        csd_version: [0; 128],
+    };
+
+    // RtlGetVersion always succeeds.
+    unsafe { RtlGetVersion(std::ptr::addr_of_mut!(info)) };
+
+    Version::new(
+        info.major_version as u16,
+        info.minor_version as u16,
+        info.build_number as u16,
+    )
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+fn get_volume_path(path: &Path) -> Result<Vec<u16>> {
+    let path_str = to_wide_path(path, false)?;
+    // The volume path can't be longer than the path itself.
+    let mut volume = vec![0u16; path_str.len().max(MAX_PATH)];
+    let ret = unsafe {
+        GetVolumePathNameW(path_str.as_ptr(), volume.as_mut_ptr(), volume.len() as u32)
+    };
+
+    if ret == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(volume)
+}
+
+// A write-protected volume gives ERROR_WRITE_PROTECT, or ERROR_ACCESS_DENIED
+// for some file systems, which doesn't say which path is the problem.
+#[cfg(not(target_vendor = "uwp"))]
+fn read_only_error(error: Error, from: &Path, to: &Path) -> Error {
+    if !matches!(error.kind(), ErrorKind::ReadOnlyFilesystem | ErrorKind::PermissionDenied) {
+        return error;
+    }
+
+    for path in [from, to] {
+        let flags = get_volume_path(path).and_then(|volume| get_volume_information(&volume));
+
+        if flags.is_ok_and(|(_, flags)| flags & FILE_READ_ONLY_VOLUME != 0) {
+            return crate::ReadOnlyCause::Mount(path.to_path_buf()).into_error(error);
+        }
+    }
+
+    error
+}
+
+// Volume mount points mean that a path can be on a different volume to its
+// drive letter.
+#[cfg(not(target_vendor = "uwp"))]
+pub fn same_volume(a: &Path, b: &Path) -> Result<bool> {
+    let a = get_volume_path(a)?;
+    let b = get_volume_path(b)?;
+    let len = |v: &[u16]| v.iter().position(|c| *c == 0).unwrap_or(v.len());
+
+    Ok(a[..len(&a)] == b[..len(&b)])
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+fn get_volume_information(volume: &[u16]) -> Result<(String, u32)> {
+    let mut name = [0u16; MAX_PATH + 1];
+    let mut flags = 0;
+    let ret = unsafe {
+        GetVolumeInformationW(
+            volume.as_ptr(),
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &mut flags,
+            name.as_mut_ptr(),
+            name.len() as u32,
+        )
+    };
+
+    if ret == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+    Ok((String::from_utf16_lossy(&name[..len]), flags))
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+fn get_volume_information_by_handle(file: &File) -> Result<String> {
+    use std::os::windows::io::AsRawHandle;
+
+    let mut name = [0u16; MAX_PATH + 1];
+    let ret = unsafe {
+        GetVolumeInformationByHandleW(
+            file.as_raw_handle(),
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            name.as_mut_ptr(),
+            name.len() as u32,
+        )
+    };
+
+    if ret == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+    Ok(String::from_utf16_lossy(&name[..len]))
+}
+
+#[repr(C)]
+struct FILE_REMOTE_PROTOCOL_INFO {
+    structure_version: u16,
+    structure_size: u16,
+    protocol: u32,
+    protocol_major_version: u16,
+    protocol_minor_version: u16,
+    protocol_revision: u16,
+    reserved: u16,
+    flags: u32,
+    generic_reserved: [u32; 8],
+    protocol_specific: [u32; 16],
+}
+
+const FILE_REMOTE_PROTOCOL_INFO_CLASS: c_int = 13;
+
+// There's no drive type for a handle. Instead, asking for the remote protocol
+// fails with ERROR_INVALID_PARAMETER for files that aren't on a network share.
+#[cfg(not(target_vendor = "uwp"))]
+fn is_remote_handle(file: &File) -> Result<bool> {
+    use std::os::windows::io::AsRawHandle;
+
+    let mut info = std::mem::MaybeUninit::<FILE_REMOTE_PROTOCOL_INFO>::zeroed();
+    let ret = unsafe {
+        GetFileInformationByHandleEx(
+            file.as_raw_handle(),
+            FILE_REMOTE_PROTOCOL_INFO_CLASS,
+            info.as_mut_ptr() as *mut c_void,
+            std::mem::size_of::<FILE_REMOTE_PROTOCOL_INFO>() as u32,
+        )
+    };
+
+    if ret != 0 {
+        return Ok(true);
+    }
+
+    let error = std::io::Error::last_os_error();
+
+    if error.raw_os_error() == Some(ERROR_INVALID_PARAMETER) {
+        Ok(false)
+    } else {
+        Err(error)
+    }
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+pub fn atomic_support(path: &Path) -> Result<AtomicSupport> {
+    let volume = get_volume_path(path)?;
+
+    atomic_support_with(
+        || Ok(unsafe { GetDriveTypeW(volume.as_ptr()) } == DRIVE_REMOTE),
+        || Ok(get_volume_information(&volume)?.0),
+    )
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+pub fn atomic_support_fd(dir: &File) -> Result<AtomicSupport> {
+    atomic_support_with(
+        || is_remote_handle(dir),
+        || get_volume_information_by_handle(dir),
+    )
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+fn atomic_support_with(
+    is_remote: impl FnOnce() -> Result<bool>,
+    filesystem_name: impl FnOnce() -> Result<String>,
+) -> Result<AtomicSupport> {
+    // Older versions of Windows fall back to MoveFileExW which isn't documented
+    // to be atomic.
+    let found = get_version();
+    let required = VERSION_FILE_RENAME_INFO_EX;
+
+    if found < required {
+        return Ok(AtomicSupport::No(Reason::KernelTooOld { found, required }));
+    }
+
+    // SMB and WebDAV shares (mapped drives and UNC paths) go through a network
+    // redirector. Even if the server is using NTFS, whether the rename is
+    // atomic depends on the server and the protocol version.
+    if is_remote()? {
+        return Ok(AtomicSupport::Unknown);
+    }
+
+    let fs = filesystem_name()?;
+
+    if KNOWN_FILESYSTEMS.iter().any(|known| known.name == fs) {
+        Ok(AtomicSupport::Yes)
+    } else {
+        Ok(AtomicSupport::No(Reason::Filesystem(FsInfo {
+            name: Some(fs),
+            magic: None,
+            network: false,
+        })))
+    }
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+pub fn fs_info(path: &Path) -> Result<FsInfo> {
+    let volume = get_volume_path(path)?;
+    let (fs, _) = get_volume_information(&volume)?;
+
+    Ok(FsInfo {
+        name: Some(fs),
+        magic: None,
+        network: unsafe { GetDriveTypeW(volume.as_ptr()) } == DRIVE_REMOTE,
+    })
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+pub fn fs_info_fd(dir: &File) -> Result<FsInfo> {
+    Ok(FsInfo {
+        name: Some(get_volume_information_by_handle(dir)?),
+        magic: None,
+        network: is_remote_handle(dir)?,
+    })
+}
+
+#[cfg(target_vendor = "uwp")]
+pub fn atomic_support(_path: &Path) -> Result<AtomicSupport> {
     // It's supported if the linker doesn't complain. Whether it's atomic or not
     // is a more difficult question to answer.
-    Ok(true)
+    Ok(AtomicSupport::Yes)
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+#[link(name = "kernel32")]
+extern "system" {
+    fn DeviceIoControl(
+        hDevice: *mut c_void,
+        dwIoControlCode: u32,
+        lpInBuffer: *const c_void,
+        nInBufferSize: u32,
+        lpOutBuffer: *mut c_void,
+        nOutBufferSize: u32,
+        lpBytesReturned: *mut u32,
+        lpOverlapped: *mut c_void,
+    ) -> c_int;
+}
+
+#[repr(C)]
+struct FILE_FS_PERSISTENT_VOLUME_INFORMATION {
+    volume_flags: u32,
+    flag_mask: u32,
+    version: u32,
+    reserved: u32,
+}
+
+const FSCTL_QUERY_PERSISTENT_VOLUME_STATE: u32 = 0x0009023C;
+const PERSISTENT_VOLUME_STATE_DEV_VOLUME: u32 = 0x00002000;
+
+// Only ReFS volumes can be Dev Drives. Older versions of Windows don't know
+// about the flag and leave it cleared.
+#[cfg(not(target_vendor = "uwp"))]
+fn is_dev_drive(volume: &[u16]) -> Result<bool> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+
+    let len = volume.iter().position(|c| *c == 0).unwrap_or(volume.len());
+    let root = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(OsString::from_wide(&volume[..len]))?;
+
+    let input = FILE_FS_PERSISTENT_VOLUME_INFORMATION {
+        volume_flags: 0,
+        flag_mask: PERSISTENT_VOLUME_STATE_DEV_VOLUME,
+        version: 1,
+        reserved: 0,
+    };
+    let mut output = FILE_FS_PERSISTENT_VOLUME_INFORMATION {
+        volume_flags: 0,
+        flag_mask: 0,
+        version: 0,
+        reserved: 0,
+    };
+    let size = std::mem::size_of::<FILE_FS_PERSISTENT_VOLUME_INFORMATION>() as u32;
+    let mut returned = 0;
+    let ret = unsafe {
+        DeviceIoControl(
+            root.as_raw_handle(),
+            FSCTL_QUERY_PERSISTENT_VOLUME_STATE,
+            std::ptr::addr_of!(input) as *const c_void,
+            size,
+            std::ptr::addr_of_mut!(output) as *mut c_void,
+            size,
+            &mut returned,
+            std::ptr::null_mut(),
+        )
+    };
+
+    if ret == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(output.volume_flags & PERSISTENT_VOLUME_STATE_DEV_VOLUME != 0)
+}
+
+#[cfg(not(target_vendor = "uwp"))]
+pub fn volume_capabilities(path: &Path, capabilities: &mut crate::Capabilities) -> Result<()> {
+    let volume = get_volume_path(path)?;
+    let (fs, flags) = get_volume_information(&volume)?;
+
+    capabilities.posix_rename = flags & FILE_SUPPORTS_POSIX_UNLINK_RENAME != 0;
+    capabilities.block_cloning = flags & FILE_SUPPORTS_BLOCK_REFCOUNTING != 0;
+    // The query isn't supported by some file systems and the redirector.
+    capabilities.dev_drive = fs == "ReFS" && is_dev_drive(&volume).unwrap_or_else(|e| {
+        crate::instrument::detection_failed("Dev Drive state", &e);
+        false
+    });
+    // None of the file systems that Windows supports normalize names.
+    capabilities.normalization = Some(crate::Normalization::Sensitive);
+    // NTFS directories can be made case sensitive (for WSL) but that isn't
+    // checked.
+    capabilities.case_sensitive = Some(false);
+    capabilities.file_system = Some(fs);
+
+    Ok(())
 }
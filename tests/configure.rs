@@ -0,0 +1,73 @@
+//! `configure` changes the defaults for the whole process, so this is kept in
+//! its own test binary where nothing else is running at the same time.
+
+use std::io::{ErrorKind, Result};
+use renamore::{Backend, Config, RenameOptions};
+
+#[test]
+fn configure() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let from = dir.path().join("from");
+    let to = dir.path().join("to");
+    std::fs::write(&from, "from")?;
+
+    // RenameOptions::new starts from the defaults.
+    let mut defaults = RenameOptions::new();
+    defaults.backend(Backend::EmulateOnly);
+    renamore::configure(Config::new().defaults(defaults));
+    let error = RenameOptions::new().rename_exclusive(&from, &to).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+
+    // Without a native implementation, rename_exclusive only falls back when
+    // it's been told to.
+    let mut defaults = RenameOptions::new();
+    defaults.backend(Backend::NonAtomicOnly);
+    renamore::configure(Config::new().defaults(defaults.clone()));
+    let error = renamore::rename_exclusive(&from, &to).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+
+    renamore::configure(Config::new().defaults(defaults.clone()).fallback(true));
+    renamore::rename_exclusive(&from, &to)?;
+    assert_eq!(std::fs::read_to_string(&to)?, "from");
+
+    // So does the detailed version.
+    renamore::rename_exclusive_detailed(&to, &from)?;
+    assert_eq!(std::fs::read_to_string(&from)?, "from");
+
+    // RenameOptions::rename_exclusive is still atomic or nothing.
+    let error = RenameOptions::new().rename_exclusive(&from, &to).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+
+    // allow_non_atomic still wins.
+    renamore::configure(Config::new().defaults(defaults).fallback(true).allow_non_atomic(false));
+    let error = renamore::rename_exclusive(&from, &to).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+
+    // The built-in defaults come back.
+    renamore::configure(&Config::new());
+    let atomic = renamore::rename_exclusive_is_atomic(dir.path())?;
+    assert_eq!(RenameOptions::new().rename_exclusive(&from, &to).is_ok(), atomic);
+
+    if atomic {
+        std::fs::rename(&to, &from)?;
+    }
+
+    // The environment is ignored unless env_overrides is set.
+    std::env::set_var("RENAMORE_FAIL_RENAMES", "ENOSPC");
+    std::env::set_var("RENAMORE_FORCE_NON_ATOMIC", "1");
+    assert_eq!(renamore::rename_exclusive_fallback(&from, &to)?, atomic);
+
+    renamore::configure(Config::new().env_overrides(true));
+    let error = renamore::rename_exclusive_fallback(&to, &from).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::StorageFull);
+    assert!(to.try_exists()?);
+
+    std::env::remove_var("RENAMORE_FAIL_RENAMES");
+    assert!(!renamore::rename_exclusive_fallback(&to, &from)?);
+    assert_eq!(std::fs::read_to_string(&from)?, "from");
+
+    std::env::remove_var("RENAMORE_FORCE_NON_ATOMIC");
+    renamore::configure(&Config::new());
+
+    Ok(())
+}
@@ -0,0 +1,224 @@
+//! Checks the behaviour of real file systems against what the crate expects.
+//!
+//! Each test creates a small image, formats it, loop-mounts it and then
+//! compares `rename_exclusive_is_atomic` with what `rename_exclusive` actually
+//! does. This is only built on Linux with the `loop-tests` feature.
+//!
+//! ```text
+//! sudo -E cargo test --features loop-tests --test loop_mount
+//! ```
+//!
+//! Mounting needs root (or `CAP_SYS_ADMIN`) and loop devices, which aren't
+//! available in most containers. Block devices can't be mounted from inside a
+//! user namespace either, even as root there. If the image can't be mounted,
+//! or the `mkfs` for a file system isn't installed, that test is skipped with a
+//! message rather than failing. Setting `RENAMORE_LOOP_TESTS_REQUIRED` turns
+//! skips into failures so that CI notices when nothing was checked.
+
+#![cfg(all(target_os = "linux", feature = "loop-tests"))]
+
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+struct Mount {
+    dir: PathBuf,
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        let _ = Command::new("umount").arg(&self.dir).status();
+    }
+}
+
+const CAP_SYS_ADMIN: u32 = 21;
+
+fn has_cap_sys_admin() -> bool {
+    let Ok(status) = std::fs::read_to_string("/proc/self/status") else {
+        return false;
+    };
+    status.lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        .is_some_and(|caps| caps & 1 << CAP_SYS_ADMIN != 0)
+}
+
+// The initial user namespace maps every ID to itself. In any other namespace,
+// root only has CAP_SYS_ADMIN over that namespace, which isn't enough to mount
+// a block device.
+fn in_user_namespace() -> bool {
+    let Ok(map) = std::fs::read_to_string("/proc/self/uid_map") else {
+        return false;
+    };
+    map.split_whitespace().ne(["0", "0", "4294967295"])
+}
+
+fn skip(what: &str, why: &str) {
+    if std::env::var_os("RENAMORE_LOOP_TESTS_REQUIRED").is_some() {
+        panic!("{} was skipped: {}", what, why);
+    }
+    eprintln!("skipping {}: {}", what, why);
+}
+
+// Returns None, after saying why, if the file system can't be mounted here.
+fn mount(image_dir: &Path, file_system: &str, mkfs: &[&str], size: u64) -> Result<Option<Mount>> {
+    if !has_cap_sys_admin() {
+        skip(file_system, "mounting needs root or CAP_SYS_ADMIN");
+        return Ok(None);
+    }
+    if in_user_namespace() {
+        skip(file_system, "block devices can't be mounted in a user namespace");
+        return Ok(None);
+    }
+    if !Path::new("/dev/loop-control").exists() {
+        skip(file_system, "there are no loop devices");
+        return Ok(None);
+    }
+
+    let image = image_dir.join("image");
+    let dir = image_dir.join("mount");
+
+    std::fs::File::create(&image)?.set_len(size)?;
+    std::fs::create_dir(&dir)?;
+
+    let formatted = Command::new(mkfs[0])
+        .args(&mkfs[1..])
+        .arg(&image)
+        .stdout(Stdio::null())
+        .status();
+
+    let formatted = match formatted {
+        Ok(status) => status.success(),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            skip(file_system, &format!("{} isn't installed", mkfs[0]));
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+    assert!(formatted, "{} failed", mkfs[0]);
+
+    let mounted = Command::new("mount")
+        .args(["-t", file_system, "-o", "loop"])
+        .arg(&image)
+        .arg(&dir)
+        .status()?
+        .success();
+
+    if !mounted {
+        skip(file_system, "the image couldn't be mounted");
+        return Ok(None);
+    }
+
+    Ok(Some(Mount { dir }))
+}
+
+fn check(file_system: &str, mkfs: &[&str], size: u64) -> Result<()> {
+    let image_dir = tempfile::tempdir()?;
+    let Some(mount) = mount(image_dir.path(), file_system, mkfs, size)? else {
+        return Ok(());
+    };
+
+    let info = renamore::fs_info(&mount.dir)?;
+    let atomic = renamore::rename_exclusive_is_atomic(&mount.dir)?;
+    eprintln!("{}: {:?}, is_atomic = {}", file_system, info, atomic);
+
+    let a = mount.dir.join("a");
+    let b = mount.dir.join("b");
+    let c = mount.dir.join("c");
+
+    std::fs::write(&a, "a")?;
+    std::fs::write(&b, "b")?;
+
+    match renamore::rename_exclusive(&a, &b) {
+        Ok(()) => panic!("{}: rename_exclusive replaced the destination", file_system),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            assert!(
+                atomic,
+                "{}: is_atomic is false but the rename was refused atomically",
+                file_system,
+            );
+        }
+        Err(e) if e.kind() == ErrorKind::Unsupported => {
+            assert!(!atomic, "{}: is_atomic is true but the rename isn't supported", file_system);
+        }
+        Err(e) => return Err(e),
+    }
+
+    assert_eq!(std::fs::read_to_string(&a)?, "a");
+    assert_eq!(std::fs::read_to_string(&b)?, "b");
+
+    if atomic {
+        renamore::rename_exclusive(&a, &c)?;
+        assert!(!a.exists());
+        assert_eq!(std::fs::read_to_string(&c)?, "a");
+    } else {
+        assert!(!renamore::rename_exclusive_fallback(&a, &c)?);
+        assert_eq!(std::fs::read_to_string(&c)?, "a");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn ext4() -> Result<()> {
+    check("ext4", &["mkfs.ext4", "-q", "-F"], 16 << 20)
+}
+
+#[test]
+fn btrfs() -> Result<()> {
+    // mkfs.btrfs refuses to make anything smaller than about 110 MiB.
+    check("btrfs", &["mkfs.btrfs", "-q", "-f"], 128 << 20)
+}
+
+#[test]
+fn vfat() -> Result<()> {
+    check("vfat", &["mkfs.vfat"], 16 << 20)
+}
+
+#[test]
+fn xfs() -> Result<()> {
+    // mkfs.xfs needs at least 300 MiB.
+    check("xfs", &["mkfs.xfs", "-q", "-f"], 300 << 20)
+}
+
+// Renaming out of a bind mount fails with EXDEV even though it's the same file
+// system. That should be reported as a boundary.
+#[test]
+fn bind_mount_boundary() -> Result<()> {
+    // Unlike the loop mounts, this works as root in a user namespace as long as
+    // it has its own mount namespace too.
+    if !has_cap_sys_admin() {
+        skip("bind mount", "mounting needs root or CAP_SYS_ADMIN");
+        return Ok(());
+    }
+
+    let dir = tempfile::tempdir()?;
+    let inner = dir.path().join("inner");
+    let bound = dir.path().join("bound");
+
+    std::fs::create_dir(&inner)?;
+    std::fs::create_dir(&bound)?;
+
+    if !Command::new("mount").arg("--bind").arg(&inner).arg(&bound).status()?.success() {
+        skip("bind mount", "the directory couldn't be mounted");
+        return Ok(());
+    }
+    let mount = Mount { dir: bound };
+
+    let a = mount.dir.join("a");
+    std::fs::write(&a, "a")?;
+
+    // The bind mount has the same device as where it came from.
+    assert_eq!(
+        renamore::rename_exclusive_atomic_support_for(&a, dir.path().join("b"))?,
+        renamore::AtomicSupport::No(renamore::Reason::CrossesDevices),
+    );
+    assert!(renamore::rename_exclusive_is_atomic_for(&a, mount.dir.join("b"))?);
+
+    let error = renamore::rename_exclusive(&a, dir.path().join("b")).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::CrossesDevices);
+    let boundary = error.get_ref().unwrap().downcast_ref::<renamore::BoundaryError>().unwrap();
+    assert_eq!(boundary.io_error().raw_os_error(), Some(18));
+
+    Ok(())
+}